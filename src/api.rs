@@ -1,15 +1,21 @@
-use std::mem;
+use std::{convert::TryFrom, mem};
 
 use chrono::{DateTime, Utc};
+use pgp::{types::CompressionAlgorithm, SignedSecretKey};
 
 use crate::{
-    channel::{Channel, SignedMessage},
+    attachment::{self, Attachment},
+    channel::{
+        Channel, ChannelMode, Message, MessagePage, MessagePosition, ReadMarker, RetentionPolicy,
+        SignedMessage, ThreadSummary, Tombstone,
+    },
     check_name_validity, check_permission,
+    conversation::Conversation,
     error::Error,
     hub::{Hub, HubMember},
-    new_id,
+    hub_cache, new_id,
     permission::{ChannelPermission, HubPermission, PermissionSetting},
-    Result, ID,
+    storage, Result, ID,
 };
 
 /// Creates a hub, returning the ID of the new hub if successful.
@@ -33,11 +39,11 @@ pub async fn create_hub<S: Into<String>>(owner_id: S, name: S) -> Result<ID> {
     let owner_id: String = owner_id.into();
     check_name_validity(&name)?;
     let mut id = new_id();
-    while Hub::load(id).await.is_ok() {
+    while storage::hub_store().hub_exists(id).await {
         id = new_id();
     }
     let mut new_hub = Hub::new(name, id, owner_id.clone());
-    let channel_id = new_hub.new_channel(&owner_id, "chat".to_string()).await?;
+    let channel_id = new_hub.new_channel(&owner_id, "chat".to_string())?;
     if let Some(group) = new_hub.groups.get_mut(&new_hub.default_group) {
         group.set_channel_permission(
             channel_id,
@@ -50,7 +56,8 @@ pub async fn create_hub<S: Into<String>>(owner_id: S, name: S) -> Result<ID> {
             Some(true),
         );
     }
-    new_hub.save().await?;
+    hub_cache::save(&mut new_hub).await?;
+    new_hub.channels[&channel_id].create_dir().await?;
     Ok(id)
 }
 
@@ -68,7 +75,7 @@ pub async fn create_hub<S: Into<String>>(owner_id: S, name: S) -> Result<ID> {
 /// * The user is not in the hub.
 /// * The hub failed to load for any of the reasons outlined in [`Hub::load`].
 pub async fn get_hub(user_id: &str, hub_id: ID) -> Result<Hub> {
-    let hub = Hub::load(hub_id).await?;
+    let hub = hub_cache::get(hub_id).await?;
     hub.strip(user_id)
 }
 
@@ -88,11 +95,12 @@ pub async fn get_hub(user_id: &str, hub_id: ID) -> Result<Hub> {
 /// * The user does not have permission to delete the hub.
 /// * The hub's data files could not be deleted.
 pub async fn delete_hub(user_id: &str, hub_id: ID) -> Result {
-    let hub = Hub::load(hub_id).await?;
+    let hub = hub_cache::get(hub_id).await?;
     let member = hub.get_member(user_id)?;
     check_permission!(member, HubPermission::All, hub);
-    tokio::fs::remove_file(hub.get_info_path()).await?;
-    tokio::fs::remove_dir_all(hub.get_data_path()).await?;
+    hub_cache::delete(hub_id).await?;
+    storage::message_store().delete_hub_messages(hub_id).await?;
+    attachment::delete_hub_attachments(&storage::data_dir()?, hub_id).await?;
     Ok(())
 }
 
@@ -120,12 +128,12 @@ pub async fn rename_hub<S: Into<String> + Clone>(
 ) -> Result<String> {
     let new_name: String = new_name.into();
     check_name_validity(&new_name)?;
-    let mut hub = Hub::load(hub_id).await?;
-    let member = hub.get_member(user_id)?;
-    check_permission!(member, HubPermission::Administrate, hub);
-    let old_name = mem::replace(&mut hub.name, new_name);
-    hub.save().await?;
-    Ok(old_name)
+    hub_cache::modify(hub_id, |hub| {
+        let member = hub.get_member(user_id)?;
+        check_permission!(member, HubPermission::Administrate, hub);
+        Ok(mem::replace(&mut hub.name, new_name.clone()))
+    })
+    .await
 }
 
 /// Changes the description of a hub.
@@ -151,15 +159,15 @@ pub async fn change_hub_description<S: Into<String> + Clone>(
     new_description: S,
 ) -> Result<String> {
     let new_description: String = new_description.into();
-    if new_description.as_bytes().len() > crate::MAX_DESCRIPTION_SIZE {
+    if new_description.len() > crate::MAX_DESCRIPTION_SIZE {
         Err(Error::TooBig)
     } else {
-        let mut hub = Hub::load(hub_id).await?;
-        let member = hub.get_member(user_id)?;
-        check_permission!(member, HubPermission::Administrate, hub);
-        let old_name = mem::replace(&mut hub.description, new_description);
-        hub.save().await?;
-        Ok(old_name)
+        hub_cache::modify(hub_id, |hub| {
+            let member = hub.get_member(user_id)?;
+            check_permission!(member, HubPermission::Administrate, hub);
+            Ok(mem::replace(&mut hub.description, new_description.clone()))
+        })
+        .await
     }
 }
 
@@ -179,7 +187,7 @@ pub async fn change_hub_description<S: Into<String> + Clone>(
 /// * The user who is checking is not in the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 pub async fn user_banned(actor_id: &str, hub_id: ID, user_id: &str) -> Result<bool> {
-    let hub = Hub::load(hub_id).await?;
+    let hub = hub_cache::get(hub_id).await?;
     hub.check_membership(actor_id)?;
     Ok(hub.bans.contains(user_id))
 }
@@ -200,7 +208,7 @@ pub async fn user_banned(actor_id: &str, hub_id: ID, user_id: &str) -> Result<bo
 /// * The user who is checking is not in the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 pub async fn user_muted(actor_id: &str, hub_id: ID, user_id: &str) -> Result<bool> {
-    let hub = Hub::load(hub_id).await?;
+    let hub = hub_cache::get(hub_id).await?;
     hub.check_membership(actor_id)?;
     Ok(hub.mutes.contains(user_id))
 }
@@ -221,7 +229,7 @@ pub async fn user_muted(actor_id: &str, hub_id: ID, user_id: &str) -> Result<boo
 /// * The user whose information is being requested is not in the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 pub async fn get_hub_member(actor_id: &str, hub_id: ID, user_id: &str) -> Result<HubMember> {
-    let hub = Hub::load(hub_id).await?;
+    let hub = hub_cache::get(hub_id).await?;
     hub.check_membership(actor_id)?;
     Ok(hub.get_member(user_id)?.clone())
 }
@@ -238,9 +246,7 @@ pub async fn get_hub_member(actor_id: &str, hub_id: ID, user_id: &str) -> Result
 /// * The user could not be added to the hub for any of the reasons outlined by [`User::join_hub`].
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
 pub async fn join_hub(user_id: String, hub_id: ID) -> Result {
-    let mut hub = Hub::load(hub_id).await?;
-    hub.user_join(user_id)?;
-    hub.save().await
+    hub_cache::modify(hub_id, |hub| hub.user_join(user_id.clone()).map(|_| ())).await
 }
 
 /// Removes the given user from a hub.
@@ -255,25 +261,25 @@ pub async fn join_hub(user_id: String, hub_id: ID) -> Result {
 /// * The user could not be removed from the hub for any of the reasons outlined by [`User::leave_hub`].
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
 pub async fn leave_hub(user_id: &str, hub_id: ID) -> Result {
-    let mut hub = Hub::load(hub_id).await?;
-    hub.user_leave(user_id)?;
-    hub.save().await
+    hub_cache::modify(hub_id, |hub| hub.user_leave(user_id)).await
 }
 
 /// Handles kicking, banning, muting, unbanning and unmuting users in/from hubs.
 async fn hub_user_op(actor_id: &str, hub_id: ID, user_id: &str, op: HubPermission) -> Result {
-    let mut hub = Hub::load(hub_id).await?;
-    let member = hub.get_member(actor_id)?;
-    check_permission!(member, op, hub);
-    match op {
-        HubPermission::Kick => hub.kick_user(&user_id)?,
-        HubPermission::Ban => hub.ban_user(user_id.to_string())?,
-        HubPermission::Unban => hub.unban_user(user_id),
-        HubPermission::Mute => hub.mute_user(user_id.to_string()),
-        HubPermission::Unmute => hub.unmute_user(user_id),
-        _ => return Err(Error::UnexpectedServerArg),
-    }
-    hub.save().await
+    hub_cache::modify(hub_id, |hub| {
+        let member = hub.get_member(actor_id)?;
+        check_permission!(member, op, hub);
+        match op {
+            HubPermission::Kick => hub.kick_user(user_id)?,
+            HubPermission::Ban => hub.ban_user(user_id.to_string())?,
+            HubPermission::Unban => hub.unban_user(user_id),
+            HubPermission::Mute => hub.mute_user(user_id.to_string()),
+            HubPermission::Unmute => hub.unmute_user(user_id),
+            _ => return Err(Error::UnexpectedServerArg),
+        }
+        Ok(())
+    })
+    .await
 }
 
 /// Maps the different possible options for [`hub_user_op`] to separate functions.
@@ -401,10 +407,13 @@ pub async fn create_channel<S: Into<String> + Clone>(
     hub_id: ID,
     name: S,
 ) -> Result<ID> {
-    check_name_validity(&name.clone().into())?;
-    let mut hub = Hub::load(hub_id).await?;
-    let channel_id = hub.new_channel(user_id, name.into()).await?;
-    hub.save().await?;
+    let name: String = name.into();
+    check_name_validity(&name)?;
+    let channel_id =
+        hub_cache::modify(hub_id, |hub| hub.new_channel(user_id, name.clone())).await?;
+    storage::message_store()
+        .create_channel(hub_id, channel_id)
+        .await?;
     Ok(channel_id)
 }
 
@@ -424,7 +433,7 @@ pub async fn create_channel<S: Into<String> + Clone>(
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 /// * The channel does not exist.
 pub async fn get_channel(user_id: &str, hub_id: ID, channel_id: ID) -> Result<Channel> {
-    let hub = Hub::load(hub_id).await?;
+    let hub = hub_cache::get(hub_id).await?;
     Ok(hub.get_channel(user_id, channel_id)?.clone())
 }
 
@@ -454,13 +463,12 @@ pub async fn rename_channel<S: Into<String> + Clone>(
     channel_id: ID,
     new_name: S,
 ) -> Result<String> {
-    check_name_validity(&new_name.clone().into())?;
-    let mut hub = Hub::load(hub_id).await?;
-    let old_name = hub
-        .rename_channel(user_id, channel_id, new_name.into())
-        .await?;
-    hub.save().await?;
-    Ok(old_name)
+    let new_name: String = new_name.into();
+    check_name_validity(&new_name)?;
+    hub_cache::modify(hub_id, |hub| {
+        hub.rename_channel(user_id, channel_id, new_name.clone())
+    })
+    .await
 }
 
 /// Renames a text channel in a hub.
@@ -490,18 +498,176 @@ pub async fn change_channel_description<S: Into<String> + Clone>(
     new_description: S,
 ) -> Result<String> {
     let description: String = new_description.into();
-    if description.as_bytes().len() > crate::MAX_DESCRIPTION_SIZE {
+    if description.len() > crate::MAX_DESCRIPTION_SIZE {
         Err(Error::TooBig)
     } else {
-        let mut hub = Hub::load(hub_id).await?;
-        let old_name = hub
-            .change_channel_description(user_id, channel_id, description)
-            .await?;
-        hub.save().await?;
-        Ok(old_name)
+        hub_cache::modify(hub_id, |hub| {
+            hub.change_channel_description(user_id, channel_id, description.clone())
+        })
+        .await
     }
 }
 
+/// Changes how long the messages in a text channel are kept.
+/// Returns the previous retention policy of the channel if successful.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to configure the channel.
+/// * `hub_id` - ID of the hub that has the channel.
+/// * `channel_id` - ID of the channel whose retention policy is to be changed.
+/// * `retention` - New retention policy for the channel.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * THe user is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The user does not have permission to configure the channel.
+/// * The retention policy could not be changed for any of the reasons outlined by [`Hub::set_channel_retention`].
+pub async fn set_channel_retention(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    retention: RetentionPolicy,
+) -> Result<RetentionPolicy> {
+    hub_cache::modify(hub_id, |hub| {
+        hub.set_channel_retention(user_id, channel_id, retention)
+    })
+    .await
+}
+
+/// Changes who can send messages in a text channel.
+/// Returns the previous mode of the channel if successful.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to configure the channel.
+/// * `hub_id` - ID of the hub that has the channel.
+/// * `channel_id` - ID of the channel whose mode is to be changed.
+/// * `mode` - New mode for the channel.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The mode could not be changed for any of the reasons outlined by [`Hub::set_channel_mode`].
+pub async fn set_channel_mode(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    mode: ChannelMode,
+) -> Result<ChannelMode> {
+    hub_cache::modify(hub_id, |hub| {
+        hub.set_channel_mode(user_id, channel_id, mode)
+    })
+    .await
+}
+
+/// Pins a message in a channel, returns false if the message was already pinned.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to configure the channel.
+/// * `hub_id` - ID of the hub that has the channel.
+/// * `channel_id` - ID of the channel the message was sent in.
+/// * `message_id` - ID of the message to pin.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The message could not be found in the channel or has been deleted.
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The message could not be pinned for any of the reasons outlined by [`Hub::pin_message`].
+pub async fn pin_message(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    message_id: ID,
+) -> Result<bool> {
+    let hub = hub_cache::get(hub_id).await?;
+    Hub::get_channel(&hub, user_id, channel_id)?
+        .get_message(message_id)
+        .await
+        .filter(|message| message.tombstone().is_none())
+        .ok_or(Error::MessageNotFound)?;
+    hub_cache::modify(hub_id, |hub| {
+        hub.pin_message(user_id, channel_id, message_id)
+    })
+    .await
+}
+
+/// Unpins a message in a channel, returns false if the message was not pinned.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to configure the channel.
+/// * `hub_id` - ID of the hub that has the channel.
+/// * `channel_id` - ID of the channel the message was pinned in.
+/// * `message_id` - ID of the message to unpin.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The message could not be unpinned for any of the reasons outlined by [`Hub::unpin_message`].
+pub async fn unpin_message(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    message_id: ID,
+) -> Result<bool> {
+    hub_cache::modify(hub_id, |hub| {
+        hub.unpin_message(user_id, channel_id, message_id)
+    })
+    .await
+}
+
+/// Unpins messages that were deleted or pruned from a channel, returns false if none of them were pinned.
+///
+/// # Arguments
+///
+/// * `hub_id` - ID of the hub that has the channel.
+/// * `channel_id` - ID of the channel the messages were deleted from.
+/// * `message_ids` - IDs of the deleted messages.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+pub async fn unpin_deleted(hub_id: ID, channel_id: ID, message_ids: &[ID]) -> Result<bool> {
+    hub_cache::modify(hub_id, |hub| Ok(hub.unpin_deleted(channel_id, message_ids))).await
+}
+
+/// Gets the messages pinned in a channel, oldest pin first.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn get_pinned_messages(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+) -> Result<Vec<SignedMessage>> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    Ok(channel.get_pinned_messages().await)
+}
+
 /// Deletes a text channel in a hub.
 ///
 /// # Arguments
@@ -520,9 +686,137 @@ pub async fn change_channel_description<S: Into<String> + Clone>(
 /// * The user does not have permission to delete channels.
 /// * The channel could not be deleted for any of the reasons outlined by [`Hub::delete_channel`].
 pub async fn delete_channel(user_id: &str, hub_id: ID, channel_id: ID) -> Result {
-    let mut hub = Hub::load(hub_id).await?;
-    hub.delete_channel(user_id, channel_id).await?;
-    hub.save().await
+    hub_cache::modify(hub_id, |hub| hub.delete_channel(user_id, channel_id)).await
+}
+
+/// Creates a channel category at the end of a hub's categories, returning the ID of the new category if successful.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to manage channels.
+/// * `hub_id` - ID of the hub in which the category should be created.
+/// * `name` - Name for the new category.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The name failed to pass the checks for any of the reasons outlined in [`check_name_validity`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The category could not be created for any of the reasons outlined by [`Hub::new_category`].
+pub async fn create_category<S: Into<String> + Clone>(
+    user_id: &str,
+    hub_id: ID,
+    name: S,
+) -> Result<ID> {
+    let name: String = name.into();
+    check_name_validity(&name)?;
+    hub_cache::modify(hub_id, |hub| hub.new_category(user_id, name.clone())).await
+}
+
+/// Renames a channel category in a hub.
+/// Returns the previous name of the category if successful.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to manage channels.
+/// * `hub_id` - ID of the hub that has the category.
+/// * `category_id` - ID of the category to be renamed.
+/// * `new_name` - New name for the category.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The name failed to pass the checks for any of the reasons outlined in [`check_name_validity`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The category could not be renamed for any of the reasons outlined by [`Hub::rename_category`].
+pub async fn rename_category<S: Into<String> + Clone>(
+    user_id: &str,
+    hub_id: ID,
+    category_id: ID,
+    new_name: S,
+) -> Result<String> {
+    let new_name: String = new_name.into();
+    check_name_validity(&new_name)?;
+    hub_cache::modify(hub_id, |hub| {
+        hub.rename_category(user_id, category_id, new_name.clone())
+    })
+    .await
+}
+
+/// Deletes a channel category in a hub, the channels in it are kept but are no longer in a category.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to manage channels.
+/// * `hub_id` - ID of the hub that has the category.
+/// * `category_id` - ID of the category to be deleted.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The category could not be deleted for any of the reasons outlined by [`Hub::delete_category`].
+pub async fn delete_category(user_id: &str, hub_id: ID, category_id: ID) -> Result {
+    hub_cache::modify(hub_id, |hub| hub.delete_category(user_id, category_id)).await
+}
+
+/// Moves a channel to a position in a category, or among the channels that are not in a category.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to manage channels.
+/// * `hub_id` - ID of the hub that has the channel.
+/// * `channel_id` - ID of the channel to move.
+/// * `category_id` - ID of the category to move the channel into, `None` to take the channel out of its category.
+/// * `position` - Position of the channel in the category, positions past the end put the channel last.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The channel could not be moved for any of the reasons outlined by [`Hub::move_channel`].
+pub async fn move_channel(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    category_id: Option<ID>,
+    position: u32,
+) -> Result {
+    hub_cache::modify(hub_id, |hub| {
+        hub.move_channel(user_id, channel_id, category_id, position)
+    })
+    .await
+}
+
+/// Moves a channel category to a position among the categories of its hub.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to manage channels.
+/// * `hub_id` - ID of the hub that has the category.
+/// * `category_id` - ID of the category to move.
+/// * `position` - New position of the category, positions past the end put the category last.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The category could not be moved for any of the reasons outlined by [`Hub::move_category`].
+pub async fn move_category(user_id: &str, hub_id: ID, category_id: ID, position: u32) -> Result {
+    hub_cache::modify(hub_id, |hub| {
+        hub.move_category(user_id, category_id, position)
+    })
+    .await
 }
 
 /// Gets a message from a text channel in a hub.
@@ -549,7 +843,7 @@ pub async fn get_message(
     channel_id: ID,
     message_id: ID,
 ) -> Result<SignedMessage> {
-    let hub = Hub::load(hub_id).await?;
+    let hub = hub_cache::get(hub_id).await?;
     let channel = Hub::get_channel(&hub, user_id, channel_id)?;
     if let Some(message) = channel.get_message(message_id).await {
         Ok(message)
@@ -558,109 +852,596 @@ pub async fn get_message(
     }
 }
 
-/// Gets messages sent after a given message.
-/// If successful they are returned in an array. The array is orderd oldest message to newest
-/// If there are no messages after the given message or the given message is not found, an empty array is returned.
+/// Creates a new message, the message still has to be signed by the server and then the user before it can be sent.
 ///
 /// # Arguments
 ///
-/// * `user_id` - ID of the user who is requesting the message.
-/// * `hub_id` - ID of the hub where the message is located.
-/// * `channel_id` - ID of the channel where the message is located.
-/// * `from` - ID of the message to start from.
-/// * `max` - The maximum number of messages to retreive.
+/// * `user_id` - ID of the user who is sending the message.
+/// * `hub_id` - ID of the hub to send the message in.
+/// * `channel_id` - ID of the channel to send the message in.
+/// * `content` - Content of the message.
+/// * `reply_to` - ID of the message that the new message is a reply to, if any.
+/// * `attachments` - Hashes of the attachments to include in the message, they must have been uploaded to the channel with [`upload_attachment`].
 ///
 /// # Errors
 ///
 /// This function may return an error for any of the following reasons:
 ///
+/// * The content is too big or there are too many attachments.
 /// * The user is not in the hub.
-/// * The channel could not be found in the hub.
+/// * The user cannot send messages in the channel for any of the reasons outlined by [`Hub::check_can_send`].
+/// * The message being replied to could not be found in the channel or has been deleted.
+/// * One of the attachments could not be found for any of the reasons outlined by [`attachment::claim`].
 /// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
-pub async fn get_messages_after(
+pub async fn init_message(
     user_id: &str,
     hub_id: ID,
     channel_id: ID,
-    from: ID,
-    max: usize,
-) -> Result<Vec<SignedMessage>> {
-    let hub = Hub::load(hub_id).await?;
-    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
-    Ok(channel.get_messages_after(from, max).await)
+    content: String,
+    reply_to: Option<ID>,
+    attachments: &[String],
+) -> Result<Message> {
+    if content.len() > crate::MESSAGE_MAX_SIZE || attachments.len() > crate::MAX_MESSAGE_ATTACHMENTS
+    {
+        return Err(Error::TooBig);
+    }
+    let hub = hub_cache::get(hub_id).await?;
+    hub.check_can_send(user_id, channel_id)?;
+    let mut message = Message::new(user_id.to_string(), content, hub_id, channel_id);
+    for hash in attachments {
+        message
+            .attachments
+            .push(attachment::claim(&storage::data_dir()?, hub_id, channel_id, hash).await?);
+    }
+    if let Some(reply_to) = reply_to {
+        let channel = hub.get_channel(user_id, channel_id)?;
+        let parent = channel
+            .get_message(reply_to)
+            .await
+            .filter(|parent| parent.tombstone().is_none())
+            .ok_or(Error::MessageNotFound)?;
+        Ok(message.in_reply_to(&Message::try_from(&parent)?))
+    } else {
+        Ok(message)
+    }
 }
 
-/// Gets a set of messages between two times (both in milliseconds since Unix Epoch).
-/// If successful they are returned in an array. The array is orderd oldest message to newest
-/// unless the `invert` argument is `true` in which case the order is newest to oldest message.
-/// If there are no messages in the given time frame, an empty array is returned.
+/// Stores a message created by [`init_message`] or [`init_direct_message`] once it has been signed by both the server and the user, signatures must be verified by the caller.
+/// Returns the sequence number of the message in its channel.
 ///
 /// # Arguments
 ///
-/// * `user_id` - ID of the user who is requesting the message.
-/// * `hub_id` - ID of the hub where the message is located.
-/// * `channel_id` - ID of the channel where the message is located.
-/// * `from` - Earliest time a message can be sent to be included in the results.
-/// * `to` - Latest time a message can be sent to be included in the results.
-/// * `invert` - If true the search is done from newest message to oldest message, if false the search is done from oldest message to newest message.
-/// * `max` - The maximum number of messages to retreive.
+/// * `user_id` - ID of the user who is sending the message.
+/// * `message` - The message that was signed.
+/// * `armoured_message` - The double signed message.
 ///
 /// # Errors
 ///
 /// This function may return an error for any of the following reasons:
 ///
-/// * The user is not in the hub.
-/// * The channel could not be found in the hub.
-/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The message is a revision of another message.
+/// * The message was not created for the given user.
+/// * The user can no longer send messages in the channel for any of the reasons outlined by [`Hub::check_can_send`].
+/// * The user is no longer in the conversation the message was created for.
+/// * The message could not be stored for any of the reasons outlined by [`Channel::write_message`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
-pub async fn get_messages(
+pub async fn send_message(
     user_id: &str,
-    hub_id: ID,
-    channel_id: ID,
-    from: DateTime<Utc>,
-    to: DateTime<Utc>,
-    invert: bool,
-    max: usize,
-) -> Result<Vec<SignedMessage>> {
-    let hub = Hub::load(hub_id).await?;
-    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
-    Ok(channel.get_messages_between(from, to, invert, max).await)
+    message: &Message,
+    armoured_message: String,
+) -> Result<u64> {
+    if message.revision_of.is_some() {
+        return Err(Error::InvalidMessage);
+    }
+    if message.sender != user_id {
+        return Err(Error::NotMessageSender);
+    }
+    // Direct messages use the conversation's ID as both the hub and the channel ID.
+    if message.hub_id == message.channel_id {
+        get_conversation(user_id, message.hub_id).await?;
+    } else {
+        hub_cache::get(message.hub_id)
+            .await?
+            .check_can_send(user_id, message.channel_id)?;
+    }
+    let sequence = Channel::write_message(
+        message.hub_id,
+        message.channel_id,
+        SignedMessage::new(message.id, message.created, armoured_message),
+    )
+    .await?;
+    Channel::write_reply(message).await?;
+    Ok(sequence)
 }
 
-/// Sets a hub wide permission for a hub member.
+/// Stores a file so that it can be attached to messages sent in a channel, see [`attachment::store`].
 ///
 /// # Arguments
 ///
-/// * `user_id` - ID of the user who is making the change.
-/// * `hub_id` - The hub in which the change is being made.
-/// * `member_id` - The hub member whose permissions are being changed.
-/// * `permission` - The permission whose setting is being changed.
-/// * `value` - The new setting for the permission.
+/// * `user_id` - ID of the user who is uploading the file.
+/// * `hub_id` - ID of the hub the file is for.
+/// * `channel_id` - ID of the channel the file is for.
+/// * `name` - Name of the file.
+/// * `bytes` - Content of the file.
+/// * `quota` - Maximum total size in bytes of the attachments uploaded to the hub.
 ///
 /// # Errors
 ///
-/// This function may return an error for any of the following reasons.
+/// This function may return an error for any of the following reasons:
 ///
-/// * The user making the change is not in the hub.
-/// * The user whose permission is being changed is not in the hub.
-/// * The user making the change does not have permission to do so.
-/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The user is not in the hub.
+/// * The user cannot send messages in the channel for any of the reasons outlined by [`Hub::check_can_send`].
+/// * The file could not be stored for any of the reasons outlined by [`attachment::store`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
-pub async fn set_member_hub_permission(
+pub async fn upload_attachment(
     user_id: &str,
     hub_id: ID,
-    member_id: &str,
-    permission: HubPermission,
-    value: PermissionSetting,
-) -> Result {
-    let mut hub = Hub::load(hub_id).await?;
-    {
-        let member = hub.get_member(user_id)?;
-        check_permission!(member, HubPermission::Administrate, hub);
+    channel_id: ID,
+    name: String,
+    bytes: &[u8],
+    quota: u64,
+) -> Result<Attachment> {
+    let hub = hub_cache::get(hub_id).await?;
+    hub.check_can_send(user_id, channel_id)?;
+    attachment::store(
+        &storage::data_dir()?,
+        hub_id,
+        channel_id,
+        name,
+        bytes,
+        quota,
+    )
+    .await
+}
+
+/// Gets an attachment uploaded to a channel along with its content.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is downloading the attachment.
+/// * `hub_id` - ID of the hub the attachment was uploaded to.
+/// * `channel_id` - ID of the channel the attachment was uploaded to.
+/// * `hash` - Hash of the attachment's content.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The attachment could not be read for any of the reasons outlined by [`attachment::read`].
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn download_attachment(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    hash: &str,
+) -> Result<(Attachment, Vec<u8>)> {
+    let hub = hub_cache::get(hub_id).await?;
+    Hub::get_channel(&hub, user_id, channel_id)?;
+    attachment::read(&storage::data_dir()?, hub_id, channel_id, hash).await
+}
+
+/// Creates a revision of a message with new content, the revision still has to be signed by the server and then the user before it can be stored with [`edit_message`].
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is editing the message.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `message_id` - ID of the message to edit.
+/// * `content` - New content of the message.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The new content is too big.
+/// * The user is not in the hub.
+/// * The user cannot send messages in the channel for any of the reasons outlined by [`Hub::check_can_send`].
+/// * The message could not be found or has been deleted.
+/// * The user did not send the message.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn init_message_edit(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    message_id: ID,
+    content: String,
+) -> Result<Message> {
+    if content.len() > crate::MESSAGE_MAX_SIZE {
+        return Err(Error::TooBig);
+    }
+    let hub = hub_cache::get(hub_id).await?;
+    hub.check_can_send(user_id, channel_id)?;
+    let channel = hub.get_channel(user_id, channel_id)?;
+    let original = Message::try_from(
+        channel
+            .get_message(message_id)
+            .await
+            .filter(|message| message.tombstone().is_none())
+            .ok_or(Error::MessageNotFound)?,
+    )?;
+    if original.sender != user_id {
+        return Err(Error::NotMessageSender);
+    }
+    // Messages of imported hubs were signed with the ID the hub was exported with, the revision belongs to the channel it is stored in.
+    Ok(Message {
+        hub_id,
+        channel_id,
+        ..original.new_revision(content)
+    })
+}
+
+/// Stores a revision created by [`init_message_edit`] once it has been signed by both the server and the user, signatures must be verified by the caller.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is editing the message.
+/// * `revision` - The revision of the message that was signed.
+/// * `armoured_revision` - The double signed revision.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The revision was not created for the given user.
+/// * The user can no longer send messages in the channel for any of the reasons outlined by [`Hub::check_can_send`].
+/// * The revision could not be stored for any of the reasons outlined by [`Channel::add_revision`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn edit_message(user_id: &str, revision: &Message, armoured_revision: String) -> Result {
+    if revision.sender != user_id {
+        return Err(Error::NotMessageSender);
+    }
+    let hub = hub_cache::get(revision.hub_id).await?;
+    hub.check_can_send(user_id, revision.channel_id)?;
+    hub.get_channel(user_id, revision.channel_id)?
+        .add_revision(SignedMessage::new(
+            revision.id,
+            revision.created,
+            armoured_revision,
+        ))
+        .await
+}
+
+/// Deletes a message, leaving a tombstone signed by the server in its place. Users can always delete their own messages,
+/// deleting messages sent by other users requires the [`ChannelPermission::ManageMessages`] permission.
+/// If successful the signed tombstone is returned.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is deleting the message.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `message_id` - ID of the message to delete.
+/// * `secret_key` - The server's secret key, used to sign the tombstone.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The message could not be found or was already deleted.
+/// * The user did not send the message and does not have permission to manage messages in the channel.
+/// * The tombstone could not be signed.
+/// * The message could not be replaced for any of the reasons outlined by [`Channel::redact_message`].
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn delete_message(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    message_id: ID,
+    secret_key: &SignedSecretKey,
+) -> Result<SignedMessage> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = hub.get_channel(user_id, channel_id)?;
+    let message = Message::try_from(
+        channel
+            .get_message(message_id)
+            .await
+            .ok_or(Error::MessageNotFound)?,
+    )
+    .map_err(|_| Error::MessageNotFound)?;
+    if message.sender != user_id {
+        let member = hub.get_member(user_id)?;
+        check_permission!(member, channel_id, ChannelPermission::ManageMessages, hub);
+    }
+    let tombstone = SignedMessage::new(
+        message.id,
+        message.created,
+        Tombstone {
+            hub_id,
+            channel_id,
+            ..Tombstone::new(&message, user_id.to_string())
+        }
+        .sign(secret_key, String::new)?
+        .compress(CompressionAlgorithm::ZIP)?
+        .to_armored_string(None)?,
+    );
+    channel.redact_message(tombstone.clone()).await?;
+    if channel.pinned.contains(&message_id) {
+        unpin_deleted(hub_id, channel_id, &[message_id]).await?;
     }
-    let member = hub.get_member_mut(member_id)?;
-    member.set_permission(permission, value);
-    hub.save().await
+    Ok(tombstone)
+}
+
+/// Adds a reaction to a message, returns false if the user had already reacted to the message with the same emoji.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is reacting.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `message_id` - ID of the message to react to.
+/// * `emoji` - The emoji to react with.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The reaction could not be added for any of the reasons outlined by [`Channel::add_reaction`].
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn add_reaction(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    message_id: ID,
+    emoji: &str,
+) -> Result<bool> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    channel.add_reaction(message_id, user_id, emoji).await
+}
+
+/// Removes a reaction from a message, returns false if the user had not reacted to the message with the emoji.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user whose reaction is being removed.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `message_id` - ID of the message to remove the reaction from.
+/// * `emoji` - The emoji to remove.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The reaction could not be removed for any of the reasons outlined by [`Channel::remove_reaction`].
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn remove_reaction(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    message_id: ID,
+    emoji: &str,
+) -> Result<bool> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    channel.remove_reaction(message_id, user_id, emoji).await
+}
+
+/// Gets the revisions of a message, ordered oldest to newest, the original message is not included.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn get_message_revisions(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    message_id: ID,
+) -> Result<Vec<SignedMessage>> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    Ok(channel.get_revisions(message_id).await)
+}
+
+/// Gets the message that started a thread followed by the replies in it, ordered oldest to newest.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The thread could not be read for any of the reasons outlined by [`Channel::get_thread`].
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn get_thread(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    root_id: ID,
+) -> Result<Vec<SignedMessage>> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    channel.get_thread(root_id).await
+}
+
+/// Lists the threads in a channel along with their reply counts, see [`Channel::get_threads`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn list_threads(user_id: &str, hub_id: ID, channel_id: ID) -> Result<Vec<ThreadSummary>> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    Ok(channel.get_threads().await)
+}
+
+/// Marks the messages in a channel up to and including the given message as read by a user, see [`Channel::mark_read`].
+/// Returns the user's read marker in the channel afterwards.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who read the message.
+/// * `hub_id` - ID of the hub where the channel is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `message_id` - ID of the last message the user read.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The marker could not be moved for any of the reasons outlined by [`Channel::mark_read`].
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn mark_read(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    message_id: ID,
+) -> Result<ReadMarker> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    channel.mark_read(user_id, message_id).await
+}
+
+/// Lists a page of messages from a position in a channel's history, see [`Channel::list_messages`].
+/// The page is ordered oldest message to newest and holds at most [`crate::MAX_MESSAGE_PAGE_SIZE`] messages.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the messages.
+/// * `hub_id` - ID of the hub where the channel is located.
+/// * `channel_id` - ID of the channel to list messages from.
+/// * `position` - Where in the channel's history to list messages from.
+/// * `limit` - The maximum number of messages to retreive.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The position is around a message that could not be found.
+/// * The messages could not be listed for any of the reasons outlined by [`Channel::list_messages`].
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn list_messages(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    position: MessagePosition,
+    limit: usize,
+) -> Result<MessagePage> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    channel
+        .list_messages(position, limit.min(crate::MAX_MESSAGE_PAGE_SIZE))
+        .await
+}
+
+/// Gets messages sent after a given message.
+/// If successful they are returned in an array. The array is orderd oldest message to newest
+/// If there are no messages after the given message or the given message is not found, an empty array is returned.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the message.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `from` - ID of the message to start from.
+/// * `max` - The maximum number of messages to retreive.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The channel could not be found in the hub.
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn get_messages_after(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    from: ID,
+    max: usize,
+) -> Result<Vec<SignedMessage>> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    Ok(channel.get_messages_after(from, max).await)
+}
+
+/// Gets a set of messages between two times (both in milliseconds since Unix Epoch).
+/// If successful they are returned in an array. The array is orderd oldest message to newest
+/// unless the `invert` argument is `true` in which case the order is newest to oldest message.
+/// If there are no messages in the given time frame, an empty array is returned.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the message.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `from` - Earliest time a message can be sent to be included in the results.
+/// * `to` - Latest time a message can be sent to be included in the results.
+/// * `invert` - If true the search is done from newest message to oldest message, if false the search is done from oldest message to newest message.
+/// * `max` - The maximum number of messages to retreive.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The channel could not be found in the hub.
+/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn get_messages(
+    user_id: &str,
+    hub_id: ID,
+    channel_id: ID,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    invert: bool,
+    max: usize,
+) -> Result<Vec<SignedMessage>> {
+    let hub = hub_cache::get(hub_id).await?;
+    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    Ok(channel.get_messages_between(from, to, invert, max).await)
+}
+
+/// Sets a hub wide permission for a hub member.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is making the change.
+/// * `hub_id` - The hub in which the change is being made.
+/// * `member_id` - The hub member whose permissions are being changed.
+/// * `permission` - The permission whose setting is being changed.
+/// * `value` - The new setting for the permission.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons.
+///
+/// * The user making the change is not in the hub.
+/// * The user whose permission is being changed is not in the hub.
+/// * The user making the change does not have permission to do so.
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn set_member_hub_permission(
+    user_id: &str,
+    hub_id: ID,
+    member_id: &str,
+    permission: HubPermission,
+    value: PermissionSetting,
+) -> Result {
+    hub_cache::modify(hub_id, |hub| {
+        {
+            let member = hub.get_member(user_id)?;
+            check_permission!(member, HubPermission::Administrate, hub);
+        }
+        let member = hub.get_member_mut(member_id)?;
+        member.set_permission(permission, value);
+        Ok(())
+    })
+    .await
 }
 
 /// Sets a channel specific permission for a hub member.
@@ -691,12 +1472,402 @@ pub async fn set_member_channel_permission(
     permission: ChannelPermission,
     value: PermissionSetting,
 ) -> Result {
-    let mut hub = Hub::load(hub_id).await?;
-    {
-        let member = hub.get_member(user_id)?;
-        check_permission!(member, HubPermission::Administrate, hub);
+    hub_cache::modify(hub_id, |hub| {
+        {
+            let member = hub.get_member(user_id)?;
+            check_permission!(member, HubPermission::Administrate, hub);
+        }
+        let member = hub.get_member_mut(member_id)?;
+        member.set_channel_permission(channel_id, permission, value);
+        Ok(())
+    })
+    .await
+}
+
+/// Sets a channel permission for a hub member in every channel of a category in which the member has no setting of their own for that permission.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is making the change.
+/// * `hub_id` - The hub in which the change is being made.
+/// * `member_id` - The hub member whose permissions are being changed.
+/// * `category_id` - The category that the change should apply to.
+/// * `permission` - The permission whose setting is being changed.
+/// * `value` - The new setting for the permission.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons.
+///
+/// * The user making the change is not in the hub.
+/// * The user whose permission is being changed is not in the hub.
+/// * The user making the change does not have permission to do so.
+/// * The category does not exist.
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn set_member_category_permission(
+    user_id: &str,
+    hub_id: ID,
+    member_id: &str,
+    category_id: ID,
+    permission: ChannelPermission,
+    value: PermissionSetting,
+) -> Result {
+    hub_cache::modify(hub_id, |hub| {
+        {
+            let member = hub.get_member(user_id)?;
+            check_permission!(member, HubPermission::Administrate, hub);
+        }
+        if !hub.categories.contains_key(&category_id) {
+            return Err(Error::CategoryNotFound);
+        }
+        // Category settings are stored with the channel settings, under the category's ID.
+        let member = hub.get_member_mut(member_id)?;
+        member.set_channel_permission(category_id, permission, value);
+        Ok(())
+    })
+    .await
+}
+
+/// Sets a channel permission for a permission group in every channel of a category in which the group has no setting of its own for that permission.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is making the change.
+/// * `hub_id` - The hub in which the change is being made.
+/// * `group_id` - The permission group whose permissions are being changed.
+/// * `category_id` - The category that the change should apply to.
+/// * `permission` - The permission whose setting is being changed.
+/// * `value` - The new setting for the permission.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons.
+///
+/// * The user making the change is not in the hub.
+/// * The permission group does not exist.
+/// * The user making the change does not have permission to do so.
+/// * The category does not exist.
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn set_group_category_permission(
+    user_id: &str,
+    hub_id: ID,
+    group_id: ID,
+    category_id: ID,
+    permission: ChannelPermission,
+    value: PermissionSetting,
+) -> Result {
+    hub_cache::modify(hub_id, |hub| {
+        {
+            let member = hub.get_member(user_id)?;
+            check_permission!(member, HubPermission::Administrate, hub);
+        }
+        if !hub.categories.contains_key(&category_id) {
+            return Err(Error::CategoryNotFound);
+        }
+        let group = hub.groups.get_mut(&group_id).ok_or(Error::GroupNotFound)?;
+        group.set_channel_permission(category_id, permission, value);
+        Ok(())
+    })
+    .await
+}
+
+/// Starts a direct conversation between a user and the given users, returning the existing conversation if there already is one between exactly the same users.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is starting the conversation.
+/// * `participants` - Fingerprints of the other users in the conversation.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The participants are not valid for any of the reasons outlined by [`Conversation::new`].
+/// * The conversation could not be loaded or saved.
+/// * The conversation's message folder could not be created.
+pub async fn start_conversation(user_id: &str, participants: &[String]) -> Result<Conversation> {
+    let mut participants = participants.to_vec();
+    participants.push(user_id.to_string());
+    let conversation = Conversation::new(&participants)?;
+    let store = storage::hub_store();
+    match store.load_conversation(conversation.id).await {
+        Ok(existing) => Ok(existing),
+        Err(Error::ConversationNotFound) => {
+            store.save_conversation(&conversation).await?;
+            conversation.message_channel().create_dir().await?;
+            Ok(conversation)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Gets a direct conversation that a user is in.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the conversation.
+/// * `conversation_id` - ID of the conversation to get.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The conversation does not exist.
+/// * The user is not in the conversation.
+pub async fn get_conversation(user_id: &str, conversation_id: ID) -> Result<Conversation> {
+    let conversation = storage::hub_store()
+        .load_conversation(conversation_id)
+        .await?;
+    conversation.check_participant(user_id)?;
+    Ok(conversation)
+}
+
+/// Lists the direct conversations that a user is in, oldest first.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user whose conversations should be listed.
+///
+/// # Errors
+///
+/// This function may return an error if the conversations could not be read from storage.
+pub async fn list_conversations(user_id: &str) -> Result<Vec<Conversation>> {
+    let mut conversations = storage::hub_store().list_conversations(user_id).await?;
+    conversations.sort_by_key(|conversation| conversation.created);
+    Ok(conversations)
+}
+
+/// Creates a new message in a direct conversation, the message still has to be signed by the server and then the user before it can be sent, exactly like the messages created by [`init_message`].
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is sending the message.
+/// * `conversation_id` - ID of the conversation to send the message in.
+/// * `content` - Content of the message.
+/// * `reply_to` - ID of the message that the new message is a reply to, if any.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The content is too big.
+/// * The conversation could not be gotten for any of the reasons outlined by [`get_conversation`].
+/// * The message being replied to could not be found in the conversation or has been deleted.
+pub async fn init_direct_message(
+    user_id: &str,
+    conversation_id: ID,
+    content: String,
+    reply_to: Option<ID>,
+) -> Result<Message> {
+    if content.len() > crate::MESSAGE_MAX_SIZE {
+        return Err(Error::TooBig);
+    }
+    let conversation = get_conversation(user_id, conversation_id).await?;
+    let message = Message::new(
+        user_id.to_string(),
+        content,
+        conversation.id,
+        conversation.id,
+    );
+    if let Some(reply_to) = reply_to {
+        let parent = conversation
+            .message_channel()
+            .get_message(reply_to)
+            .await
+            .filter(|parent| parent.tombstone().is_none())
+            .ok_or(Error::MessageNotFound)?;
+        Ok(message.in_reply_to(&Message::try_from(&parent)?))
+    } else {
+        Ok(message)
+    }
+}
+
+/// Lists a page of messages from a position in a direct conversation's history, see [`Channel::list_messages`].
+/// The page is ordered oldest message to newest and holds at most [`crate::MAX_MESSAGE_PAGE_SIZE`] messages.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the messages.
+/// * `conversation_id` - ID of the conversation to list messages from.
+/// * `position` - Where in the conversation's history to list messages from.
+/// * `limit` - The maximum number of messages to retreive.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The conversation could not be gotten for any of the reasons outlined by [`get_conversation`].
+/// * The messages could not be listed for any of the reasons outlined by [`Channel::list_messages`].
+pub async fn list_direct_messages(
+    user_id: &str,
+    conversation_id: ID,
+    position: MessagePosition,
+    limit: usize,
+) -> Result<MessagePage> {
+    get_conversation(user_id, conversation_id)
+        .await?
+        .message_channel()
+        .list_messages(position, limit.min(crate::MAX_MESSAGE_PAGE_SIZE))
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        channel::Message,
+        error::Error,
+        hub_cache,
+        permission::ChannelPermission,
+        test_util::{double_signed, SECRET_KEY},
+        ID,
+    };
+
+    /// Creates a hub owned by `owner` with `members` in it, returns the IDs of the hub and of its default channel.
+    async fn test_hub(owner: &str, members: &[&str]) -> (ID, ID) {
+        let hub_id = super::create_hub(owner, "test_hub")
+            .await
+            .expect("Failed to create a hub.");
+        for member in members {
+            super::join_hub(member.to_string(), hub_id)
+                .await
+                .expect("Failed to join the hub.");
+        }
+        let hub = hub_cache::get(hub_id)
+            .await
+            .expect("Failed to get the hub.");
+        let channel_id = *hub.channels.keys().next().expect("Hub has no channels.");
+        (hub_id, channel_id)
+    }
+
+    /// Sends a message the way a client would, signatures are left out since they are checked before the API is called.
+    async fn send(user_id: &str, hub_id: ID, channel_id: ID, content: &str) -> Message {
+        let message =
+            super::init_message(user_id, hub_id, channel_id, content.to_string(), None, &[])
+                .await
+                .expect("Failed to create a message.");
+        super::send_message(user_id, &message, double_signed(&message))
+            .await
+            .expect("Failed to send a message.");
+        message
+    }
+
+    #[tokio::test]
+    async fn edit_permissions() {
+        let (hub_id, channel_id) = test_hub("owner", &["member"]).await;
+        let message = send("owner", hub_id, channel_id, "hello").await;
+        assert!(matches!(
+            super::init_message_edit("member", hub_id, channel_id, message.id, "hi".to_string())
+                .await,
+            Err(Error::NotMessageSender)
+        ));
+        // A revision that claims to be sent by someone else than the sender of the original is rejected when it is stored.
+        let forged = Message {
+            sender: "member".to_string(),
+            ..message.new_revision("hi".to_string())
+        };
+        assert!(matches!(
+            super::edit_message("member", &forged, double_signed(&forged)).await,
+            Err(Error::NotMessageSender)
+        ));
+        let revision =
+            super::init_message_edit("owner", hub_id, channel_id, message.id, "hi".to_string())
+                .await
+                .expect("Failed to create a revision.");
+        assert!(matches!(
+            super::edit_message("member", &revision, double_signed(&revision)).await,
+            Err(Error::NotMessageSender)
+        ));
+        super::edit_message("owner", &revision, double_signed(&revision))
+            .await
+            .expect("Failed to edit the message.");
+        super::delete_message("owner", hub_id, channel_id, message.id, &SECRET_KEY)
+            .await
+            .expect("Failed to delete the message.");
+        assert!(matches!(
+            super::init_message_edit("owner", hub_id, channel_id, message.id, "hey".to_string())
+                .await,
+            Err(Error::MessageNotFound)
+        ));
+        assert!(matches!(
+            super::edit_message("owner", &revision, double_signed(&revision)).await,
+            Err(Error::MessageNotFound)
+        ));
+        hub_cache::delete(hub_id)
+            .await
+            .expect("Failed to delete the hub.");
+    }
+
+    #[tokio::test]
+    async fn delete_permissions() {
+        let (hub_id, channel_id) = test_hub("owner", &["member", "other"]).await;
+        let message = send("member", hub_id, channel_id, "hello").await;
+        assert!(matches!(
+            super::delete_message("other", hub_id, channel_id, message.id, &SECRET_KEY).await,
+            Err(Error::MissingChannelPermission(
+                ChannelPermission::ManageMessages
+            ))
+        ));
+        super::pin_message("owner", hub_id, channel_id, message.id)
+            .await
+            .expect("Failed to pin the message.");
+        let tombstone = super::delete_message("owner", hub_id, channel_id, message.id, &SECRET_KEY)
+            .await
+            .expect("Failed to delete the message.");
+        assert!(tombstone.tombstone().is_some());
+        let hub = hub_cache::get(hub_id)
+            .await
+            .expect("Failed to get the hub.");
+        assert!(hub.channels[&channel_id].pinned.is_empty());
+        assert!(matches!(
+            super::delete_message("member", hub_id, channel_id, message.id, &SECRET_KEY).await,
+            Err(Error::MessageNotFound)
+        ));
+        let own = send("member", hub_id, channel_id, "again").await;
+        super::delete_message("member", hub_id, channel_id, own.id, &SECRET_KEY)
+            .await
+            .expect("Failed to delete an own message.");
+        hub_cache::delete(hub_id)
+            .await
+            .expect("Failed to delete the hub.");
+    }
+
+    #[tokio::test]
+    async fn reactions() {
+        let (hub_id, channel_id) = test_hub("owner", &["member"]).await;
+        let message = send("owner", hub_id, channel_id, "hello").await;
+        let react = |user_id: &'static str, emoji: &'static str| {
+            super::add_reaction(user_id, hub_id, channel_id, message.id, emoji)
+        };
+        assert!(react("member", "👍").await.expect("Failed to react."));
+        assert!(!react("member", "👍").await.expect("Failed to react."));
+        assert!(react("owner", "👍").await.expect("Failed to react."));
+        assert!(matches!(react("member", "").await, Err(Error::InvalidText)));
+        let reactions = hub_cache::get(hub_id).await.unwrap().channels[&channel_id]
+            .get_reactions(message.id)
+            .await;
+        assert_eq!(reactions.len(), 1);
+        assert_eq!(reactions[0].users, vec!["member", "owner"]);
+        assert!(
+            super::remove_reaction("member", hub_id, channel_id, message.id, "👍")
+                .await
+                .expect("Failed to remove a reaction.")
+        );
+        assert!(
+            !super::remove_reaction("member", hub_id, channel_id, message.id, "👍")
+                .await
+                .expect("Failed to remove a reaction.")
+        );
+        super::delete_message("owner", hub_id, channel_id, message.id, &SECRET_KEY)
+            .await
+            .expect("Failed to delete the message.");
+        assert!(matches!(
+            react("member", "👍").await,
+            Err(Error::MessageNotFound)
+        ));
+        hub_cache::delete(hub_id)
+            .await
+            .expect("Failed to delete the hub.");
     }
-    let member = hub.get_member_mut(member_id)?;
-    member.set_channel_permission(channel_id, permission, value);
-    hub.save().await
 }
@@ -0,0 +1,531 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Cursor, Read},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+use pgp::{types::KeyTrait, Deserializable, SignedPublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::{Reaction, SignedMessage},
+    error::Error,
+    hub::Hub,
+    hub_cache,
+    signing::user_public_key_folder,
+    storage::{self, fs::day_of},
+    Result, ID,
+};
+
+/// Version of the archive layout written by [`export_hub`], archives with a newer version cannot be imported.
+pub const ARCHIVE_FORMAT_VERSION: u32 = 2;
+/// First archive version whose messages carry their sequence numbers, messages in older archives are numbered as they are imported.
+const SEQUENCE_FORMAT_VERSION: u32 = 2;
+
+/// Path of the manifest inside of an archive.
+const MANIFEST_PATH: &str = "manifest.json";
+/// Path of the hub record inside of an archive.
+const HUB_PATH: &str = "hub.bin";
+
+/// Description of the contents of a hub archive, stored as JSON so that it can be read without this crate.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchiveManifest {
+    /// Version of the archive layout, see [`ARCHIVE_FORMAT_VERSION`].
+    pub format_version: u32,
+    /// Version of the server that created the archive.
+    pub server_version: String,
+    /// ID of the hub when it was exported.
+    pub hub_id: ID,
+    /// Name of the hub when it was exported.
+    pub hub_name: String,
+    /// When the archive was created.
+    pub exported: DateTime<Utc>,
+    /// IDs of the channels in the archive.
+    pub channels: Vec<ID>,
+    /// Number of messages in the archive, revisions are not counted.
+    pub message_count: usize,
+    /// Fingerprints of the user public keys in the archive.
+    pub public_keys: Vec<String>,
+}
+
+/// Adds a file to an archive that is being built.
+fn append(builder: &mut tar::Builder<Vec<u8>>, path: &str, bytes: &[u8]) -> Result {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, path, bytes)?;
+    Ok(())
+}
+
+/// Serializes messages the same way as the messages in message files, one bincode record after the other.
+fn serialize_messages<'a, I: IntoIterator<Item = &'a SignedMessage>>(
+    messages: I,
+) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for message in messages {
+        bytes.extend(bincode::serialize(message)?);
+    }
+    Ok(bytes)
+}
+
+/// Reads messages written by [`serialize_messages`] into an archive with the given format version.
+fn deserialize_messages(bytes: &[u8], format_version: u32) -> Result<Vec<SignedMessage>> {
+    let schema_version = if format_version < SEQUENCE_FORMAT_VERSION {
+        storage::schema::MESSAGE_SEQUENCE_VERSION - 1
+    } else {
+        storage::schema::MESSAGE_SEQUENCE_VERSION
+    };
+    let mut cursor = Cursor::new(bytes);
+    let mut messages = Vec::new();
+    while (cursor.position() as usize) < bytes.len() {
+        messages.push(storage::schema::read_message(schema_version, &mut cursor)?);
+    }
+    Ok(messages)
+}
+
+/// Bundles a hub into a tar archive: a manifest, the hub record, every channel's messages as one file per day along with their revisions, reactions and threads, and the public keys of the hub's members that are stored on this server.
+/// Attachments are not included.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The hub could not be loaded.
+/// * Any of the hub's messages could not be read.
+/// * A public key file exists but could not be read.
+pub async fn export_hub(data_dir: &str, hub_id: ID) -> Result<Vec<u8>> {
+    let hub = hub_cache::get(hub_id).await?;
+    let messages = storage::message_store();
+    let mut builder = tar::Builder::new(Vec::new());
+    append(&mut builder, HUB_PATH, &storage::schema::encode_hub(&hub)?)?;
+    let mut message_count = 0;
+    for channel_id in hub.channels.keys() {
+        let folder = format!("channels/{:x}", channel_id.as_u128());
+        let mut channel_messages = messages
+            .get_last_messages(hub_id, *channel_id, usize::MAX)
+            .await?;
+        channel_messages.reverse();
+        message_count += channel_messages.len();
+        let mut days: BTreeMap<i64, Vec<&SignedMessage>> = BTreeMap::new();
+        for message in channel_messages.iter() {
+            days.entry(day_of(&message.created))
+                .or_default()
+                .push(message);
+        }
+        for (day, day_messages) in days {
+            append(
+                &mut builder,
+                &format!("{}/{}", folder, day),
+                &serialize_messages(day_messages)?,
+            )?;
+        }
+        for message in channel_messages.iter() {
+            let revisions = messages
+                .get_revisions(hub_id, *channel_id, message.id)
+                .await?;
+            if !revisions.is_empty() {
+                append(
+                    &mut builder,
+                    &format!("{}/revisions/{:x}", folder, message.id.as_u128()),
+                    &serialize_messages(revisions.iter())?,
+                )?;
+            }
+            let reactions = messages
+                .get_reactions(hub_id, *channel_id, message.id)
+                .await?;
+            if !reactions.is_empty() {
+                append(
+                    &mut builder,
+                    &format!("{}/reactions/{:x}", folder, message.id.as_u128()),
+                    &bincode::serialize(&reactions)?,
+                )?;
+            }
+        }
+        let mut replies = Vec::new();
+        for thread in messages.get_threads(hub_id, *channel_id).await? {
+            for reply in messages
+                .get_replies(hub_id, *channel_id, thread.root)
+                .await?
+            {
+                replies.push((thread.root, reply));
+            }
+        }
+        if !replies.is_empty() {
+            append(
+                &mut builder,
+                &format!("{}/threads", folder),
+                &bincode::serialize(&replies)?,
+            )?;
+        }
+    }
+    let mut public_keys = Vec::new();
+    for user_id in hub.members.keys() {
+        let path = format!("{}{}.asc", user_public_key_folder(data_dir), user_id);
+        match tokio::fs::read(&path).await {
+            Ok(key) => {
+                append(&mut builder, &format!("keys/{}.asc", user_id), &key)?;
+                public_keys.push(user_id.clone());
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let manifest = ArchiveManifest {
+        format_version: ARCHIVE_FORMAT_VERSION,
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        hub_id,
+        hub_name: hub.name.clone(),
+        exported: Utc::now(),
+        channels: hub.channels.keys().cloned().collect(),
+        message_count,
+        public_keys,
+    };
+    append(
+        &mut builder,
+        MANIFEST_PATH,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    Ok(builder.into_inner()?)
+}
+
+/// Reads every file in a tar archive.
+fn read_entries(archive: &[u8]) -> Result<HashMap<String, Vec<u8>>> {
+    let mut files = HashMap::new();
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        files.insert(path, bytes);
+    }
+    Ok(files)
+}
+
+/// Reads the manifest of an archive created by [`export_hub`] without importing it.
+///
+/// # Errors
+///
+/// This function will return [`Error::InvalidArchive`] if the archive has no valid manifest.
+pub fn read_manifest(archive: &[u8]) -> Result<ArchiveManifest> {
+    read_entries(archive)?
+        .get(MANIFEST_PATH)
+        .and_then(|bytes| serde_json::from_slice(bytes).ok())
+        .ok_or(Error::InvalidArchive)
+}
+
+/// Checks that a public key from an archive is a valid PGP public key that is named after its own fingerprint, so that it can neither be written outside of the public key folder nor replace the key of another user.
+fn check_public_key(fingerprint: &str, key: &[u8]) -> Result {
+    if fingerprint.len() != 40 || !fingerprint.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::InvalidArchive);
+    }
+    let text = std::str::from_utf8(key).map_err(|_| Error::InvalidArchive)?;
+    let key = SignedPublicKey::from_string(text)
+        .map_err(|_| Error::InvalidArchive)?
+        .0;
+    if hex::encode_upper(key.fingerprint()) == fingerprint {
+        Ok(())
+    } else {
+        Err(Error::InvalidArchive)
+    }
+}
+
+/// Restores a hub from an archive created by [`export_hub`], under the given ID or the one it was exported with, and rebuilds the search indexes of its channels.
+/// Public keys are only written if there is no key stored with the same fingerprint. Signed messages keep the hub ID they were sent with, they are edited and deleted in the channel they were imported to.
+/// If the import fails after the hub's messages started being stored, everything that was stored for the hub is deleted again, public keys that were written are kept since they were checked against their fingerprints.
+/// Returns the ID of the imported hub.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The archive is not valid or was made with a newer [`ARCHIVE_FORMAT_VERSION`].
+/// * A public key in the archive is not valid or is not named after its fingerprint.
+/// * A hub with the ID to import to already exists.
+/// * The hub or its messages could not be stored.
+/// * The search indexes could not be rebuilt.
+pub async fn import_hub(data_dir: &str, archive: &[u8], new_id: Option<ID>) -> Result<ID> {
+    let files = read_entries(archive)?;
+    let manifest: ArchiveManifest = files
+        .get(MANIFEST_PATH)
+        .and_then(|bytes| serde_json::from_slice(bytes).ok())
+        .ok_or(Error::InvalidArchive)?;
+    if manifest.format_version == 0 || manifest.format_version > ARCHIVE_FORMAT_VERSION {
+        return Err(Error::InvalidArchive);
+    }
+    let mut hub = storage::schema::decode_hub(files.get(HUB_PATH).ok_or(Error::InvalidArchive)?)?;
+    let keys = manifest
+        .public_keys
+        .iter()
+        .map(|fingerprint| {
+            let key = files
+                .get(&format!("keys/{}.asc", fingerprint))
+                .ok_or(Error::InvalidArchive)?;
+            check_public_key(fingerprint, key)?;
+            Ok((fingerprint, key))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let hub_id = new_id.unwrap_or(hub.id);
+    match hub_cache::get(hub_id).await {
+        Ok(_) => return Err(Error::HubAlreadyExists),
+        Err(Error::HubNotFound) => {}
+        Err(err) => return Err(err),
+    }
+    hub.id = hub_id;
+    for channel in hub.channels.values_mut() {
+        channel.hub_id = hub_id;
+    }
+    for member in hub.members.values_mut() {
+        member.hub = hub_id;
+    }
+    if let Err(err) = restore_hub(data_dir, &files, manifest.format_version, &keys, &mut hub).await
+    {
+        let _ = storage::message_store().delete_hub_messages(hub_id).await;
+        let _ = hub_cache::delete(hub_id).await;
+        return Err(err);
+    }
+    Ok(hub_id)
+}
+
+/// Stores the messages, public keys and record of a hub read from an archive and rebuilds the search indexes of its channels, see [`import_hub`].
+async fn restore_hub(
+    data_dir: &str,
+    files: &HashMap<String, Vec<u8>>,
+    format_version: u32,
+    keys: &[(&String, &Vec<u8>)],
+    hub: &mut Hub,
+) -> Result {
+    let hub_id = hub.id;
+    let messages = storage::message_store();
+    for channel_id in hub.channels.keys() {
+        messages.create_channel(hub_id, *channel_id).await?;
+        let folder = format!("channels/{:x}/", channel_id.as_u128());
+        let mut days: Vec<(i64, &Vec<u8>)> = files
+            .iter()
+            .filter_map(|(path, bytes)| {
+                path.strip_prefix(&folder)
+                    .and_then(|name| i64::from_str(name).ok())
+                    .map(|day| (day, bytes))
+            })
+            .collect();
+        days.sort_by_key(|(day, _)| *day);
+        for (_, bytes) in days {
+            for message in deserialize_messages(bytes, format_version)? {
+                messages
+                    .import_message(hub_id, *channel_id, message)
+                    .await?;
+            }
+        }
+        for (path, bytes) in files.iter() {
+            let message_id = |prefix: &str| {
+                path.strip_prefix(&folder)
+                    .and_then(|name| name.strip_prefix(prefix))
+                    .and_then(|name| u128::from_str_radix(name, 16).ok())
+                    .map(ID::from_u128)
+            };
+            if let Some(message_id) = message_id("revisions/") {
+                for revision in deserialize_messages(bytes, format_version)? {
+                    messages
+                        .add_revision(hub_id, *channel_id, message_id, revision)
+                        .await?;
+                }
+            } else if let Some(message_id) = message_id("reactions/") {
+                let reactions: Vec<Reaction> = bincode::deserialize(bytes)?;
+                for reaction in reactions {
+                    for user_id in reaction.users.iter() {
+                        messages
+                            .add_reaction(hub_id, *channel_id, message_id, user_id, &reaction.emoji)
+                            .await?;
+                    }
+                }
+            }
+        }
+        if let Some(bytes) = files.get(&format!("{}threads", folder)) {
+            let replies: Vec<(ID, ID)> = bincode::deserialize(bytes)?;
+            for (root_id, message_id) in replies {
+                messages
+                    .add_reply(hub_id, *channel_id, root_id, message_id)
+                    .await?;
+            }
+        }
+    }
+    for (fingerprint, key) in keys {
+        let folder = user_public_key_folder(data_dir);
+        let path = format!("{}{}.asc", folder, fingerprint);
+        if !std::path::Path::new(&path).is_file() {
+            tokio::fs::create_dir_all(&folder).await?;
+            tokio::fs::write(&path, key).await?;
+        }
+    }
+    hub_cache::save(hub).await?;
+    for channel_id in hub.channels.keys() {
+        crate::server::rebuild_index(data_dir, hub_id, *channel_id).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{append, check_public_key, export_hub, import_hub, read_entries, read_manifest};
+    use crate::{
+        channel::SignedMessage, error::Error, hub::Hub, new_id, storage, test_util::TempDir, ID,
+    };
+    use chrono::{Duration, Utc};
+
+    #[tokio::test]
+    async fn export_import() {
+        let temp_dir = TempDir::new("archive");
+        let data_dir = temp_dir.path();
+        let hub_id = new_id();
+        let owner = ID::nil().to_string();
+        let mut hub = Hub::new("archived".to_string(), hub_id, owner.clone());
+        let channel_id = hub
+            .new_channel(&owner, "archived".to_string())
+            .expect("Failed to add a channel to the hub.");
+        storage::hub_store()
+            .save_hub(&hub)
+            .await
+            .expect("Failed to save the hub.");
+        let store = storage::message_store();
+        for channel_id in hub.channels.keys() {
+            store
+                .create_channel(hub_id, *channel_id)
+                .await
+                .expect("Failed to create a channel.");
+        }
+        let ids: Vec<ID> = (0..3).map(|_| new_id()).collect();
+        let sent = Utc::now() - Duration::days(3);
+        for id in ids.iter() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, sent, String::new()),
+                )
+                .await
+                .expect("Failed to add a message.");
+        }
+        store
+            .add_reaction(hub_id, channel_id, ids[0], &owner, "👍")
+            .await
+            .expect("Failed to add a reaction.");
+        store
+            .add_reply(hub_id, channel_id, ids[0], ids[2])
+            .await
+            .expect("Failed to add a reply.");
+        let archive = export_hub(&data_dir, hub_id)
+            .await
+            .expect("Failed to export the hub.");
+        let manifest = read_manifest(&archive).expect("Failed to read the manifest.");
+        assert_eq!(manifest.hub_id, hub_id);
+        assert_eq!(manifest.message_count, 3);
+        assert!(matches!(
+            import_hub(&data_dir, &archive, None).await,
+            Err(Error::HubAlreadyExists)
+        ));
+        let imported_id = import_hub(&data_dir, &archive, Some(new_id()))
+            .await
+            .expect("Failed to import the hub.");
+        let imported = storage::hub_store()
+            .load_hub(imported_id)
+            .await
+            .expect("Failed to load the imported hub.");
+        assert_eq!(imported.name, "archived");
+        assert!(imported.channels.values().all(|c| c.hub_id == imported_id));
+        let messages = store
+            .get_last_messages(imported_id, channel_id, usize::MAX)
+            .await
+            .expect("Failed to read the imported messages.");
+        assert_eq!(
+            messages.iter().rev().map(|m| m.id).collect::<Vec<ID>>(),
+            ids
+        );
+        assert_eq!(
+            messages
+                .iter()
+                .rev()
+                .map(|m| m.sequence)
+                .collect::<Vec<u64>>(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            store
+                .get_messages_between(
+                    imported_id,
+                    channel_id,
+                    sent - Duration::days(1),
+                    sent + Duration::days(1),
+                    false,
+                    usize::MAX,
+                )
+                .await
+                .expect("Failed to read the imported messages.")
+                .len(),
+            3
+        );
+        assert_eq!(
+            store
+                .get_reactions(imported_id, channel_id, ids[0])
+                .await
+                .expect("Failed to read the imported reactions.")
+                .len(),
+            1
+        );
+        assert_eq!(
+            store
+                .get_replies(imported_id, channel_id, ids[0])
+                .await
+                .expect("Failed to read the imported replies."),
+            vec![ids[2]]
+        );
+        // An import that fails part of the way through leaves nothing of the hub behind.
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, bytes) in read_entries(&archive).expect("Failed to read the archive.") {
+            let bytes = if path.contains("/reactions/") {
+                vec![0xff]
+            } else {
+                bytes
+            };
+            append(&mut builder, &path, &bytes).expect("Failed to build the archive.");
+        }
+        let broken = builder.into_inner().expect("Failed to build the archive.");
+        let broken_id = new_id();
+        assert!(import_hub(&data_dir, &broken, Some(broken_id))
+            .await
+            .is_err());
+        assert!(!storage::hub_store().hub_exists(broken_id).await);
+        assert!(store
+            .get_last_messages(broken_id, channel_id, usize::MAX)
+            .await
+            .unwrap_or_default()
+            .is_empty());
+        for id in [hub_id, imported_id].iter() {
+            let _ = crate::hub_cache::delete(*id).await;
+            let _ = store.delete_hub_messages(*id).await;
+        }
+    }
+
+    #[test]
+    fn public_key_names() {
+        for name in [
+            "../../hubs/info/key".to_string(),
+            "AA".repeat(21),
+            "ZZ".repeat(20),
+        ]
+        .iter()
+        {
+            assert!(matches!(
+                check_public_key(name, b"key"),
+                Err(Error::InvalidArchive)
+            ));
+        }
+        assert!(matches!(
+            check_public_key(&"AA".repeat(20), b"not a key"),
+            Err(Error::InvalidArchive)
+        ));
+    }
+}
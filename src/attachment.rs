@@ -0,0 +1,493 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    path::PathBuf,
+    time::Duration,
+};
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{fs, sync::Mutex};
+
+use crate::{
+    channel::Message,
+    error::Error,
+    hub_cache,
+    storage::{self, fs::write_atomic},
+    Result, ID,
+};
+
+/// Gets the folder in which attachment contents are stored inside of the given data folder, one file per blob named after the SHA-256 hash of its content.
+pub fn blob_folder(data_dir: &str) -> String {
+    format!("{}/blobs/", data_dir)
+}
+
+/// Gets the folder in which the attachments uploaded to each hub are recorded inside of the given data folder, one file per hub.
+pub fn attachment_index_folder(data_dir: &str) -> String {
+    format!("{}/attachments/", data_dir)
+}
+
+/// How long an uploaded attachment is kept without any message referencing it, gives clients time to send the message after uploading.
+pub const ATTACHMENT_GRACE_PERIOD: chrono::Duration = chrono::Duration::hours(24);
+
+/// How often unreferenced attachments are deleted.
+pub const GARBAGE_COLLECTION_INTERVAL: Duration = Duration::from_secs(6 * 3600);
+
+lazy_static! {
+    /// Held while the attachment records of any hub are being changed.
+    static ref INDEX_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// File attached to a message, the content is stored separately and identified by its SHA-256 hash.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, SimpleObject)]
+pub struct Attachment {
+    /// Lowercase hex encoded SHA-256 hash of the content.
+    pub hash: String,
+    /// Name of the file the first time it was uploaded to the hub.
+    pub name: String,
+    /// Size of the content in bytes.
+    pub size: u64,
+}
+
+/// Record of an attachment uploaded to a hub.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct AttachmentRecord {
+    attachment: Attachment,
+    /// Channels the attachment was uploaded to, it can only be referenced and downloaded in these channels.
+    channels: Vec<ID>,
+    /// Last time the attachment was uploaded.
+    uploaded: DateTime<Utc>,
+}
+
+/// Attachments uploaded to a hub, by hash.
+type HubAttachments = HashMap<String, AttachmentRecord>;
+
+/// Hashes content the way attachment blobs are named.
+pub fn hash_content(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Gets the path of the file that holds the content with the given hash, blobs are spread over folders named after the first byte of their hash.
+fn blob_path(data_dir: &str, hash: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}/{}", blob_folder(data_dir), &hash[..2], hash))
+}
+
+/// Gets the path of the file in which the attachments uploaded to a hub are recorded.
+fn index_path(data_dir: &str, hub_id: ID) -> PathBuf {
+    PathBuf::from(format!(
+        "{}{:x}",
+        attachment_index_folder(data_dir),
+        hub_id.as_u128()
+    ))
+}
+
+/// Reads the attachment records of a hub, a hub without a records file has no attachments.
+async fn read_index(data_dir: &str, hub_id: ID) -> Result<HubAttachments> {
+    match fs::read(index_path(data_dir, hub_id)).await {
+        Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Replaces the attachment records of a hub, the file is deleted if there are no records left.
+async fn write_index(data_dir: &str, hub_id: ID, attachments: &HubAttachments) -> Result {
+    let path = index_path(data_dir, hub_id);
+    if attachments.is_empty() {
+        let _ = fs::remove_file(&path).await;
+        return Ok(());
+    }
+    fs::create_dir_all(attachment_index_folder(data_dir)).await?;
+    write_atomic(&path.to_string_lossy(), &bincode::serialize(attachments)?).await
+}
+
+/// Gets the total size in bytes of the attachments uploaded to a hub, content uploaded more than once is only counted once.
+///
+/// # Errors
+///
+/// This function will return an error if the hub's attachment records could not be read.
+pub async fn hub_usage(data_dir: &str, hub_id: ID) -> Result<u64> {
+    Ok(read_index(data_dir, hub_id)
+        .await?
+        .values()
+        .map(|record| record.attachment.size)
+        .sum())
+}
+
+/// Stores the content of an attachment uploaded to a channel, content that is already stored is not stored again.
+/// Permissions are not checked, this should be done by the caller.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The name is empty or too big.
+/// * The content is bigger than [`crate::MAX_ATTACHMENT_SIZE`].
+/// * Storing the content would make the hub use more than `quota` bytes.
+/// * The content or the hub's attachment records could not be written.
+pub async fn store(
+    data_dir: &str,
+    hub_id: ID,
+    channel_id: ID,
+    name: String,
+    bytes: &[u8],
+    quota: u64,
+) -> Result<Attachment> {
+    if name.is_empty() || name.len() > crate::MAX_NAME_SIZE {
+        return Err(Error::InvalidName);
+    }
+    if bytes.len() > crate::MAX_ATTACHMENT_SIZE {
+        return Err(Error::TooBig);
+    }
+    let hash = hash_content(bytes);
+    let _guard = INDEX_LOCK.lock().await;
+    let mut attachments = read_index(data_dir, hub_id).await?;
+    if let Some(record) = attachments.get_mut(&hash) {
+        if !record.channels.contains(&channel_id) {
+            record.channels.push(channel_id);
+        }
+        record.uploaded = Utc::now();
+        let attachment = record.attachment.clone();
+        write_index(data_dir, hub_id, &attachments).await?;
+        return Ok(attachment);
+    }
+    let used: u64 = attachments.values().map(|r| r.attachment.size).sum();
+    if used + bytes.len() as u64 > quota {
+        return Err(Error::QuotaExceeded);
+    }
+    let path = blob_path(data_dir, &hash);
+    if !path.is_file() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        write_atomic(&path.to_string_lossy(), bytes).await?;
+    }
+    let attachment = Attachment {
+        hash: hash.clone(),
+        name,
+        size: bytes.len() as u64,
+    };
+    attachments.insert(
+        hash,
+        AttachmentRecord {
+            attachment: attachment.clone(),
+            channels: vec![channel_id],
+            uploaded: Utc::now(),
+        },
+    );
+    write_index(data_dir, hub_id, &attachments).await?;
+    Ok(attachment)
+}
+
+/// Gets an attachment that was uploaded to a channel.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * No attachment with the given hash was uploaded to the channel.
+/// * The hub's attachment records could not be read.
+pub async fn get(data_dir: &str, hub_id: ID, channel_id: ID, hash: &str) -> Result<Attachment> {
+    read_index(data_dir, hub_id)
+        .await?
+        .remove(hash)
+        .filter(|record| record.channels.contains(&channel_id))
+        .map(|record| record.attachment)
+        .ok_or(Error::AttachmentNotFound)
+}
+
+/// Gets an attachment that was uploaded to a channel so that it can be included in a message, the attachment counts as uploaded again so that [`collect_garbage`] keeps it until the message is sent.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * No attachment with the given hash was uploaded to the channel.
+/// * The hub's attachment records could not be read or updated.
+pub async fn claim(data_dir: &str, hub_id: ID, channel_id: ID, hash: &str) -> Result<Attachment> {
+    let _guard = INDEX_LOCK.lock().await;
+    let mut attachments = read_index(data_dir, hub_id).await?;
+    let record = attachments
+        .get_mut(hash)
+        .filter(|record| record.channels.contains(&channel_id))
+        .ok_or(Error::AttachmentNotFound)?;
+    record.uploaded = Utc::now();
+    let attachment = record.attachment.clone();
+    write_index(data_dir, hub_id, &attachments).await?;
+    Ok(attachment)
+}
+
+/// Reads the content of an attachment that was uploaded to a channel.
+/// Permissions are not checked, this should be done by the caller.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The attachment could not be found for any of the reasons outlined by [`get`].
+/// * The content could not be read.
+pub async fn read(
+    data_dir: &str,
+    hub_id: ID,
+    channel_id: ID,
+    hash: &str,
+) -> Result<(Attachment, Vec<u8>)> {
+    let attachment = get(data_dir, hub_id, channel_id, hash).await?;
+    let bytes = fs::read(blob_path(data_dir, &attachment.hash))
+        .await
+        .map_err(|err| {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                Error::AttachmentNotFound
+            } else {
+                err.into()
+            }
+        })?;
+    Ok((attachment, bytes))
+}
+
+/// Gets the hashes of the attachments referenced by the messages sent in a hub.
+/// Revisions keep the attachments of the message they revise so only the original messages are checked.
+async fn referenced_hashes(hub_id: ID) -> Result<HashSet<String>> {
+    let hub = hub_cache::get(hub_id).await?;
+    let mut hashes = HashSet::new();
+    for channel_id in hub.channels.keys() {
+        for message in storage::message_store()
+            .get_last_messages(hub_id, *channel_id, usize::MAX)
+            .await?
+        {
+            if let Ok(message) = Message::try_from(&message) {
+                hashes.extend(message.attachments.into_iter().map(|a| a.hash));
+            }
+        }
+    }
+    Ok(hashes)
+}
+
+/// Gets the IDs of the hubs that have attachment records.
+async fn indexed_hubs(data_dir: &str) -> Result<Vec<ID>> {
+    let mut hubs = Vec::new();
+    if let Ok(mut dir) = fs::read_dir(attachment_index_folder(data_dir)).await {
+        while let Some(entry) = dir.next_entry().await? {
+            if let Some(id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| u128::from_str_radix(name, 16).ok())
+            {
+                hubs.push(ID::from_u128(id));
+            }
+        }
+    }
+    Ok(hubs)
+}
+
+/// Deletes the attachments that are no longer referenced by any message and were uploaded before `now` minus [`ATTACHMENT_GRACE_PERIOD`], along with the records of hubs that no longer exist.
+/// Content is deleted once no hub has a record of it, returns the number of blobs that were deleted.
+/// Messages are scanned before the attachment records are locked so that uploads are not held up while every message is read, attachments included in messages created during the scan are kept since [`claim`] counts them as uploaded again.
+///
+/// # Errors
+///
+/// This function will return an error if any hub's messages or attachment records could not be read, or if the records could not be updated.
+pub async fn collect_garbage(data_dir: &str, now: DateTime<Utc>) -> Result<usize> {
+    let cutoff = now - ATTACHMENT_GRACE_PERIOD;
+    let mut referenced = HashMap::new();
+    for hub_id in indexed_hubs(data_dir).await? {
+        if storage::hub_store().hub_exists(hub_id).await {
+            referenced.insert(hub_id, Some(referenced_hashes(hub_id).await?));
+        } else {
+            referenced.insert(hub_id, None);
+        }
+    }
+    let _guard = INDEX_LOCK.lock().await;
+    let mut live = HashSet::new();
+    // Hubs whose records were created after the scan are kept as they are.
+    for hub_id in indexed_hubs(data_dir).await? {
+        let attachments = match referenced.get(&hub_id) {
+            Some(None) => {
+                fs::remove_file(index_path(data_dir, hub_id)).await?;
+                continue;
+            }
+            Some(Some(referenced)) => {
+                let mut attachments = read_index(data_dir, hub_id).await?;
+                let count = attachments.len();
+                attachments
+                    .retain(|hash, record| referenced.contains(hash) || record.uploaded >= cutoff);
+                if attachments.len() != count {
+                    write_index(data_dir, hub_id, &attachments).await?;
+                }
+                attachments
+            }
+            None => read_index(data_dir, hub_id).await?,
+        };
+        live.extend(attachments.into_keys());
+    }
+    let mut removed = 0;
+    if let Ok(mut folders) = fs::read_dir(blob_folder(data_dir)).await {
+        while let Some(folder) = folders.next_entry().await? {
+            let mut blobs = match fs::read_dir(folder.path()).await {
+                Ok(blobs) => blobs,
+                Err(_) => continue,
+            };
+            while let Some(blob) = blobs.next_entry().await? {
+                let name = blob.file_name();
+                if !name.to_str().is_some_and(|hash| live.contains(hash)) {
+                    fs::remove_file(blob.path()).await?;
+                    removed += 1;
+                }
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Runs [`collect_garbage`] every [`GARBAGE_COLLECTION_INTERVAL`], meant to be spawned as a background task.
+pub async fn collect_garbage_periodically(data_dir: String) {
+    let mut interval = tokio::time::interval(GARBAGE_COLLECTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        let _ = collect_garbage(&data_dir, Utc::now()).await;
+    }
+}
+
+/// Deletes the attachment records of a hub, the content is deleted by the next [`collect_garbage`] if no other hub uses it.
+///
+/// # Errors
+///
+/// This function will return an error if the records exist but could not be deleted.
+pub async fn delete_hub_attachments(data_dir: &str, hub_id: ID) -> Result {
+    let _guard = INDEX_LOCK.lock().await;
+    match fs::remove_file(index_path(data_dir, hub_id)).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        claim, collect_garbage, get, hash_content, hub_usage, read, read_index, store, write_index,
+    };
+    use crate::{error::Error, hub::Hub, hub_cache, new_id, test_util::TempDir};
+    use chrono::{Duration, Utc};
+
+    #[tokio::test]
+    async fn store_and_collect() {
+        let temp_dir = TempDir::new("attachment");
+        let data_dir = temp_dir.path();
+        let data_dir = data_dir.as_str();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        let content = new_id().as_bytes().to_vec();
+        let attachment = store(
+            data_dir,
+            hub_id,
+            channel_id,
+            "a.txt".to_string(),
+            &content,
+            64,
+        )
+        .await
+        .expect("Failed to store an attachment.");
+        assert_eq!(attachment.hash, hash_content(&content));
+        let again = store(
+            data_dir,
+            hub_id,
+            channel_id,
+            "b.txt".to_string(),
+            &content,
+            16,
+        )
+        .await
+        .expect("Failed to store an attachment again.");
+        assert_eq!(again.name, "a.txt");
+        assert_eq!(
+            hub_usage(data_dir, hub_id)
+                .await
+                .expect("Failed to get usage."),
+            16
+        );
+        assert!(matches!(
+            store(
+                data_dir,
+                hub_id,
+                channel_id,
+                "c.txt".to_string(),
+                &[0; 49],
+                64
+            )
+            .await,
+            Err(Error::QuotaExceeded)
+        ));
+        let (_, bytes) = read(data_dir, hub_id, channel_id, &attachment.hash)
+            .await
+            .expect("Failed to read the attachment.");
+        assert_eq!(bytes, content);
+        assert!(matches!(
+            get(data_dir, hub_id, new_id(), &attachment.hash).await,
+            Err(Error::AttachmentNotFound)
+        ));
+        collect_garbage(data_dir, Utc::now() + Duration::days(2))
+            .await
+            .expect("Failed to collect garbage.");
+        assert!(matches!(
+            read(data_dir, hub_id, channel_id, &attachment.hash).await,
+            Err(Error::AttachmentNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn claimed_during_collection() {
+        let temp_dir = TempDir::new("attachment");
+        let data_dir = temp_dir.path();
+        let data_dir = data_dir.as_str();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        let mut hub = Hub::new("test_hub".to_string(), hub_id, hub_id.to_string());
+        hub_cache::save(&mut hub)
+            .await
+            .expect("Failed to save the hub.");
+        let mut hashes = Vec::new();
+        for name in ["claimed.txt", "unclaimed.txt"].iter() {
+            let content = new_id().as_bytes().to_vec();
+            let attachment = store(data_dir, hub_id, channel_id, name.to_string(), &content, 64)
+                .await
+                .expect("Failed to store an attachment.");
+            hashes.push(attachment.hash);
+        }
+        let mut attachments = read_index(data_dir, hub_id)
+            .await
+            .expect("Failed to read the attachment records.");
+        for record in attachments.values_mut() {
+            record.uploaded = Utc::now() - Duration::days(2);
+        }
+        write_index(data_dir, hub_id, &attachments)
+            .await
+            .expect("Failed to write the attachment records.");
+        // A message that includes the attachment is created after its channel was scanned but before the records are locked.
+        claim(data_dir, hub_id, channel_id, &hashes[0])
+            .await
+            .expect("Failed to claim the attachment.");
+        assert_eq!(
+            collect_garbage(data_dir, Utc::now())
+                .await
+                .expect("Failed to collect garbage."),
+            1
+        );
+        assert!(read(data_dir, hub_id, channel_id, &hashes[0]).await.is_ok());
+        assert!(matches!(
+            read(data_dir, hub_id, channel_id, &hashes[1]).await,
+            Err(Error::AttachmentNotFound)
+        ));
+        assert!(matches!(
+            claim(data_dir, hub_id, new_id(), &hashes[0]).await,
+            Err(Error::AttachmentNotFound)
+        ));
+        hub_cache::delete(hub_id)
+            .await
+            .expect("Failed to delete the hub.");
+    }
+}
@@ -1,15 +1,45 @@
-use std::{convert::TryFrom, str::FromStr};
+use std::{collections::HashMap, convert::TryFrom, sync::Mutex};
 
 use chrono::{DateTime, Utc};
-use tokio::fs;
+use lazy_static::lazy_static;
 
 use serde::{Deserialize, Serialize};
 
-use fs::OpenOptions;
+use crate::{attachment::Attachment, error::Error, new_id, storage, Result, ID};
 
-use crate::{error::Error, hub::HUB_DATA_FOLDER, new_id, Result, ID};
+use async_graphql::{Enum, SimpleObject};
 
-use async_graphql::SimpleObject;
+/// Unread counts of a user in a channel along with the messages they were counted over, so that only newer messages have to be read the next time.
+#[derive(Clone, Copy, Debug)]
+struct CountedUnread {
+    /// Sequence number of the first unread message when the messages were counted.
+    from: u64,
+    /// Sequence number after the last message that was counted.
+    next: u64,
+    /// Number of messages that were counted, including the ones that were not unread.
+    scanned: usize,
+    counts: UnreadCounts,
+}
+
+/// Unread counts of the users of a channel, see [`Channel::unread_counts`].
+#[derive(Debug, Default)]
+struct ChannelUnread {
+    /// Incremented whenever messages that may have been counted are changed, counts started before that are not kept.
+    generation: u64,
+    users: HashMap<String, CountedUnread>,
+}
+
+lazy_static! {
+    /// Unread counts by hub and channel ID, the lock must never be held across an await.
+    static ref UNREAD_COUNTS: Mutex<HashMap<(ID, ID), ChannelUnread>> = Mutex::new(HashMap::new());
+}
+
+/// Locks the cached unread counts.
+fn unread_counts() -> std::sync::MutexGuard<'static, HashMap<(ID, ID), ChannelUnread>> {
+    UNREAD_COUNTS
+        .lock()
+        .expect("Unread count cache lock was poisoned.")
+}
 
 /// Text channel, used to group a manage sets of messages.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -24,6 +54,198 @@ pub struct Channel {
     pub name: String,
     /// Date the channel was created in milliseconds since Unix Epoch.
     pub created: DateTime<Utc>,
+    /// Limits on how long messages sent in the channel are kept.
+    pub retention: RetentionPolicy,
+    /// IDs of the messages pinned in the channel, oldest pin first.
+    #[serde(default)]
+    pub pinned: Vec<ID>,
+    /// ID of the category the channel is in, if any.
+    pub category: Option<ID>,
+    /// Position of the channel among the other channels in the same category, lowest first.
+    pub position: u32,
+    /// Who can send messages in the channel, on top of the [`crate::permission::ChannelPermission::Write`] permission.
+    pub mode: ChannelMode,
+}
+
+/// Restriction on who can send messages in a channel.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChannelMode {
+    /// Anyone with permission to write in the channel can send messages.
+    #[default]
+    Normal,
+    /// Only the members of the permission group with the given ID, and members who can manage the channel, can send messages.
+    Announcement(ID),
+    /// Nobody can send or edit messages, the channel is kept as a read-only archive.
+    Archived,
+}
+
+/// Kind of a [`ChannelMode`] without the data it holds, used where the mode cannot be represented with data such as in GraphQL.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Enum)]
+pub enum ChannelModeKind {
+    Normal,
+    Announcement,
+    Archived,
+}
+
+impl ChannelMode {
+    /// Gets the kind of the mode.
+    pub fn kind(&self) -> ChannelModeKind {
+        match self {
+            Self::Normal => ChannelModeKind::Normal,
+            Self::Announcement(_) => ChannelModeKind::Announcement,
+            Self::Archived => ChannelModeKind::Archived,
+        }
+    }
+
+    /// Gets the ID of the permission group that can send messages if this is an announcement mode.
+    pub fn announcers(&self) -> Option<ID> {
+        if let Self::Announcement(group) = self {
+            Some(*group)
+        } else {
+            None
+        }
+    }
+
+    /// Creates a mode of the given kind, `group` is the group that can send messages in announcement mode and is ignored by the other modes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`Error::GroupNotFound`] if the kind is [`ChannelModeKind::Announcement`] and no group is given.
+    pub fn from_kind(kind: ChannelModeKind, group: Option<ID>) -> Result<Self> {
+        match kind {
+            ChannelModeKind::Normal => Ok(Self::Normal),
+            ChannelModeKind::Announcement => {
+                group.map(Self::Announcement).ok_or(Error::GroupNotFound)
+            }
+            ChannelModeKind::Archived => Ok(Self::Archived),
+        }
+    }
+}
+
+/// Named group of channels in a hub, channels in a category use the category's permission settings unless they have their own.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, SimpleObject)]
+pub struct ChannelCategory {
+    /// ID of the category.
+    pub id: ID,
+    /// Name of the category.
+    pub name: String,
+    /// Position of the category among the other categories of its hub, lowest first.
+    pub position: u32,
+    /// Time the category was created.
+    pub created: DateTime<Utc>,
+}
+
+impl ChannelCategory {
+    /// Creates a category at the given position.
+    pub fn new(name: String, id: ID, position: u32) -> Self {
+        Self {
+            id,
+            name,
+            position,
+            created: Utc::now(),
+        }
+    }
+}
+
+/// Position in a channel's message history from which to list messages, see [`Channel::list_messages`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessagePosition {
+    /// The most recent messages.
+    Latest,
+    /// Messages sent right before the message with the given ID.
+    Before(ID),
+    /// Messages sent right after the message with the given ID.
+    After(ID),
+    /// The message with the given ID and the messages sent right before and after it.
+    Around(ID),
+    /// Messages whose sequence number is equal to or greater than the given one.
+    FromSequence(u64),
+}
+
+impl MessagePosition {
+    /// Gets the position described by a set of cursors or a sequence number, only one of them can be given.
+    /// If none are given the position is [`MessagePosition::Latest`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * More than one cursor, or a cursor and a sequence number were given.
+    /// * One of the cursors is not valid.
+    pub fn from_cursors(
+        before: Option<&str>,
+        after: Option<&str>,
+        around: Option<&str>,
+        from_sequence: Option<u64>,
+    ) -> Result<Self> {
+        match (before, after, around, from_sequence) {
+            (None, None, None, None) => Ok(Self::Latest),
+            (Some(cursor), None, None, None) => Ok(Self::Before(MessageCursor::decode(cursor)?.0)),
+            (None, Some(cursor), None, None) => Ok(Self::After(MessageCursor::decode(cursor)?.0)),
+            (None, None, Some(cursor), None) => Ok(Self::Around(MessageCursor::decode(cursor)?.0)),
+            (None, None, None, Some(sequence)) => Ok(Self::FromSequence(sequence)),
+            _ => Err(Error::InvalidCursor),
+        }
+    }
+}
+
+/// Opaque position of a message in a channel, clients should only pass cursors back to the server and not rely on their contents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageCursor(pub ID);
+
+impl MessageCursor {
+    /// Encodes the cursor as a string.
+    pub fn encode(&self) -> String {
+        format!("{:032x}", self.0.as_u128())
+    }
+
+    /// Decodes a cursor created by [`MessageCursor::encode`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`Error::InvalidCursor`] if the string is not a valid cursor.
+    pub fn decode(cursor: &str) -> Result<Self> {
+        if cursor.len() != 32 {
+            return Err(Error::InvalidCursor);
+        }
+        u128::from_str_radix(cursor, 16)
+            .map(|id| Self(ID::from_u128(id)))
+            .map_err(|_| Error::InvalidCursor)
+    }
+}
+
+/// A page of messages returned by [`Channel::list_messages`], ordered oldest to newest.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MessagePage {
+    /// The messages in the page.
+    pub messages: Vec<SignedMessage>,
+    /// Whether or not there are older messages than the ones in the page.
+    pub has_before: bool,
+    /// Whether or not there are newer messages than the ones in the page.
+    pub has_after: bool,
+}
+
+/// Limits on how long a channel's messages are kept, messages are kept forever if neither limit is set.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, SimpleObject)]
+pub struct RetentionPolicy {
+    /// Number of days after which messages are deleted.
+    pub max_age_days: Option<u32>,
+    /// Maximum number of messages to keep, the oldest messages are deleted first.
+    pub max_messages: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Checks if the policy keeps every message forever.
+    pub fn keeps_everything(&self) -> bool {
+        self.max_age_days.is_none() && self.max_messages.is_none()
+    }
+
+    /// Gets the time before which messages are no longer kept, if there is a maximum age.
+    pub fn cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.max_age_days
+            .map(|days| now - chrono::Duration::days(days as i64))
+    }
 }
 
 impl Channel {
@@ -35,26 +257,65 @@ impl Channel {
             hub_id,
             description: String::new(),
             created: Utc::now(),
+            retention: RetentionPolicy::default(),
+            pinned: Vec::new(),
+            category: None,
+            position: 0,
+            mode: ChannelMode::Normal,
+        }
+    }
+
+    /// Pins a message, returns false if the message was already pinned.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`Error::ChannelArchived`] if the channel is archived and [`Error::TooManyPins`] if the channel already has [`crate::MAX_PINNED_MESSAGES`] pinned messages.
+    pub fn pin(&mut self, message_id: ID) -> Result<bool> {
+        if self.mode == ChannelMode::Archived {
+            Err(Error::ChannelArchived)
+        } else if self.pinned.contains(&message_id) {
+            Ok(false)
+        } else if self.pinned.len() >= crate::MAX_PINNED_MESSAGES {
+            Err(Error::TooManyPins)
+        } else {
+            self.pinned.push(message_id);
+            Ok(true)
         }
     }
 
-    /// Get the path of the channel's data folder, used for storing message files.
-    pub fn get_folder(&self) -> String {
-        format!(
-            "{}{:x}/{:x}",
-            HUB_DATA_FOLDER,
-            self.hub_id.as_u128(),
-            self.id.as_u128()
-        )
+    /// Unpins a message, returns false if the message was not pinned.
+    pub fn unpin(&mut self, message_id: ID) -> bool {
+        self.unpin_all(&[message_id])
+    }
+
+    /// Unpins every one of the given messages that is pinned, returns false if none of them were pinned.
+    pub fn unpin_all(&mut self, message_ids: &[ID]) -> bool {
+        let pinned = self.pinned.len();
+        self.pinned.retain(|id| !message_ids.contains(id));
+        pinned != self.pinned.len()
+    }
+
+    /// Gets the pinned messages that are still stored, oldest pin first. Messages that have since been deleted are skipped.
+    pub async fn get_pinned_messages(&self) -> Vec<SignedMessage> {
+        let mut messages: Vec<SignedMessage> = self
+            .get_messages(self.pinned.clone())
+            .await
+            .into_iter()
+            .filter(|message| message.tombstone().is_none())
+            .collect();
+        messages.sort_by_key(|m| self.pinned.iter().position(|id| id == &m.id));
+        messages
     }
 
-    /// Creates the channel data folder.
+    /// Creates the channel's message storage.
     pub async fn create_dir(&self) -> Result {
-        tokio::fs::create_dir_all(self.get_folder()).await?;
-        Ok(())
+        storage::message_store()
+            .create_channel(self.hub_id, self.id)
+            .await
     }
 
-    /// Adds a message to the channel, writes it to the file corresponding to the day the message was sent, one file per day of messages, only created if a message is sent that day.
+    /// Adds a message to the channel, with the default storage this means writing it to the file corresponding to the day the message was sent, one file per day of messages, only created if a message is sent that day.
+    /// Returns the sequence number the message was given in the channel.
     ///
     /// # Errors
     ///
@@ -63,17 +324,13 @@ impl Channel {
     ///
     /// * The message file does not exist and could not be created.
     /// * Was unable to write to the message file.
-    pub async fn add_message(&self, message: SignedMessage) -> Result {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(self.get_current_file().await)
-            .await?;
-        bincode::serialize_into(file.into_std().await, &message)?;
-        Ok(())
+    pub async fn add_message(&self, message: SignedMessage) -> Result<u64> {
+        storage::message_store()
+            .add_message(self.hub_id, self.id, message)
+            .await
     }
 
-    pub async fn write_message(hub_id: ID, channel_id: ID, message: SignedMessage) -> Result {
+    pub async fn write_message(hub_id: ID, channel_id: ID, message: SignedMessage) -> Result<u64> {
         Self::new("".to_string(), channel_id, hub_id)
             .add_message(message)
             .await
@@ -81,57 +338,21 @@ impl Channel {
 
     /// Gets the last messages sent, `max` indicates the maximum number of messages to return.
     pub async fn get_last_messages(&self, max: usize) -> Vec<Message> {
-        let mut result: Vec<Message> = Vec::new();
-        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
-            }
-            files.sort_by_key(|f| f.file_name());
-            files.reverse();
-            for file in files.iter() {
-                if let Ok(file) = fs::File::open(file.path()).await {
-                    let mut found = bincode::deserialize_from(file.into_std().await)
-                        .into_iter()
-                        .collect::<Vec<Message>>();
-                    found.sort_by_key(|m| m.created);
-                    found.reverse();
-                    result.append(&mut found);
-                    if result.len() >= max {
-                        result.truncate(max);
-                        return result;
-                    }
-                }
-            }
-        }
-        result
+        storage::message_store()
+            .get_last_messages(self.hub_id, self.id, max)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|m| Message::try_from(m).ok())
+            .collect()
     }
 
     /// Tries to get all the messages listed by their IDs in `ids`. Not guaranteed to return all or any of the wanted messages.
     pub async fn get_messages(&self, ids: Vec<ID>) -> Vec<SignedMessage> {
-        let mut result: Vec<SignedMessage> = Vec::new();
-        if let Ok(mut dir) = tokio::fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
-            }
-            for file in files.iter() {
-                if let Ok(file) = tokio::fs::read(file.path()).await {
-                    let iter = bincode::deserialize::<SignedMessage>(&file).into_iter();
-                    let mut found: Vec<SignedMessage> =
-                        iter.filter(|m| ids.contains(&m.id)).collect();
-                    result.append(&mut found);
-                    if ids.len() == result.len() {
-                        return result;
-                    }
-                }
-            }
-        }
-        result
+        storage::message_store()
+            .get_messages(self.hub_id, self.id, &ids)
+            .await
+            .unwrap_or_default()
     }
 
     /// Gets a set of messages between two times given in milliseconds since Unix Epoch.
@@ -149,134 +370,458 @@ impl Channel {
         invert: bool,
         max: usize,
     ) -> Vec<SignedMessage> {
-        let mut result: Vec<SignedMessage> = Vec::new();
-        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
-            }
-            files.sort_by_key(|f| f.file_name());
-            if invert {
-                files.reverse() // Reverse the order of the list of files to search in the correct direction if `invert` is true.
-            }
-            let div_from = from.timestamp() / 86400; // Get the day that `from` corresponds to.
-            let div_to = to.timestamp() / 86400; // Get the day that `to` corresponds to.
-            for file in files.iter().filter(|f| {
-                if let Ok(fname) = f.file_name().into_string() {
-                    if let Ok(n) = i64::from_str(&fname) {
-                        return n >= div_from && n <= div_to; // Check that the file is of a day within the given `to` and `from` times.
-                    }
-                }
-                false
-            }) {
-                if let Ok(file) = fs::File::open(file.path()).await {
-                    let mut filtered = bincode::deserialize_from(file.into_std().await)
-                        .into_iter()
-                        .filter(|message: &SignedMessage| {
-                            message.created >= from && message.created <= to
-                        })
-                        .collect::<Vec<SignedMessage>>();
-                    if invert {
-                        filtered.reverse() // Invert the order of found messages for that file if `invert` is true.
-                    }
-                    filtered.truncate(max - result.len()); // Remove any extra messages if `max` has been reached.
-                    result.append(&mut filtered);
-                    let len = result.len();
-                    if len == max {
-                        return result;
-                    } else if result.len() > max {
-                        result.truncate(max);
-                        return result;
-                    }
-                }
-            }
-        }
-        result
+        storage::message_store()
+            .get_messages_between(self.hub_id, self.id, from, to, invert, max)
+            .await
+            .unwrap_or_default()
     }
 
     /// Gets all messages that were sent after the message with the given ID.
     pub async fn get_messages_after(&self, id: ID, max: usize) -> Vec<SignedMessage> {
-        let mut result: Vec<SignedMessage> = Vec::new();
-        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
-            }
-            for file in files.iter() {
-                if let Ok(file) = fs::File::open(file.path()).await {
-                    let mut iter = bincode::deserialize_from::<std::fs::File, SignedMessage>(
-                        file.into_std().await,
-                    )
-                    .into_iter();
-                    if iter.any(|m| m.id == id) {
-                        result.append(&mut iter.collect());
-                        let len = result.len();
-                        if len == max {
-                            return result;
-                        } else if result.len() > max {
-                            result.truncate(max);
-                            return result;
-                        }
-                    }
-                }
-            }
-        }
-        result
+        storage::message_store()
+            .get_messages_after(self.hub_id, self.id, id, max)
+            .await
+            .unwrap_or_default()
     }
 
-    /// Unlimited asynchronus version of [`get_messages_after`] for internal use.
+    /// Unlimited version of [`Channel::get_messages_after`] for internal use.
     pub async fn get_all_messages_from(&self, id: ID) -> Vec<SignedMessage> {
-        let mut result: Vec<SignedMessage> = Vec::new();
-        if let Ok(mut dir) = tokio::fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
+        self.get_messages_after(id, usize::MAX).await
+    }
+
+    /// Deletes the messages that the channel's retention policy no longer allows keeping, returns the IDs of the deleted messages.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The expired messages could not be deleted from storage.
+    pub async fn prune_messages(&self) -> Result<Vec<ID>> {
+        if self.retention.keeps_everything() {
+            return Ok(Vec::new());
+        }
+        let removed = storage::message_store()
+            .prune_messages(self.hub_id, self.id, self.retention, Utc::now())
+            .await?;
+        if !removed.is_empty() {
+            self.forget_unread_counts();
+        }
+        Ok(removed)
+    }
+
+    /// Lists up to `limit` messages from the given position in the channel's history, ordered oldest to newest.
+    /// Messages are always ordered by the order in which they were stored, so pages never overlap or skip messages that were sent at the same time.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The position is [`MessagePosition::Before`], [`MessagePosition::After`] or [`MessagePosition::Around`] a message that does not exist or was pruned.
+    /// * The messages could not be read from storage.
+    pub async fn list_messages(
+        &self,
+        position: MessagePosition,
+        limit: usize,
+    ) -> Result<MessagePage> {
+        let store = storage::message_store();
+        let mut page = MessagePage {
+            messages: Vec::new(),
+            has_before: false,
+            has_after: false,
+        };
+        match position {
+            MessagePosition::Latest => {
+                let mut messages = store
+                    .get_last_messages(self.hub_id, self.id, limit.saturating_add(1))
+                    .await?;
+                page.has_before = messages.len() > limit;
+                messages.truncate(limit);
+                messages.reverse();
+                page.messages = messages;
+            }
+            MessagePosition::Before(id) => {
+                store
+                    .get_message(self.hub_id, self.id, id)
+                    .await?
+                    .ok_or(Error::MessageNotFound)?;
+                let mut messages = store
+                    .get_messages_before(self.hub_id, self.id, id, limit.saturating_add(1))
+                    .await?;
+                page.has_before = messages.len() > limit;
+                page.has_after = true;
+                if page.has_before {
+                    messages.remove(0);
                 }
+                page.messages = messages;
             }
-
-            for file in files.iter() {
-                if let Ok(file) = tokio::fs::read(file.path()).await {
-                    let mut iter = bincode::deserialize::<SignedMessage>(&file).into_iter();
-                    if iter.any(|m| m.id == id) {
-                        result.append(&mut iter.collect());
-                    }
+            MessagePosition::After(id) => {
+                store
+                    .get_message(self.hub_id, self.id, id)
+                    .await?
+                    .ok_or(Error::MessageNotFound)?;
+                let mut messages = store
+                    .get_messages_after(self.hub_id, self.id, id, limit.saturating_add(1))
+                    .await?;
+                page.has_before = true;
+                page.has_after = messages.len() > limit;
+                messages.truncate(limit);
+                page.messages = messages;
+            }
+            MessagePosition::Around(id) => {
+                let message = store
+                    .get_message(self.hub_id, self.id, id)
+                    .await?
+                    .ok_or(Error::MessageNotFound)?;
+                if limit == 0 {
+                    return Ok(page);
+                }
+                let before_count = (limit - 1) / 2;
+                let after_count = limit - 1 - before_count;
+                let mut messages = store
+                    .get_messages_before(self.hub_id, self.id, id, before_count + 1)
+                    .await?;
+                page.has_before = messages.len() > before_count;
+                if page.has_before {
+                    messages.remove(0);
                 }
+                messages.push(message);
+                let mut after = store
+                    .get_messages_after(self.hub_id, self.id, id, after_count + 1)
+                    .await?;
+                page.has_after = after.len() > after_count;
+                after.truncate(after_count);
+                messages.append(&mut after);
+                page.messages = messages;
+            }
+            MessagePosition::FromSequence(sequence) => {
+                let mut messages = store
+                    .get_messages_from_sequence(
+                        self.hub_id,
+                        self.id,
+                        sequence,
+                        limit.saturating_add(1),
+                    )
+                    .await?;
+                page.has_after = messages.len() > limit;
+                messages.truncate(limit);
+                page.has_before = match messages.first() {
+                    Some(first) => !store
+                        .get_messages_before(self.hub_id, self.id, first.id, 1)
+                        .await?
+                        .is_empty(),
+                    None => !store
+                        .get_last_messages(self.hub_id, self.id, 1)
+                        .await?
+                        .is_empty(),
+                };
+                page.messages = messages;
             }
         }
-        result
+        Ok(page)
     }
 
     /// Get the first message with the given ID.
     pub async fn get_message(&self, id: ID) -> Option<SignedMessage> {
-        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
-            }
-            for file in files.iter() {
-                if let Ok(file) = fs::File::open(file.path()).await {
-                    if let Some(msg) = bincode::deserialize_from(file.into_std().await)
-                        .into_iter()
-                        .find(|m: &SignedMessage| m.id == id)
-                    {
-                        return Some(msg);
+        storage::message_store()
+            .get_message(self.hub_id, self.id, id)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Adds a revision to the message that it revises, the original message is kept as is.
+    /// The revision should already have had its signatures verified, it is given the sequence number of the original message.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The revision is not a revision of a message in this channel.
+    /// * The message being revised could not be found or has been deleted.
+    /// * The revision was not sent by the sender of the original message.
+    /// * The message already has a revision with the same ID.
+    /// * The revision could not be written to storage.
+    pub async fn add_revision(&self, mut revision: SignedMessage) -> Result {
+        let message = Message::try_from(&revision)?;
+        let original_id = match message.revision_of {
+            Some(id) if message.hub_id == self.hub_id && message.channel_id == self.id => id,
+            _ => return Err(Error::InvalidMessage),
+        };
+        let signed_original = self
+            .get_message(original_id)
+            .await
+            .filter(|original| original.tombstone().is_none())
+            .ok_or(Error::MessageNotFound)?;
+        let original = Message::try_from(&signed_original)?;
+        if original.sender != message.sender {
+            return Err(Error::NotMessageSender);
+        }
+        revision.sequence = signed_original.sequence;
+        storage::message_store()
+            .add_revision(self.hub_id, self.id, original_id, revision)
+            .await?;
+        self.forget_unread_counts();
+        Ok(())
+    }
+
+    /// Gets the revisions of a message, ordered oldest to newest. The original message is not included.
+    pub async fn get_revisions(&self, message_id: ID) -> Vec<SignedMessage> {
+        storage::message_store()
+            .get_revisions(self.hub_id, self.id, message_id)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Replaces a message with a tombstone, the message's revisions are deleted along with it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The message could not be found.
+    /// * The message could not be replaced in storage.
+    pub async fn redact_message(&self, tombstone: SignedMessage) -> Result {
+        storage::message_store()
+            .redact_message(self.hub_id, self.id, tombstone)
+            .await?;
+        self.forget_unread_counts();
+        Ok(())
+    }
+
+    /// Adds a user's reaction to a message, returns false if the user had already reacted with the same emoji.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The channel is archived.
+    /// * The reaction is not valid, see [`crate::check_reaction_validity`].
+    /// * The message could not be found or was deleted.
+    /// * The reaction could not be written to storage.
+    pub async fn add_reaction(&self, message_id: ID, user_id: &str, emoji: &str) -> Result<bool> {
+        if self.mode == ChannelMode::Archived {
+            return Err(Error::ChannelArchived);
+        }
+        crate::check_reaction_validity(emoji)?;
+        match self.get_message(message_id).await {
+            Some(message) if message.tombstone().is_none() => {}
+            _ => return Err(Error::MessageNotFound),
+        }
+        storage::message_store()
+            .add_reaction(self.hub_id, self.id, message_id, user_id, emoji)
+            .await
+    }
+
+    /// Removes a user's reaction from a message, returns false if the user had not reacted with the emoji.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reaction could not be removed from storage.
+    pub async fn remove_reaction(
+        &self,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool> {
+        storage::message_store()
+            .remove_reaction(self.hub_id, self.id, message_id, user_id, emoji)
+            .await
+    }
+
+    /// Gets the reactions to a message, ordered by when each emoji was first used.
+    pub async fn get_reactions(&self, message_id: ID) -> Vec<Reaction> {
+        storage::message_store()
+            .get_reactions(self.hub_id, self.id, message_id)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Records that a message is a reply in the thread started by `root_id`.
+    /// Whether the channel accepts messages is not checked, this should be done by the caller before the message is written.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reply could not be written to storage.
+    pub async fn add_reply(&self, root_id: ID, message_id: ID) -> Result {
+        storage::message_store()
+            .add_reply(self.hub_id, self.id, root_id, message_id)
+            .await
+    }
+
+    /// Records a message in the thread it is a reply in, does nothing if the message is not a reply.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the reply could not be written to storage.
+    pub async fn write_reply(message: &Message) -> Result {
+        if let Some(root_id) = message.thread {
+            Self::new("".to_string(), message.channel_id, message.hub_id)
+                .add_reply(root_id, message.id)
+                .await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Gets the message that started a thread followed by the replies in it, ordered oldest to newest.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The message that started the thread could not be found.
+    /// * The replies could not be read from storage.
+    pub async fn get_thread(&self, root_id: ID) -> Result<Vec<SignedMessage>> {
+        let store = storage::message_store();
+        let root = store
+            .get_message(self.hub_id, self.id, root_id)
+            .await?
+            .ok_or(Error::MessageNotFound)?;
+        let replies = store.get_replies(self.hub_id, self.id, root_id).await?;
+        let mut messages = store.get_messages(self.hub_id, self.id, &replies).await?;
+        // Stores may return the replies in any order, they are put back in the order they were sent in.
+        let positions: HashMap<ID, usize> = replies
+            .iter()
+            .enumerate()
+            .map(|(position, id)| (*id, position))
+            .collect();
+        messages.sort_by_key(|m| positions.get(&m.id).copied());
+        messages.insert(0, root);
+        Ok(messages)
+    }
+
+    /// Lists the threads in the channel along with their reply counts, ordered by when each thread got its first reply.
+    pub async fn get_threads(&self) -> Vec<ThreadSummary> {
+        storage::message_store()
+            .get_threads(self.hub_id, self.id)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Moves a user's read marker to a message, returns the user's marker afterwards.
+    /// Markers only move forward, the marker is left where it is if the user has already read a later message.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The message could not be found in the channel.
+    /// * The marker could not be read from or written to storage.
+    pub async fn mark_read(&self, user_id: &str, message_id: ID) -> Result<ReadMarker> {
+        let store = storage::message_store();
+        let message = store
+            .get_message(self.hub_id, self.id, message_id)
+            .await?
+            .ok_or(Error::MessageNotFound)?;
+        let marker = ReadMarker {
+            message_id,
+            sequence: message.sequence,
+            updated: Utc::now(),
+        };
+        if store
+            .set_read_marker(self.hub_id, self.id, user_id, marker)
+            .await?
+        {
+            Ok(marker)
+        } else {
+            Ok(store
+                .get_read_marker(self.hub_id, self.id, user_id)
+                .await?
+                .unwrap_or(marker))
+        }
+    }
+
+    /// Gets the last message that a user has read in the channel, `None` if they have not read any.
+    pub async fn get_read_marker(&self, user_id: &str) -> Option<ReadMarker> {
+        storage::message_store()
+            .get_read_marker(self.hub_id, self.id, user_id)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Counts the messages sent after a user's read marker, along with how many of them mention the user, see [`Message::mentions`].
+    /// The user's own messages and deleted messages are not counted, edited messages are checked for mentions in their latest revision and counting stops at [`crate::MAX_UNREAD_COUNT`] messages.
+    /// Counts are cached until the marker moves or a message in the channel is edited, deleted or pruned, so only messages sent since the last call are read.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the marker or the messages could not be read from storage.
+    pub async fn unread_counts(&self, user_id: &str) -> Result<UnreadCounts> {
+        let store = storage::message_store();
+        let from = store
+            .get_read_marker(self.hub_id, self.id, user_id)
+            .await?
+            .map_or(0, |marker| marker.sequence + 1);
+        let (generation, cached) = {
+            let mut cache = unread_counts();
+            let channel = cache.entry((self.hub_id, self.id)).or_default();
+            (
+                channel.generation,
+                channel
+                    .users
+                    .get(user_id)
+                    .copied()
+                    .filter(|cached| cached.from == from),
+            )
+        };
+        let mut counted = cached.unwrap_or(CountedUnread {
+            from,
+            next: from,
+            scanned: 0,
+            counts: UnreadCounts::default(),
+        });
+        if counted.scanned < crate::MAX_UNREAD_COUNT {
+            for signed in store
+                .get_messages_from_sequence(
+                    self.hub_id,
+                    self.id,
+                    counted.next,
+                    crate::MAX_UNREAD_COUNT - counted.scanned,
+                )
+                .await?
+            {
+                counted.scanned += 1;
+                counted.next = signed.sequence + 1;
+                if let Ok(message) = Message::try_from(&signed) {
+                    if message.sender != user_id {
+                        counted.counts.unread += 1;
+                        if self.get_latest_revision(message).await.mentions(user_id) {
+                            counted.counts.mentions += 1;
+                        }
                     }
                 }
             }
         }
-        None
+        let mut cache = unread_counts();
+        let channel = cache.entry((self.hub_id, self.id)).or_default();
+        if channel.generation == generation {
+            channel.users.insert(user_id.to_string(), counted);
+        }
+        Ok(counted.counts)
     }
 
-    /// Gets the path of the current message file, filename is time in milliseconds from Unix Epoch divided by `86400000` (the number of milliseconds in a day).
-    pub async fn get_current_file(&self) -> String {
-        format!("{}/{}", self.get_folder(), Utc::now().date())
+    /// Drops the cached unread counts of the channel, called whenever messages that may have been counted are changed.
+    fn forget_unread_counts(&self) {
+        let mut cache = unread_counts();
+        let channel = cache.entry((self.hub_id, self.id)).or_default();
+        channel.generation += 1;
+        channel.users.clear();
+    }
+
+    /// Gets the latest version of a message, which is the newest revision if it has been edited.
+    pub async fn get_latest_revision(&self, message: Message) -> Message {
+        self.get_revisions(message.id)
+            .await
+            .last()
+            .and_then(|revision| Message::try_from(revision).ok())
+            .unwrap_or(message)
     }
 }
 
@@ -285,14 +830,150 @@ pub struct SignedMessage {
     pub id: ID,
     pub created: DateTime<Utc>,
     pub armoured_content: String,
+    /// Position of the message in its channel, starting at 0 for the first message ever sent in it and increasing by one with every message, numbers are never reused even once messages are deleted.
+    /// Assigned by the server when the message is stored, revisions and tombstones have the number of the message they replace.
+    pub sequence: u64,
 }
 
 impl SignedMessage {
+    /// Creates a message that has not been given a sequence number yet.
     pub fn new(id: ID, created: DateTime<Utc>, armoured_content: String) -> Self {
         Self {
             id,
             created,
             armoured_content,
+            sequence: 0,
+        }
+    }
+
+    /// Gets the tombstone stored in place of this message if it was deleted.
+    pub fn tombstone(&self) -> Option<Tombstone> {
+        Tombstone::from_signed(&self.armoured_content).ok()
+    }
+}
+
+/// Reaction to a message along with the users that reacted with it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, SimpleObject)]
+#[graphql(complex)]
+pub struct Reaction {
+    /// The emoji that was used to react.
+    pub emoji: String,
+    /// IDs of the users that reacted with the emoji, in the order that they reacted.
+    pub users: Vec<String>,
+}
+
+impl Reaction {
+    /// Adds a user's reaction to a list of reactions, returns false if the user had already reacted with the same emoji.
+    pub fn add_to(reactions: &mut Vec<Reaction>, user_id: &str, emoji: &str) -> bool {
+        if let Some(reaction) = reactions.iter_mut().find(|r| r.emoji == emoji) {
+            if reaction.users.iter().any(|u| u == user_id) {
+                return false;
+            }
+            reaction.users.push(user_id.to_string());
+        } else {
+            reactions.push(Reaction {
+                emoji: emoji.to_string(),
+                users: vec![user_id.to_string()],
+            });
+        }
+        true
+    }
+
+    /// Removes a user's reaction from a list of reactions, returns false if the user had not reacted with the emoji.
+    pub fn remove_from(reactions: &mut Vec<Reaction>, user_id: &str, emoji: &str) -> bool {
+        let index = if let Some(index) = reactions.iter().position(|r| r.emoji == emoji) {
+            index
+        } else {
+            return false;
+        };
+        let users = &mut reactions[index].users;
+        let len = users.len();
+        users.retain(|u| u != user_id);
+        let removed = users.len() != len;
+        if users.is_empty() {
+            reactions.remove(index);
+        }
+        removed
+    }
+}
+
+/// Thread of replies to a message along with how many replies it has.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, SimpleObject)]
+pub struct ThreadSummary {
+    /// ID of the message that started the thread.
+    pub root: ID,
+    /// Number of replies in the thread.
+    pub reply_count: u64,
+    /// ID of the newest reply in the thread.
+    pub last_reply: ID,
+}
+
+/// Last message in a channel that a user has read, kept by the server so that all of the user's devices agree.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, SimpleObject)]
+pub struct ReadMarker {
+    /// ID of the last message read.
+    pub message_id: ID,
+    /// Sequence number of the last message read, messages with higher numbers are unread.
+    pub sequence: u64,
+    /// Time the marker was last moved.
+    pub updated: DateTime<Utc>,
+}
+
+/// Numbers of unread messages in a channel, see [`Channel::unread_counts`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, SimpleObject)]
+pub struct UnreadCounts {
+    /// Number of unread messages.
+    pub unread: usize,
+    /// Number of unread messages that mention the user.
+    pub mentions: usize,
+}
+
+impl ThreadSummary {
+    /// Groups `(root, reply)` pairs into threads, ordered by when each thread got its first reply.
+    pub fn from_replies<I: IntoIterator<Item = (ID, ID)>>(replies: I) -> Vec<Self> {
+        let mut threads: Vec<Self> = Vec::new();
+        for (root, reply) in replies {
+            if let Some(thread) = threads.iter_mut().find(|t| t.root == root) {
+                thread.reply_count += 1;
+                thread.last_reply = reply;
+            } else {
+                threads.push(Self {
+                    root,
+                    reply_count: 1,
+                    last_reply: reply,
+                });
+            }
+        }
+        threads
+    }
+}
+
+/// Record signed by the server that is left in place of a deleted message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+pub struct Tombstone {
+    /// ID of the message that was deleted.
+    pub message_id: ID,
+    /// ID of the hub the message was sent in.
+    pub hub_id: ID,
+    /// ID of the channel the message was sent in.
+    pub channel_id: ID,
+    /// ID of the user that sent the message.
+    pub sender: String,
+    /// ID of the user that deleted the message, either the sender or a moderator.
+    pub deleted_by: String,
+    /// Date the message was deleted.
+    pub deleted: DateTime<Utc>,
+}
+
+impl Tombstone {
+    pub fn new(message: &Message, deleted_by: String) -> Self {
+        Self {
+            message_id: message.id,
+            hub_id: message.hub_id,
+            channel_id: message.channel_id,
+            sender: message.sender.clone(),
+            deleted_by,
+            deleted: Utc::now(),
         }
     }
 }
@@ -328,6 +1009,18 @@ pub struct Message {
     pub created: DateTime<Utc>,
     /// The actual text of the message.
     pub content: String,
+    /// ID of the message that this message is a revision of, `None` if this is not an edit.
+    #[serde(default)]
+    pub revision_of: Option<ID>,
+    /// ID of the message that this message is a reply to.
+    #[serde(default)]
+    pub reply_to: Option<ID>,
+    /// ID of the message that started the thread this message is a reply in, `None` if this message is not a reply.
+    #[serde(default)]
+    pub thread: Option<ID>,
+    /// Files attached to the message.
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
 }
 
 impl Message {
@@ -339,6 +1032,101 @@ impl Message {
             hub_id,
             created: Utc::now(),
             id: new_id(),
+            revision_of: None,
+            reply_to: None,
+            thread: None,
+            attachments: Vec::new(),
         }
     }
+
+    /// Checks if the message mentions a user, which it does if its content contains `@` followed by the user's ID.
+    pub fn mentions(&self, user_id: &str) -> bool {
+        self.content.contains(&format!("@{}", user_id))
+    }
+
+    /// Makes this message a reply to the given message, in the same thread as it.
+    pub fn in_reply_to(mut self, message: &Message) -> Self {
+        self.reply_to = Some(message.id);
+        self.thread = Some(message.thread.unwrap_or(message.id));
+        self
+    }
+
+    /// Creates a revision of this message with new content, the revision has its own ID and creation time.
+    pub fn new_revision(&self, content: String) -> Self {
+        Self {
+            revision_of: Some(self.revision_of.unwrap_or(self.id)),
+            reply_to: self.reply_to,
+            thread: self.thread,
+            attachments: self.attachments.clone(),
+            ..Self::new(self.sender.clone(), content, self.hub_id, self.channel_id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Channel, Message, MessagePosition, SignedMessage, UnreadCounts};
+    use crate::{error::Error, new_id, test_util::double_signed};
+
+    #[tokio::test]
+    async fn unknown_cursors() {
+        let channel = Channel::new("test_channel".to_string(), new_id(), new_id());
+        for position in [
+            MessagePosition::Before(new_id()),
+            MessagePosition::After(new_id()),
+            MessagePosition::Around(new_id()),
+        ]
+        .iter()
+        {
+            assert!(matches!(
+                channel.list_messages(*position, 10).await,
+                Err(Error::MessageNotFound)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn unread_counts() {
+        let channel = Channel::new("test_channel".to_string(), new_id(), new_id());
+        let send = |sender: &str, content: &str| {
+            let message = Message::new(
+                sender.to_string(),
+                content.to_string(),
+                channel.hub_id,
+                channel.id,
+            );
+            let signed = SignedMessage::new(message.id, message.created, double_signed(&message));
+            let channel = channel.clone();
+            async move {
+                channel
+                    .add_message(signed)
+                    .await
+                    .expect("Failed to add a message.");
+                message
+            }
+        };
+        let counts = |unread, mentions| UnreadCounts { unread, mentions };
+        let first = send("other", "hello").await;
+        let mention = send("other", "hi @reader").await;
+        send("reader", "hi @reader").await;
+        assert_eq!(channel.unread_counts("reader").await.unwrap(), counts(2, 1));
+        // Mentions are counted in the latest revision of edited messages.
+        let revision = first.new_revision("look @reader".to_string());
+        channel
+            .add_revision(SignedMessage::new(
+                revision.id,
+                revision.created,
+                double_signed(&revision),
+            ))
+            .await
+            .expect("Failed to add a revision.");
+        assert_eq!(channel.unread_counts("reader").await.unwrap(), counts(2, 2));
+        send("other", "bye").await;
+        assert_eq!(channel.unread_counts("reader").await.unwrap(), counts(3, 2));
+        channel
+            .mark_read("reader", mention.id)
+            .await
+            .expect("Failed to move the read marker.");
+        assert_eq!(channel.unread_counts("reader").await.unwrap(), counts(1, 0));
+    }
 }
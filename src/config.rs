@@ -1,5 +1,12 @@
 use serde::{Deserialize, Serialize};
 
+/// Folder in which the server keeps its data if the configuration does not say otherwise.
+pub const DEFAULT_DATA_DIR: &str = "data";
+
+fn default_data_dir() -> String {
+    DEFAULT_DATA_DIR.to_string()
+}
+
 /// Configuration object for WICRS Server.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
@@ -11,4 +18,41 @@ pub struct Config {
     pub show_version: bool,
     /// ID to give the generated PGP KeyPair.
     pub key_id: Option<String>,
+    /// Folder in which hubs, messages, attachments, search indexes and PGP keys are stored, defaults to [`DEFAULT_DATA_DIR`].
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    /// Where hubs and messages are stored, defaults to [`StorageBackend::Filesystem`].
+    #[serde(default)]
+    pub storage: StorageBackend,
+    /// Number of days after which message files are compressed, message files are never compressed if this is not set.
+    #[serde(default)]
+    pub compress_after_days: Option<u32>,
+    /// Maximum total size in bytes of the attachments uploaded to a hub, defaults to [`crate::HUB_ATTACHMENT_QUOTA`].
+    #[serde(default)]
+    pub hub_attachment_quota: Option<u64>,
+    /// When appends to message files are synced to disk, defaults to [`FsyncPolicy::Always`].
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+}
+
+/// Storage backends that can be selected in the configuration.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub enum StorageBackend {
+    /// Bincode files in the data folder.
+    #[default]
+    Filesystem,
+    /// An SQLite database at the given path.
+    Sqlite { path: String },
+    /// Memory only, everything is lost when the server stops.
+    Memory,
+}
+
+/// Policies for syncing message files to disk after messages are appended to them by the filesystem storage backend.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum FsyncPolicy {
+    /// Sync the channel's journal and message file on every append, a message that was stored survives a power loss.
+    #[default]
+    Always,
+    /// Leave it to the operating system, appends still survive the server crashing but messages stored shortly before a power loss can be lost.
+    Never,
 }
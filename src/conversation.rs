@@ -0,0 +1,139 @@
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{channel::Channel, error::Error, Result, ID};
+
+/// Private conversation between two or more users that exists outside of any hub.
+/// Its messages are stored like the messages of a channel whose hub ID and channel ID are both the ID of the conversation.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, SimpleObject)]
+#[graphql(complex)]
+pub struct Conversation {
+    /// ID of the conversation, derived from the fingerprints of its participants.
+    pub id: ID,
+    /// Fingerprints of the users in the conversation, sorted.
+    pub participants: Vec<String>,
+    /// Time the conversation was started.
+    pub created: DateTime<Utc>,
+}
+
+impl Conversation {
+    /// Creates a conversation between the given users, duplicate fingerprints are ignored.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * One of the fingerprints is not a hex encoded PGP fingerprint.
+    /// * There are fewer than two users or more than [`crate::MAX_CONVERSATION_PARTICIPANTS`].
+    pub fn new(participants: &[String]) -> Result<Self> {
+        let participants = Self::normalize(participants)?;
+        Ok(Self {
+            id: Self::id_for(&participants),
+            participants,
+            created: Utc::now(),
+        })
+    }
+
+    /// Validates a list of fingerprints and puts them in the order used to derive conversation IDs.
+    fn normalize(participants: &[String]) -> Result<Vec<String>> {
+        let mut normalized = Vec::with_capacity(participants.len());
+        for fingerprint in participants {
+            if hex::decode(fingerprint).map_or(true, |bytes| bytes.len() != 20) {
+                return Err(Error::InvalidFingerprint);
+            }
+            normalized.push(fingerprint.to_uppercase());
+        }
+        normalized.sort();
+        normalized.dedup();
+        if normalized.len() < 2 {
+            Err(Error::NotEnoughParticipants)
+        } else if normalized.len() > crate::MAX_CONVERSATION_PARTICIPANTS {
+            Err(Error::TooBig)
+        } else {
+            Ok(normalized)
+        }
+    }
+
+    /// Gets the ID of the conversation between the given users, which must already be normalized.
+    /// The same set of users always gets the same ID, so there is only ever one conversation between them.
+    fn id_for(participants: &[String]) -> ID {
+        let mut hasher = Sha256::new();
+        for fingerprint in participants {
+            hasher.update(fingerprint.as_bytes());
+            hasher.update(b"\n");
+        }
+        ID::from_slice(&hasher.finalize()[..16]).expect("SHA-256 hashes are 32 bytes long.")
+    }
+
+    /// Gets the ID of the conversation between the given users without creating it, see [`Conversation::new`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error for the same reasons as [`Conversation::new`].
+    pub fn id_between(participants: &[String]) -> Result<ID> {
+        Ok(Self::id_for(&Self::normalize(participants)?))
+    }
+
+    /// Checks if a user is in the conversation.
+    pub fn is_participant(&self, user_id: &str) -> bool {
+        self.participants.iter().any(|id| id == user_id)
+    }
+
+    /// Returns an error if a user is not in the conversation.
+    ///
+    /// # Errors
+    ///
+    /// This function will return [`Error::NotInConversation`] if the user is not one of the participants.
+    pub fn check_participant(&self, user_id: &str) -> Result {
+        if self.is_participant(user_id) {
+            Ok(())
+        } else {
+            Err(Error::NotInConversation)
+        }
+    }
+
+    /// Gets the channel that holds the messages of the conversation.
+    pub fn message_channel(&self) -> Channel {
+        let mut channel = Channel::new(String::new(), self.id, self.id);
+        channel.created = self.created;
+        channel
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Conversation;
+    use crate::error::Error;
+
+    #[test]
+    fn participants() {
+        let alice = "AA".repeat(20);
+        let bob = "bb".repeat(20);
+        let conversation = Conversation::new(&[bob.clone(), alice.clone(), alice.clone()])
+            .expect("Failed to create a conversation.");
+        assert_eq!(
+            conversation.participants,
+            vec![alice.clone(), bob.to_uppercase()]
+        );
+        assert_eq!(
+            conversation.id,
+            Conversation::id_between(&[alice.clone(), bob.to_uppercase()]).unwrap()
+        );
+        assert!(conversation.is_participant(&alice));
+        assert!(matches!(
+            conversation.check_participant(&"CC".repeat(20)),
+            Err(Error::NotInConversation)
+        ));
+        assert!(matches!(
+            Conversation::new(&[alice.clone(), alice.to_lowercase()]),
+            Err(Error::NotEnoughParticipants)
+        ));
+        assert!(matches!(
+            Conversation::new(&[alice, "not a fingerprint".to_string()]),
+            Err(Error::InvalidFingerprint)
+        ));
+    }
+}
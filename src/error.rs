@@ -17,18 +17,56 @@ pub enum Error {
     Banned,
     #[error("hub does not exist")]
     HubNotFound,
+    #[error("hub data is corrupt")]
+    HubCorrupt,
+    #[error("stored record is corrupt")]
+    RecordCorrupt,
+    #[error("storage has not been set up")]
+    StorageNotInitialized,
+    #[error("data was written by a newer version of the server")]
+    UnsupportedSchemaVersion(u32),
+    #[error("hub was changed by another request")]
+    Conflict,
+    #[error("invalid message cursor")]
+    InvalidCursor,
+    #[error("only the sender of a message can edit it")]
+    NotMessageSender,
+    #[error("channel has too many pinned messages")]
+    TooManyPins,
+    #[error("channel is archived")]
+    ChannelArchived,
+    #[error("only announcers can send messages in this channel")]
+    NotAnnouncer,
+    #[error("attachment does not exist")]
+    AttachmentNotFound,
+    #[error("hub has no space left for attachments")]
+    QuotaExceeded,
+    #[error("hub already exists")]
+    HubAlreadyExists,
+    #[error("invalid hub archive")]
+    InvalidArchive,
     #[error("channel does not exist")]
     ChannelNotFound,
+    #[error("channel category does not exist")]
+    CategoryNotFound,
     #[error("user does not have the \"{0}\" hub permission")]
     MissingHubPermission(HubPermission),
     #[error("user does not have the \"{0}\" channel permission")]
     MissingChannelPermission(ChannelPermission),
     #[error("user is not in the hub")]
     NotInHub,
+    #[error("conversation does not exist")]
+    ConversationNotFound,
+    #[error("user is not in the conversation")]
+    NotInConversation,
+    #[error("a conversation needs at least two users")]
+    NotEnoughParticipants,
     #[error("member does not exist")]
     MemberNotFound,
     #[error("message does not exist")]
     MessageNotFound,
+    #[error("message already has a revision with that ID")]
+    RevisionAlreadyExists,
     #[error("permission group does not exist")]
     GroupNotFound,
     #[error("invalid name")]
@@ -55,6 +93,10 @@ pub enum Error {
     Json(#[from] serde_json::Error),
     #[error("Bincode error")]
     Bincode(#[from] bincode::Error),
+    #[error("SQLite error")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("invalid date")]
+    InvalidDate(#[from] chrono::ParseError),
     #[error("Tantivy error")]
     Tantivy(#[from] tantivy::error::TantivyError),
     #[error("Tantivy error")]
@@ -111,19 +153,35 @@ impl From<&Error> for StatusCode {
             Error::Banned
             | Error::Muted
             | Error::MissingChannelPermission(_)
-            | Error::MissingHubPermission(_) => Self::FORBIDDEN,
+            | Error::MissingHubPermission(_)
+            | Error::NotMessageSender
+            | Error::ChannelArchived
+            | Error::NotAnnouncer
+            | Error::QuotaExceeded => Self::FORBIDDEN,
             Error::ChannelNotFound
+            | Error::CategoryNotFound
             | Error::GroupNotFound
             | Error::MemberNotFound
             | Error::MessageNotFound
+            | Error::AttachmentNotFound
+            | Error::ConversationNotFound
+            | Error::NotInConversation
             | Error::NotInHub => Self::NOT_FOUND,
             Error::ID(_)
             | Error::PGP(_)
             | Error::InvalidText
             | Error::TooBig
             | Error::InvalidFingerprint
-            | Error::InvalidName => Self::BAD_REQUEST,
-            Error::AlreadyTyping | Error::NotTyping => Self::CONFLICT,
+            | Error::NotEnoughParticipants
+            | Error::InvalidName
+            | Error::InvalidCursor
+            | Error::TooManyPins
+            | Error::InvalidArchive => Self::BAD_REQUEST,
+            Error::AlreadyTyping
+            | Error::NotTyping
+            | Error::Conflict
+            | Error::HubAlreadyExists
+            | Error::RevisionAlreadyExists => Self::CONFLICT,
             _ => Self::INTERNAL_SERVER_ERROR,
         }
     }
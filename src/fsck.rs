@@ -0,0 +1,548 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    ffi::OsStr,
+    fmt,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use pgp::{Deserializable, SignedPublicKey};
+use tokio::fs;
+
+use crate::{
+    channel::{Message, SignedMessage, Tombstone},
+    hub::{hub_data_folder, hub_info_folder, Hub},
+    server,
+    signing::{self, KeyPair},
+    storage::{self, fs::journal, schema, FsStore, MessageStore},
+    Result, ID,
+};
+
+/// Problem found in the data folder by [`check`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    /// A hub information file could not be read or deserialized.
+    CorruptHub(PathBuf),
+    /// The server stopped in the middle of appending to one of the channel's message files.
+    InterruptedWrite { hub_id: ID, channel_id: ID },
+    /// A message file could not be read or decompressed, or was written by a newer version of the server.
+    UnreadableMessageFile(PathBuf),
+    /// A message file contains bytes that are not a well-formed message, starting at the given offset in its decompressed contents.
+    MalformedMessageFile { path: PathBuf, valid_length: u64 },
+    /// A channel of a hub has no data folder.
+    MissingChannelFolder { hub_id: ID, channel_id: ID },
+    /// The server's or the sender's signature on a message does not verify, or the signed content does not match the stored message.
+    InvalidSignature {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    },
+    /// The log of a channel's search index refers to a message that is not in the channel.
+    DanglingIndexLog { hub_id: ID, channel_id: ID },
+    /// A data folder that belongs to a hub that does not exist.
+    OrphanedHubFolder(PathBuf),
+    /// A data folder left behind by a channel that was deleted.
+    OrphanedChannelFolder {
+        hub_id: ID,
+        channel_id: ID,
+        path: PathBuf,
+    },
+}
+
+impl Problem {
+    /// Checks if [`check`] can repair the problem, the other problems are only reported.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(
+            self,
+            Self::CorruptHub(_) | Self::UnreadableMessageFile(_) | Self::InvalidSignature { .. }
+        )
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CorruptHub(path) => write!(f, "hub file {} is corrupt", path.display()),
+            Self::InterruptedWrite { hub_id, channel_id } => write!(
+                f,
+                "a write to channel {} of hub {} was interrupted",
+                channel_id, hub_id
+            ),
+            Self::UnreadableMessageFile(path) => {
+                write!(f, "message file {} cannot be read", path.display())
+            }
+            Self::MalformedMessageFile { path, valid_length } => write!(
+                f,
+                "message file {} is malformed after byte {}",
+                path.display(),
+                valid_length
+            ),
+            Self::MissingChannelFolder { hub_id, channel_id } => write!(
+                f,
+                "channel {} of hub {} has no data folder",
+                channel_id, hub_id
+            ),
+            Self::InvalidSignature {
+                hub_id,
+                channel_id,
+                message_id,
+            } => write!(
+                f,
+                "message {} in channel {} of hub {} has an invalid signature",
+                message_id, channel_id, hub_id
+            ),
+            Self::DanglingIndexLog { hub_id, channel_id } => write!(
+                f,
+                "search index log of channel {} of hub {} refers to a message that does not exist",
+                channel_id, hub_id
+            ),
+            Self::OrphanedHubFolder(path) => write!(
+                f,
+                "data folder {} does not belong to any hub",
+                path.display()
+            ),
+            Self::OrphanedChannelFolder {
+                hub_id, channel_id, ..
+            } => write!(
+                f,
+                "channel {} of hub {} was deleted but still has a data folder",
+                channel_id, hub_id
+            ),
+        }
+    }
+}
+
+/// Results of checking a data folder.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Number of hub files that were checked.
+    pub hubs: usize,
+    /// Number of messages and revisions that were checked.
+    pub messages: usize,
+    /// Number of messages whose signatures could not be checked because the public key of the server or of the sender is not stored in the data folder.
+    pub unverified: usize,
+    /// Every problem that was found, including the ones that were repaired.
+    pub problems: Vec<Problem>,
+    /// Number of problems that were repaired.
+    pub repaired: usize,
+}
+
+/// Public keys used to verify the signatures on messages.
+struct Keys {
+    server: Option<SignedPublicKey>,
+    users: HashMap<String, SignedPublicKey>,
+}
+
+impl Keys {
+    /// Loads the server's public key and every cached user public key from a data folder.
+    async fn load(data_dir: &str) -> Self {
+        let server = match fs::read_to_string(signing::public_key_path(data_dir)).await {
+            Ok(armoured) => SignedPublicKey::from_string(&armoured)
+                .ok()
+                .map(|(key, _)| key),
+            Err(_) => KeyPair::load(
+                &signing::secret_key_path(data_dir),
+                &signing::public_key_path(data_dir),
+            )
+            .await
+            .ok()
+            .map(|key_pair| key_pair.public_key),
+        };
+        let mut users = HashMap::new();
+        if let Ok(mut dir) = fs::read_dir(signing::user_public_key_folder(data_dir)).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                let path = entry.path();
+                let fingerprint = match path.file_stem().and_then(OsStr::to_str) {
+                    Some(fingerprint) => fingerprint.to_string(),
+                    None => continue,
+                };
+                if let Ok(armoured) = fs::read_to_string(&path).await {
+                    if let Ok((key, _)) = SignedPublicKey::from_string(&armoured) {
+                        users.insert(fingerprint, key);
+                    }
+                }
+            }
+        }
+        Self { server, users }
+    }
+
+    /// Checks the signatures on a message or tombstone, returns `None` if a key needed to check them is missing.
+    fn verify(&self, message: &SignedMessage) -> Option<bool> {
+        let server_key = self.server.as_ref()?;
+        if message.tombstone().is_some() {
+            return Some(
+                Tombstone::from_signed_verify(&message.armoured_content, server_key)
+                    .is_ok_and(|tombstone| tombstone.message_id == message.id),
+            );
+        }
+        let sender = match Message::try_from(message) {
+            Ok(unverified) => unverified.sender,
+            Err(_) => return Some(false),
+        };
+        let user_key = self.users.get(&sender)?;
+        Some(
+            Message::from_double_signed_verify(&message.armoured_content, server_key, user_key)
+                .is_ok_and(|verified| verified.id == message.id),
+        )
+    }
+}
+
+/// Parses the name of a hub or channel file or folder, which is its ID in hex.
+fn id_from_name(name: &OsStr) -> Option<ID> {
+    name.to_str()
+        .and_then(|name| u128::from_str_radix(name, 16).ok())
+        .map(ID::from_u128)
+}
+
+/// Checks every record in a message file, adding the IDs of the messages in it to `ids` if given.
+async fn check_message_file(
+    path: &Path,
+    keys: &Keys,
+    hub_id: ID,
+    channel_id: ID,
+    report: &mut Report,
+    mut ids: Option<&mut HashSet<ID>>,
+) {
+    let bytes = match storage::fs::read_day_bytes(path).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            report
+                .problems
+                .push(Problem::UnreadableMessageFile(path.to_path_buf()));
+            return;
+        }
+    };
+    let (version, start) = match schema::message_file_start(&bytes) {
+        Ok(header) => header,
+        Err(_) => {
+            report
+                .problems
+                .push(Problem::UnreadableMessageFile(path.to_path_buf()));
+            return;
+        }
+    };
+    let len = bytes.len() as u64;
+    let mut cursor = Cursor::new(bytes);
+    cursor.set_position(start);
+    while cursor.position() < len {
+        let offset = cursor.position();
+        let message = match schema::read_message(version, &mut cursor) {
+            Ok(message) => message,
+            Err(_) => {
+                report.problems.push(Problem::MalformedMessageFile {
+                    path: path.to_path_buf(),
+                    valid_length: offset,
+                });
+                return;
+            }
+        };
+        report.messages += 1;
+        match keys.verify(&message) {
+            Some(true) => {}
+            Some(false) => report.problems.push(Problem::InvalidSignature {
+                hub_id,
+                channel_id,
+                message_id: message.id,
+            }),
+            None => report.unverified += 1,
+        }
+        if let Some(ids) = ids.as_mut() {
+            ids.insert(message.id);
+        }
+    }
+}
+
+/// Checks the message files, revisions and search index log of a channel.
+async fn check_channel(
+    data_dir: &str,
+    store: &FsStore,
+    keys: &Keys,
+    hub_id: ID,
+    channel_id: ID,
+    report: &mut Report,
+) -> Result {
+    let folder = store.channel_folder(hub_id, channel_id);
+    if !Path::new(&folder).is_dir() {
+        report
+            .problems
+            .push(Problem::MissingChannelFolder { hub_id, channel_id });
+        return Ok(());
+    }
+    let mut ids = HashSet::new();
+    for (_, path) in store.day_files(hub_id, channel_id).await {
+        check_message_file(&path, keys, hub_id, channel_id, report, Some(&mut ids)).await;
+    }
+    if let Ok(mut dir) = fs::read_dir(format!("{}/revisions", folder)).await {
+        while let Some(entry) = dir.next_entry().await? {
+            check_message_file(&entry.path(), keys, hub_id, channel_id, report, None).await;
+        }
+    }
+    let log_path = server::index_log_path(data_dir, hub_id, channel_id);
+    if let Ok(bytes) = fs::read(&log_path).await {
+        let mut buf = [0; 16];
+        let logged = bytes
+            .as_slice()
+            .read_exact(&mut buf)
+            .map(|_| ID::from_u128(u128::from_le_bytes(buf)));
+        if !logged.is_ok_and(|id| ids.contains(&id)) {
+            report
+                .problems
+                .push(Problem::DanglingIndexLog { hub_id, channel_id });
+        }
+    }
+    Ok(())
+}
+
+/// Repairs a problem, returns false if the problem cannot be repaired.
+async fn repair(data_dir: &str, store: &FsStore, problem: &Problem) -> Result<bool> {
+    match problem {
+        Problem::MissingChannelFolder { hub_id, channel_id } => {
+            fs::create_dir_all(store.channel_folder(*hub_id, *channel_id)).await?
+        }
+        Problem::MalformedMessageFile { path, valid_length } => {
+            storage::fs::truncate_message_file(path, *valid_length).await?
+        }
+        Problem::DanglingIndexLog { hub_id, channel_id } => {
+            server::rebuild_index(data_dir, *hub_id, *channel_id).await?
+        }
+        Problem::OrphanedHubFolder(path) | Problem::OrphanedChannelFolder { path, .. } => {
+            fs::remove_dir_all(path).await?
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Checks the hubs and messages stored in a data folder by the filesystem store:
+///
+/// * Every hub file can be deserialized.
+/// * Every channel of every hub and every direct conversation has a data folder and no write to it was interrupted.
+/// * Every message file and revision file only contains well-formed messages whose signatures verify against the server's public key and the cached public keys of their senders.
+/// * The search index log of every channel refers to a message in the channel.
+/// * There are no data folders left by deleted hubs or channels, hubs and conversations in the configured [`storage::hub_store`] are never considered deleted.
+///
+/// If `repair_problems` is true, interrupted writes are finished, missing channel folders are created, malformed message files are cut off at the first malformed record, search indexes with dangling logs are rebuilt and orphaned data folders are deleted.
+/// Repairs should only be made while the server is not running, rebuilding search indexes requires the stores to have been set up with [`storage::init`].
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * A folder in the data folder could not be listed.
+/// * A repair failed.
+pub async fn check(data_dir: &str, repair_problems: bool) -> Result<Report> {
+    let store = FsStore::in_dir(data_dir);
+    let keys = Keys::load(data_dir).await;
+    let mut report = Report::default();
+    let mut hubs: HashMap<ID, Hub> = HashMap::new();
+    let mut known = HashSet::new();
+    if let Ok(mut dir) = fs::read_dir(hub_info_folder(data_dir)).await {
+        while let Some(entry) = dir.next_entry().await? {
+            let hub_id = match id_from_name(&entry.file_name()) {
+                Some(id) => id,
+                None => continue,
+            };
+            report.hubs += 1;
+            known.insert(hub_id);
+            let hub = match fs::read(entry.path()).await {
+                Ok(bytes) => schema::decode_hub(&bytes),
+                Err(err) => Err(err.into()),
+            };
+            match hub {
+                Ok(hub) => {
+                    hubs.insert(hub_id, hub);
+                }
+                Err(_) => report.problems.push(Problem::CorruptHub(entry.path())),
+            }
+        }
+    }
+    // Hubs and conversations kept by another storage backend only have their search indexes in the data folder, which must not be deleted as orphans.
+    let configured = storage::hub_store();
+    known.extend(configured.list_hubs().await?);
+    known.extend(
+        configured
+            .list_all_conversations()
+            .await?
+            .iter()
+            .map(|conversation| conversation.id),
+    );
+    // The messages of a direct conversation are kept like those of a channel whose hub ID and channel ID are both the conversation's ID.
+    let mut channels: Vec<(ID, ID)> = hubs
+        .values()
+        .flat_map(|hub| {
+            hub.channels
+                .keys()
+                .map(move |channel_id| (hub.id, *channel_id))
+        })
+        .collect();
+    for conversation in store.all_conversations().await {
+        known.insert(conversation.id);
+        channels.push((conversation.id, conversation.id));
+    }
+    // Interrupted writes are finished before anything else is checked, so that the partial records they left are not cut off as malformed.
+    for (hub_id, channel_id) in channels.iter().copied() {
+        let folder = PathBuf::from(store.channel_folder(hub_id, channel_id));
+        let pending = fs::read(journal::journal_path(&folder))
+            .await
+            .is_ok_and(|bytes| !journal::decode(&bytes).is_empty());
+        if pending {
+            report
+                .problems
+                .push(Problem::InterruptedWrite { hub_id, channel_id });
+            if repair_problems {
+                store.recover_messages(hub_id, channel_id).await?;
+                report.repaired += 1;
+            }
+        }
+    }
+    let recovered = report.problems.len();
+    for (hub_id, channel_id) in channels {
+        check_channel(data_dir, &store, &keys, hub_id, channel_id, &mut report).await?;
+    }
+    if let Ok(mut dir) = fs::read_dir(hub_data_folder(data_dir)).await {
+        while let Some(entry) = dir.next_entry().await? {
+            let hub_id = match id_from_name(&entry.file_name()) {
+                Some(id) if entry.path().is_dir() => id,
+                _ => continue,
+            };
+            if !known.contains(&hub_id) {
+                report
+                    .problems
+                    .push(Problem::OrphanedHubFolder(entry.path()));
+                continue;
+            }
+            let hub = match hubs.get(&hub_id) {
+                Some(hub) => hub,
+                None => continue,
+            };
+            let mut channels = fs::read_dir(entry.path()).await?;
+            while let Some(channel) = channels.next_entry().await? {
+                if let Some(channel_id) = id_from_name(&channel.file_name()) {
+                    if channel.path().is_dir() && !hub.channels.contains_key(&channel_id) {
+                        report.problems.push(Problem::OrphanedChannelFolder {
+                            hub_id,
+                            channel_id,
+                            path: channel.path(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    if repair_problems {
+        for problem in report.problems[recovered..].iter() {
+            if repair(data_dir, &store, problem).await? {
+                report.repaired += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, Problem};
+    use crate::{
+        channel::SignedMessage,
+        hub::Hub,
+        new_id,
+        storage::{self, HubStore, MessageStore},
+        test_util::TempDir,
+    };
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn check_and_repair() {
+        let temp_dir = TempDir::new("fsck");
+        let data_dir = temp_dir.path();
+        let store = temp_dir.store();
+        let owner = new_id().to_string();
+        let mut hub = Hub::new("checked".to_string(), new_id(), owner.clone());
+        let channel_id = hub
+            .new_channel(&owner, "checked".to_string())
+            .expect("Failed to add a channel.");
+        let missing_id = hub
+            .new_channel(&owner, "missing".to_string())
+            .expect("Failed to add a channel.");
+        store.save_hub(&hub).await.expect("Failed to save the hub.");
+        store
+            .create_channel(hub.id, channel_id)
+            .await
+            .expect("Failed to create a channel.");
+        let deleted_id = new_id();
+        store
+            .create_channel(hub.id, deleted_id)
+            .await
+            .expect("Failed to create a channel.");
+        for _ in 0..2 {
+            store
+                .add_message(
+                    hub.id,
+                    channel_id,
+                    SignedMessage::new(new_id(), Utc::now(), String::new()),
+                )
+                .await
+                .expect("Failed to add a message.");
+        }
+        let (_, path) = store.day_files(hub.id, channel_id).await.pop().unwrap();
+        let mut bytes = tokio::fs::read(&path).await.unwrap();
+        let valid_length = bytes.len() as u64;
+        bytes.extend_from_slice(&[0xff; 5]);
+        tokio::fs::write(&path, bytes).await.unwrap();
+
+        let report = check(&data_dir, false).await.expect("Failed to check.");
+        assert_eq!(report.hubs, 1);
+        assert_eq!(report.messages, 2);
+        assert_eq!(report.unverified, 2);
+        assert_eq!(report.repaired, 0);
+        assert_eq!(report.problems.len(), 3);
+        assert!(report.problems.contains(&Problem::MissingChannelFolder {
+            hub_id: hub.id,
+            channel_id: missing_id
+        }));
+        assert!(report.problems.contains(&Problem::MalformedMessageFile {
+            path: path.clone(),
+            valid_length
+        }));
+        assert!(report.problems.iter().any(|problem| matches!(
+            problem,
+            Problem::OrphanedChannelFolder { channel_id, .. } if *channel_id == deleted_id
+        )));
+
+        let report = check(&data_dir, true).await.expect("Failed to repair.");
+        assert_eq!(report.repaired, 3);
+        let report = check(&data_dir, false).await.expect("Failed to check.");
+        assert!(report.problems.is_empty());
+        assert_eq!(report.messages, 2);
+        assert_eq!(
+            store
+                .get_last_messages(hub.id, channel_id, 10)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn other_backends() {
+        let temp_dir = TempDir::new("fsck");
+        let data_dir = temp_dir.path();
+        let owner = new_id().to_string();
+        // Hubs kept by another backend only have their search index in the data folder.
+        let hub = Hub::new("elsewhere".to_string(), new_id(), owner);
+        storage::hub_store()
+            .save_hub(&hub)
+            .await
+            .expect("Failed to save the hub.");
+        let folder = temp_dir.store().hub_data_path(hub.id);
+        tokio::fs::create_dir_all(&folder).await.unwrap();
+
+        let report = check(&data_dir, true).await.expect("Failed to check.");
+        assert!(report.problems.is_empty());
+        assert!(std::path::Path::new(&folder).is_dir());
+        storage::hub_store().delete_hub(hub.id).await.unwrap();
+    }
+}
@@ -2,16 +2,51 @@ use std::{collections::HashSet, sync::Arc};
 
 use crate::{
     api,
-    channel::Channel,
+    channel::{
+        Channel, ChannelCategory, ChannelMode, ChannelModeKind, MessageCursor, MessagePosition,
+        Reaction, ReadMarker, RetentionPolicy, SignedMessage, ThreadSummary, UnreadCounts,
+    },
+    conversation::Conversation,
     hub::{Hub, HubMember, PermissionGroup},
+    hub_cache,
     permission::{ChannelPermission, ChannelPermissionSet, HubPermission, HubPermissionSet},
-    server::Server,
+    server::{HubUpdateType, Server, ServerNotification},
     ID,
 };
-use async_graphql::*;
+use async_graphql::{
+    connection::{Connection, CursorType, Edge},
+    *,
+};
 use chrono::{DateTime, Utc};
 use xactor::Addr;
 
+impl CursorType for MessageCursor {
+    type Error = crate::error::Error;
+
+    fn decode_cursor(s: &str) -> std::result::Result<Self, Self::Error> {
+        Self::decode(s)
+    }
+
+    fn encode_cursor(&self) -> String {
+        self.encode()
+    }
+}
+
+#[ComplexObject]
+impl Reaction {
+    async fn count(&self) -> usize {
+        self.users.len()
+    }
+}
+
+#[ComplexObject]
+impl Conversation {
+    /// Channel that holds the messages of the conversation.
+    async fn channel(&self) -> Channel {
+        self.message_channel()
+    }
+}
+
 pub struct QueryRoot;
 
 #[Object]
@@ -25,7 +60,7 @@ impl QueryRoot {
         ctx: &Context<'_>,
         #[graphql(desc = "ID of a hub.")] id: ID,
     ) -> Result<Hub> {
-        let hub = Hub::load(id).await?;
+        let hub = hub_cache::get(id).await?;
         Ok(hub.strip(self.requester(ctx).await?)?)
     }
 
@@ -36,11 +71,23 @@ impl QueryRoot {
     ) -> Result<Vec<Hub>> {
         let mut result = Vec::new();
         for id in ids {
-            let hub = Hub::load(id).await?;
+            let hub = hub_cache::get(id).await?;
             result.push(hub.strip(self.requester(ctx).await?)?);
         }
         Ok(result)
     }
+
+    async fn conversation(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of a direct conversation.")] id: ID,
+    ) -> Result<Conversation> {
+        Ok(api::get_conversation(self.requester(ctx).await?, id).await?)
+    }
+
+    async fn conversations(&self, ctx: &Context<'_>) -> Result<Vec<Conversation>> {
+        Ok(api::list_conversations(self.requester(ctx).await?).await?)
+    }
 }
 
 pub struct MutationRoot;
@@ -63,6 +110,100 @@ impl ChannelMutator {
 
 #[Object]
 impl ChannelMutator {
+    async fn mark_read(
+        &self,
+        #[graphql(desc = "ID of the last message that was read.")] message_id: ID,
+    ) -> Result<ReadMarker> {
+        Ok(api::mark_read(&self.user_id, self.hub_id, self.channel_id, message_id).await?)
+    }
+    async fn pin_message(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the message to pin.")] message_id: ID,
+    ) -> Result<bool> {
+        let changed =
+            api::pin_message(&self.user_id, self.hub_id, self.channel_id, message_id).await?;
+        if changed {
+            let _ = ctx
+                .data_unchecked::<Arc<Addr<Server>>>()
+                .send(ServerNotification::HubUpdated(
+                    self.hub_id,
+                    HubUpdateType::MessagePinned(self.channel_id, message_id),
+                ));
+        }
+        Ok(changed)
+    }
+    async fn unpin_message(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the message to unpin.")] message_id: ID,
+    ) -> Result<bool> {
+        let changed =
+            api::unpin_message(&self.user_id, self.hub_id, self.channel_id, message_id).await?;
+        if changed {
+            let _ = ctx
+                .data_unchecked::<Arc<Addr<Server>>>()
+                .send(ServerNotification::HubUpdated(
+                    self.hub_id,
+                    HubUpdateType::MessageUnpinned(self.channel_id, message_id),
+                ));
+        }
+        Ok(changed)
+    }
+    async fn add_reaction(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the message to react to.")] message_id: ID,
+        #[graphql(desc = "Emoji to react with.")] emoji: String,
+    ) -> Result<bool> {
+        let added = api::add_reaction(
+            &self.user_id,
+            self.hub_id,
+            self.channel_id,
+            message_id,
+            &emoji,
+        )
+        .await?;
+        if added {
+            let _ =
+                ctx.data_unchecked::<Arc<Addr<Server>>>()
+                    .send(ServerNotification::ReactionAdded(
+                        self.hub_id,
+                        self.channel_id,
+                        message_id,
+                        self.user_id.clone(),
+                        emoji,
+                    ));
+        }
+        Ok(added)
+    }
+    async fn remove_reaction(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the message to remove the reaction from.")] message_id: ID,
+        #[graphql(desc = "Emoji to remove.")] emoji: String,
+    ) -> Result<bool> {
+        let removed = api::remove_reaction(
+            &self.user_id,
+            self.hub_id,
+            self.channel_id,
+            message_id,
+            &emoji,
+        )
+        .await?;
+        if removed {
+            let _ = ctx.data_unchecked::<Arc<Addr<Server>>>().send(
+                ServerNotification::ReactionRemoved(
+                    self.hub_id,
+                    self.channel_id,
+                    message_id,
+                    self.user_id.clone(),
+                    emoji,
+                ),
+            );
+        }
+        Ok(removed)
+    }
     async fn name(
         &self,
         #[graphql(desc = "New name for the channel.")] new: String,
@@ -78,6 +219,50 @@ impl ChannelMutator {
                 .await?,
         )
     }
+    async fn retention(
+        &self,
+        #[graphql(
+            desc = "Number of days after which messages are deleted, messages are not deleted because of their age if not set."
+        )]
+        max_age_days: Option<u32>,
+        #[graphql(desc = "Maximum number of messages to keep, there is no limit if not set.")]
+        max_messages: Option<u64>,
+    ) -> Result<RetentionPolicy> {
+        Ok(api::set_channel_retention(
+            &self.user_id,
+            self.hub_id,
+            self.channel_id,
+            RetentionPolicy {
+                max_age_days,
+                max_messages,
+            },
+        )
+        .await?)
+    }
+    async fn mode(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "New mode for the channel.")] mode: ChannelModeKind,
+        #[graphql(
+            desc = "ID of the permission group whose members can send messages in announcement mode."
+        )]
+        announcers: Option<ID>,
+    ) -> Result<ChannelModeKind> {
+        let old = api::set_channel_mode(
+            &self.user_id,
+            self.hub_id,
+            self.channel_id,
+            ChannelMode::from_kind(mode, announcers)?,
+        )
+        .await?;
+        let _ = ctx
+            .data_unchecked::<Arc<Addr<Server>>>()
+            .send(ServerNotification::HubUpdated(
+                self.hub_id,
+                HubUpdateType::ChannelModeChanged(self.channel_id),
+            ));
+        Ok(old.kind())
+    }
 }
 
 struct HubMutator {
@@ -89,6 +274,13 @@ impl HubMutator {
     fn new(user_id: String, hub_id: ID) -> Self {
         Self { user_id, hub_id }
     }
+
+    /// Lets the clients that are subscribed to the hub know that it changed.
+    fn notify(&self, ctx: &Context<'_>, update_type: HubUpdateType) {
+        let _ = ctx
+            .data_unchecked::<Arc<Addr<Server>>>()
+            .send(ServerNotification::HubUpdated(self.hub_id, update_type));
+    }
 }
 
 #[Object]
@@ -127,6 +319,104 @@ impl HubMutator {
         )
         .await?)
     }
+    async fn create_category(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Name for the new category.")] name: String,
+    ) -> Result<ID> {
+        let id = api::create_category(&self.user_id, self.hub_id, name).await?;
+        self.notify(ctx, HubUpdateType::CategoryCreated(id));
+        Ok(id)
+    }
+    async fn rename_category(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the category to rename.")] id: ID,
+        #[graphql(desc = "New name for the category.")] new: String,
+    ) -> Result<String> {
+        let old = api::rename_category(&self.user_id, self.hub_id, id, new).await?;
+        self.notify(ctx, HubUpdateType::CategoryRenamed(id));
+        Ok(old)
+    }
+    async fn delete_category(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the category to delete.")] id: ID,
+    ) -> Result<ID> {
+        api::delete_category(&self.user_id, self.hub_id, id).await?;
+        self.notify(ctx, HubUpdateType::CategoryDeleted(id));
+        Ok(id)
+    }
+    async fn set_member_category_permission(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the member whose permission to change.")] member: String,
+        #[graphql(desc = "ID of the category in which to change the permission.")] category: ID,
+        #[graphql(desc = "Permission to change.")] permission: ChannelPermission,
+        #[graphql(
+            desc = "New setting for the permission, the matching hub permission is used if not set."
+        )]
+        value: Option<bool>,
+    ) -> Result<ID> {
+        api::set_member_category_permission(
+            &self.user_id,
+            self.hub_id,
+            &member,
+            category,
+            permission,
+            value,
+        )
+        .await?;
+        self.notify(ctx, HubUpdateType::CategoryPermissionChanged(category));
+        Ok(category)
+    }
+    async fn set_group_category_permission(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the permission group whose permission to change.")] group: ID,
+        #[graphql(desc = "ID of the category in which to change the permission.")] category: ID,
+        #[graphql(desc = "Permission to change.")] permission: ChannelPermission,
+        #[graphql(
+            desc = "New setting for the permission, the matching hub permission is used if not set."
+        )]
+        value: Option<bool>,
+    ) -> Result<ID> {
+        api::set_group_category_permission(
+            &self.user_id,
+            self.hub_id,
+            group,
+            category,
+            permission,
+            value,
+        )
+        .await?;
+        self.notify(ctx, HubUpdateType::CategoryPermissionChanged(category));
+        Ok(category)
+    }
+    async fn move_channel(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the channel to move.")] id: ID,
+        #[graphql(
+            desc = "ID of the category to move the channel into, the channel is taken out of its category if not set."
+        )]
+        category: Option<ID>,
+        #[graphql(desc = "Position of the channel in the category.")] position: u32,
+    ) -> Result<ID> {
+        api::move_channel(&self.user_id, self.hub_id, id, category, position).await?;
+        self.notify(ctx, HubUpdateType::ChannelsReordered);
+        Ok(id)
+    }
+    async fn move_category(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the category to move.")] id: ID,
+        #[graphql(desc = "Position of the category among the hub's categories.")] position: u32,
+    ) -> Result<ID> {
+        api::move_category(&self.user_id, self.hub_id, id, position).await?;
+        self.notify(ctx, HubUpdateType::ChannelsReordered);
+        Ok(id)
+    }
     async fn kick(
         &self,
         #[graphql(desc = "ID of the user to kick.")] id: String,
@@ -195,7 +485,18 @@ impl MutationRoot {
         ctx: &Context<'_>,
         #[graphql(desc = "Name for the new hub.")] name: String,
     ) -> Result<Hub> {
-        Ok(Hub::load(api::create_hub(self.requester(ctx).await?.clone(), name).await?).await?)
+        let hub = hub_cache::get(api::create_hub(self.requester(ctx).await?.clone(), name).await?)
+            .await?;
+        Ok(Hub::clone(&hub))
+    }
+
+    async fn start_conversation(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Fingerprints of the users to start a conversation with.")]
+        participants: Vec<String>,
+    ) -> Result<Conversation> {
+        Ok(api::start_conversation(self.requester(ctx).await?, &participants).await?)
     }
 }
 
@@ -217,6 +518,106 @@ impl Channel {
         &self.description
     }
 
+    async fn retention(&self) -> &RetentionPolicy {
+        &self.retention
+    }
+
+    async fn category(&self) -> &Option<ID> {
+        &self.category
+    }
+
+    /// IDs of the messages pinned in the channel, oldest pin first.
+    async fn pinned(&self) -> &Vec<ID> {
+        &self.pinned
+    }
+
+    async fn mode(&self) -> ChannelModeKind {
+        self.mode.kind()
+    }
+
+    async fn announcers(&self) -> Option<ID> {
+        self.mode.announcers()
+    }
+
+    async fn position(&self) -> u32 {
+        self.position
+    }
+
+    async fn messages(
+        &self,
+        #[graphql(desc = "Cursor of the message to get the messages sent before.")] before: Option<
+            String,
+        >,
+        #[graphql(desc = "Cursor of the message to get the messages sent after.")] after: Option<
+            String,
+        >,
+        #[graphql(desc = "Cursor of the message to get the messages sent around.")] around: Option<
+            String,
+        >,
+        #[graphql(desc = "Sequence number of the first message to get.")] from_sequence: Option<
+            u64,
+        >,
+        #[graphql(desc = "Maximum number of messages to get.")] limit: Option<u8>,
+    ) -> Result<Connection<MessageCursor, SignedMessage>> {
+        let position = MessagePosition::from_cursors(
+            before.as_deref(),
+            after.as_deref(),
+            around.as_deref(),
+            from_sequence,
+        )?;
+        let limit = limit.map_or(crate::MAX_MESSAGE_PAGE_SIZE, usize::from);
+        let page = self
+            .list_messages(position, limit.min(crate::MAX_MESSAGE_PAGE_SIZE))
+            .await?;
+        let mut connection = Connection::new(page.has_before, page.has_after);
+        connection.append(
+            page.messages
+                .into_iter()
+                .map(|message| Edge::new(MessageCursor(message.id), message)),
+        );
+        Ok(connection)
+    }
+
+    /// Last message in the channel that the requester has read.
+    async fn read_marker(&self, ctx: &Context<'_>) -> Option<ReadMarker> {
+        self.get_read_marker(ctx.data_unchecked::<String>()).await
+    }
+
+    /// Numbers of messages in the channel that the requester has not read and of those that mention them, see [`crate::MAX_UNREAD_COUNT`].
+    #[graphql(name = "unreadCounts")]
+    async fn get_unread_counts(&self, ctx: &Context<'_>) -> Result<UnreadCounts> {
+        Ok(self.unread_counts(ctx.data_unchecked::<String>()).await?)
+    }
+
+    async fn message_revisions(
+        &self,
+        #[graphql(desc = "ID of the message to get the revisions of.")] id: ID,
+    ) -> Vec<SignedMessage> {
+        self.get_revisions(id).await
+    }
+
+    async fn reactions(
+        &self,
+        #[graphql(desc = "ID of the message to get the reactions to.")] message_id: ID,
+    ) -> Vec<Reaction> {
+        self.get_reactions(message_id).await
+    }
+
+    async fn thread(
+        &self,
+        #[graphql(desc = "ID of the message that started the thread.")] id: ID,
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(self.get_thread(id).await?)
+    }
+
+    async fn threads(&self) -> Vec<ThreadSummary> {
+        self.get_threads().await
+    }
+
+    async fn pinned_messages(&self) -> Vec<SignedMessage> {
+        self.get_pinned_messages().await
+    }
+
     async fn search_messages(
         &self,
         ctx: &Context<'_>,
@@ -302,20 +703,41 @@ impl Hub {
         &self,
         #[graphql(desc = "IDs of the channels to get.")] ids: Vec<ID>,
     ) -> Vec<&Channel> {
-        self.channels
-            .iter()
-            .filter_map(|(id, channel)| {
-                if ids.contains(&id) {
-                    Some(channel)
-                } else {
-                    None
-                }
-            })
+        self.ordered_channels()
+            .into_iter()
+            .filter(|channel| ids.contains(&channel.id))
             .collect()
     }
 
     async fn all_channels(&self) -> Vec<&Channel> {
-        self.channels.iter().map(|(_, channel)| channel).collect()
+        self.ordered_channels()
+    }
+
+    async fn category(
+        &self,
+        #[graphql(desc = "ID of the category to get.")] id: ID,
+    ) -> Option<&ChannelCategory> {
+        self.categories.get(&id)
+    }
+
+    async fn categories(&self) -> Vec<&ChannelCategory> {
+        self.ordered_categories()
+    }
+
+    async fn category_channels(
+        &self,
+        #[graphql(
+            desc = "ID of the category to get the channels of, the channels that are not in a category are returned if not set."
+        )]
+        id: Option<ID>,
+    ) -> Vec<&Channel> {
+        self.channels_in(id)
+    }
+
+    /// Numbers of unread messages and of unread messages that mention the requester in the channels they can view.
+    #[graphql(name = "unreadCounts")]
+    async fn get_unread_counts(&self, ctx: &Context<'_>) -> Result<UnreadCounts> {
+        Ok(self.unread_counts(ctx.data_unchecked::<String>()).await?)
     }
 
     async fn member(
@@ -331,18 +753,20 @@ impl Hub {
     ) -> Vec<&HubMember> {
         self.members
             .iter()
-            .filter_map(|(id, member)| {
-                if ids.contains(&id) {
-                    Some(member)
-                } else {
-                    None
-                }
-            })
+            .filter_map(
+                |(id, member)| {
+                    if ids.contains(id) {
+                        Some(member)
+                    } else {
+                        None
+                    }
+                },
+            )
             .collect()
     }
 
     async fn all_members(&self) -> Vec<&HubMember> {
-        self.members.iter().map(|(_, member)| member).collect()
+        self.members.values().collect()
     }
 
     async fn group(
@@ -360,7 +784,7 @@ impl Hub {
             .iter()
             .filter_map(
                 |(id, group)| {
-                    if ids.contains(&id) {
+                    if ids.contains(id) {
                         Some(group)
                     } else {
                         None
@@ -371,7 +795,7 @@ impl Hub {
     }
 
     async fn all_groups(&self) -> Vec<&PermissionGroup> {
-        self.groups.iter().map(|(_, group)| group).collect()
+        self.groups.values().collect()
     }
 
     async fn member_has_permission(
@@ -381,7 +805,7 @@ impl Hub {
     ) -> bool {
         self.members
             .get(&id)
-            .map_or(false, |m| m.has_permission(permission, self))
+            .is_some_and(|m| m.has_permission(permission, self))
     }
 
     async fn member_has_channel_permission(
@@ -393,9 +817,9 @@ impl Hub {
         channel: ID,
         #[graphql(desc = "Permission to check for.")] permission: ChannelPermission,
     ) -> bool {
-        self.members.get(&id).map_or(false, |m| {
-            m.has_channel_permission(channel, permission, self)
-        })
+        self.members
+            .get(&id)
+            .is_some_and(|m| m.has_channel_permission(channel, permission, self))
     }
 }
 
@@ -425,25 +849,21 @@ impl PermissionGroup {
         &self,
         #[graphql(desc = "Permission to check for.")] permission: HubPermission,
     ) -> Option<HubPermissionSet> {
-        if let Some(setting) = self.hub_permissions.get(&permission) {
-            Some(HubPermissionSet {
+        self.hub_permissions
+            .get(&permission)
+            .map(|setting| HubPermissionSet {
                 permission,
                 setting: *setting,
             })
-        } else {
-            None
-        }
     }
 
     async fn hub_permissions(&self) -> Vec<HubPermissionSet> {
         self.hub_permissions
             .iter()
             .filter_map(|(permission, setting)| {
-                if let Some(setting) = setting {
-                    Some(HubPermissionSet::from((*permission, Some(*setting))))
-                } else {
-                    None
-                }
+                setting
+                    .as_ref()
+                    .map(|setting| HubPermissionSet::from((*permission, Some(*setting))))
             })
             .collect()
     }
@@ -473,15 +893,9 @@ impl PermissionGroup {
                     &mut permissions
                         .iter()
                         .filter_map(|(permission, setting)| {
-                            if let Some(setting) = setting {
-                                Some(ChannelPermissionSet::from((
-                                    *permission,
-                                    Some(*setting),
-                                    *channel,
-                                )))
-                            } else {
-                                None
-                            }
+                            setting.as_ref().map(|setting| {
+                                ChannelPermissionSet::from((*permission, Some(*setting), *channel))
+                            })
                         })
                         .collect::<Vec<ChannelPermissionSet>>(),
                 )
@@ -515,25 +929,21 @@ impl HubMember {
         &self,
         #[graphql(desc = "Permission to check for.")] permission: HubPermission,
     ) -> Option<HubPermissionSet> {
-        if let Some(setting) = self.hub_permissions.get(&permission) {
-            Some(HubPermissionSet {
+        self.hub_permissions
+            .get(&permission)
+            .map(|setting| HubPermissionSet {
                 permission,
                 setting: *setting,
             })
-        } else {
-            None
-        }
     }
 
     async fn hub_permissions(&self) -> Vec<HubPermissionSet> {
         self.hub_permissions
             .iter()
             .filter_map(|(permission, setting)| {
-                if let Some(setting) = setting {
-                    Some(HubPermissionSet::from((*permission, Some(*setting))))
-                } else {
-                    None
-                }
+                setting
+                    .as_ref()
+                    .map(|setting| HubPermissionSet::from((*permission, Some(*setting))))
             })
             .collect()
     }
@@ -563,15 +973,9 @@ impl HubMember {
                     &mut permissions
                         .iter()
                         .filter_map(|(permission, setting)| {
-                            if let Some(setting) = setting {
-                                Some(ChannelPermissionSet::from((
-                                    *permission,
-                                    Some(*setting),
-                                    *channel,
-                                )))
-                            } else {
-                                None
-                            }
+                            setting.as_ref().map(|setting| {
+                                ChannelPermissionSet::from((*permission, Some(*setting), *channel))
+                            })
                         })
                         .collect::<Vec<ChannelPermissionSet>>(),
                 )
@@ -19,16 +19,12 @@ use pgp::SignedPublicKey;
 use pgp::{crypto::HashAlgorithm, types::CompressionAlgorithm};
 use pgp::{packet::LiteralData, types::KeyTrait};
 
+use crate::error::{Error, Result};
 use crate::server::Server;
 use crate::signing::KeyPair;
-use crate::signing::{PUBLIC_KEY_PATH, SECRET_KEY_PATH};
+use crate::signing::{public_key_path, secret_key_path, user_public_key_folder};
 use crate::ID;
 use crate::{channel::Message, config::Config};
-use crate::{
-    error::{Error, Result},
-    hub::Hub,
-    permission::ChannelPermission,
-};
 use crate::{
     graphql_model::{MutationRoot, QueryRoot},
     server::ServerNotification,
@@ -41,13 +37,47 @@ pub struct ServerInfo {
     pub key_server: String,
 }
 
+/// Query parameters accepted when starting to send a message.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SendMessageQuery {
+    /// ID of the message that the new message is a reply to.
+    pub reply_to: Option<ID>,
+    /// Comma separated hashes of the attachments to include in the message.
+    pub attachments: Option<String>,
+}
+
+/// Query parameters accepted when uploading an attachment.
+#[derive(Deserialize, Clone, Debug)]
+pub struct UploadAttachmentQuery {
+    /// Name of the file being uploaded.
+    pub name: String,
+}
+
 pub async fn start(config: Config) -> Result {
-    let key_pair = if let Ok(key_pair) = KeyPair::load(SECRET_KEY_PATH, PUBLIC_KEY_PATH).await {
+    crate::storage::init(&config)?;
+    let recovered = crate::storage::recover_all().await?;
+    if recovered > 0 {
+        println!(
+            "Finished {} interrupted writes to message files.",
+            recovered
+        );
+    }
+    if let Some(days) = config.compress_after_days {
+        tokio::spawn(crate::storage::compact_periodically(days));
+    }
+    tokio::spawn(crate::hub_cache::evict_idle_periodically());
+    tokio::spawn(crate::attachment::collect_garbage_periodically(
+        config.data_dir.clone(),
+    ));
+    tokio::fs::create_dir_all(user_public_key_folder(&config.data_dir)).await?;
+    let secret_key_path = secret_key_path(&config.data_dir);
+    let public_key_path = public_key_path(&config.data_dir);
+    let key_pair = if let Ok(key_pair) = KeyPair::load(&secret_key_path, &public_key_path).await {
         key_pair
     } else {
         println!(
             "WARNING: Failed to load secret key from {}, generating a new one.",
-            SECRET_KEY_PATH
+            secret_key_path
         );
         let key_id = if let Some(key_id) = config.key_id.clone() {
             key_id
@@ -61,10 +91,10 @@ pub async fn start(config: Config) -> Result {
         };
         println!("Generating new PGP key pair for the server...");
         let key_pair = KeyPair::new(key_id)?;
-        key_pair.save(SECRET_KEY_PATH, PUBLIC_KEY_PATH).await?;
+        key_pair.save(&secret_key_path, &public_key_path).await?;
         println!(
             "Server key pair generated, private key saved to {}, public key saved to {}.",
-            SECRET_KEY_PATH, PUBLIC_KEY_PATH
+            secret_key_path, public_key_path
         );
         key_pair
     };
@@ -98,7 +128,7 @@ pub async fn start(config: Config) -> Result {
     let key_pair_ws = key_pair.clone();
     let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription).finish();
     let server = Arc::new(
-        Server::new(key_pair.secret_key.clone())
+        Server::new(key_pair.secret_key.clone(), config.data_dir.clone())
             .await?
             .start()
             .await
@@ -107,17 +137,25 @@ pub async fn start(config: Config) -> Result {
     let send_message_server_arc = server.clone();
     let key_pair_send = key_pair.clone();
     let key_pair_send_init = key_pair.clone();
+    let edit_message_server_arc = server.clone();
+    let key_pair_edit = key_pair.clone();
+    let key_pair_edit_init = key_pair.clone();
+    let delete_message_server_arc = server.clone();
+    let key_pair_delete = key_pair.clone();
     let graphql_server_arc = server.clone();
     let key_server_url = config.key_server.clone();
+    let key_data_dir = config.data_dir.clone();
     let public_key_filter =
         warp::any()
             .and(warp::header("pgp-fingerprint"))
             .and_then(move |header: String| {
                 let key_server_url = key_server_url.clone();
+                let key_data_dir = key_data_dir.clone();
                 async move {
                     crate::signing::get_or_import_public_key(
                         &hex::decode(header).map_err(|_| Error::InvalidFingerprint)?,
                         &key_server_url,
+                        &key_data_dir,
                     )
                     .await
                     .and_then(|key| {
@@ -193,28 +231,38 @@ pub async fn start(config: Config) -> Result {
         });
 
     let signed_body_smi = signed_body.clone();
+    let signed_body_emi = signed_body.clone();
+    let signed_body_dm = signed_body.clone();
 
     let send_message_init = warp::any()
         .and(warp::path!("v3" / "send_message_init" / String / String))
+        .and(warp::query::<SendMessageQuery>())
         .and(signed_body_smi)
         .and_then(
-            move |hub_id: String, channel_id: String, (content, sender): (String, String)| {
+            move |hub_id: String,
+                  channel_id: String,
+                  query: SendMessageQuery,
+                  (content, sender): (String, String)| {
                 let key_pair = key_pair_send_init.clone();
                 async move {
                     Ok::<_, Infallible>(
                         async {
-                            let hub_id = ID::parse_str(&hub_id)?;
-                            let hub = Hub::load(hub_id).await?;
-                            let channel_id = ID::parse_str(&channel_id)?;
-                            let member = hub.get_member(&sender)?;
-                            crate::check_permission!(
-                                &member,
-                                channel_id,
-                                ChannelPermission::Write,
-                                &hub
-                            );
+                            let message = crate::api::init_message(
+                                &sender,
+                                ID::parse_str(&hub_id)?,
+                                ID::parse_str(&channel_id)?,
+                                content,
+                                query.reply_to,
+                                &query
+                                    .attachments
+                                    .map(|hashes| {
+                                        hashes.split(',').map(str::to_string).collect::<Vec<_>>()
+                                    })
+                                    .unwrap_or_default(),
+                            )
+                            .await?;
                             Ok::<_, Error>(
-                                Message::new(sender, content, hub_id, channel_id)
+                                message
                                     .sign(&key_pair.secret_key, String::new)?
                                     .compress(CompressionAlgorithm::ZIP)?
                                     .to_armored_string(None)?,
@@ -227,6 +275,91 @@ pub async fn start(config: Config) -> Result {
             },
         );
 
+    let upload_pub_key = public_key_filter.clone();
+    let attachment_quota = config
+        .hub_attachment_quota
+        .unwrap_or(crate::HUB_ATTACHMENT_QUOTA);
+
+    let upload_attachment = warp::any()
+        .and(warp::path!("v3" / "upload_attachment" / String / String))
+        .and(warp::query::<UploadAttachmentQuery>())
+        .and(upload_pub_key)
+        // Armouring makes the signed content about a third bigger than the file.
+        .and(warp::body::content_length_limit(
+            crate::MAX_ATTACHMENT_SIZE as u64 * 2,
+        ))
+        .and(warp::body::bytes())
+        .and_then(
+            move |hub_id: String,
+                  channel_id: String,
+                  query: UploadAttachmentQuery,
+                  client_public_key: SignedPublicKey,
+                  body: Bytes| async move {
+                Ok::<_, Infallible>(
+                    async {
+                        let (content, sender) = crate::signing::verify_message_extract_bytes(
+                            &client_public_key,
+                            &String::from_utf8(body.to_vec())?,
+                        )?;
+                        let attachment = crate::api::upload_attachment(
+                            &sender,
+                            ID::parse_str(&hub_id)?,
+                            ID::parse_str(&channel_id)?,
+                            query.name,
+                            &content,
+                            attachment_quota,
+                        )
+                        .await?;
+                        Ok::<_, Error>(warp::reply::json(&attachment))
+                    }
+                    .await
+                    .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                )
+            },
+        );
+
+    let signed_body_da = signed_body.clone();
+
+    let download_attachment = warp::any()
+        .and(warp::path!("v3" / "attachment" / String / String / String))
+        .and(signed_body_da)
+        .and_then(
+            move |hub_id: String,
+                  channel_id: String,
+                  hash: String,
+                  (_, sender): (String, String)| async move {
+                Ok::<_, Infallible>(
+                    async {
+                        let (attachment, content) = crate::api::download_attachment(
+                            &sender,
+                            ID::parse_str(&hub_id)?,
+                            ID::parse_str(&channel_id)?,
+                            &hash,
+                        )
+                        .await?;
+                        warp::http::response::Builder::new()
+                            .header("Content-Type", "application/octet-stream")
+                            .header(
+                                "Content-Disposition",
+                                format!(
+                                        "attachment; filename=\"{}\"",
+                                        attachment
+                                            .name
+                                            .chars()
+                                            .filter(|c| c == &' '
+                                                || (c.is_ascii_graphic() && c != &'"'))
+                                            .collect::<String>()
+                                    ),
+                            )
+                            .body(content)
+                            .map_err(|_| Error::UnexpectedServerArg)
+                    }
+                    .await
+                    .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                )
+            },
+        );
+
     let send_message_pub_key = public_key_filter.clone();
 
     let send_message = warp::any()
@@ -245,12 +378,88 @@ pub async fn start(config: Config) -> Result {
                             &key_pair.public_key,
                             &client_public_key,
                         )?;
+                        let user_id = hex::encode_upper(client_public_key.fingerprint());
+                        let sequence =
+                            crate::api::send_message(&user_id, &message, body.clone()).await?;
                         let _ = server.send(ServerNotification::NewMessage(
                             message.hub_id,
                             message.channel_id,
                             message.id,
                             body,
                             message,
+                            sequence,
+                        ));
+                        Ok::<_, Error>(warp::reply())
+                    }
+                    .await
+                    .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                )
+            }
+        });
+
+    let edit_message_init = warp::any()
+        .and(warp::path!(
+            "v3" / "edit_message_init" / String / String / String
+        ))
+        .and(signed_body_emi)
+        .and_then(
+            move |hub_id: String,
+                  channel_id: String,
+                  message_id: String,
+                  (content, sender): (String, String)| {
+                let key_pair = key_pair_edit_init.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let revision = crate::api::init_message_edit(
+                                &sender,
+                                ID::parse_str(&hub_id)?,
+                                ID::parse_str(&channel_id)?,
+                                ID::parse_str(&message_id)?,
+                                content,
+                            )
+                            .await?;
+                            Ok::<_, Error>(
+                                revision
+                                    .sign(&key_pair.secret_key, String::new)?
+                                    .compress(CompressionAlgorithm::ZIP)?
+                                    .to_armored_string(None)?,
+                            )
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            },
+        );
+
+    let edit_message_pub_key = public_key_filter.clone();
+
+    let edit_message = warp::any()
+        .and(warp::path!("v3" / "edit_message"))
+        .and(edit_message_pub_key)
+        .and(warp::body::bytes())
+        .and_then(move |client_public_key: SignedPublicKey, body: Bytes| {
+            let key_pair = key_pair_edit.clone();
+            let server = edit_message_server_arc.clone();
+            async move {
+                Ok::<_, Infallible>(
+                    async {
+                        let body = String::from_utf8(body.to_vec())?;
+                        let revision = Message::from_double_signed_verify(
+                            &body,
+                            &key_pair.public_key,
+                            &client_public_key,
+                        )?;
+                        let message_id = revision.revision_of.ok_or(Error::InvalidMessage)?;
+                        let user_id = hex::encode_upper(client_public_key.fingerprint());
+                        crate::api::edit_message(&user_id, &revision, body.clone()).await?;
+                        let _ = server.send(ServerNotification::MessageEdited(
+                            revision.hub_id,
+                            revision.channel_id,
+                            message_id,
+                            body,
+                            revision,
                         ));
                         Ok::<_, Error>(warp::reply())
                     }
@@ -260,6 +469,49 @@ pub async fn start(config: Config) -> Result {
             }
         });
 
+    let delete_message = warp::any()
+        .and(warp::path!(
+            "v3" / "delete_message" / String / String / String
+        ))
+        .and(signed_body_dm)
+        .and_then(
+            move |hub_id: String,
+                  channel_id: String,
+                  message_id: String,
+                  (_, sender): (String, String)| {
+                let key_pair = key_pair_delete.clone();
+                let server = delete_message_server_arc.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let (hub_id, channel_id, message_id) = (
+                                ID::parse_str(&hub_id)?,
+                                ID::parse_str(&channel_id)?,
+                                ID::parse_str(&message_id)?,
+                            );
+                            let tombstone = crate::api::delete_message(
+                                &sender,
+                                hub_id,
+                                channel_id,
+                                message_id,
+                                &key_pair.secret_key,
+                            )
+                            .await?;
+                            let _ = server.send(ServerNotification::MessageDeleted(
+                                hub_id,
+                                channel_id,
+                                message_id,
+                                tombstone.armoured_content.clone(),
+                            ));
+                            Ok::<_, Error>(tombstone.armoured_content)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            },
+        );
+
     let web_socket = warp::path!("v3" / "websocket")
         .and(public_key_filter)
         .and(warp::ws())
@@ -303,7 +555,12 @@ pub async fn start(config: Config) -> Result {
         .or(server_info)
         .or(web_socket)
         .or(send_message_init)
-        .or(send_message);
+        .or(send_message)
+        .or(edit_message_init)
+        .or(edit_message)
+        .or(delete_message)
+        .or(upload_attachment)
+        .or(download_attachment);
     warp::serve(routes)
         .run(
             config
@@ -5,24 +5,28 @@ use std::{
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
 
 use crate::{
-    channel::Channel,
+    channel::{Channel, ChannelCategory, ChannelMode, RetentionPolicy, UnreadCounts},
     check_name_validity, check_permission,
     error::Error,
     new_id,
     permission::{
         ChannelPermission, ChannelPermissions, HubPermission, HubPermissions, PermissionSetting,
     },
+    storage::{self, HubStore},
     Result, ID,
 };
 
-/// Relative path of the folder in which Hub information files (`${ID}`) files are stored.
-pub const HUB_INFO_FOLDER: &str = "data/hubs/info/";
-/// Relative path of the folder in which Hub data files are stored (channel directories and messages).
-pub const HUB_DATA_FOLDER: &str = "data/hubs/data/";
+/// Gets the path of the folder in which Hub information files (`${ID}`) files are stored, inside of the given data folder.
+pub fn hub_info_folder(data_dir: &str) -> String {
+    format!("{}/hubs/info/", data_dir)
+}
+
+/// Gets the path of the folder in which Hub data files are stored (channel directories and messages), inside of the given data folder.
+pub fn hub_data_folder(data_dir: &str) -> String {
+    format!("{}/hubs/data/", data_dir)
+}
 
 /// Represents a member of a hub that maps to a user.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -86,10 +90,7 @@ impl HubMember {
         permission: ChannelPermission,
         value: PermissionSetting,
     ) {
-        let channel_permissions = self
-            .channel_permissions
-            .entry(channel)
-            .or_insert_with(HashMap::new);
+        let channel_permissions = self.channel_permissions.entry(channel).or_default();
         channel_permissions.insert(permission, value);
     }
 
@@ -125,7 +126,7 @@ impl HubMember {
             };
         } else {
             for group in self.groups.iter() {
-                if let Some(group) = hub.groups.get(&group) {
+                if let Some(group) = hub.groups.get(group) {
                     if group.has_permission(permission) {
                         return true;
                     }
@@ -150,38 +151,48 @@ impl HubMember {
             // If the user has the `All` hub permission we do not need to check individual permissions, even for channels.
             return true;
         }
-        if let Some(channel) = self.channel_permissions.get(&channel) {
-            if let Some(value) = channel.get(&ChannelPermission::All) {
-                if value == &Some(true) {
-                    return true;
-                }
-            }
-            if let Some(value) = channel.get(&permission) {
-                match value {
-                    &Some(true) => {
-                        return true;
-                    }
-                    &Some(false) => {
-                        return false;
-                    }
-                    None => {
-                        if self.has_permission(permission.into(), hub) {
-                            return true;
-                        }
-                    }
-                };
+        let category = hub
+            .channels
+            .get(&channel)
+            .and_then(|channel| channel.category);
+        match channel_setting(&self.channel_permissions, channel, category, permission) {
+            Some(Some(value)) => value,
+            Some(None) => self.has_permission(permission.into(), hub),
+            None => self.groups.iter().any(|group| {
+                hub.groups.get(group).is_some_and(|group| {
+                    group.has_inherited_channel_permission(channel, category, permission)
+                })
+            }),
+        }
+    }
+}
+
+/// Finds the setting of a channel permission in a set of channel permission settings, any permission that is not set for the channel itself is taken from its category.
+/// Returns `None` if there are no settings for either the channel or its category, `Some(None)` if the setting is left to the matching hub permission and `Some(Some(false))` if there are settings but the permission is not among them.
+fn channel_setting(
+    settings: &HashMap<ID, ChannelPermissions>,
+    channel_id: ID,
+    category_id: Option<ID>,
+    permission: ChannelPermission,
+) -> Option<PermissionSetting> {
+    let mut result = None;
+    for scope in std::iter::once(channel_id).chain(category_id) {
+        if let Some(scope) = settings.get(&scope) {
+            if scope.get(&ChannelPermission::All) == Some(&Some(true)) {
+                return Some(Some(true));
             }
-        } else {
-            for group in self.groups.iter() {
-                if let Some(group) = hub.groups.get(&group) {
-                    if group.has_channel_permission(channel, permission) {
-                        return true;
+            match scope.get(&permission) {
+                Some(Some(value)) => return Some(Some(*value)),
+                Some(None) => result = Some(None),
+                None => {
+                    if result.is_none() {
+                        result = Some(Some(false));
                     }
                 }
             }
         }
-        false
     }
+    result
 }
 
 /// Represents a set of permissions that can be easily given to any hub member.
@@ -236,10 +247,7 @@ impl PermissionGroup {
         permission: ChannelPermission,
         value: PermissionSetting,
     ) {
-        let channel_permissions = self
-            .channel_permissions
-            .entry(channel_id)
-            .or_insert_with(HashMap::new);
+        let channel_permissions = self.channel_permissions.entry(channel_id).or_default();
         channel_permissions.insert(permission, value);
     }
 
@@ -266,26 +274,31 @@ impl PermissionGroup {
         false
     }
 
-    /// Checks if the group has a permission in a specific channel.
-    pub fn has_channel_permission(&self, channel_id: ID, permission: ChannelPermission) -> bool {
+    /// Checks if the group has a permission in a channel that is in the given category, any permission the group has no setting for in the channel itself is taken from the category.
+    pub fn has_inherited_channel_permission(
+        &self,
+        channel_id: ID,
+        category_id: Option<ID>,
+        permission: ChannelPermission,
+    ) -> bool {
         if self.has_all_permissions() {
             return true;
         }
-        if let Some(channel) = self.channel_permissions.get(&channel_id) {
-            if let Some(value) = channel.get(&ChannelPermission::All) {
-                if value == &Some(true) {
-                    return true;
-                }
-            }
-            if let Some(value) = channel.get(&permission) {
-                if value == &Some(true)
-                    || (value == &None && self.has_permission(permission.into()))
-                {
-                    return true;
-                }
-            }
+        match channel_setting(
+            &self.channel_permissions,
+            channel_id,
+            category_id,
+            permission,
+        ) {
+            Some(Some(value)) => value,
+            Some(None) => self.has_permission(permission.into()),
+            None => false,
         }
-        false
+    }
+
+    /// Checks if the group has a permission in a specific channel.
+    pub fn has_channel_permission(&self, channel_id: ID, permission: ChannelPermission) -> bool {
+        self.has_inherited_channel_permission(channel_id, None, permission)
     }
 }
 
@@ -314,6 +327,10 @@ pub struct Hub {
     pub id: ID,
     /// Time the hub was created in milliseconds since Unix Epoch.
     pub created: DateTime<Utc>,
+    /// Number of times the hub has been saved, used to detect changes that were made from outdated copies of the hub.
+    pub version: u64,
+    /// Map of channel categories to their IDs.
+    pub categories: HashMap<ID, ChannelCategory>,
 }
 
 impl Hub {
@@ -339,9 +356,20 @@ impl Hub {
             channels: HashMap::new(),
             members,
             created: Utc::now(),
+            version: 0,
+            categories: HashMap::new(),
         }
     }
 
+    /// Generates an ID that is not used by any channel or category in the hub.
+    fn new_channel_id(&self) -> ID {
+        let mut id = new_id();
+        while self.channels.contains_key(&id) || self.categories.contains_key(&id) {
+            id = new_id();
+        }
+        id
+    }
+
     /// Creates a new channel while checking that the given user has permission to do so.
     ///
     /// # Errors
@@ -352,17 +380,18 @@ impl Hub {
     /// * Failed to pass [`check_name_validity`].
     /// * The user it not in the hub.
     /// * The user does not have permission create new channels.
-    /// * Any of the reasons outlined in [`Channel::create_dir`].
-    pub async fn new_channel(&mut self, member_id: &str, name: String) -> Result<ID> {
+    ///
+    /// The channel's message storage is not created, see [`Channel::create_dir`].
+    pub fn new_channel(&mut self, member_id: &str, name: String) -> Result<ID> {
         check_name_validity(&name)?;
         let member = self.get_member(member_id)?;
         check_permission!(member, HubPermission::ManageChannels, self);
-        let mut id = new_id();
-        while self.channels.contains_key(&id) {
-            id = new_id();
-        }
-        let channel = Channel::new(name, id, self.id);
-        channel.create_dir().await?;
+        let id = self.new_channel_id();
+        let mut channel = Channel::new(name, id, self.id);
+        channel.position = self
+            .channels_in(None)
+            .last()
+            .map_or(0, |last| last.position + 1);
         {
             self.get_member_mut(member_id)?.set_channel_permission(
                 channel.id,
@@ -374,6 +403,204 @@ impl Hub {
         Ok(id)
     }
 
+    /// Gets the categories of the hub ordered by their position.
+    pub fn ordered_categories(&self) -> Vec<&ChannelCategory> {
+        let mut categories: Vec<&ChannelCategory> = self.categories.values().collect();
+        categories.sort_by_key(|category| (category.position, category.created, category.id));
+        categories
+    }
+
+    /// Gets the channels in a category, or the channels that are not in any category if `category` is `None`, ordered by their position.
+    pub fn channels_in(&self, category: Option<ID>) -> Vec<&Channel> {
+        let mut channels: Vec<&Channel> = self
+            .channels
+            .values()
+            .filter(|channel| channel.category == category)
+            .collect();
+        channels.sort_by_key(|channel| (channel.position, channel.created, channel.id));
+        channels
+    }
+
+    /// Gets the channels of the hub in the order they should be shown in: the channels that are not in a category followed by the channels of each category in order.
+    pub fn ordered_channels(&self) -> Vec<&Channel> {
+        let mut channels = self.channels_in(None);
+        for category in self.ordered_categories() {
+            channels.extend(self.channels_in(Some(category.id)));
+        }
+        channels
+    }
+
+    /// Creates a new channel category at the end of the hub's categories while checking that the given user has permission to do so.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * Failed to pass [`check_name_validity`].
+    /// * The user it not in the hub.
+    /// * The user does not have permission to manage channels.
+    pub fn new_category(&mut self, member_id: &str, name: String) -> Result<ID> {
+        check_name_validity(&name)?;
+        let member = self.get_member(member_id)?;
+        check_permission!(member, HubPermission::ManageChannels, self);
+        let id = self.new_channel_id();
+        let position = self
+            .ordered_categories()
+            .last()
+            .map_or(0, |last| last.position + 1);
+        self.categories
+            .insert(id, ChannelCategory::new(name, id, position));
+        Ok(id)
+    }
+
+    /// Renames a channel category while checking that the given user has permission to do so.
+    /// Returns the previous name of the category.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * Failed to pass [`check_name_validity`].
+    /// * The user it not in the hub.
+    /// * The user does not have permission to manage channels.
+    /// * The category does not exist.
+    pub fn rename_category(
+        &mut self,
+        member_id: &str,
+        category_id: ID,
+        new_name: String,
+    ) -> Result<String> {
+        check_name_validity(&new_name)?;
+        let member = self.get_member(member_id)?;
+        check_permission!(member, HubPermission::ManageChannels, self);
+        if let Some(category) = self.categories.get_mut(&category_id) {
+            Ok(mem::replace(&mut category.name, new_name))
+        } else {
+            Err(Error::CategoryNotFound)
+        }
+    }
+
+    /// Deletes a channel category while checking that the given user has permission to do so.
+    /// The channels in the category are moved to the end of the channels that are not in a category and the permission settings of the category are removed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user it not in the hub.
+    /// * The user does not have permission to manage channels.
+    /// * The category does not exist.
+    pub fn delete_category(&mut self, member_id: &str, category_id: ID) -> Result {
+        let member = self.get_member(member_id)?;
+        check_permission!(member, HubPermission::ManageChannels, self);
+        if self.categories.remove(&category_id).is_none() {
+            return Err(Error::CategoryNotFound);
+        }
+        let mut position = self
+            .channels_in(None)
+            .last()
+            .map_or(0, |last| last.position + 1);
+        let moved: Vec<ID> = self
+            .channels_in(Some(category_id))
+            .iter()
+            .map(|channel| channel.id)
+            .collect();
+        for id in moved {
+            if let Some(channel) = self.channels.get_mut(&id) {
+                channel.category = None;
+                channel.position = position;
+                position += 1;
+            }
+        }
+        for member in self.members.values_mut() {
+            member.channel_permissions.remove(&category_id);
+        }
+        for group in self.groups.values_mut() {
+            group.channel_permissions.remove(&category_id);
+        }
+        Ok(())
+    }
+
+    /// Moves a channel into a category, or out of any category if `category_id` is `None`, at the given position while checking that the given user has permission to do so.
+    /// The positions of the other channels in the category are updated to make room, positions past the end of the category put the channel last.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user it not in the hub.
+    /// * The user does not have permission to manage channels.
+    /// * The channel does not exist.
+    /// * The category does not exist.
+    pub fn move_channel(
+        &mut self,
+        member_id: &str,
+        channel_id: ID,
+        category_id: Option<ID>,
+        position: u32,
+    ) -> Result {
+        let member = self.get_member(member_id)?;
+        check_permission!(member, HubPermission::ManageChannels, self);
+        if !self.channels.contains_key(&channel_id) {
+            return Err(Error::ChannelNotFound);
+        }
+        if let Some(category_id) = category_id {
+            if !self.categories.contains_key(&category_id) {
+                return Err(Error::CategoryNotFound);
+            }
+        }
+        let mut order: Vec<ID> = self
+            .channels_in(category_id)
+            .iter()
+            .map(|channel| channel.id)
+            .filter(|id| id != &channel_id)
+            .collect();
+        order.insert((position as usize).min(order.len()), channel_id);
+        for (position, id) in order.into_iter().enumerate() {
+            if let Some(channel) = self.channels.get_mut(&id) {
+                channel.category = category_id;
+                channel.position = position as u32;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves a channel category to the given position while checking that the given user has permission to do so.
+    /// The positions of the other categories are updated to make room, positions past the end put the category last.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user it not in the hub.
+    /// * The user does not have permission to manage channels.
+    /// * The category does not exist.
+    pub fn move_category(&mut self, member_id: &str, category_id: ID, position: u32) -> Result {
+        let member = self.get_member(member_id)?;
+        check_permission!(member, HubPermission::ManageChannels, self);
+        if !self.categories.contains_key(&category_id) {
+            return Err(Error::CategoryNotFound);
+        }
+        let mut order: Vec<ID> = self
+            .ordered_categories()
+            .iter()
+            .map(|category| category.id)
+            .filter(|id| id != &category_id)
+            .collect();
+        order.insert((position as usize).min(order.len()), category_id);
+        for (position, id) in order.into_iter().enumerate() {
+            if let Some(category) = self.categories.get_mut(&id) {
+                category.position = position as u32;
+            }
+        }
+        Ok(())
+    }
+
     /// Gets a reference to the channel.
     /// Returns an error if the channel could not be found or the user did not have permission to view the channel.
     pub fn get_channel(&self, member_id: &str, channel_id: ID) -> Result<&Channel> {
@@ -446,13 +673,13 @@ impl Hub {
     /// * The user does not have permission to view the channel.
     /// * The user does not have permission to configure the channel.
     /// * The channel does not exist.
-    pub async fn change_channel_description(
+    pub fn change_channel_description(
         &mut self,
         user_id: &str,
         channel_id: ID,
         new_description: String,
     ) -> Result<String> {
-        if new_description.as_bytes().len() > crate::MAX_DESCRIPTION_SIZE {
+        if new_description.len() > crate::MAX_DESCRIPTION_SIZE {
             Err(Error::TooBig)
         } else if let Some(user) = self.members.get(user_id) {
             check_permission!(user, channel_id, ChannelPermission::Manage, self);
@@ -478,7 +705,7 @@ impl Hub {
     /// * The user does not have permission to view the channel.
     /// * The user does not have permission to configure the channel.
     /// * The channel does not exist.
-    pub async fn rename_channel(
+    pub fn rename_channel(
         &mut self,
         user_id: &str,
         channel_id: ID,
@@ -497,6 +724,162 @@ impl Hub {
         }
     }
 
+    /// Changes the retention policy of a channel while checking that the given user has permission to do so.
+    /// Returns the previous retention policy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user it not in the hub.
+    /// * The user does not have permission to view the channel.
+    /// * The user does not have permission to configure the channel.
+    /// * The channel does not exist.
+    pub fn set_channel_retention(
+        &mut self,
+        user_id: &str,
+        channel_id: ID,
+        retention: RetentionPolicy,
+    ) -> Result<RetentionPolicy> {
+        if let Some(user) = self.members.get(user_id) {
+            check_permission!(user, channel_id, ChannelPermission::Manage, self);
+            if let Some(channel) = self.channels.get_mut(&channel_id) {
+                Ok(mem::replace(&mut channel.retention, retention))
+            } else {
+                Err(Error::ChannelNotFound)
+            }
+        } else {
+            Err(Error::NotInHub)
+        }
+    }
+
+    /// Changes the mode of a channel while checking that the given user has permission to do so.
+    /// Returns the previous mode of the channel.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user it not in the hub.
+    /// * The user does not have permission to configure the channel.
+    /// * The channel does not exist.
+    /// * The mode is [`ChannelMode::Announcement`] and its permission group does not exist.
+    pub fn set_channel_mode(
+        &mut self,
+        user_id: &str,
+        channel_id: ID,
+        mode: ChannelMode,
+    ) -> Result<ChannelMode> {
+        if let Some(user) = self.members.get(user_id) {
+            check_permission!(user, channel_id, ChannelPermission::Manage, self);
+            if let Some(group) = mode.announcers() {
+                if !self.groups.contains_key(&group) {
+                    return Err(Error::GroupNotFound);
+                }
+            }
+            if let Some(channel) = self.channels.get_mut(&channel_id) {
+                Ok(mem::replace(&mut channel.mode, mode))
+            } else {
+                Err(Error::ChannelNotFound)
+            }
+        } else {
+            Err(Error::NotInHub)
+        }
+    }
+
+    /// Checks that a user can send messages in a channel: they need permission to write in it and the channel's mode has to allow them to.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user it not in the hub.
+    /// * The user does not have permission to write in the channel.
+    /// * The channel does not exist.
+    /// * The channel is archived.
+    /// * The channel is an announcement channel and the user is not in its group of announcers and cannot manage the channel.
+    pub fn check_can_send(&self, user_id: &str, channel_id: ID) -> Result {
+        let member = self.get_member(user_id)?;
+        check_permission!(member, channel_id, ChannelPermission::Write, self);
+        let channel = self
+            .channels
+            .get(&channel_id)
+            .ok_or(Error::ChannelNotFound)?;
+        match channel.mode {
+            ChannelMode::Normal => Ok(()),
+            ChannelMode::Archived => Err(Error::ChannelArchived),
+            ChannelMode::Announcement(group) => {
+                if member.groups.contains(&group)
+                    || member.has_channel_permission(channel_id, ChannelPermission::Manage, self)
+                {
+                    Ok(())
+                } else {
+                    Err(Error::NotAnnouncer)
+                }
+            }
+        }
+    }
+
+    /// Pins a message in a channel while checking that the given user has permission to do so.
+    /// Returns false if the message was already pinned, the message is not checked to exist.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user it not in the hub.
+    /// * The user does not have permission to configure the channel.
+    /// * The channel does not exist.
+    /// * The channel could not pin the message for any of the reasons outlined by [`Channel::pin`].
+    pub fn pin_message(&mut self, user_id: &str, channel_id: ID, message_id: ID) -> Result<bool> {
+        if let Some(user) = self.members.get(user_id) {
+            check_permission!(user, channel_id, ChannelPermission::Manage, self);
+            if let Some(channel) = self.channels.get_mut(&channel_id) {
+                channel.pin(message_id)
+            } else {
+                Err(Error::ChannelNotFound)
+            }
+        } else {
+            Err(Error::NotInHub)
+        }
+    }
+
+    /// Unpins a message in a channel while checking that the given user has permission to do so.
+    /// Returns false if the message was not pinned.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user it not in the hub.
+    /// * The user does not have permission to configure the channel.
+    /// * The channel does not exist.
+    pub fn unpin_message(&mut self, user_id: &str, channel_id: ID, message_id: ID) -> Result<bool> {
+        if let Some(user) = self.members.get(user_id) {
+            check_permission!(user, channel_id, ChannelPermission::Manage, self);
+            if let Some(channel) = self.channels.get_mut(&channel_id) {
+                Ok(channel.unpin(message_id))
+            } else {
+                Err(Error::ChannelNotFound)
+            }
+        } else {
+            Err(Error::NotInHub)
+        }
+    }
+
+    /// Unpins messages that were deleted from a channel, no permissions are checked since the messages no longer exist.
+    /// Returns false if none of the messages were pinned or the channel does not exist.
+    pub fn unpin_deleted(&mut self, channel_id: ID, message_ids: &[ID]) -> bool {
+        self.channels
+            .get_mut(&channel_id)
+            .is_some_and(|channel| channel.unpin_all(message_ids))
+    }
+
     /// Deletes a channel while checking that the given user has permission to do so.
     ///
     /// # Errors
@@ -508,7 +891,7 @@ impl Hub {
     /// * The channel does not exist.
     /// * THe user does not have permission to view the channel.
     /// * The user does not have permission to delete the channel.
-    pub async fn delete_channel(&mut self, user_id: &str, channel_id: ID) -> Result {
+    pub fn delete_channel(&mut self, user_id: &str, channel_id: ID) -> Result {
         if let Some(user) = self.members.get(user_id) {
             check_permission!(user, HubPermission::ManageChannels, self);
             if self.channels.remove(&channel_id).is_some() {
@@ -521,41 +904,38 @@ impl Hub {
         }
     }
 
-    /// Gets the file path to be used for storing the hub's data.
-    pub fn get_info_path(&self) -> String {
-        format!("{}{:x}", HUB_INFO_FOLDER, self.id.as_u128())
-    }
-
-    /// Gets the path of the directory in which channel folders should be stored.
-    pub fn get_data_path(&self) -> String {
-        format!("{}{:x}/", HUB_DATA_FOLDER, self.id.as_u128())
-    }
-
-    /// Saves the hub's data to disk.
+    /// Saves the hub's data using the configured [`crate::storage::HubStore`], incrementing its version.
+    /// This bypasses the [`crate::hub_cache`], use [`crate::hub_cache::save`] to keep the cached copy up to date.
     ///
     /// # Errors
     ///
     /// This function will return an error in the following situations, but is not
     /// limited to just these cases:
     ///
+    /// * The hub was saved by someone else since this copy was loaded, in which case [`Error::Conflict`] is returned.
     /// * The hub data could not be serialized.
     /// * The hub info folder does not exist and could not be created.
     /// * The data could not be written to the disk.
-    pub async fn save(&self) -> Result {
-        tokio::fs::create_dir_all(HUB_INFO_FOLDER).await?;
-        let mut file = tokio::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(self.get_info_path())
-            .await?;
-        let bytes = bincode::serialize(self)?;
-        let mut buf: &[u8] = bytes.as_slice();
-        file.write_buf(&mut buf).await?;
-        file.flush().await?;
-        Ok(())
+    pub async fn save(&mut self) -> Result {
+        self.save_to(storage::hub_store().as_ref()).await
     }
 
-    /// Loads a hub's data given its ID.
+    /// Saves the hub's data in the given store, incrementing its version, see [`Hub::save`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error for the same reasons as [`Hub::save`].
+    pub async fn save_to(&mut self, store: &dyn HubStore) -> Result {
+        self.version += 1;
+        let result = store.save_hub(self).await;
+        if result.is_err() {
+            self.version -= 1;
+        }
+        result
+    }
+
+    /// Loads a hub's data given its ID using the configured [`crate::storage::HubStore`].
+    /// This always reads from storage, use [`crate::hub_cache::get`] to avoid doing so on every request.
     ///
     /// # Errors
     ///
@@ -565,15 +945,7 @@ impl Hub {
     /// * There is no hub with that ID.
     /// * The hub's data file was corrupt and could not be deserialized.
     pub async fn load(id: ID) -> Result<Self> {
-        let filename = format!("{}{:x}", HUB_INFO_FOLDER, id.as_u128());
-        let path = std::path::Path::new(&filename);
-        if !path.exists() {
-            return Err(Error::HubNotFound);
-        }
-        let mut file = tokio::fs::OpenOptions::new().read(true).open(path).await?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf).await?;
-        Ok(bincode::deserialize(&buf)?)
+        storage::hub_store().load_hub(id).await
     }
 
     /// Adds a user to a hub, creating and returning the resulting hub member.
@@ -682,6 +1054,25 @@ impl Hub {
         }
     }
 
+    /// Adds up the unread counts of every channel the given user can view, see [`Channel::unread_counts`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The given user is not in the hub.
+    /// * The unread messages of one of the channels could not be counted.
+    pub async fn unread_counts(&self, user_id: &str) -> Result<UnreadCounts> {
+        let mut total = UnreadCounts::default();
+        for channel in self.get_channels_for_user(user_id)?.values() {
+            let counts = channel.unread_counts(user_id).await?;
+            total.unread += counts.unread;
+            total.mentions += counts.mentions;
+        }
+        Ok(total)
+    }
+
     /// Returns a hub object with only the items that the given user is allowed to view.
     /// Only hides channels that the user does not have permission to view.
     ///
@@ -697,17 +1088,221 @@ impl Hub {
 
 #[cfg(test)]
 mod test {
-    use super::{Hub, ID};
+    use super::{Hub, PermissionGroup, ID};
+    use crate::{
+        channel::ChannelMode,
+        error::Error,
+        new_id,
+        permission::{ChannelPermission, HubPermission},
+        storage::HubStore,
+        test_util::TempDir,
+    };
 
     #[tokio::test]
     async fn save_load() {
-        let id = ID::nil();
+        let root = TempDir::new("hub");
+        let store = root.store();
+        let id = new_id();
         let mut hub = Hub::new("test_hub".to_string(), id, id.to_string());
-        let _ = tokio::fs::remove_file(&hub.get_info_path()).await;
         hub.new_channel(&id.to_string(), "test_channel".to_string())
+            .expect("Failed to add a channel to the test hub.");
+        hub.save_to(&store).await.expect("Failed to save the hub.");
+        store
+            .load_hub(hub.id)
             .await
+            .expect("Failed to load the hub.");
+    }
+
+    #[tokio::test]
+    async fn stale_save() {
+        let root = TempDir::new("hub");
+        let store = root.store();
+        let id = new_id();
+        let mut hub = Hub::new("test_hub".to_string(), id, id.to_string());
+        hub.save_to(&store).await.expect("Failed to save the hub.");
+        let mut stale = store.load_hub(id).await.expect("Failed to load the hub.");
+        hub.name = "renamed".to_string();
+        hub.save_to(&store).await.expect("Failed to save the hub.");
+        stale.description = "outdated".to_string();
+        assert!(matches!(stale.save_to(&store).await, Err(Error::Conflict)));
+        assert_eq!(stale.version, 1);
+        let loaded = store.load_hub(id).await.expect("Failed to load the hub.");
+        assert_eq!(loaded.version, 2);
+        assert_eq!(loaded.name, "renamed");
+    }
+
+    #[test]
+    fn pinned_messages() {
+        let owner = ID::nil().to_string();
+        let mut hub = Hub::new("test_hub".to_string(), ID::nil(), owner.clone());
+        let channel_id = hub
+            .new_channel(&owner, "test_channel".to_string())
             .expect("Failed to add a channel to the test hub.");
-        hub.save().await.expect("Failed to save the hub.");
-        Hub::load(hub.id).await.expect("Failed to load the hub.");
+        let message_id = new_id();
+        assert!(matches!(
+            hub.pin_message("stranger", channel_id, message_id),
+            Err(Error::NotInHub)
+        ));
+        assert!(hub
+            .pin_message(&owner, channel_id, message_id)
+            .expect("Failed to pin a message."));
+        assert!(!hub
+            .pin_message(&owner, channel_id, message_id)
+            .expect("Failed to pin a message."));
+        for _ in 1..crate::MAX_PINNED_MESSAGES {
+            hub.pin_message(&owner, channel_id, new_id())
+                .expect("Failed to pin a message.");
+        }
+        assert!(matches!(
+            hub.pin_message(&owner, channel_id, new_id()),
+            Err(Error::TooManyPins)
+        ));
+        assert!(hub
+            .unpin_message(&owner, channel_id, message_id)
+            .expect("Failed to unpin a message."));
+        assert!(!hub
+            .unpin_message(&owner, channel_id, message_id)
+            .expect("Failed to unpin a message."));
+        let deleted = hub.channels[&channel_id].pinned[..2].to_vec();
+        assert!(hub.unpin_deleted(channel_id, &[deleted[0], deleted[1], new_id()]));
+        assert!(!hub.unpin_deleted(channel_id, &deleted));
+        assert_eq!(
+            hub.channels[&channel_id].pinned.len(),
+            crate::MAX_PINNED_MESSAGES - 3
+        );
+    }
+
+    #[test]
+    fn categories() {
+        let owner = ID::nil().to_string();
+        let mut hub = Hub::new("test_hub".to_string(), ID::nil(), owner.clone());
+        hub.user_join("member".to_string())
+            .expect("Failed to add a member to the test hub.");
+        let channels: Vec<ID> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                hub.new_channel(&owner, name.to_string())
+                    .expect("Failed to add a channel to the test hub.")
+            })
+            .collect();
+        assert!(matches!(
+            hub.new_category("member", "nope".to_string()),
+            Err(Error::MissingHubPermission(HubPermission::ManageChannels))
+        ));
+        let first = hub
+            .new_category(&owner, "first".to_string())
+            .expect("Failed to add a category.");
+        let second = hub
+            .new_category(&owner, "second".to_string())
+            .expect("Failed to add a category.");
+        hub.move_channel(&owner, channels[2], Some(first), 0)
+            .expect("Failed to move a channel.");
+        hub.move_channel(&owner, channels[0], Some(first), 0)
+            .expect("Failed to move a channel.");
+        hub.move_channel(&owner, channels[1], Some(second), 10)
+            .expect("Failed to move a channel.");
+        let order = |hub: &Hub| {
+            hub.ordered_channels()
+                .iter()
+                .map(|channel| channel.id)
+                .collect::<Vec<ID>>()
+        };
+        assert_eq!(order(&hub), vec![channels[0], channels[2], channels[1]]);
+        hub.move_category(&owner, second, 0)
+            .expect("Failed to move a category.");
+        assert_eq!(order(&hub), vec![channels[1], channels[0], channels[2]]);
+        assert!(matches!(
+            hub.move_channel(&owner, channels[0], Some(new_id()), 0),
+            Err(Error::CategoryNotFound)
+        ));
+
+        let can_read = |hub: &Hub, channel: ID| {
+            hub.members["member"].has_channel_permission(channel, ChannelPermission::Read, hub)
+        };
+        assert!(!can_read(&hub, channels[0]));
+        let member = hub.get_member_mut("member").unwrap();
+        member.set_channel_permission(first, ChannelPermission::Read, Some(true));
+        member.set_channel_permission(channels[2], ChannelPermission::Read, Some(false));
+        assert!(can_read(&hub, channels[0]));
+        assert!(!can_read(&hub, channels[1]));
+        assert!(!can_read(&hub, channels[2]));
+        let can_write = |hub: &Hub, user: &str, channel: ID| {
+            hub.members[user].has_channel_permission(channel, ChannelPermission::Write, hub)
+        };
+        assert!(!can_write(&hub, "member", channels[2]));
+        hub.get_member_mut("member")
+            .unwrap()
+            .set_channel_permission(first, ChannelPermission::Write, Some(true));
+        assert!(can_write(&hub, "member", channels[2]));
+
+        hub.user_join("other".to_string())
+            .expect("Failed to add a member to the test hub.");
+        let everyone = hub.default_group;
+        let group = hub.groups.get_mut(&everyone).unwrap();
+        group.set_channel_permission(first, ChannelPermission::Write, Some(true));
+        group.set_channel_permission(channels[0], ChannelPermission::Manage, Some(false));
+        group.set_channel_permission(channels[2], ChannelPermission::Write, Some(false));
+        assert!(can_write(&hub, "other", channels[0]));
+        assert!(!can_write(&hub, "other", channels[2]));
+        assert!(!can_write(&hub, "other", channels[1]));
+
+        hub.delete_category(&owner, first)
+            .expect("Failed to delete a category.");
+        assert!(!can_read(&hub, channels[0]));
+        assert_eq!(hub.channels_in(None).len(), 2);
+        assert_eq!(order(&hub), vec![channels[0], channels[2], channels[1]]);
+    }
+
+    #[test]
+    fn channel_modes() {
+        let owner = ID::nil().to_string();
+        let mut hub = Hub::new("test_hub".to_string(), ID::nil(), owner.clone());
+        hub.user_join("member".to_string())
+            .expect("Failed to add a member to the test hub.");
+        let channel_id = hub
+            .new_channel(&owner, "news".to_string())
+            .expect("Failed to add a channel to the test hub.");
+        let everyone = hub.default_group;
+        let group = hub.groups.get_mut(&everyone).unwrap();
+        group.set_channel_permission(channel_id, ChannelPermission::Read, Some(true));
+        group.set_channel_permission(channel_id, ChannelPermission::Write, Some(true));
+        assert!(hub.check_can_send("member", channel_id).is_ok());
+        assert!(matches!(
+            hub.set_channel_mode("member", channel_id, ChannelMode::Archived),
+            Err(Error::MissingChannelPermission(ChannelPermission::Manage))
+        ));
+        assert!(matches!(
+            hub.set_channel_mode(&owner, channel_id, ChannelMode::Announcement(new_id())),
+            Err(Error::GroupNotFound)
+        ));
+
+        let announcers = PermissionGroup::new("announcers".to_string(), new_id());
+        hub.groups.insert(announcers.id, announcers.clone());
+        hub.set_channel_mode(&owner, channel_id, ChannelMode::Announcement(announcers.id))
+            .expect("Failed to change the channel's mode.");
+        assert!(matches!(
+            hub.check_can_send("member", channel_id),
+            Err(Error::NotAnnouncer)
+        ));
+        assert!(hub.check_can_send(&owner, channel_id).is_ok());
+        let mut member = hub.members.remove("member").unwrap();
+        member.join_group(hub.groups.get_mut(&announcers.id).unwrap());
+        hub.members.insert(member.user_id.clone(), member);
+        assert!(hub.check_can_send("member", channel_id).is_ok());
+
+        let old = hub
+            .set_channel_mode(&owner, channel_id, ChannelMode::Archived)
+            .expect("Failed to change the channel's mode.");
+        assert_eq!(old, ChannelMode::Announcement(announcers.id));
+        for user in ["member", owner.as_str()].iter() {
+            assert!(matches!(
+                hub.check_can_send(user, channel_id),
+                Err(Error::ChannelArchived)
+            ));
+        }
+        assert!(matches!(
+            hub.pin_message(&owner, channel_id, new_id()),
+            Err(Error::ChannelArchived)
+        ));
     }
 }
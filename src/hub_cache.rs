@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+
+use crate::{hub::Hub, storage, Error, Result, ID};
+
+/// How long a hub can go without being used before it is removed from the cache.
+pub const HUB_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How often the cache checks for idle hubs to remove.
+pub const HUB_EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of times a change to a hub is attempted before giving up on it because the hub kept being saved without going through the cache.
+const MAX_HUB_SAVE_ATTEMPTS: usize = 8;
+
+/// A hub kept in memory along with the last time it was used.
+struct CachedHub {
+    hub: Arc<Hub>,
+    last_used: Instant,
+}
+
+/// Keeps recently used hubs in memory so that permission checks don't have to load the hub from storage.
+/// Hubs are loaded from and saved to the configured [`crate::storage::HubStore`] by the callers of [`get`] and [`save`] without holding the cache's lock, which is only taken to look up or replace a cached hub, so slow storage for one hub does not hold up requests for the others.
+/// Changes made through [`modify`] are applied one at a time per hub, conflicting saves made without it are caught by the store, which checks the version of every hub it saves.
+#[derive(Default)]
+struct HubCache {
+    hubs: HashMap<ID, CachedHub>,
+    locks: HashMap<ID, Arc<tokio::sync::Mutex<()>>>,
+}
+
+impl HubCache {
+    /// Gets a cached hub, if there is one.
+    fn get(&mut self, hub_id: ID) -> Option<Arc<Hub>> {
+        self.hubs.get_mut(&hub_id).map(|cached| {
+            cached.last_used = Instant::now();
+            cached.hub.clone()
+        })
+    }
+
+    /// Caches a hub that was just loaded or saved unless a newer version of it is already cached, returns the hub that ends up cached.
+    fn insert(&mut self, hub: Arc<Hub>) -> Arc<Hub> {
+        let cached = self.hubs.entry(hub.id).or_insert_with(|| CachedHub {
+            hub: hub.clone(),
+            last_used: Instant::now(),
+        });
+        if cached.hub.version < hub.version {
+            cached.hub = hub;
+        }
+        cached.last_used = Instant::now();
+        cached.hub.clone()
+    }
+
+    /// Gets the lock that must be held while changing a hub.
+    fn lock(&mut self, hub_id: ID) -> Arc<tokio::sync::Mutex<()>> {
+        self.locks.entry(hub_id).or_default().clone()
+    }
+
+    /// Removes the hubs that have not been used for [`HUB_IDLE_TIMEOUT`] as of `now`, along with the locks of hubs that are no longer cached and are not being changed.
+    fn evict_idle(&mut self, now: Instant) {
+        self.hubs
+            .retain(|_, cached| now.saturating_duration_since(cached.last_used) < HUB_IDLE_TIMEOUT);
+        let hubs = &self.hubs;
+        self.locks
+            .retain(|hub_id, lock| hubs.contains_key(hub_id) || Arc::strong_count(lock) > 1);
+    }
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<HubCache> = Mutex::new(HubCache::default());
+}
+
+/// Locks the hub cache, the lock must never be held across an await.
+fn cache() -> MutexGuard<'static, HubCache> {
+    CACHE.lock().expect("Hub cache lock was poisoned.")
+}
+
+/// Gets a hub from the cache, loading it from storage if needed.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn get(hub_id: ID) -> Result<Arc<Hub>> {
+    if let Some(hub) = cache().get(hub_id) {
+        return Ok(hub);
+    }
+    let hub = Arc::new(Hub::load(hub_id).await?);
+    Ok(cache().insert(hub))
+}
+
+/// Applies a change to a copy of a hub and saves it, returning the result of the change.
+/// Changes to the same hub are applied one after the other, if the hub was saved without going through the cache in the meantime the change is applied again to a freshly loaded copy of the hub.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The change returned an error.
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The hub was saved without going through the cache on every attempt, in which case [`Error::Conflict`] is returned.
+pub async fn modify<T, F>(hub_id: ID, mut change: F) -> Result<T>
+where
+    F: FnMut(&mut Hub) -> Result<T>,
+{
+    let lock = cache().lock(hub_id);
+    let _guard = lock.lock().await;
+    for _ in 0..MAX_HUB_SAVE_ATTEMPTS {
+        let mut hub = Hub::clone(&*get(hub_id).await?);
+        let result = change(&mut hub)?;
+        match save(&mut hub).await {
+            Ok(()) => return Ok(result),
+            Err(Error::Conflict) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(Error::Conflict)
+}
+
+/// Increments the version of a hub, saves it and replaces the cached copy with it.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The hub was saved by someone else since this copy was gotten, in which case [`Error::Conflict`] is returned.
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+pub async fn save(hub: &mut Hub) -> Result {
+    if let Err(err) = hub.save().await {
+        if let Error::Conflict = err {
+            evict(hub.id);
+        }
+        return Err(err);
+    }
+    cache().insert(Arc::new(hub.clone()));
+    Ok(())
+}
+
+/// Removes a hub from the cache so that it is loaded from storage again the next time it is needed.
+/// Used after a hub was written to storage without going through [`save`].
+pub fn evict(hub_id: ID) {
+    cache().hubs.remove(&hub_id);
+}
+
+/// Removes the hubs that have not been used for [`HUB_IDLE_TIMEOUT`] from the cache every [`HUB_EVICTION_INTERVAL`], meant to be spawned as a background task.
+pub async fn evict_idle_periodically() {
+    let mut interval = tokio::time::interval(HUB_EVICTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        cache().evict_idle(Instant::now());
+    }
+}
+
+/// Deletes a hub from storage and from the cache.
+///
+/// # Errors
+///
+/// This function will return an error if the hub could not be deleted from storage.
+pub async fn delete(hub_id: ID) -> Result {
+    storage::hub_store().delete_hub(hub_id).await?;
+    evict(hub_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Instant};
+
+    use super::{HubCache, HUB_IDLE_TIMEOUT};
+    use crate::{error::Error, hub::Hub, new_id};
+
+    #[tokio::test]
+    async fn cached_saves() {
+        let id = new_id();
+        let mut hub = Hub::new("test_hub".to_string(), id, id.to_string());
+        super::save(&mut hub)
+            .await
+            .expect("Failed to save the hub.");
+        let first = super::get(id).await.expect("Failed to get the hub.");
+        let second = super::get(id).await.expect("Failed to get the hub.");
+        assert!(Arc::ptr_eq(&first, &second));
+        let mut stale = Hub::clone(&first);
+        hub.name = "renamed".to_string();
+        super::save(&mut hub)
+            .await
+            .expect("Failed to save the hub.");
+        assert_eq!(super::get(id).await.unwrap().name, "renamed");
+        assert!(matches!(
+            super::save(&mut stale).await,
+            Err(Error::Conflict)
+        ));
+        super::delete(id).await.expect("Failed to delete the hub.");
+        assert!(matches!(super::get(id).await, Err(Error::HubNotFound)));
+    }
+
+    #[test]
+    fn idle_eviction() {
+        let mut cache = HubCache::default();
+        let (read, changed) = (new_id(), new_id());
+        for id in [read, changed].iter() {
+            cache.insert(Arc::new(Hub::new(
+                "test_hub".to_string(),
+                *id,
+                id.to_string(),
+            )));
+        }
+        let lock = cache.lock(changed);
+        // Hubs that are only ever read are evicted as well.
+        assert!(cache.get(read).is_some());
+        cache.evict_idle(Instant::now());
+        assert_eq!(cache.hubs.len(), 2);
+        cache.evict_idle(Instant::now() + HUB_IDLE_TIMEOUT);
+        assert!(cache.hubs.is_empty());
+        assert!(cache.locks.contains_key(&changed));
+        drop(lock);
+        cache.evict_idle(Instant::now() + HUB_IDLE_TIMEOUT);
+        assert!(cache.locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_modifications() {
+        let id = new_id();
+        let mut hub = Hub::new("test_hub".to_string(), id, id.to_string());
+        super::save(&mut hub)
+            .await
+            .expect("Failed to save the hub.");
+        let version = hub.version;
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                tokio::spawn(super::modify(id, move |hub| {
+                    hub.user_join(format!("user_{}", i)).map(|_| ())
+                }))
+            })
+            .collect();
+        for task in tasks {
+            task.await
+                .expect("Task panicked.")
+                .expect("Failed to change the hub.");
+        }
+        let saved = Hub::load(id).await.expect("Failed to load the hub.");
+        assert_eq!(saved.version, version + 16);
+        assert!((0..16).all(|i| saved.members.contains_key(&format!("user_{}", i))));
+        super::delete(id).await.expect("Failed to delete the hub.");
+    }
+}
@@ -5,22 +5,37 @@ pub use pgp;
 
 /// Public API for performing user actions, should be used for creating API implementations like the HTTP API or similar.
 pub mod api;
+/// Export and import of whole hubs as archives.
+pub mod archive;
+/// Content-addressed storage of the files attached to messages.
+pub mod attachment;
 /// Message storage and retreival for channels.
 pub mod channel;
 /// Various objects for storing configuration.
 pub mod config;
+/// Direct conversations between users outside of hubs.
+pub mod conversation;
 /// Errors
 pub mod error;
+/// Offline integrity checks and repairs of the data folder.
+pub mod fsck;
 /// GraphQL model definition.
 pub mod graphql_model;
 /// Definition of the HTTP API.
 pub mod httpapi;
 /// Hubs, permission management, channel management and member management.
 pub mod hub;
+/// In memory cache of recently used hubs.
+pub mod hub_cache;
 /// Permissions are defined here.
 pub mod permission;
 /// Server implementation.
 pub mod server;
+/// Pluggable storage backends for hubs and messages.
+pub mod storage;
+/// Fixtures shared by the tests of several modules.
+#[cfg(test)]
+mod test_util;
 /// Definition of the WebSocket API.
 pub mod websocket;
 
@@ -38,12 +53,36 @@ pub const MAX_DESCRIPTION_SIZE: usize = 8192;
 /// Maximum size of a message in bytes. Clients should be able to accept larger and smaller values.
 pub const MESSAGE_MAX_SIZE: usize = 8192;
 
+/// Maximum size of a message reaction in bytes, enough for emojis made up of several code points.
+pub const MAX_REACTION_SIZE: usize = 32;
+
+/// Maximum size of an attachment in bytes.
+pub const MAX_ATTACHMENT_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maximum number of attachments in a single message.
+pub const MAX_MESSAGE_ATTACHMENTS: usize = 10;
+
+/// Default maximum total size in bytes of the attachments uploaded to a hub, can be changed in the configuration.
+pub const HUB_ATTACHMENT_QUOTA: u64 = 1024 * 1024 * 1024;
+
+/// Maximum number of messages that can be pinned in a channel at once.
+pub const MAX_PINNED_MESSAGES: usize = 50;
+
+/// Maximum number of users in a direct conversation, including the user who started it.
+pub const MAX_CONVERSATION_PARTICIPANTS: usize = 10;
+
+/// Maximum number of messages that can be listed in one page, larger requests are clamped to this.
+pub const MAX_MESSAGE_PAGE_SIZE: usize = 100;
+
+/// Maximum number of unread messages counted in a channel, clients should show counts this high as "at least this many".
+pub const MAX_UNREAD_COUNT: usize = 1000;
+
 /// How long to wait before commiting new messages to the tantivy search engine in milliseconds, this takes a lot of time, which is why it should be done only periodically.
 pub const TANTIVY_COMMIT_THRESHOLD: u8 = 10;
 
 /// Checks if a name is valid (not too long and only allowed characters).
 pub fn is_valid_name(name: &str) -> bool {
-    name.as_bytes().len() <= MAX_NAME_SIZE
+    name.len() <= MAX_NAME_SIZE
 }
 
 /// Wraps `is_valid_name` to return a `Result<()>`.
@@ -61,6 +100,24 @@ pub fn check_name_validity(name: &str) -> Result {
     }
 }
 
+/// Checks if a reaction is valid, it must not be empty or too big and cannot contain whitespace or control characters.
+///
+/// # Errors
+///
+/// This function returns an error for any of the following reasons:
+///
+/// * The reaction is too big (maximum in bytes defined by [`MAX_REACTION_SIZE`]).
+/// * The reaction is empty or contains whitespace or control characters.
+pub fn check_reaction_validity(emoji: &str) -> Result {
+    if emoji.len() > MAX_REACTION_SIZE {
+        Err(Error::TooBig)
+    } else if emoji.is_empty() || emoji.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        Err(Error::InvalidText)
+    } else {
+        Ok(())
+    }
+}
+
 /// Checks that a hub member has a given permission and returns an error if it doesn't.
 #[macro_export]
 macro_rules! check_permission {
@@ -15,9 +15,154 @@ fn load_config(path: &str) -> wicrs_server::config::Config {
     }
 }
 
-/// Main function, loads config and starts a server for the HTTP API.
+/// Copies all hubs and messages from the data folder into the SQLite database, the database path is taken from the first argument if given, otherwise from the config.
+async fn migrate_sqlite(
+    config: &wicrs_server::config::Config,
+    path: Option<String>,
+) -> wicrs_server::error::Result {
+    use wicrs_server::{config::StorageBackend, storage};
+    let path = if let Some(path) = path {
+        path
+    } else if let StorageBackend::Sqlite { path } = &config.storage {
+        path.clone()
+    } else {
+        println!("No SQLite database path given and the config does not use SQLite storage.");
+        exit(1);
+    };
+    let from = storage::FsStore::in_dir(&config.data_dir);
+    let to = storage::SqliteStore::open(&path)?;
+    let count = storage::migrate(&from, &from, &to, &to).await?;
+    println!("Migrated {} hubs to {}.", count, path);
+    Ok(())
+}
+
+/// Compresses the old message files of the hub whose ID is given, files are compressed if they are older than the configured `compress_after_days` or if that is not set, if they are from before today.
+async fn compact(
+    config: &wicrs_server::config::Config,
+    hub_id: Option<String>,
+) -> wicrs_server::error::Result {
+    use wicrs_server::storage;
+    let hub_id = if let Some(hub_id) = hub_id {
+        wicrs_server::ID::parse_str(&hub_id)?
+    } else {
+        println!("No hub ID given.");
+        exit(1);
+    };
+    storage::init(config)?;
+    let count = storage::compact_hub(hub_id, config.compress_after_days.unwrap_or(0)).await?;
+    println!("Compressed {} message files.", count);
+    Ok(())
+}
+
+/// Writes an archive of the hub whose ID is given to the given path, or to `<hub id>.tar` if no path is given.
+async fn export(
+    config: &wicrs_server::config::Config,
+    hub_id: Option<String>,
+    path: Option<String>,
+) -> wicrs_server::error::Result {
+    let hub_id = if let Some(hub_id) = hub_id {
+        wicrs_server::ID::parse_str(&hub_id)?
+    } else {
+        println!("No hub ID given.");
+        exit(1);
+    };
+    wicrs_server::storage::init(config)?;
+    let path = path.unwrap_or_else(|| format!("{}.tar", hub_id));
+    let archive = wicrs_server::archive::export_hub(&config.data_dir, hub_id).await?;
+    tokio::fs::write(&path, archive).await?;
+    println!("Exported hub {} to {}.", hub_id, path);
+    Ok(())
+}
+
+/// Restores a hub from the archive at the given path, under the given hub ID if there is one.
+async fn import(
+    config: &wicrs_server::config::Config,
+    path: Option<String>,
+    hub_id: Option<String>,
+) -> wicrs_server::error::Result {
+    let path = if let Some(path) = path {
+        path
+    } else {
+        println!("No archive path given.");
+        exit(1);
+    };
+    let hub_id = hub_id
+        .map(|hub_id| wicrs_server::ID::parse_str(&hub_id))
+        .transpose()?;
+    wicrs_server::storage::init(config)?;
+    let archive = tokio::fs::read(&path).await?;
+    let hub_id = wicrs_server::archive::import_hub(&config.data_dir, &archive, hub_id).await?;
+    println!("Imported hub {} from {}.", hub_id, path);
+    Ok(())
+}
+
+/// Deletes the attachments that are no longer used by any message.
+async fn collect_garbage(config: &wicrs_server::config::Config) -> wicrs_server::error::Result {
+    wicrs_server::storage::init(config)?;
+    let count =
+        wicrs_server::attachment::collect_garbage(&config.data_dir, chrono::Utc::now()).await?;
+    println!("Deleted {} unused attachments.", count);
+    Ok(())
+}
+
+/// Rewrites every hub and message file that was written by an older version of the server in the current format.
+async fn migrate_all(config: &wicrs_server::config::Config) -> wicrs_server::error::Result {
+    wicrs_server::storage::init(config)?;
+    let (hubs, files) = wicrs_server::storage::upgrade_all().await?;
+    println!("Upgraded {} hubs and {} message files.", hubs, files);
+    Ok(())
+}
+
+/// Checks the hubs and messages in the data folder, repairing the problems that can be repaired if the argument is `--repair`. Exits with code 1 if any problems are left.
+async fn fsck(
+    config: &wicrs_server::config::Config,
+    flag: Option<String>,
+) -> wicrs_server::error::Result {
+    let repair = match flag.as_deref() {
+        None => false,
+        Some("--repair") => true,
+        Some(flag) => {
+            println!("Unknown flag \"{}\".", flag);
+            exit(1);
+        }
+    };
+    wicrs_server::storage::init(config)?;
+    let report = wicrs_server::fsck::check(&config.data_dir, repair).await?;
+    for problem in report.problems.iter() {
+        println!("{}", problem);
+    }
+    println!(
+        "Checked {} hubs and {} messages, {} messages could not be verified.",
+        report.hubs, report.messages, report.unverified
+    );
+    println!(
+        "Found {} problems, repaired {}.",
+        report.problems.len(),
+        report.repaired
+    );
+    if report.repaired < report.problems.len() {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// Main function, loads config and starts a server for the HTTP API, or runs the maintenance command given as the first argument.
 #[tokio::main]
 async fn main() -> wicrs_server::error::Result {
     let config = load_config("config.json");
-    wicrs_server::httpapi::start(config).await
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => wicrs_server::httpapi::start(config).await,
+        Some("migrate-sqlite") => migrate_sqlite(&config, args.next()).await,
+        Some("migrate-all") => migrate_all(&config).await,
+        Some("compact") => compact(&config, args.next()).await,
+        Some("collect-garbage") => collect_garbage(&config).await,
+        Some("export") => export(&config, args.next(), args.next()).await,
+        Some("import") => import(&config, args.next(), args.next()).await,
+        Some("fsck") => fsck(&config, args.next()).await,
+        Some(command) => {
+            println!("Unknown command \"{}\".", command);
+            exit(1);
+        }
+    }
 }
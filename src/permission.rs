@@ -58,6 +58,7 @@ pub enum HubPermission {
     Kick,
     Ban,
     Unban,
+    ManageMessages,
 }
 
 impl Display for HubPermission {
@@ -73,6 +74,7 @@ impl Display for HubPermission {
             HubPermission::Kick => "KICK",
             HubPermission::Ban => "BAN",
             HubPermission::Unban => "UNBAN",
+            HubPermission::ManageMessages => "MANAGE_MESSAGES",
         })
     }
 }
@@ -87,6 +89,7 @@ pub enum ChannelPermission {
     Read,
     Manage,
     All,
+    ManageMessages,
 }
 
 impl Display for ChannelPermission {
@@ -96,6 +99,7 @@ impl Display for ChannelPermission {
             ChannelPermission::Read => "READ",
             ChannelPermission::Manage => "MANAGE",
             ChannelPermission::All => "ALL",
+            ChannelPermission::ManageMessages => "MANAGE_MESSAGES",
         })
     }
 }
@@ -106,6 +110,7 @@ impl From<ChannelPermission> for HubPermission {
             ChannelPermission::Read => HubPermission::ReadChannels,
             ChannelPermission::Manage => HubPermission::ManageChannels,
             ChannelPermission::All => HubPermission::All,
+            ChannelPermission::ManageMessages => HubPermission::ManageMessages,
         }
     }
 }
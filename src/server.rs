@@ -2,6 +2,7 @@ use crate::{
     channel::{self, Message},
     check_permission,
     hub::Hub,
+    hub_cache,
     websocket::ServerMessage,
     Error, Result, ID,
 };
@@ -16,13 +17,14 @@ use std::{
     convert::TryFrom,
     io::Read,
     sync::Arc,
+    time::Duration,
 };
 use tantivy::{
     collector::TopDocs,
     directory::MmapDirectory,
     doc,
     query::QueryParser,
-    schema::{Field, Schema, FAST, STORED, TEXT},
+    schema::{Field, Schema, Term, FAST, INDEXED, STORED, TEXT},
     Index, IndexReader, IndexWriter, LeasedItem, ReloadPolicy, Searcher,
 };
 use tokio::io::AsyncWriteExt;
@@ -80,6 +82,14 @@ pub mod client_command {
         pub channel_id: ID,
         pub connection_id: u128,
     }
+    /// Subscribes the client to notifications of new messages in the given direct conversation.
+    #[message(result = "Result")]
+    #[derive(Debug, Clone)]
+    pub struct SubscribeConversation {
+        pub user_id: String,
+        pub conversation_id: ID,
+        pub connection_id: u128,
+    }
     /// Notifies other clients subscribed to the given channel that the given user has started typing.
     #[message(result = "Result")]
     #[derive(Debug, Clone)]
@@ -114,6 +124,18 @@ pub struct NewMessageForIndex {
     pub message: channel::Message,
 }
 
+/// Message to tell the message server that a message was edited, the message's document is replaced with one for the new content.
+#[message(result = "Result")]
+#[derive(Clone, Debug)]
+pub struct EditedMessageForIndex {
+    pub hub_id: ID,
+    pub channel_id: ID,
+    /// ID of the message that was edited.
+    pub message_id: ID,
+    /// The new revision of the message.
+    pub revision: channel::Message,
+}
+
 /// Command for a [`MessageServer`] to search the given channel with a query.
 #[message(result = "Result<Vec<ID>>")]
 #[derive(Clone, Debug)]
@@ -151,16 +173,55 @@ pub enum HubUpdateType {
     ChannelDeleted(ID),
     ChannelRenamed(ID),
     ChannelDescriptionUpdated(ID),
+    /// A message was pinned, contains the channel and message IDs.
+    MessagePinned(ID, ID),
+    /// A message was unpinned, contains the channel and message IDs.
+    MessageUnpinned(ID, ID),
+    CategoryCreated(ID),
+    CategoryDeleted(ID),
+    CategoryRenamed(ID),
+    /// The permission settings of a member or a permission group in the category with the given ID were changed.
+    CategoryPermissionChanged(ID),
+    /// Channels or categories were moved, clients should get the hub's channels again to know their new order.
+    ChannelsReordered,
+    /// The mode of the channel with the given ID was changed.
+    ChannelModeChanged(ID),
 }
 
 /// Message to notify the server of a change made externally, usually used so the server can notify clients.
 #[message(result = "()")]
 #[derive(Debug, Clone)]
 pub enum ServerNotification {
-    NewMessage(ID, ID, ID, String, channel::Message),
+    /// A message was sent, contains the hub, channel and message IDs followed by the armoured message, the message and its sequence number in the channel.
+    NewMessage(ID, ID, ID, String, channel::Message, u64),
+    /// A user reacted to a message, contains the hub, channel and message IDs followed by the user's ID and the emoji.
+    ReactionAdded(ID, ID, ID, String, String),
+    /// A user removed their reaction to a message, contains the hub, channel and message IDs followed by the user's ID and the emoji.
+    ReactionRemoved(ID, ID, ID, String, String),
+    /// A message was deleted, contains the hub, channel and message IDs followed by the armoured tombstone left in its place.
+    MessageDeleted(ID, ID, ID, String),
+    /// A message was edited, contains the hub, channel and message IDs followed by the armoured revision and the revision itself.
+    MessageEdited(ID, ID, ID, String, channel::Message),
     HubUpdated(ID, HubUpdateType),
 }
 
+/// Message to tell the message server to remove messages that were deleted from a channel from the search index.
+#[message(result = "Result")]
+#[derive(Clone, Debug)]
+pub struct RemoveFromIndex {
+    pub hub_id: ID,
+    pub channel_id: ID,
+    pub ids: Vec<ID>,
+}
+
+/// Tells the [`MessageServer`] to delete the messages that channels' retention policies no longer allow keeping, sent every [`RETENTION_PRUNE_INTERVAL`].
+#[message(result = "()")]
+#[derive(Clone, Copy, Debug)]
+struct PruneMessages;
+
+/// How often messages that have expired under their channel's retention policy are deleted.
+pub const RETENTION_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
 /// Tells the [`Server`] to get an address to it's [`MessageServer`].
 #[message(result = "Addr<MessageServer>")]
 #[derive(Clone, Copy)]
@@ -170,7 +231,7 @@ lazy_static! {
     static ref MESSAGE_SCHEMA: Schema = {
         let mut schema_builder = Schema::builder();
         schema_builder.add_text_field("content", TEXT);
-        schema_builder.add_bytes_field("id", STORED | FAST);
+        schema_builder.add_bytes_field("id", INDEXED | STORED | FAST);
         schema_builder.build()
     };
     static ref MESSAGE_SCHEMA_FIELDS: MessageSchemaFields = MessageSchemaFields {
@@ -192,30 +253,109 @@ pub fn add_message_to_writer(writer: &mut IndexWriter, message: channel::Message
     Ok(())
 }
 
-/// Logs the given message ID to a file, should be called after any Tantivy commits.
-async fn log_last_message(hub_id: ID, channel_id: ID, message_id: ID) -> Result {
-    let log_path_string = format!(
+/// Adds the latest revision of a message to a Tantivy [`IndexWriter`], under the ID of the original message.
+async fn add_latest_revision_to_writer(
+    writer: &mut IndexWriter,
+    channel: &channel::Channel,
+    message: channel::Message,
+) -> Result {
+    let id = message.id;
+    let mut latest = channel.get_latest_revision(message).await;
+    latest.id = id;
+    add_message_to_writer(writer, latest)
+}
+
+/// Gets the term that matches the search index document of the message with the given ID.
+fn message_id_term(message_id: ID) -> Result<Term> {
+    Ok(Term::from_field_bytes(
+        MESSAGE_SCHEMA_FIELDS.id,
+        &bincode::serialize(&message_id)?,
+    ))
+}
+
+/// Gets the path of the folder in which the Tantivy index of a channel is stored.
+pub fn index_folder(data_dir: &str, hub_id: ID, channel_id: ID) -> String {
+    format!(
+        "{}/{:x}/{:x}/index",
+        crate::hub::hub_data_folder(data_dir),
+        hub_id.as_u128(),
+        channel_id.as_u128()
+    )
+}
+
+/// Gets the path of the file in which the ID of the last message added to a channel's index is logged.
+pub fn index_log_path(data_dir: &str, hub_id: ID, channel_id: ID) -> String {
+    format!(
         "{}/{:x}/{:x}/log",
-        crate::hub::HUB_DATA_FOLDER,
+        crate::hub::hub_data_folder(data_dir),
         hub_id.as_u128(),
         channel_id.as_u128()
-    );
-    tokio::fs::write(log_path_string, &message_id.as_u128().to_ne_bytes()).await?;
+    )
+}
+
+/// Logs the given message ID to a file, should be called after any Tantivy commits.
+async fn log_last_message(data_dir: &str, hub_id: ID, channel_id: ID, message_id: ID) -> Result {
+    tokio::fs::write(
+        index_log_path(data_dir, hub_id, channel_id),
+        &message_id.as_u128().to_ne_bytes(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Adds every message sent in a channel to an index, commits it and logs the last message as indexed.
+async fn index_all_messages(
+    data_dir: &str,
+    writer: &mut IndexWriter,
+    hub_id: ID,
+    channel_id: ID,
+) -> Result {
+    let hub = Hub::load(hub_id).await?;
+    if let Some(channel) = hub.channels.get(&channel_id) {
+        let mut messages = channel.get_last_messages(usize::MAX).await;
+        messages.reverse();
+        let last_id = messages.last().map(|last| last.id);
+        for message in messages {
+            add_latest_revision_to_writer(writer, channel, message).await?;
+        }
+        writer.commit()?;
+        if let Some(last_id) = last_id {
+            log_last_message(data_dir, hub_id, channel_id, last_id).await?;
+        }
+    }
     Ok(())
 }
 
-async fn log_if_nologs(hub_id: ID, channel_id: ID, message_id: ID) -> Result {
+/// Deletes the Tantivy index of a channel and indexes all of its messages again.
+/// Must not be used while a running server has the channel's index open.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The old index could not be deleted.
+/// * The new index could not be created or written to.
+/// * The hub could not be loaded.
+pub async fn rebuild_index(data_dir: &str, hub_id: ID, channel_id: ID) -> Result {
+    let dir_string = index_folder(data_dir, hub_id, channel_id);
+    let dir_path = std::path::Path::new(&dir_string);
+    if dir_path.is_dir() {
+        tokio::fs::remove_dir_all(dir_path).await?;
+    }
+    tokio::fs::create_dir_all(dir_path).await?;
+    let index = Index::create(MmapDirectory::open(dir_path)?, MESSAGE_SCHEMA.clone())?;
+    let mut writer = index.writer(50_000_000)?;
+    index_all_messages(data_dir, &mut writer, hub_id, channel_id).await
+}
+
+async fn log_if_nologs(data_dir: &str, hub_id: ID, channel_id: ID, message_id: ID) -> Result {
     let mut file = tokio::fs::OpenOptions::new()
         .write(true)
         .create_new(true)
-        .open(format!(
-            "{}/{:x}/{:x}/log",
-            crate::hub::HUB_DATA_FOLDER,
-            hub_id.as_u128(),
-            channel_id.as_u128()
-        ))
+        .open(index_log_path(data_dir, hub_id, channel_id))
         .await?;
-    file.write(&message_id.as_u128().to_ne_bytes()).await?;
+    file.write_all(&message_id.as_u128().to_ne_bytes()).await?;
     Ok(())
 }
 
@@ -225,6 +365,7 @@ pub type IndexReaderMap = HashMap<(ID, ID), IndexReader>;
 pub type PendingMessageMap = HashMap<(ID, ID), (u8, ID)>;
 
 pub struct MessageServer {
+    data_dir: String,
     indexes: IndexMap,
     index_writers: IndexWriterMap,
     index_readers: IndexReaderMap,
@@ -232,8 +373,10 @@ pub struct MessageServer {
 }
 
 impl MessageServer {
-    pub fn new() -> Self {
+    /// Creates a message server that keeps the search indexes of channels inside of the given data folder.
+    pub fn new(data_dir: String) -> Self {
         Self {
+            data_dir,
             indexes: HashMap::new(),
             index_writers: HashMap::new(),
             index_readers: HashMap::new(),
@@ -243,45 +386,46 @@ impl MessageServer {
 
     /// Sets up the Tantivy index for a given channel, also makes sure that the index is up to date by commiting any messages sent after the last message sent (logged by [`log_last_message`]).
     async fn setup_index(&mut self, hub_id: ID, channel_id: ID) -> Result {
-        let dir_string = format!(
-            "{}/{:x}/{:x}/index",
-            crate::hub::HUB_DATA_FOLDER,
-            hub_id.as_u128(),
-            channel_id.as_u128()
-        );
+        let dir_string = index_folder(&self.data_dir, hub_id, channel_id);
         let dir_path = std::path::Path::new(&dir_string);
         if !dir_path.is_dir() {
             tokio::fs::create_dir_all(dir_path).await?;
         }
-        let dir = MmapDirectory::open(dir_path)?;
-        let index = Index::open_or_create(dir, MESSAGE_SCHEMA.clone())?;
+        let log_path_string = index_log_path(&self.data_dir, hub_id, channel_id);
+        let log_path = std::path::Path::new(&log_path_string);
+        let mut rebuild = false;
+        let index =
+            match Index::open_or_create(MmapDirectory::open(dir_path)?, MESSAGE_SCHEMA.clone()) {
+                Ok(index) => index,
+                Err(tantivy::TantivyError::SchemaError(_)) => {
+                    // Indexes created before message IDs were indexed cannot have messages removed from them, so they are rebuilt.
+                    tokio::fs::remove_dir_all(dir_path).await?;
+                    tokio::fs::create_dir_all(dir_path).await?;
+                    if log_path.is_file() {
+                        tokio::fs::remove_file(log_path).await?;
+                    }
+                    rebuild = true;
+                    Index::create(MmapDirectory::open(dir_path)?, MESSAGE_SCHEMA.clone())?
+                }
+                Err(err) => return Err(err.into()),
+            };
         let reader = index
             .reader_builder()
             .reload_policy(ReloadPolicy::OnCommit)
             .try_into()?;
         let mut writer = index.writer(50_000_000)?;
         let key = (hub_id, channel_id);
-        let log_path_string = format!(
-            "{}/{:x}/{:x}/log",
-            crate::hub::HUB_DATA_FOLDER,
-            hub_id.as_u128(),
-            channel_id.as_u128()
-        );
-        let log_path = std::path::Path::new(&log_path_string);
-        if log_path.is_file() {
+        if rebuild {
+            index_all_messages(&self.data_dir, &mut writer, hub_id, channel_id).await?;
+            reader.reload()?;
+        } else if log_path.is_file() {
             let mut buf: [u8; 16] = [0; 16];
             tokio::fs::read(log_path)
                 .await?
                 .as_slice()
                 .read_exact(&mut buf)?;
             let last_id = ID::from_u128(u128::from_le_bytes(buf));
-            let filename = format!("{}{:x}.json", crate::hub::HUB_INFO_FOLDER, hub_id.as_u128());
-            let path = std::path::Path::new(&filename);
-            if !path.exists() {
-                return Err(Error::HubNotFound);
-            }
-            let json = tokio::fs::read_to_string(path).await?;
-            let hub = serde_json::from_str::<Hub>(&json)?;
+            let hub = Hub::load(hub_id).await?;
             if let Some(channel) = hub.channels.get(&channel_id) {
                 let messages: Vec<Message> = channel
                     .get_all_messages_from(last_id)
@@ -289,18 +433,14 @@ impl MessageServer {
                     .iter()
                     .filter_map(|signed_message| Message::try_from(signed_message).ok())
                     .collect();
-                let last_id = if let Some(last) = messages.last() {
-                    Some(last.id)
-                } else {
-                    None
-                };
+                let last_id = messages.last().map(|last| last.id);
 
                 for message in messages {
-                    add_message_to_writer(&mut writer, message)?;
+                    add_latest_revision_to_writer(&mut writer, channel, message).await?;
                 }
                 writer.commit()?;
                 if let Some(last_id) = last_id {
-                    log_last_message(hub_id, channel_id, last_id).await?;
+                    log_last_message(&self.data_dir, hub_id, channel_id, last_id).await?;
                 }
                 reader.reload()?;
             }
@@ -343,20 +483,84 @@ impl MessageServer {
             Err(Error::GetIndexWriter)
         }
     }
-}
 
-impl Default for MessageServer {
-    fn default() -> Self {
-        Self::new()
+    /// Deletes the messages that channels' retention policies no longer allow keeping from storage and from the search index, pinned messages that were deleted are unpinned.
+    /// Failures are logged and do not stop the other channels from being pruned.
+    async fn prune_expired(&mut self) {
+        let hub_ids = match crate::storage::hub_store().list_hubs().await {
+            Ok(hub_ids) => hub_ids,
+            Err(err) => {
+                println!(
+                    "WARNING: Failed to list the hubs to prune messages in: {}",
+                    err
+                );
+                return;
+            }
+        };
+        for hub_id in hub_ids {
+            let hub = match Hub::load(hub_id).await {
+                Ok(hub) => hub,
+                Err(err) => {
+                    println!(
+                        "WARNING: Failed to load hub {} to prune messages in it: {}",
+                        hub_id, err
+                    );
+                    continue;
+                }
+            };
+            for channel in hub.channels.values() {
+                let ids = match channel.prune_messages().await {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        println!(
+                            "WARNING: Failed to prune messages in channel {} of hub {}: {}",
+                            channel.id, hub_id, err
+                        );
+                        continue;
+                    }
+                };
+                if ids.iter().any(|id| channel.pinned.contains(id)) {
+                    if let Err(err) = crate::api::unpin_deleted(hub_id, channel.id, &ids).await {
+                        println!(
+                            "WARNING: Failed to unpin pruned messages in channel {} of hub {}: {}",
+                            channel.id, hub_id, err
+                        );
+                    }
+                }
+                if !ids.is_empty() {
+                    if let Err(err) = self.remove_from_index(hub_id, channel.id, ids).await {
+                        println!(
+                            "WARNING: Failed to remove pruned messages in channel {} of hub {} from the search index: {}",
+                            channel.id, hub_id, err
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes the documents of the given messages from a channel's search index.
+    async fn remove_from_index(&mut self, hub_id: ID, channel_id: ID, ids: Vec<ID>) -> Result {
+        let writer = self.get_writer(hub_id, channel_id).await?;
+        for id in ids {
+            writer.delete_term(message_id_term(id)?);
+        }
+        writer.commit()?;
+        Ok(())
     }
 }
 
 #[async_trait]
 impl Actor for MessageServer {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> xactor::Result<()> {
+        ctx.send_interval(PruneMessages, RETENTION_PRUNE_INTERVAL);
+        Ok(())
+    }
+
     async fn stopped(&mut self, _ctx: &mut xactor::Context<Self>) {
         for (hc_id, writer) in self.index_writers.iter_mut() {
-            if let Some((_, id)) = self.pending_messages.get(&hc_id) {
-                let _ = log_last_message(hc_id.0, hc_id.1, *id);
+            if let Some((_, id)) = self.pending_messages.get(hc_id) {
+                let _ = log_last_message(&self.data_dir, hc_id.0, hc_id.1, *id).await;
             }
             let _ = writer.commit();
         }
@@ -379,7 +583,7 @@ impl Handler<SearchMessageIndex> for MessageServer {
             if let Some(pending) = pending {
                 if pending.0 != 0 {
                     let _ = self.get_writer(msg.hub_id, msg.channel_id).await?.commit();
-                    log_last_message(msg.hub_id, msg.channel_id, pending.1).await?;
+                    log_last_message(&self.data_dir, msg.hub_id, msg.channel_id, pending.1).await?;
 
                     self.pending_messages
                         .insert((msg.hub_id, msg.channel_id), (0, pending.1));
@@ -418,17 +622,17 @@ impl Handler<NewMessageForIndex> for MessageServer {
         {
             new_pending = pending + 1;
             if pending >= crate::TANTIVY_COMMIT_THRESHOLD {
-                let mut writer = self.get_writer(msg.hub_id, msg.channel_id).await?;
-                add_message_to_writer(&mut writer, msg.message)?;
+                let writer = self.get_writer(msg.hub_id, msg.channel_id).await?;
+                add_message_to_writer(writer, msg.message)?;
                 writer.commit()?;
-                log_last_message(msg.hub_id, msg.channel_id, message_id).await?;
+                log_last_message(&self.data_dir, msg.hub_id, msg.channel_id, message_id).await?;
                 new_pending = 0;
             } else {
-                log_if_nologs(msg.hub_id, msg.channel_id, message_id).await?;
+                log_if_nologs(&self.data_dir, msg.hub_id, msg.channel_id, message_id).await?;
             }
         } else {
             new_pending = 1;
-            log_if_nologs(msg.hub_id, msg.channel_id, message_id).await?;
+            log_if_nologs(&self.data_dir, msg.hub_id, msg.channel_id, message_id).await?;
         }
         let _ = self
             .pending_messages
@@ -437,6 +641,34 @@ impl Handler<NewMessageForIndex> for MessageServer {
     }
 }
 
+#[async_trait]
+impl Handler<EditedMessageForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: EditedMessageForIndex) -> Result {
+        let writer = self.get_writer(msg.hub_id, msg.channel_id).await?;
+        writer.delete_term(message_id_term(msg.message_id)?);
+        let mut revision = msg.revision;
+        revision.id = msg.message_id;
+        add_message_to_writer(writer, revision)?;
+        writer.commit()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Handler<RemoveFromIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: RemoveFromIndex) -> Result {
+        self.remove_from_index(msg.hub_id, msg.channel_id, msg.ids)
+            .await
+    }
+}
+
+#[async_trait]
+impl Handler<PruneMessages> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: PruneMessages) {
+        self.prune_expired().await;
+    }
+}
+
 pub type SubscribedChannelMap = Arc<RwLock<HashMap<(ID, ID), Arc<RwLock<HashSet<u128>>>>>>;
 pub type SubscribedHubMap = Arc<RwLock<HashMap<ID, Arc<RwLock<HashSet<u128>>>>>>;
 pub type SubscribedMap = Arc<RwLock<HashMap<u128, Arc<RwLock<(HashSet<(ID, ID)>, HashSet<ID>)>>>>>;
@@ -454,21 +686,42 @@ pub struct Server {
 }
 
 impl Server {
-    /// Creates a new server with default options, also creates a [`MessageServer`] with the given `commit_threshold` (how many messages should be added to the search index before commiting to the index).
-    pub async fn new(secret_key: SignedSecretKey) -> Result<Self> {
+    /// Creates a new server with default options, also creates a [`MessageServer`] that keeps the search indexes of channels in `data_dir`.
+    pub async fn new(secret_key: SignedSecretKey, data_dir: String) -> Result<Self> {
         Ok(Self {
             subscribed_channels: Arc::new(RwLock::new(HashMap::new())),
             subscribed_hubs: Arc::new(RwLock::new(HashMap::new())),
             subscribed: Arc::new(RwLock::new(HashMap::new())),
             connected: Arc::new(RwLock::new(HashMap::new())),
             secret_key,
-            message_server: MessageServer::new()
+            message_server: MessageServer::new(data_dir)
                 .start()
                 .await
                 .map_err(|_| Error::ServerStartFailed)?,
         })
     }
 
+    /// Subscribes a client to the messages sent in a channel, the key is the ID of the hub followed by the ID of the channel.
+    async fn subscribe_channel(&self, connection_id: u128, key: (ID, ID)) {
+        self.subscribed
+            .write()
+            .await
+            .entry(connection_id)
+            .or_default()
+            .write()
+            .await
+            .0
+            .insert(key);
+        self.subscribed_channels
+            .write()
+            .await
+            .entry(key)
+            .or_default()
+            .write()
+            .await
+            .insert(connection_id);
+    }
+
     /// Sends a [`ServreMessage`] to all clients subscribed to notifications for the given hub.
     async fn send_hub(&self, message: ServerMessage, hub_id: &ID) -> Result {
         if let Some(subscribed_arc) = self.subscribed_hubs.read().await.get(hub_id) {
@@ -537,14 +790,14 @@ impl Handler<client_command::Disconnect> for Server {
             let subscribed = subscribed.write().await;
             let subscribed_channels = self.subscribed_channels.write().await;
             for channel in subscribed.0.iter() {
-                if let Some(subs) = subscribed_channels.get(&channel) {
+                if let Some(subs) = subscribed_channels.get(channel) {
                     subs.write().await.remove(&msg.connection_id);
                 }
             }
             drop(subscribed_channels);
             let subscribed_hubs = self.subscribed_hubs.write().await;
             for hub in subscribed.1.iter() {
-                if let Some(subs) = subscribed_hubs.get(&hub) {
+                if let Some(subs) = subscribed_hubs.get(hub) {
                     subs.write().await.remove(&msg.connection_id);
                 }
             }
@@ -561,7 +814,7 @@ impl Handler<client_command::SubscribeHub> for Server {
         _ctx: &mut Context<Self>,
         msg: client_command::SubscribeHub,
     ) -> Result {
-        Hub::load(msg.hub_id)
+        hub_cache::get(msg.hub_id)
             .await
             .and_then(|hub| Ok(hub.get_member(&msg.user_id)?.clone()))?;
         self.subscribed
@@ -604,7 +857,7 @@ impl Handler<client_command::SubscribeChannel> for Server {
         _ctx: &mut Context<Self>,
         msg: client_command::SubscribeChannel,
     ) -> Result {
-        Hub::load(msg.hub_id)
+        hub_cache::get(msg.hub_id)
             .await
             .and_then(|hub| {
                 if let Ok(member) = hub.get_member(&msg.user_id) {
@@ -623,24 +876,22 @@ impl Handler<client_command::SubscribeChannel> for Server {
                 );
                 Ok(())
             })?;
-        let key = (msg.hub_id, msg.channel_id);
-        self.subscribed
-            .write()
-            .await
-            .entry(msg.connection_id)
-            .or_default()
-            .write()
-            .await
-            .0
-            .insert(key);
-        self.subscribed_channels
-            .write()
-            .await
-            .entry(key)
-            .or_default()
-            .write()
-            .await
-            .insert(msg.connection_id);
+        self.subscribe_channel(msg.connection_id, (msg.hub_id, msg.channel_id))
+            .await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Handler<client_command::SubscribeConversation> for Server {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: client_command::SubscribeConversation,
+    ) -> Result {
+        let conversation = crate::api::get_conversation(&msg.user_id, msg.conversation_id).await?;
+        self.subscribe_channel(msg.connection_id, (conversation.id, conversation.id))
+            .await;
         Ok(())
     }
 }
@@ -665,25 +916,9 @@ impl Handler<client_command::StartTyping> for Server {
         _ctx: &mut Context<Self>,
         msg: client_command::StartTyping,
     ) -> Result {
-        Hub::load(msg.hub_id)
-            .await
-            .and_then(|hub| {
-                if let Ok(member) = hub.get_member(&msg.user_id) {
-                    let member = member.clone();
-                    Ok((hub, member))
-                } else {
-                    Err(Error::MemberNotFound)
-                }
-            })
-            .and_then(|(hub, user)| {
-                check_permission!(
-                    user,
-                    msg.channel_id,
-                    crate::permission::ChannelPermission::Write,
-                    hub
-                );
-                Ok(())
-            })?;
+        hub_cache::get(msg.hub_id)
+            .await?
+            .check_can_send(&msg.user_id, msg.channel_id)?;
         let _ = self
             .send_channel(
                 ServerMessage::UserStartedTyping {
@@ -706,7 +941,7 @@ impl Handler<client_command::StopTyping> for Server {
         _ctx: &mut Context<Self>,
         msg: client_command::StopTyping,
     ) -> Result {
-        Hub::load(msg.hub_id)
+        hub_cache::get(msg.hub_id)
             .await
             .and_then(|hub| {
                 if let Ok(member) = hub.get_member(&msg.user_id) {
@@ -750,6 +985,7 @@ impl Handler<ServerNotification> for Server {
                 message_id,
                 armoured_message,
                 message,
+                sequence,
             ) => {
                 let _ = self
                     .message_server
@@ -766,6 +1002,93 @@ impl Handler<ServerNotification> for Server {
                             channel_id,
                             message_id,
                             armoured_message,
+                            sequence,
+                        },
+                        hub_id,
+                        channel_id,
+                    )
+                    .await;
+            }
+            ServerNotification::MessageEdited(
+                hub_id,
+                channel_id,
+                message_id,
+                armoured_revision,
+                revision,
+            ) => {
+                let _ = self
+                    .message_server
+                    .call(EditedMessageForIndex {
+                        hub_id,
+                        channel_id,
+                        message_id,
+                        revision,
+                    })
+                    .await;
+                let _ = self
+                    .send_channel(
+                        ServerMessage::MessageEdited {
+                            hub_id,
+                            channel_id,
+                            message_id,
+                            armoured_revision,
+                        },
+                        hub_id,
+                        channel_id,
+                    )
+                    .await;
+            }
+            ServerNotification::MessageDeleted(
+                hub_id,
+                channel_id,
+                message_id,
+                armoured_tombstone,
+            ) => {
+                let _ = self
+                    .message_server
+                    .call(RemoveFromIndex {
+                        hub_id,
+                        channel_id,
+                        ids: vec![message_id],
+                    })
+                    .await;
+                let _ = self
+                    .send_channel(
+                        ServerMessage::MessageDeleted {
+                            hub_id,
+                            channel_id,
+                            message_id,
+                            armoured_tombstone,
+                        },
+                        hub_id,
+                        channel_id,
+                    )
+                    .await;
+            }
+            ServerNotification::ReactionAdded(hub_id, channel_id, message_id, user_id, emoji) => {
+                let _ = self
+                    .send_channel(
+                        ServerMessage::ReactionAdded {
+                            hub_id,
+                            channel_id,
+                            message_id,
+                            user_id,
+                            emoji,
+                        },
+                        hub_id,
+                        channel_id,
+                    )
+                    .await;
+            }
+            ServerNotification::ReactionRemoved(hub_id, channel_id, message_id, user_id, emoji) => {
+                let _ = self
+                    .send_channel(
+                        ServerMessage::ReactionRemoved {
+                            hub_id,
+                            channel_id,
+                            message_id,
+                            user_id,
+                            emoji,
                         },
                         hub_id,
                         channel_id,
@@ -797,3 +1120,108 @@ impl Handler<GetMessageServer> for Server {
         self.message_server.clone()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use tantivy::{collector::Count, query::QueryParser};
+
+    use super::{add_message_to_writer, MessageServer, MESSAGE_SCHEMA_FIELDS};
+    use crate::{
+        channel::{Channel, Message, RetentionPolicy, SignedMessage},
+        hub::Hub,
+        hub_cache, new_id, storage,
+        test_util::TempDir,
+        ID,
+    };
+
+    /// Counts the documents in a channel's search index that match a query.
+    async fn count_matches(
+        server: &mut MessageServer,
+        hub_id: ID,
+        channel_id: ID,
+        query: &str,
+    ) -> usize {
+        let searcher = server
+            .get_searcher(hub_id, channel_id)
+            .await
+            .expect("Failed to open the search index.");
+        let query = QueryParser::for_index(searcher.index(), vec![MESSAGE_SCHEMA_FIELDS.content])
+            .parse_query(query)
+            .expect("Failed to parse the query.");
+        searcher
+            .search(&query, &Count)
+            .expect("Failed to search the index.")
+    }
+
+    #[tokio::test]
+    async fn prune_expired() {
+        let root = TempDir::new("server");
+        let hub_id = new_id();
+        let owner = hub_id.to_string();
+        let mut hub = Hub::new("test_hub".to_string(), hub_id, owner.clone());
+        let channel_id = hub
+            .new_channel(&owner, "chat".to_string())
+            .expect("Failed to create a channel.");
+        let mut expired = Message::new(owner.clone(), "expired".to_string(), hub_id, channel_id);
+        expired.created = Utc::now() - Duration::days(10);
+        let kept = Message::new(owner.clone(), "kept".to_string(), hub_id, channel_id);
+        if let Some(channel) = hub.channels.get_mut(&channel_id) {
+            channel.retention = RetentionPolicy {
+                max_age_days: Some(1),
+                max_messages: None,
+            };
+            channel.pinned.push(expired.id);
+        }
+        hub_cache::save(&mut hub)
+            .await
+            .expect("Failed to save the hub.");
+        let mut server = MessageServer::new(root.path());
+        let writer = server
+            .get_writer(hub_id, channel_id)
+            .await
+            .expect("Failed to open the search index.");
+        for message in [expired.clone(), kept.clone()].iter() {
+            Channel::write_message(
+                hub_id,
+                channel_id,
+                SignedMessage::new(message.id, message.created, message.content.clone()),
+            )
+            .await
+            .expect("Failed to store a message.");
+            add_message_to_writer(writer, message.clone()).expect("Failed to index a message.");
+        }
+        writer.commit().expect("Failed to commit the search index.");
+        assert_eq!(
+            count_matches(&mut server, hub_id, channel_id, "expired").await,
+            1
+        );
+        server.prune_expired().await;
+        assert_eq!(
+            count_matches(&mut server, hub_id, channel_id, "expired").await,
+            0
+        );
+        assert_eq!(
+            count_matches(&mut server, hub_id, channel_id, "kept").await,
+            1
+        );
+        let message_store = storage::message_store();
+        assert!(message_store
+            .get_message(hub_id, channel_id, expired.id)
+            .await
+            .expect("Failed to read a message.")
+            .is_none());
+        assert!(message_store
+            .get_message(hub_id, channel_id, kept.id)
+            .await
+            .expect("Failed to read a message.")
+            .is_some());
+        let hub = hub_cache::get(hub_id)
+            .await
+            .expect("Failed to get the hub.");
+        assert!(hub.channels[&channel_id].pinned.is_empty());
+        hub_cache::delete(hub_id)
+            .await
+            .expect("Failed to delete the hub.");
+    }
+}
@@ -1,7 +1,10 @@
 use std::convert::TryFrom;
 
 use crate::error::Result;
-use crate::{channel::Message, error::Error};
+use crate::{
+    channel::{Message, Tombstone},
+    error::Error,
+};
 use chrono::{Duration, Utc};
 use pgp::crypto::{hash::HashAlgorithm, sym::SymmetricKeyAlgorithm};
 use pgp::packet::LiteralData;
@@ -18,9 +21,20 @@ use pgp::{
 use reqwest::StatusCode;
 use smallvec::*;
 
-pub const SECRET_KEY_PATH: &str = "data/secret_key.asc";
-pub const PUBLIC_KEY_PATH: &str = "data/public_key.asc";
-pub const USER_PUBLIC_KEY_FOLDER: &str = "data/user_public_keys/";
+/// Gets the path of the server's secret key inside of the given data folder.
+pub fn secret_key_path(data_dir: &str) -> String {
+    format!("{}/secret_key.asc", data_dir)
+}
+
+/// Gets the path of the server's public key inside of the given data folder.
+pub fn public_key_path(data_dir: &str) -> String {
+    format!("{}/public_key.asc", data_dir)
+}
+
+/// Gets the path of the folder in which the public keys of users are cached inside of the given data folder.
+pub fn user_public_key_folder(data_dir: &str) -> String {
+    format!("{}/user_public_keys/", data_dir)
+}
 
 #[derive(Clone, Debug)]
 pub struct KeyPair {
@@ -81,7 +95,7 @@ impl KeyPair {
             key_pair
         } else {
             let key_pair = KeyPair::new(id)?;
-            key_pair.save(SECRET_KEY_PATH, PUBLIC_KEY_PATH).await?;
+            key_pair.save(secret_key_path, public_key_path).await?;
             key_pair
         };
         Ok(result)
@@ -141,6 +155,44 @@ impl Message {
     }
 }
 
+impl Tombstone {
+    pub fn sign<F: FnOnce() -> String>(
+        &self,
+        secret_key: &impl SecretKeyTrait,
+        password: F,
+    ) -> Result<OpenPGPMessage> {
+        Ok(OpenPGPMessage::Literal(LiteralData::from_str(
+            "tombstone",
+            &serde_json::to_string(self)?,
+        ))
+        .sign(&secret_key, password, HashAlgorithm::SHA2_256)?)
+    }
+
+    pub fn from_signed(tombstone_str: &str) -> Result<Self> {
+        Self::from_pgp(OpenPGPMessage::from_string(tombstone_str)?.0)
+    }
+
+    pub fn from_signed_verify(
+        tombstone_str: &str,
+        server_public_key: &impl PublicKeyTrait,
+    ) -> Result<Self> {
+        let signed = OpenPGPMessage::from_string(tombstone_str)?.0;
+        signed.verify(server_public_key)?;
+        Self::from_pgp(signed)
+    }
+
+    fn from_pgp(message: OpenPGPMessage) -> Result<Self> {
+        Ok(serde_json::from_str(
+            &message
+                .decompress()?
+                .get_literal()
+                .map_or_else(|| Err(Error::InvalidMessage), Ok)?
+                .to_string()
+                .map_or_else(|| Err(Error::InvalidMessage), Ok)?,
+        )?)
+    }
+}
+
 impl TryFrom<&Message> for OpenPGPMessage {
     type Error = Error;
 
@@ -209,12 +261,13 @@ impl TryFrom<OpenPGPMessage> for Message {
 pub async fn get_or_import_public_key(
     fingerprint: &[u8],
     key_server: &str,
+    data_dir: &str,
 ) -> Result<SignedPublicKey> {
     if fingerprint.len() != 20 {
         return Err(Error::InvalidFingerprint);
     }
     let fingerprint = hex::encode_upper(fingerprint);
-    let file_name = format!("{}{}.asc", USER_PUBLIC_KEY_FOLDER, fingerprint);
+    let file_name = format!("{}{}.asc", user_public_key_folder(data_dir), fingerprint);
     let path = std::path::Path::new(&file_name);
     if path.is_file() {
         Ok(SignedPublicKey::from_string(&tokio::fs::read_to_string(path).await?)?.0)
@@ -245,6 +298,15 @@ pub fn verify_message_extract(
     public_key: &SignedPublicKey,
     message: &str,
 ) -> Result<(String, String)> {
+    let (content, fingerprint) = verify_message_extract_bytes(public_key, message)?;
+    Ok((String::from_utf8(content)?, fingerprint))
+}
+
+/// Verifies a message signed by the given key like [`verify_message_extract`] but returns the signed content as bytes so that it does not have to be text.
+pub fn verify_message_extract_bytes(
+    public_key: &SignedPublicKey,
+    message: &str,
+) -> Result<(Vec<u8>, String)> {
     let message = OpenPGPMessage::from_string(message)?.0;
     message.verify(&public_key)?;
     let message = message.decompress()?;
@@ -264,8 +326,10 @@ pub fn verify_message_extract(
         let message = message.ok_or(Error::InvalidMessage)?;
         let literal_message = message.get_literal().ok_or(Error::InvalidMessage)?;
 
-        let content = String::from_utf8(literal_message.data().to_vec())?;
-        Ok((content, hex::encode_upper(public_key.fingerprint())))
+        Ok((
+            literal_message.data().to_vec(),
+            hex::encode_upper(public_key.fingerprint()),
+        ))
     } else {
         Err(Error::InvalidMessage)
     }
@@ -0,0 +1,2113 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    io::Cursor,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::Mutex,
+};
+
+use super::{schema, HubStore, MessageStore};
+use crate::{
+    channel::{Reaction, ReadMarker, RetentionPolicy, SignedMessage, ThreadSummary},
+    config::FsyncPolicy,
+    conversation::Conversation,
+    error::Error,
+    hub::{hub_data_folder, hub_info_folder, Hub},
+    new_id, Result, ID,
+};
+
+use index::IndexEntry;
+use journal::Record;
+
+/// Sidecar indexes that map message IDs to their position in message files.
+pub mod index;
+/// Write-ahead journal that keeps appends to message files from leaving partial records behind.
+pub mod journal;
+
+/// Bytes at the start of every zstd frame, used to tell compressed message files apart from uncompressed ones.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// zstd compression level used for message files.
+const ZSTD_LEVEL: i32 = 3;
+/// Size in bytes of a record in a channel's threads file: the ID of the thread root followed by the ID of the reply.
+const THREAD_RECORD_SIZE: usize = 32;
+
+/// Locks that must be held while appending to the files of a channel, keyed by hub and channel ID.
+pub type AppendLockMap = Arc<Mutex<HashMap<(ID, ID), Arc<Mutex<()>>>>>;
+/// Indexes of the channels that messages have been looked up in, keyed by hub and channel ID.
+pub type ChannelIndexMap = Arc<Mutex<HashMap<(ID, ID), ChannelIndex>>>;
+
+/// Index entries of every message file in a channel, oldest to newest, along with where the entry of each message is so that messages can be found by ID without searching every file.
+#[derive(Debug, Default)]
+pub struct ChannelIndex {
+    files: Vec<(PathBuf, Vec<IndexEntry>)>,
+    positions: HashMap<ID, (usize, usize)>,
+}
+
+impl ChannelIndex {
+    /// Creates the index of a channel from the index entries of its message files, oldest to newest.
+    fn new(files: Vec<(PathBuf, Vec<IndexEntry>)>) -> Self {
+        let mut positions = HashMap::new();
+        for (file, (_, entries)) in files.iter().enumerate() {
+            for (position, entry) in entries.iter().enumerate() {
+                positions.entry(entry.id).or_insert((file, position));
+            }
+        }
+        Self { files, positions }
+    }
+
+    /// Gets the message file and index entry of the first message with the given ID.
+    fn get(&self, id: &ID) -> Option<(PathBuf, IndexEntry)> {
+        self.positions
+            .get(id)
+            .map(|(file, position)| (self.files[*file].0.clone(), self.files[*file].1[*position]))
+    }
+
+    /// Gets the entries of up to `max` messages starting at the given position, grouped by message file.
+    fn take_from(
+        &self,
+        file: usize,
+        position: usize,
+        max: usize,
+    ) -> Vec<(PathBuf, Vec<IndexEntry>)> {
+        let mut result = Vec::new();
+        let mut left = max;
+        for (index, (path, entries)) in self.files.iter().enumerate().skip(file) {
+            if left == 0 {
+                break;
+            }
+            let start = if index == file { position } else { 0 }.min(entries.len());
+            let wanted: Vec<IndexEntry> = entries[start..].iter().take(left).copied().collect();
+            if !wanted.is_empty() {
+                left -= wanted.len();
+                result.push((path.clone(), wanted));
+            }
+        }
+        result
+    }
+
+    /// Gets the entries of up to `max` messages right before the given position, grouped by message file.
+    fn take_before(
+        &self,
+        file: usize,
+        position: usize,
+        max: usize,
+    ) -> Vec<(PathBuf, Vec<IndexEntry>)> {
+        let mut result = Vec::new();
+        let mut left = max;
+        for (index, (path, entries)) in self.files.iter().enumerate().take(file + 1).rev() {
+            if left == 0 {
+                break;
+            }
+            let end = if index == file {
+                position
+            } else {
+                entries.len()
+            };
+            let start = end.saturating_sub(left);
+            if start < end {
+                left -= end - start;
+                result.push((path.clone(), entries[start..end].to_vec()));
+            }
+        }
+        result.reverse();
+        result
+    }
+
+    /// Gets the entries of up to `max` messages starting at the first message with a sequence number of at least `from`, grouped by message file.
+    fn take_from_sequence(&self, from: u64, max: usize) -> Vec<(PathBuf, Vec<IndexEntry>)> {
+        let file = self.files.partition_point(|(_, entries)| {
+            entries.last().is_some_and(|last| last.sequence < from)
+        });
+        let position = self.files.get(file).map_or(0, |(_, entries)| {
+            entries.partition_point(|entry| entry.sequence < from)
+        });
+        self.take_from(file, position, max)
+    }
+
+    /// Adds the entry of a message that was appended to the given file, returns false if the index can no longer be kept in order and has to be loaded again.
+    fn push(&mut self, path: &std::path::Path, entry: IndexEntry) -> bool {
+        let day = |path: &std::path::Path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(parse_day)
+                .map(|(day, _)| day)
+        };
+        let file = match self.files.last_mut() {
+            Some((last, entries)) if last == path => {
+                entries.push(entry);
+                self.files.len() - 1
+            }
+            Some((last, _)) if day(last) >= day(path) => return false,
+            _ => {
+                self.files.push((path.to_path_buf(), vec![entry]));
+                self.files.len() - 1
+            }
+        };
+        let position = self.files[file].1.len() - 1;
+        self.positions.entry(entry.id).or_insert((file, position));
+        true
+    }
+}
+
+/// Stores hubs as bincode files in one folder and messages as bincode files in per channel folders, one file per day of messages, named after the number of days since Unix Epoch.
+/// Every message file has an index file next to it (see [`index`]) so that messages can be found without deserializing the whole channel history, the indexes of a channel are kept in memory once it has been used (see [`ChannelIndex`]).
+/// Appends to message files are written to the channel's journal first (see [`journal`]) so that they can be finished if the server crashes in the middle of one.
+#[derive(Clone, Debug)]
+pub struct FsStore {
+    info_folder: String,
+    data_folder: String,
+    fsync: FsyncPolicy,
+    append_locks: AppendLockMap,
+    indexes: ChannelIndexMap,
+    save_lock: Arc<Mutex<()>>,
+}
+
+impl FsStore {
+    /// Creates a store that keeps hub information files in `info_folder` and channel data in `data_folder`.
+    pub fn new<S: Into<String>>(info_folder: S, data_folder: S) -> Self {
+        Self {
+            info_folder: info_folder.into(),
+            data_folder: data_folder.into(),
+            fsync: FsyncPolicy::default(),
+            append_locks: Arc::new(Mutex::new(HashMap::new())),
+            indexes: Arc::new(Mutex::new(HashMap::new())),
+            save_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Creates a store that keeps hubs and channel data in the standard folders inside of the given data folder.
+    pub fn in_dir(data_dir: &str) -> Self {
+        Self::new(hub_info_folder(data_dir), hub_data_folder(data_dir))
+    }
+
+    /// Sets when appends to message files are synced to disk.
+    pub fn with_fsync(mut self, fsync: FsyncPolicy) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Gets the file path used for storing a hub's information.
+    pub fn hub_info_path(&self, hub_id: ID) -> String {
+        format!("{}{:x}", self.info_folder, hub_id.as_u128())
+    }
+
+    /// Gets the path of the folder in which conversation information files are stored, inside of the hub information folder.
+    pub fn conversation_folder(&self) -> String {
+        format!("{}conversations/", self.info_folder)
+    }
+
+    /// Gets the file path used for storing a conversation's information.
+    pub fn conversation_path(&self, conversation_id: ID) -> String {
+        format!(
+            "{}{:x}",
+            self.conversation_folder(),
+            conversation_id.as_u128()
+        )
+    }
+
+    /// Loads every saved conversation, conversation files that cannot be read are skipped.
+    pub async fn all_conversations(&self) -> Vec<Conversation> {
+        let mut result = Vec::new();
+        if let Ok(mut dir) = fs::read_dir(self.conversation_folder()).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                if let Ok(bytes) = fs::read(entry.path()).await {
+                    if let Ok(conversation) = schema::decode_conversation(&bytes) {
+                        result.push(conversation);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Gets the path of the directory in which a hub's channel folders are stored.
+    pub fn hub_data_path(&self, hub_id: ID) -> String {
+        format!("{}{:x}/", self.data_folder, hub_id.as_u128())
+    }
+
+    /// Gets the path of a channel's data folder, used for storing message files.
+    pub fn channel_folder(&self, hub_id: ID, channel_id: ID) -> String {
+        format!(
+            "{}{:x}/{:x}",
+            self.data_folder,
+            hub_id.as_u128(),
+            channel_id.as_u128()
+        )
+    }
+
+    /// Gets the path of the file in which the revisions of a message are stored.
+    fn revisions_path(&self, hub_id: ID, channel_id: ID, message_id: ID) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/revisions/{:x}",
+            self.channel_folder(hub_id, channel_id),
+            message_id.as_u128()
+        ))
+    }
+
+    /// Gets the path of the file in which the reactions to a message are stored.
+    fn reactions_path(&self, hub_id: ID, channel_id: ID, message_id: ID) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/reactions/{:x}",
+            self.channel_folder(hub_id, channel_id),
+            message_id.as_u128()
+        ))
+    }
+
+    /// Gets the path of the file in which the replies sent in a channel's threads are recorded.
+    fn threads_path(&self, hub_id: ID, channel_id: ID) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/threads",
+            self.channel_folder(hub_id, channel_id)
+        ))
+    }
+
+    /// Reads every thread record of a channel as (root, reply) pairs oldest to newest, a torn record at the end is ignored.
+    async fn read_thread_records(&self, hub_id: ID, channel_id: ID) -> Result<Vec<(ID, ID)>> {
+        match fs::read(self.threads_path(hub_id, channel_id)).await {
+            Ok(bytes) => Ok(bytes
+                .chunks_exact(THREAD_RECORD_SIZE)
+                .map(|record| {
+                    let id_at = |range: std::ops::Range<usize>| {
+                        ID::from_u128(u128::from_le_bytes(
+                            record[range].try_into().expect("Thread record too short."),
+                        ))
+                    };
+                    (id_at(0..16), id_at(16..32))
+                })
+                .collect()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Reads the reactions to a message, there are none if the message has no reactions file.
+    async fn read_reactions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<Reaction>> {
+        match fs::read(self.reactions_path(hub_id, channel_id, message_id)).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the reactions file of a message, the file is deleted if there are no reactions left.
+    async fn write_reactions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        reactions: &[Reaction],
+    ) -> Result {
+        let path = self.reactions_path(hub_id, channel_id, message_id);
+        if reactions.is_empty() {
+            let _ = fs::remove_file(&path).await;
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        write_atomic(&path.to_string_lossy(), &bincode::serialize(reactions)?).await
+    }
+
+    /// Gets the path of the file in which the read markers of a channel's readers are stored.
+    fn read_markers_path(&self, hub_id: ID, channel_id: ID) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/read_markers",
+            self.channel_folder(hub_id, channel_id)
+        ))
+    }
+
+    /// Reads the read markers of a channel, keyed by user ID, there are none if the channel has no read markers file.
+    async fn read_markers(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+    ) -> Result<HashMap<String, ReadMarker>> {
+        match fs::read(self.read_markers_path(hub_id, channel_id)).await {
+            Ok(bytes) => schema::decode_read_markers(&bytes),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Deletes the revisions and reactions of a message.
+    async fn remove_message_sidecars(&self, hub_id: ID, channel_id: ID, message_id: ID) {
+        let _ = fs::remove_file(self.revisions_path(hub_id, channel_id, message_id)).await;
+        let _ = fs::remove_file(self.reactions_path(hub_id, channel_id, message_id)).await;
+    }
+
+    /// Gets the path of the file that keeps the sequence number the next message sent in a channel should have at least, written when messages are pruned so that numbers are not reused.
+    fn sequence_floor_path(&self, hub_id: ID, channel_id: ID) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/next_sequence",
+            self.channel_folder(hub_id, channel_id)
+        ))
+    }
+
+    /// Gets the path of the message file for the current day.
+    fn current_day_file(&self, hub_id: ID, channel_id: ID) -> String {
+        self.day_file(hub_id, channel_id, day_of(&Utc::now()))
+    }
+
+    /// Gets the path of the message file for the given day.
+    fn day_file(&self, hub_id: ID, channel_id: ID, day: i64) -> String {
+        format!("{}/{}", self.channel_folder(hub_id, channel_id), day)
+    }
+
+    /// Lists the message files of a channel oldest to newest, along with the day each one is for.
+    /// Files named after their date by older versions of the server are included, before any file for the same day that is named after its day number.
+    pub async fn day_files(&self, hub_id: ID, channel_id: ID) -> Vec<(i64, PathBuf)> {
+        let mut files = Vec::new();
+        if let Ok(mut dir) = fs::read_dir(self.channel_folder(hub_id, channel_id)).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                let path = entry.path();
+                if path.is_file() {
+                    if let Some((day, legacy)) = entry.file_name().to_str().and_then(parse_day) {
+                        files.push((day, !legacy, path));
+                    }
+                }
+            }
+        }
+        files.sort_by_key(|(day, current, _)| (*day, *current));
+        files
+            .into_iter()
+            .map(|(day, _, path)| (day, path))
+            .collect()
+    }
+
+    /// Renames the message files of a channel that are named after their date to the number of their day, unless there already is a file for that day.
+    /// The caller must hold the channel's append lock.
+    async fn rename_legacy_day_files(&self, hub_id: ID, channel_id: ID) -> Result {
+        let folder = self.channel_folder(hub_id, channel_id);
+        for (day, path) in self.day_files(hub_id, channel_id).await {
+            if !path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(parse_day)
+                .is_some_and(|(_, legacy)| legacy)
+            {
+                continue;
+            }
+            let renamed = PathBuf::from(format!("{}/{}", folder, day));
+            if renamed.exists() {
+                continue;
+            }
+            let _ = fs::remove_file(index::index_path(&path)).await;
+            fs::rename(&path, &renamed).await?;
+        }
+        Ok(())
+    }
+
+    /// Loads the index of every message file in a channel, oldest to newest, see [`index::load`].
+    async fn channel_index(&self, hub_id: ID, channel_id: ID) -> Vec<(PathBuf, Vec<IndexEntry>)> {
+        let mut result = Vec::new();
+        let mut next_sequence = 0;
+        for (_, path) in self.day_files(hub_id, channel_id).await {
+            if let Ok(entries) = index::load(&path, next_sequence).await {
+                if let Some(last) = entries.last() {
+                    next_sequence = last.sequence + 1;
+                }
+                result.push((path, entries));
+            }
+        }
+        result
+    }
+
+    /// Runs a function on the index of a channel, the index is loaded with [`FsStore::channel_index`] the first time the channel is used and kept up to date as messages are appended.
+    /// The caller must not hold the channel's append lock.
+    async fn with_index<T>(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        f: impl FnOnce(&ChannelIndex) -> T,
+    ) -> T {
+        if let Some(index) = self.indexes.lock().await.get(&(hub_id, channel_id)) {
+            return f(index);
+        }
+        // Loading under the append lock makes sure that no message is appended between loading the index and caching it.
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let files = self.channel_index(hub_id, channel_id).await;
+        let mut indexes = self.indexes.lock().await;
+        f(indexes
+            .entry((hub_id, channel_id))
+            .or_insert_with(|| ChannelIndex::new(files)))
+    }
+
+    /// Drops the cached index of a channel so that it is loaded again the next time it is used, called whenever message files are rewritten or removed.
+    async fn forget_index(&self, hub_id: ID, channel_id: ID) {
+        self.indexes.lock().await.remove(&(hub_id, channel_id));
+    }
+
+    /// Reads the messages that the given index entries point to, see [`index::read_entries`].
+    async fn read_indexed(files: Vec<(PathBuf, Vec<IndexEntry>)>) -> Result<Vec<SignedMessage>> {
+        let mut result = Vec::new();
+        for (path, entries) in files {
+            result.append(&mut index::read_entries(&path, &entries).await?);
+        }
+        Ok(result)
+    }
+
+    /// Gets the sequence number that the next message sent in a channel should have.
+    async fn next_sequence(&self, hub_id: ID, channel_id: ID) -> u64 {
+        if let Some((_, path)) = self.day_files(hub_id, channel_id).await.last() {
+            let entries = index::read(path).await;
+            let file_len = fs::metadata(path).await.map_or(0, |m| m.len());
+            if let Some(last) = entries.last() {
+                if last.end() == file_len {
+                    return last.sequence + 1;
+                }
+            }
+        }
+        let floor = fs::read(self.sequence_floor_path(hub_id, channel_id))
+            .await
+            .ok()
+            .and_then(|bytes| bytes.as_slice().try_into().ok())
+            .map_or(0, u64::from_le_bytes);
+        self.channel_index(hub_id, channel_id)
+            .await
+            .iter()
+            .rev()
+            .find_map(|(_, entries)| entries.last())
+            .map_or(0, |last| last.sequence + 1)
+            .max(floor)
+    }
+
+    /// Appends bytes to a file in a channel folder through the channel's journal, writing `header` first if the file is empty.
+    /// Returns the offset at which the bytes were written.
+    async fn append_journaled(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        path: &std::path::Path,
+        header: &[u8],
+        bytes: &[u8],
+    ) -> Result<u64> {
+        let folder = PathBuf::from(self.channel_folder(hub_id, channel_id));
+        let file = path
+            .strip_prefix(&folder)
+            .map_err(|_| Error::Other("file is not in the channel folder".to_string()))?;
+        let offset = fs::metadata(path).await.map_or(0, |m| m.len());
+        let mut record = Record {
+            file: file.to_string_lossy().to_string(),
+            offset,
+            bytes: Vec::with_capacity(header.len() + bytes.len()),
+        };
+        if offset == 0 {
+            record.bytes.extend_from_slice(header);
+        }
+        record.bytes.extend_from_slice(bytes);
+        journal::write(&folder, &record, self.fsync).await?;
+        if let Err(err) = journal::apply(&folder, &record, self.fsync).await {
+            let _ = journal::clear(&folder).await;
+            return Err(err);
+        }
+        journal::clear(&folder).await?;
+        Ok(offset + (record.bytes.len() - bytes.len()) as u64)
+    }
+
+    /// Appends a message to the file for the given day, or for the current day if none is given, and returns the sequence number it was stored with.
+    /// If the channel already has a file for a later day than the given one the message is appended to that file instead, or to the file for the current day if that file is compressed.
+    /// A message file that was written by an older version of the server is upgraded before the message is appended to it.
+    async fn append_message(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        mut message: SignedMessage,
+        day: Option<i64>,
+    ) -> Result<u64> {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let path = match (day, self.day_files(hub_id, channel_id).await.pop()) {
+            // Compressed files cannot be appended to, they are never for the current day.
+            (Some(_), Some((_, path))) if is_compressed(&path).await => {
+                PathBuf::from(self.current_day_file(hub_id, channel_id))
+            }
+            (Some(day), Some((last, path))) if last >= day => path,
+            (Some(day), _) => PathBuf::from(self.day_file(hub_id, channel_id, day)),
+            (None, _) => PathBuf::from(self.current_day_file(hub_id, channel_id)),
+        };
+        if !is_current_message_file(&path).await {
+            let entries = self
+                .channel_index(hub_id, channel_id)
+                .await
+                .into_iter()
+                .find(|(file, _)| *file == path)
+                .map(|(_, entries)| entries)
+                .unwrap_or_default();
+            upgrade_message_file(&path, Some(&entries), 0).await?;
+            self.forget_index(hub_id, channel_id).await;
+        }
+        let sequence = self
+            .next_sequence(hub_id, channel_id)
+            .await
+            .max(message.sequence);
+        message.sequence = sequence;
+        let bytes = bincode::serialize(&message)?;
+        let offset = self
+            .append_journaled(
+                hub_id,
+                channel_id,
+                &path,
+                &schema::message_file_header(),
+                &bytes,
+            )
+            .await?;
+        let entry = IndexEntry {
+            id: message.id,
+            offset,
+            length: bytes.len() as u32,
+            sequence,
+        };
+        index::append(&path, entry).await?;
+        let mut indexes = self.indexes.lock().await;
+        if let Some(index) = indexes.get_mut(&(hub_id, channel_id)) {
+            if !index.push(&path, entry) {
+                indexes.remove(&(hub_id, channel_id));
+            }
+        }
+        Ok(sequence)
+    }
+
+    /// Gets the lock that must be held while appending messages to a channel.
+    async fn append_lock(&self, hub_id: ID, channel_id: ID) -> Arc<Mutex<()>> {
+        self.append_locks
+            .lock()
+            .await
+            .entry((hub_id, channel_id))
+            .or_default()
+            .clone()
+    }
+}
+
+/// Gets the number of days between Unix Epoch and the given time, used to name message files.
+pub fn day_of(time: &DateTime<Utc>) -> i64 {
+    time.timestamp().div_euclid(86400)
+}
+
+/// Gets the day a message file is for from its name, along with whether the file was named after its date (`2021-05-01UTC`) by an older version of the server.
+pub fn parse_day(name: &str) -> Option<(i64, bool)> {
+    if let Ok(day) = i64::from_str(name) {
+        return Some((day, false));
+    }
+    let date = NaiveDate::parse_from_str(name.strip_suffix("UTC")?, "%Y-%m-%d").ok()?;
+    Some((day_of(&date.and_hms_opt(0, 0, 0)?.and_utc()), true))
+}
+
+/// Writes a file by writing to a temporary file first, syncing it to disk and then renaming it, so that the file is either completely replaced or left untouched.
+pub async fn write_atomic(path: &str, bytes: &[u8]) -> Result {
+    let tmp_path = format!("{}.{:x}.tmp", path, new_id().as_u128());
+    let mut file = fs::File::create(&tmp_path).await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(&tmp_path, path).await?;
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        if let Ok(dir) = fs::File::open(parent).await {
+            let _ = dir.sync_all().await;
+        }
+    }
+    Ok(())
+}
+
+/// Checks if a message file has been compressed by [`FsStore::compact_messages`].
+pub async fn is_compressed(path: &std::path::Path) -> bool {
+    let mut magic = [0; 4];
+    if let Ok(mut file) = fs::File::open(path).await {
+        file.read_exact(&mut magic).await.is_ok() && magic == ZSTD_MAGIC
+    } else {
+        false
+    }
+}
+
+/// Reads the contents of a message file, decompressing them if the file was compressed.
+/// Offsets in the file's index always refer to the decompressed contents.
+pub async fn read_day_bytes(path: &std::path::Path) -> Result<Vec<u8>> {
+    let bytes = fs::read(path).await?;
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        Ok(zstd::decode_all(bytes.as_slice())?)
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// Cuts a message file down to its first `length` bytes, counted in the decompressed contents, keeping it compressed if it was.
+/// The file's index is deleted so that it is rebuilt the next time the file is read.
+pub async fn truncate_message_file(path: &std::path::Path, length: u64) -> Result {
+    let compressed = is_compressed(path).await;
+    let mut bytes = read_day_bytes(path).await?;
+    bytes.truncate(length as usize);
+    if compressed {
+        bytes = zstd::encode_all(bytes.as_slice(), ZSTD_LEVEL)?;
+    }
+    let _ = fs::remove_file(index::index_path(path)).await;
+    write_atomic(&path.to_string_lossy(), &bytes).await
+}
+
+/// Checks if a message file does not exist yet or was written with the current schema version.
+async fn is_current_message_file(path: &std::path::Path) -> bool {
+    let mut file = match fs::File::open(path).await {
+        Ok(file) => file,
+        Err(_) => return true,
+    };
+    let mut header = [0; schema::MESSAGE_HEADER_SIZE as usize];
+    if file.read_exact(&mut header).await.is_err() {
+        return file.metadata().await.map_or(true, |m| m.len() == 0);
+    }
+    matches!(
+        schema::message_file_start(&header),
+        Ok((version, start)) if version == schema::SCHEMA_VERSION && start != 0
+    )
+}
+
+/// Reads every message stored in a message file, stops at the first record that cannot be deserialized.
+pub async fn read_day_file(path: &std::path::Path) -> Result<Vec<SignedMessage>> {
+    read_day_file_from(path, 0).await
+}
+
+/// Reads the messages stored in a message file starting at the given byte offset or at the first record if the offset points into the file's header, stops at the first record that cannot be deserialized.
+/// Messages in files written before sequence numbers were stored in records get their sequence numbers from the file's index.
+pub async fn read_day_file_from(path: &std::path::Path, offset: u64) -> Result<Vec<SignedMessage>> {
+    let bytes = read_day_bytes(path).await?;
+    let len = bytes.len() as u64;
+    let (version, start) = schema::message_file_start(&bytes)?;
+    let sequences: HashMap<u64, u64> = if version < schema::MESSAGE_SEQUENCE_VERSION {
+        index::read(path)
+            .await
+            .iter()
+            .map(|entry| (entry.offset, entry.sequence))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    let mut cursor = Cursor::new(bytes);
+    cursor.set_position(offset.max(start));
+    let mut result = Vec::new();
+    while cursor.position() < len {
+        let offset = cursor.position();
+        if let Ok(mut message) = schema::read_message(version, &mut cursor) {
+            if let Some(sequence) = sequences.get(&offset) {
+                message.sequence = *sequence;
+            }
+            result.push(message);
+        } else {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// Rewrites a message file with a header and records for the current schema version unless it already has them, returns false if the file was already up to date.
+/// If the file has an index, the given entries are moved along with the records they point to and give the records their sequence numbers, records without an entry get `sequence`.
+async fn upgrade_message_file(
+    path: &std::path::Path,
+    entries: Option<&[IndexEntry]>,
+    sequence: u64,
+) -> Result<bool> {
+    let bytes = read_day_bytes(path).await?;
+    let (version, start) = schema::message_file_start(&bytes)?;
+    if start != 0 && version == schema::SCHEMA_VERSION {
+        return Ok(false);
+    }
+    let entries = entries.unwrap_or_default();
+    let len = bytes.len() as u64;
+    let mut cursor = Cursor::new(bytes);
+    cursor.set_position(start);
+    let mut upgraded = schema::message_file_header().to_vec();
+    let mut moved = Vec::with_capacity(entries.len());
+    while cursor.position() < len {
+        let offset = cursor.position();
+        let mut message = match schema::read_message(version, &mut cursor) {
+            Ok(message) => message,
+            Err(_) => break,
+        };
+        let entry = entries.iter().find(|entry| entry.offset == offset);
+        if version < schema::MESSAGE_SEQUENCE_VERSION {
+            message.sequence = entry.map_or(sequence, |entry| entry.sequence);
+        }
+        let record = bincode::serialize(&message)?;
+        if let Some(entry) = entry {
+            moved.push(IndexEntry {
+                offset: upgraded.len() as u64,
+                length: record.len() as u32,
+                ..*entry
+            });
+        }
+        upgraded.extend(record);
+    }
+    if is_compressed(path).await {
+        upgraded = zstd::encode_all(upgraded.as_slice(), ZSTD_LEVEL)?;
+    }
+    let _ = fs::remove_file(index::index_path(path)).await;
+    write_atomic(&path.to_string_lossy(), &upgraded).await?;
+    if !moved.is_empty() {
+        index::write(path, &moved).await?;
+    }
+    Ok(true)
+}
+
+#[async_trait]
+impl HubStore for FsStore {
+    async fn save_hub(&self, hub: &Hub) -> Result {
+        let _guard = self.save_lock.lock().await;
+        match self.load_hub(hub.id).await {
+            Ok(stored) if stored.version + 1 != hub.version => return Err(Error::Conflict),
+            Err(Error::HubNotFound) | Ok(_) => {}
+            Err(err) => return Err(err),
+        }
+        fs::create_dir_all(&self.info_folder).await?;
+        let bytes = schema::encode_hub(hub)?;
+        write_atomic(&self.hub_info_path(hub.id), &bytes).await
+    }
+
+    async fn load_hub(&self, id: ID) -> Result<Hub> {
+        let filename = self.hub_info_path(id);
+        let path = std::path::Path::new(&filename);
+        if !path.exists() {
+            return Err(Error::HubNotFound);
+        }
+        let buf = fs::read(path).await?;
+        schema::decode_hub(&buf)
+    }
+
+    async fn hub_exists(&self, id: ID) -> bool {
+        std::path::Path::new(&self.hub_info_path(id)).exists()
+    }
+
+    async fn delete_hub(&self, id: ID) -> Result {
+        fs::remove_file(self.hub_info_path(id)).await?;
+        Ok(())
+    }
+
+    async fn list_hubs(&self) -> Result<Vec<ID>> {
+        let mut result = Vec::new();
+        if let Ok(mut dir) = fs::read_dir(&self.info_folder).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                if let Some(id) = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| u128::from_str_radix(name, 16).ok())
+                {
+                    result.push(ID::from_u128(id));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    async fn upgrade_hubs(&self) -> Result<usize> {
+        let _guard = self.save_lock.lock().await;
+        let mut count = 0;
+        for id in self.list_hubs().await? {
+            let path = self.hub_info_path(id);
+            let bytes = fs::read(&path).await?;
+            if !schema::is_current_hub(&bytes) {
+                let hub = schema::decode_hub(&bytes)?;
+                write_atomic(&path, &schema::encode_hub(&hub)?).await?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    async fn save_conversation(&self, conversation: &Conversation) -> Result {
+        fs::create_dir_all(self.conversation_folder()).await?;
+        write_atomic(
+            &self.conversation_path(conversation.id),
+            &schema::encode_conversation(conversation)?,
+        )
+        .await
+    }
+
+    async fn load_conversation(&self, id: ID) -> Result<Conversation> {
+        match fs::read(self.conversation_path(id)).await {
+            Ok(bytes) => schema::decode_conversation(&bytes),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::ConversationNotFound)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list_conversations(&self, user_id: &str) -> Result<Vec<Conversation>> {
+        Ok(self
+            .all_conversations()
+            .await
+            .into_iter()
+            .filter(|conversation| conversation.is_participant(user_id))
+            .collect())
+    }
+
+    async fn list_all_conversations(&self) -> Result<Vec<Conversation>> {
+        Ok(self.all_conversations().await)
+    }
+}
+
+#[async_trait]
+impl MessageStore for FsStore {
+    async fn create_channel(&self, hub_id: ID, channel_id: ID) -> Result {
+        fs::create_dir_all(self.channel_folder(hub_id, channel_id)).await?;
+        Ok(())
+    }
+
+    async fn delete_hub_messages(&self, hub_id: ID) -> Result {
+        let path = self.hub_data_path(hub_id);
+        if std::path::Path::new(&path).is_dir() {
+            fs::remove_dir_all(path).await?;
+        }
+        self.indexes
+            .lock()
+            .await
+            .retain(|(hub, _), _| hub != &hub_id);
+        Ok(())
+    }
+
+    async fn add_message(&self, hub_id: ID, channel_id: ID, message: SignedMessage) -> Result<u64> {
+        self.append_message(hub_id, channel_id, message, None).await
+    }
+
+    /// The message is appended to the file for the day it was created, or to the newest message file if that is for a later day so that messages stay in sequence order.
+    async fn import_message(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message: SignedMessage,
+    ) -> Result<u64> {
+        let day = day_of(&message.created);
+        self.append_message(hub_id, channel_id, message, Some(day))
+            .await
+    }
+
+    async fn get_message(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+    ) -> Result<Option<SignedMessage>> {
+        match self
+            .with_index(hub_id, channel_id, |index| index.get(&id))
+            .await
+        {
+            Some((path, entry)) => Ok(Some(index::read_entry(&path, &entry).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        ids: &[ID],
+    ) -> Result<Vec<SignedMessage>> {
+        let files = self
+            .with_index(hub_id, channel_id, |index| {
+                let mut found: Vec<(usize, usize)> = ids
+                    .iter()
+                    .filter_map(|id| index.positions.get(id).copied())
+                    .collect();
+                found.sort_unstable();
+                found.dedup();
+                let mut files: Vec<(PathBuf, Vec<IndexEntry>)> = Vec::new();
+                for (file, position) in found {
+                    let (path, entries) = &index.files[file];
+                    match files.last_mut() {
+                        Some((last, wanted)) if last == path => wanted.push(entries[position]),
+                        _ => files.push((path.clone(), vec![entries[position]])),
+                    }
+                }
+                files
+            })
+            .await;
+        Self::read_indexed(files).await
+    }
+
+    async fn get_last_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        let mut result = Vec::new();
+        for (_, path) in self.day_files(hub_id, channel_id).await.iter().rev() {
+            if let Ok(messages) = read_day_file(path).await {
+                result.extend(messages.into_iter().rev());
+                if result.len() >= max {
+                    break;
+                }
+            }
+        }
+        result.truncate(max);
+        Ok(result)
+    }
+
+    async fn get_messages_between(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        invert: bool,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        let mut result = Vec::new();
+        let (day_from, day_to) = (day_of(&from), day_of(&to));
+        let mut files = self.day_files(hub_id, channel_id).await;
+        files.retain(|(day, _)| *day >= day_from && *day <= day_to);
+        if invert {
+            files.reverse();
+        }
+        for (_, path) in files {
+            if let Ok(messages) = read_day_file(&path).await {
+                let mut filtered = messages
+                    .into_iter()
+                    .filter(|m| m.created >= from && m.created <= to)
+                    .collect::<Vec<SignedMessage>>();
+                if invert {
+                    filtered.reverse();
+                }
+                result.append(&mut filtered);
+                if result.len() >= max {
+                    break;
+                }
+            }
+        }
+        result.truncate(max);
+        Ok(result)
+    }
+
+    async fn get_messages_before(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        let files = self
+            .with_index(hub_id, channel_id, |index| {
+                index
+                    .positions
+                    .get(&id)
+                    .map(|(file, position)| index.take_before(*file, *position, max))
+            })
+            .await
+            .unwrap_or_default();
+        Self::read_indexed(files).await
+    }
+
+    async fn get_messages_after(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        let files = self
+            .with_index(hub_id, channel_id, |index| {
+                index
+                    .positions
+                    .get(&id)
+                    .map(|(file, position)| index.take_from(*file, position + 1, max))
+            })
+            .await
+            .unwrap_or_default();
+        Self::read_indexed(files).await
+    }
+
+    async fn get_messages_from_sequence(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        from: u64,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        let files = self
+            .with_index(hub_id, channel_id, |index| {
+                index.take_from_sequence(from, max)
+            })
+            .await;
+        Self::read_indexed(files).await
+    }
+
+    /// Revisions are appended to one file per message, in the same format as message files.
+    async fn add_revision(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        revision: SignedMessage,
+    ) -> Result {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let path = self.revisions_path(hub_id, channel_id, message_id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        if path.is_file()
+            && read_day_file(&path)
+                .await?
+                .iter()
+                .any(|existing| existing.id == revision.id)
+        {
+            return Err(Error::RevisionAlreadyExists);
+        }
+        if !is_current_message_file(&path).await {
+            upgrade_message_file(&path, None, revision.sequence).await?;
+        }
+        self.append_journaled(
+            hub_id,
+            channel_id,
+            &path,
+            &schema::message_file_header(),
+            &bincode::serialize(&revision)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_revisions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<SignedMessage>> {
+        let path = self.revisions_path(hub_id, channel_id, message_id);
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let mut revisions = read_day_file(&path).await?;
+        if !is_current_message_file(&path).await {
+            if let Some(message) = self.get_message(hub_id, channel_id, message_id).await? {
+                for revision in revisions.iter_mut() {
+                    revision.sequence = message.sequence;
+                }
+            }
+        }
+        Ok(revisions)
+    }
+
+    /// Rewrites the message file that holds the message, the file's index is removed first so that it is rebuilt if the rewrite is interrupted.
+    async fn redact_message(&self, hub_id: ID, channel_id: ID, tombstone: SignedMessage) -> Result {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let (path, entries) = self
+            .channel_index(hub_id, channel_id)
+            .await
+            .into_iter()
+            .find(|(_, entries)| entries.iter().any(|e| e.id == tombstone.id))
+            .ok_or(Error::MessageNotFound)?;
+        let compressed = is_compressed(&path).await;
+        let messages = index::read_entries(&path, &entries).await?;
+        let mut bytes = schema::message_file_header().to_vec();
+        let mut new_entries = Vec::with_capacity(entries.len());
+        let mut redacted = false;
+        for (entry, message) in entries.iter().zip(messages.iter()) {
+            let record = if !redacted && entry.id == tombstone.id {
+                redacted = true;
+                bincode::serialize(&SignedMessage {
+                    sequence: entry.sequence,
+                    ..tombstone.clone()
+                })?
+            } else {
+                bincode::serialize(message)?
+            };
+            new_entries.push(IndexEntry {
+                id: entry.id,
+                offset: bytes.len() as u64,
+                length: record.len() as u32,
+                sequence: entry.sequence,
+            });
+            bytes.extend(record);
+        }
+        if compressed {
+            bytes = zstd::encode_all(bytes.as_slice(), ZSTD_LEVEL)?;
+        }
+        let _ = fs::remove_file(index::index_path(&path)).await;
+        write_atomic(&path.to_string_lossy(), &bytes).await?;
+        index::write(&path, &new_entries).await?;
+        self.forget_index(hub_id, channel_id).await;
+        self.remove_message_sidecars(hub_id, channel_id, tombstone.id)
+            .await;
+        Ok(())
+    }
+
+    /// Reactions are kept in one file per message that is replaced whenever a reaction is added or removed.
+    async fn add_reaction(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool> {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let mut reactions = self.read_reactions(hub_id, channel_id, message_id).await?;
+        if !Reaction::add_to(&mut reactions, user_id, emoji) {
+            return Ok(false);
+        }
+        self.write_reactions(hub_id, channel_id, message_id, &reactions)
+            .await?;
+        Ok(true)
+    }
+
+    async fn remove_reaction(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool> {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let mut reactions = self.read_reactions(hub_id, channel_id, message_id).await?;
+        if !Reaction::remove_from(&mut reactions, user_id, emoji) {
+            return Ok(false);
+        }
+        self.write_reactions(hub_id, channel_id, message_id, &reactions)
+            .await?;
+        Ok(true)
+    }
+
+    async fn get_reactions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<Reaction>> {
+        self.read_reactions(hub_id, channel_id, message_id).await
+    }
+
+    /// Replies are appended to a single file per channel as fixed size records.
+    async fn add_reply(&self, hub_id: ID, channel_id: ID, root_id: ID, message_id: ID) -> Result {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let mut record = [0; THREAD_RECORD_SIZE];
+        record[0..16].copy_from_slice(&root_id.as_u128().to_le_bytes());
+        record[16..32].copy_from_slice(&message_id.as_u128().to_le_bytes());
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.threads_path(hub_id, channel_id))
+            .await?;
+        file.write_all(&record).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn get_replies(&self, hub_id: ID, channel_id: ID, root_id: ID) -> Result<Vec<ID>> {
+        Ok(self
+            .read_thread_records(hub_id, channel_id)
+            .await?
+            .into_iter()
+            .filter(|(root, _)| root == &root_id)
+            .map(|(_, reply)| reply)
+            .collect())
+    }
+
+    async fn get_threads(&self, hub_id: ID, channel_id: ID) -> Result<Vec<ThreadSummary>> {
+        Ok(ThreadSummary::from_replies(
+            self.read_thread_records(hub_id, channel_id).await?,
+        ))
+    }
+
+    async fn set_read_marker(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        user_id: &str,
+        marker: ReadMarker,
+    ) -> Result<bool> {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let mut markers = self.read_markers(hub_id, channel_id).await?;
+        if markers
+            .get(user_id)
+            .is_some_and(|stored| stored.sequence > marker.sequence)
+        {
+            return Ok(false);
+        }
+        markers.insert(user_id.to_string(), marker);
+        let path = self.read_markers_path(hub_id, channel_id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        write_atomic(
+            &path.to_string_lossy(),
+            &schema::encode_read_markers(&markers)?,
+        )
+        .await?;
+        Ok(true)
+    }
+
+    async fn get_read_marker(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        user_id: &str,
+    ) -> Result<Option<ReadMarker>> {
+        Ok(self.read_markers(hub_id, channel_id).await?.remove(user_id))
+    }
+
+    /// Only deletes whole message files, a file is deleted once every message in it has expired.
+    /// The threads file is rewritten without the records that refer to deleted messages.
+    async fn prune_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        policy: RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ID>> {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let cutoff_day = policy.cutoff(now).map(|cutoff| day_of(&cutoff));
+        let files = self.channel_index(hub_id, channel_id).await;
+        let mut remaining: u64 = files.iter().map(|(_, e)| e.len() as u64).sum();
+        let next_sequence = files
+            .iter()
+            .rev()
+            .find_map(|(_, entries)| entries.last())
+            .map(|last| last.sequence + 1);
+        let mut removed = Vec::new();
+        for (path, entries) in files {
+            let day = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(parse_day)
+                .map(|(day, _)| day);
+            let too_old = matches!((day, cutoff_day), (Some(day), Some(cutoff)) if day < cutoff);
+            let over_limit = policy
+                .max_messages
+                .is_some_and(|max| remaining - entries.len() as u64 >= max);
+            if !too_old && !over_limit {
+                break;
+            }
+            if removed.is_empty() {
+                if let Some(next_sequence) = next_sequence {
+                    write_atomic(
+                        &self
+                            .sequence_floor_path(hub_id, channel_id)
+                            .to_string_lossy(),
+                        &next_sequence.to_le_bytes(),
+                    )
+                    .await?;
+                }
+            }
+            fs::remove_file(&path).await?;
+            let _ = fs::remove_file(index::index_path(&path)).await;
+            remaining -= entries.len() as u64;
+            for entry in entries.iter() {
+                self.remove_message_sidecars(hub_id, channel_id, entry.id)
+                    .await;
+            }
+            removed.extend(entries.iter().map(|e| e.id));
+        }
+        if !removed.is_empty() {
+            self.forget_index(hub_id, channel_id).await;
+            let removed_ids: HashSet<&ID> = removed.iter().collect();
+            let records = self.read_thread_records(hub_id, channel_id).await?;
+            let kept: Vec<u8> = records
+                .iter()
+                .filter(|(root, reply)| !removed_ids.contains(root) && !removed_ids.contains(reply))
+                .flat_map(|(root, reply)| {
+                    let mut record = root.as_u128().to_le_bytes().to_vec();
+                    record.extend_from_slice(&reply.as_u128().to_le_bytes());
+                    record
+                })
+                .collect();
+            if kept.len() != records.len() * THREAD_RECORD_SIZE {
+                write_atomic(
+                    &self.threads_path(hub_id, channel_id).to_string_lossy(),
+                    &kept,
+                )
+                .await?;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Compresses message files with zstd, the file for the current day is never compressed since messages are still being appended to it.
+    async fn compact_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        before: DateTime<Utc>,
+    ) -> Result<usize> {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let last_day = day_of(&before).min(day_of(&Utc::now()));
+        // Makes sure that every file has a complete index before its offsets stop matching the file on disk.
+        self.channel_index(hub_id, channel_id).await;
+        let mut count = 0;
+        for (day, path) in self.day_files(hub_id, channel_id).await {
+            if day >= last_day {
+                break;
+            }
+            if is_compressed(&path).await {
+                continue;
+            }
+            let bytes = fs::read(&path).await?;
+            let compressed = zstd::encode_all(bytes.as_slice(), ZSTD_LEVEL)?;
+            write_atomic(&path.to_string_lossy(), &compressed).await?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Finishes the append left in the channel's journal, the indexes of the message files that had to be written again are deleted so that they are rebuilt.
+    async fn recover_messages(&self, hub_id: ID, channel_id: ID) -> Result<usize> {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        let folder = PathBuf::from(self.channel_folder(hub_id, channel_id));
+        let repaired = journal::recover(&folder, self.fsync).await?;
+        for path in repaired.iter() {
+            let _ = fs::remove_file(index::index_path(path)).await;
+        }
+        self.forget_index(hub_id, channel_id).await;
+        Ok(repaired.len())
+    }
+
+    /// Message files and revision files that were written without a header, or with the header of an older schema version, are rewritten in the current version.
+    /// Message files named after their date are renamed after the number of their day first, they are always rewritten as well since those names predate file headers.
+    /// Revisions get the sequence number of the message they revise.
+    async fn upgrade_messages(&self, hub_id: ID, channel_id: ID) -> Result<usize> {
+        let lock = self.append_lock(hub_id, channel_id).await;
+        let _guard = lock.lock().await;
+        self.rename_legacy_day_files(hub_id, channel_id).await?;
+        self.forget_index(hub_id, channel_id).await;
+        let mut count = 0;
+        let mut sequences = HashMap::new();
+        for (path, entries) in self.channel_index(hub_id, channel_id).await {
+            sequences.extend(entries.iter().map(|entry| (entry.id, entry.sequence)));
+            if upgrade_message_file(&path, Some(&entries), 0).await? {
+                count += 1;
+            }
+        }
+        let revisions_folder = format!("{}/revisions", self.channel_folder(hub_id, channel_id));
+        if let Ok(mut dir) = fs::read_dir(revisions_folder).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                let path = entry.path();
+                let sequence = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| u128::from_str_radix(name, 16).ok())
+                    .and_then(|id| sequences.get(&ID::from_u128(id)))
+                    .copied()
+                    .unwrap_or_default();
+                if path.is_file() && upgrade_message_file(&path, None, sequence).await? {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{day_of, index, journal, FsStore, HubStore, MessageStore};
+    use crate::{
+        channel::{RetentionPolicy, SignedMessage},
+        conversation::Conversation,
+        error::Error,
+        hub::Hub,
+        new_id,
+        test_util::TempDir,
+        ID,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[tokio::test]
+    async fn corrupt_hub_file() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let hub = Hub::new("test_hub".to_string(), new_id(), "owner".to_string());
+        store.save_hub(&hub).await.expect("Failed to save the hub.");
+        assert_eq!(
+            store.list_hubs().await.expect("Failed to list hubs."),
+            vec![hub.id]
+        );
+        let loaded = store
+            .load_hub(hub.id)
+            .await
+            .expect("Failed to load the hub.");
+        assert_eq!(loaded.name, hub.name);
+        let path = store.hub_info_path(hub.id);
+        let mut bytes = tokio::fs::read(&path).await.unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        tokio::fs::write(&path, bytes).await.unwrap();
+        assert!(matches!(
+            store.load_hub(hub.id).await,
+            Err(Error::HubCorrupt)
+        ));
+    }
+
+    #[tokio::test]
+    async fn conversations() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (alice, bob) = ("AA".repeat(20), "BB".repeat(20));
+        let conversation =
+            Conversation::new(&[alice.clone(), bob]).expect("Failed to create a conversation.");
+        store
+            .save_conversation(&conversation)
+            .await
+            .expect("Failed to save the conversation.");
+        assert_eq!(
+            store
+                .load_conversation(conversation.id)
+                .await
+                .expect("Failed to load the conversation."),
+            conversation
+        );
+        assert_eq!(
+            store
+                .list_conversations(&alice)
+                .await
+                .expect("Failed to list conversations."),
+            vec![conversation]
+        );
+        assert!(store
+            .list_conversations(&"CC".repeat(20))
+            .await
+            .expect("Failed to list conversations.")
+            .is_empty());
+        assert!(matches!(
+            store.load_conversation(new_id()).await,
+            Err(Error::ConversationNotFound)
+        ));
+        // The conversations folder is inside the hub information folder but must not be mistaken for a hub.
+        assert!(store
+            .list_hubs()
+            .await
+            .expect("Failed to list hubs.")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn indexed_lookups() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        store
+            .create_channel(hub_id, channel_id)
+            .await
+            .expect("Failed to create the channel folder.");
+        let ids: Vec<ID> = (0..5).map(|_| new_id()).collect();
+        for id in ids.iter() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, Utc::now(), id.to_string()),
+                )
+                .await
+                .expect("Failed to add a message.");
+        }
+        let message = store
+            .get_message(hub_id, channel_id, ids[3])
+            .await
+            .expect("Failed to read the message.")
+            .expect("Message was not found.");
+        assert_eq!(message.armoured_content, ids[3].to_string());
+        let after = store
+            .get_messages_after(hub_id, channel_id, ids[1], 2)
+            .await
+            .expect("Failed to read messages.");
+        assert_eq!(after.iter().map(|m| m.id).collect::<Vec<ID>>(), ids[2..4]);
+        let before = store
+            .get_messages_before(hub_id, channel_id, ids[3], 2)
+            .await
+            .expect("Failed to read messages.");
+        assert_eq!(before.iter().map(|m| m.id).collect::<Vec<ID>>(), ids[1..3]);
+        let (_, path) = store.day_files(hub_id, channel_id).await.pop().unwrap();
+        let sequences: Vec<u64> = index::read(&path)
+            .await
+            .iter()
+            .map(|e| e.sequence)
+            .collect();
+        assert_eq!(sequences, vec![0, 1, 2, 3, 4]);
+        // An index that is behind its message file is completed from the end of its last entry when the channel is first used.
+        let entries = index::read(&path).await;
+        index::write(&path, &entries[..2])
+            .await
+            .expect("Failed to write the index.");
+        let store = root.store();
+        let message = store
+            .get_message(hub_id, channel_id, ids[4])
+            .await
+            .expect("Failed to read the message.")
+            .expect("Message was not found.");
+        assert_eq!(message.sequence, 4);
+        assert_eq!(index::read(&path).await, entries);
+    }
+
+    #[tokio::test]
+    async fn cached_index() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        store
+            .create_channel(hub_id, channel_id)
+            .await
+            .expect("Failed to create the channel folder.");
+        let ids: Vec<ID> = (0..6).map(|_| new_id()).collect();
+        let message_ids =
+            |messages: Vec<SignedMessage>| -> Vec<ID> { messages.iter().map(|m| m.id).collect() };
+        for (i, id) in ids[..4].iter().enumerate() {
+            let created = Utc::now() - Duration::days(2 - i as i64 / 2);
+            store
+                .import_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, created, id.to_string()),
+                )
+                .await
+                .expect("Failed to import a message.");
+        }
+        assert_eq!(
+            message_ids(
+                store
+                    .get_messages_after(hub_id, channel_id, ids[0], 2)
+                    .await
+                    .expect("Failed to read messages.")
+            ),
+            ids[1..3]
+        );
+        // Messages appended after the index was loaded are found without loading it again.
+        for id in ids[4..].iter() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, Utc::now(), id.to_string()),
+                )
+                .await
+                .expect("Failed to add a message.");
+        }
+        assert_eq!(
+            message_ids(
+                store
+                    .get_messages_after(hub_id, channel_id, ids[3], 10)
+                    .await
+                    .expect("Failed to read messages.")
+            ),
+            ids[4..]
+        );
+        assert_eq!(
+            message_ids(
+                store
+                    .get_messages_from_sequence(hub_id, channel_id, 3, 2)
+                    .await
+                    .expect("Failed to read messages.")
+            ),
+            ids[3..5]
+        );
+        store
+            .redact_message(
+                hub_id,
+                channel_id,
+                SignedMessage::new(ids[2], Utc::now(), String::new()),
+            )
+            .await
+            .expect("Failed to redact the message.");
+        let redacted = store
+            .get_messages(hub_id, channel_id, &[ids[5], ids[2]])
+            .await
+            .expect("Failed to read messages.");
+        assert_eq!(message_ids(redacted.clone()), vec![ids[2], ids[5]]);
+        assert!(redacted[0].armoured_content.is_empty());
+        let policy = RetentionPolicy {
+            max_age_days: None,
+            max_messages: Some(4),
+        };
+        assert_eq!(
+            store
+                .prune_messages(hub_id, channel_id, policy, Utc::now())
+                .await
+                .expect("Failed to prune messages."),
+            ids[..2]
+        );
+        assert!(store
+            .get_message(hub_id, channel_id, ids[1])
+            .await
+            .expect("Failed to read the message.")
+            .is_none());
+        assert_eq!(
+            message_ids(
+                store
+                    .get_messages_before(hub_id, channel_id, ids[5], 10)
+                    .await
+                    .expect("Failed to read messages.")
+            ),
+            ids[2..5]
+        );
+    }
+
+    #[tokio::test]
+    async fn compressed_day_files() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        store
+            .create_channel(hub_id, channel_id)
+            .await
+            .expect("Failed to create the channel folder.");
+        let ids: Vec<ID> = (0..3).map(|_| new_id()).collect();
+        for id in ids.iter() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, Utc::now(), id.to_string()),
+                )
+                .await
+                .expect("Failed to add a message.");
+        }
+        let (day, path) = store.day_files(hub_id, channel_id).await.pop().unwrap();
+        let old_path = path.with_file_name((day - 2).to_string());
+        tokio::fs::rename(&path, &old_path).await.unwrap();
+        tokio::fs::rename(index::index_path(&path), index::index_path(&old_path))
+            .await
+            .unwrap();
+        let compressed = store
+            .compact_messages(hub_id, channel_id, Utc::now())
+            .await
+            .expect("Failed to compress messages.");
+        assert_eq!(compressed, 1);
+        assert!(super::is_compressed(&old_path).await);
+        let message = store
+            .get_message(hub_id, channel_id, ids[1])
+            .await
+            .expect("Failed to read the message.")
+            .expect("Message was not found.");
+        assert_eq!(message.armoured_content, ids[1].to_string());
+        let after = store
+            .get_messages_after(hub_id, channel_id, ids[0], 10)
+            .await
+            .expect("Failed to read messages.");
+        assert_eq!(after.iter().map(|m| m.id).collect::<Vec<ID>>(), ids[1..]);
+        let last = store
+            .get_last_messages(hub_id, channel_id, 10)
+            .await
+            .expect("Failed to read messages.");
+        assert_eq!(last.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn redacted_messages() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        store
+            .create_channel(hub_id, channel_id)
+            .await
+            .expect("Failed to create the channel folder.");
+        let ids: Vec<ID> = (0..3).map(|_| new_id()).collect();
+        for id in ids.iter() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, Utc::now(), id.to_string()),
+                )
+                .await
+                .expect("Failed to add a message.");
+        }
+        let revision = SignedMessage::new(new_id(), Utc::now(), "edited".to_string());
+        store
+            .add_revision(hub_id, channel_id, ids[1], revision.clone())
+            .await
+            .expect("Failed to add a revision.");
+        assert!(matches!(
+            store
+                .add_revision(hub_id, channel_id, ids[1], revision)
+                .await,
+            Err(Error::RevisionAlreadyExists)
+        ));
+        store
+            .add_reaction(hub_id, channel_id, ids[1], "user", "👍")
+            .await
+            .expect("Failed to add a reaction.");
+        store
+            .redact_message(
+                hub_id,
+                channel_id,
+                SignedMessage::new(ids[1], Utc::now(), String::new()),
+            )
+            .await
+            .expect("Failed to redact the message.");
+        let after = store
+            .get_messages_after(hub_id, channel_id, ids[0], 10)
+            .await
+            .expect("Failed to read messages.");
+        assert_eq!(
+            after
+                .iter()
+                .map(|m| m.armoured_content.clone())
+                .collect::<Vec<String>>(),
+            vec![String::new(), ids[2].to_string()]
+        );
+        assert!(store
+            .get_revisions(hub_id, channel_id, ids[1])
+            .await
+            .expect("Failed to read revisions.")
+            .is_empty());
+        assert!(store
+            .get_reactions(hub_id, channel_id, ids[1])
+            .await
+            .expect("Failed to read reactions.")
+            .is_empty());
+        assert_eq!(store.next_sequence(hub_id, channel_id).await, 3);
+        assert!(matches!(
+            store
+                .redact_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(new_id(), Utc::now(), String::new()),
+                )
+                .await,
+            Err(Error::MessageNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn imported_messages() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        store.create_channel(hub_id, channel_id).await.unwrap();
+        let now = Utc::now();
+        let sent = [
+            now - Duration::days(3),
+            now - Duration::days(1),
+            now - Duration::days(2),
+        ];
+        for (index, created) in sent.iter().enumerate() {
+            let sequence = store
+                .import_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(new_id(), *created, String::new()),
+                )
+                .await
+                .expect("Failed to import a message.");
+            assert_eq!(sequence, index as u64);
+        }
+        // The last message was created before the second one, it is kept after it so that messages stay in sequence order.
+        let days: Vec<i64> = store
+            .day_files(hub_id, channel_id)
+            .await
+            .iter()
+            .map(|(day, _)| *day)
+            .collect();
+        assert_eq!(days, vec![day_of(&sent[0]), day_of(&sent[1])]);
+        let between = store
+            .get_messages_between(
+                hub_id,
+                channel_id,
+                sent[0] - Duration::hours(1),
+                sent[0] + Duration::hours(1),
+                false,
+                10,
+            )
+            .await
+            .expect("Failed to read messages.");
+        assert_eq!(between.len(), 1);
+        assert_eq!(
+            store
+                .get_last_messages(hub_id, channel_id, 10)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn baseline_day_files() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        store.create_channel(hub_id, channel_id).await.unwrap();
+        let created = Utc.with_ymd_and_hms(2021, 5, 1, 12, 0, 0).unwrap();
+        let ids: Vec<ID> = (0..3).map(|_| new_id()).collect();
+        let mut bytes = Vec::new();
+        for id in ids.iter() {
+            bytes.extend(bincode::serialize(&(*id, created, id.to_string())).unwrap());
+        }
+        // Named like the files written before message files were named after their day number.
+        let legacy = format!(
+            "{}/{}",
+            store.channel_folder(hub_id, channel_id),
+            created.date_naive().format("%Y-%m-%dUTC")
+        );
+        assert!(legacy.ends_with("/2021-05-01UTC"));
+        tokio::fs::write(&legacy, bytes).await.unwrap();
+
+        let days: Vec<i64> = store
+            .day_files(hub_id, channel_id)
+            .await
+            .iter()
+            .map(|(day, _)| *day)
+            .collect();
+        assert_eq!(days, vec![day_of(&created)]);
+        let wanted = ids[1];
+        let read = |store: FsStore| async move {
+            let last = store
+                .get_last_messages(hub_id, channel_id, 10)
+                .await
+                .expect("Failed to read messages.");
+            let message = store
+                .get_message(hub_id, channel_id, wanted)
+                .await
+                .expect("Failed to read the message.")
+                .expect("Message was not found.");
+            (last.len(), message.armoured_content)
+        };
+        assert_eq!(read(store.clone()).await, (3, ids[1].to_string()));
+        assert_eq!(store.upgrade_messages(hub_id, channel_id).await.unwrap(), 1);
+        assert!(!std::path::Path::new(&legacy).exists());
+        assert_eq!(read(store.clone()).await, (3, ids[1].to_string()));
+    }
+
+    #[tokio::test]
+    async fn legacy_files() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let hub = Hub::new("test_hub".to_string(), new_id(), "owner".to_string());
+        let channel_id = new_id();
+        tokio::fs::create_dir_all(&store.info_folder).await.unwrap();
+        // Hub files written before there was a header, when hubs had no categories: the empty map of categories is the last 8 bytes.
+        let hub_bytes = bincode::serialize(&hub).unwrap();
+        tokio::fs::write(
+            store.hub_info_path(hub.id),
+            &hub_bytes[..hub_bytes.len() - 8],
+        )
+        .await
+        .unwrap();
+        store.create_channel(hub.id, channel_id).await.unwrap();
+        let ids: Vec<ID> = (0..3).map(|_| new_id()).collect();
+        let mut bytes = Vec::new();
+        for id in ids.iter() {
+            // Records written before messages had sequence numbers.
+            bytes.extend(bincode::serialize(&(*id, Utc::now(), id.to_string())).unwrap());
+        }
+        // Named after the date, like the files written before message files were named after their day number.
+        let legacy = format!(
+            "{}/{}",
+            store.channel_folder(hub.id, channel_id),
+            Utc::now().date_naive().format("%Y-%m-%dUTC")
+        );
+        tokio::fs::write(&legacy, bytes).await.unwrap();
+        let path = std::path::PathBuf::from(store.current_day_file(hub.id, channel_id));
+
+        let loaded = store
+            .load_hub(hub.id)
+            .await
+            .expect("Failed to load the hub.");
+        assert_eq!(loaded.name, hub.name);
+        assert_eq!(
+            store
+                .get_last_messages(hub.id, channel_id, 10)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(store.upgrade_hubs().await.unwrap(), 1);
+        assert_eq!(store.upgrade_hubs().await.unwrap(), 0);
+        assert_eq!(store.upgrade_messages(hub.id, channel_id).await.unwrap(), 1);
+        assert_eq!(store.upgrade_messages(hub.id, channel_id).await.unwrap(), 0);
+        assert!(!std::path::Path::new(&legacy).exists());
+        assert!(tokio::fs::read(&path).await.unwrap().starts_with(b"WMSG"));
+        assert_eq!(
+            store
+                .get_last_messages(hub.id, channel_id, 10)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+        let message = store
+            .get_message(hub.id, channel_id, ids[1])
+            .await
+            .expect("Failed to read the message.")
+            .expect("Message was not found.");
+        assert_eq!(message.armoured_content, ids[1].to_string());
+        let sequences: Vec<u64> = index::read(&path)
+            .await
+            .iter()
+            .map(|e| e.sequence)
+            .collect();
+        assert_eq!(sequences, vec![0, 1, 2]);
+        assert_eq!(message.sequence, 1);
+        assert_eq!(
+            store
+                .add_message(
+                    hub.id,
+                    channel_id,
+                    SignedMessage::new(new_id(), Utc::now(), String::new()),
+                )
+                .await
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            store
+                .get_last_messages(hub.id, channel_id, 10)
+                .await
+                .unwrap()
+                .len(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn sequences() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        store.create_channel(hub_id, channel_id).await.unwrap();
+        for expected in 0..3 {
+            let sequence = store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(new_id(), Utc::now(), String::new()),
+                )
+                .await
+                .expect("Failed to add a message.");
+            assert_eq!(sequence, expected);
+        }
+        let policy = RetentionPolicy {
+            max_age_days: None,
+            max_messages: Some(0),
+        };
+        assert_eq!(
+            store
+                .prune_messages(hub_id, channel_id, policy, Utc::now())
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+        let sequence = store
+            .add_message(
+                hub_id,
+                channel_id,
+                SignedMessage::new(new_id(), Utc::now(), String::new()),
+            )
+            .await
+            .expect("Failed to add a message.");
+        assert_eq!(sequence, 3);
+        let imported = SignedMessage {
+            sequence: 10,
+            ..SignedMessage::new(new_id(), Utc::now(), String::new())
+        };
+        assert_eq!(
+            store
+                .add_message(hub_id, channel_id, imported)
+                .await
+                .unwrap(),
+            10
+        );
+        let messages = store
+            .get_messages_from_sequence(hub_id, channel_id, 4, 10)
+            .await
+            .expect("Failed to read messages.");
+        assert_eq!(
+            messages.iter().map(|m| m.sequence).collect::<Vec<u64>>(),
+            vec![10]
+        );
+        assert_eq!(store.next_sequence(hub_id, channel_id).await, 11);
+    }
+
+    #[tokio::test]
+    async fn interrupted_appends() {
+        let root = TempDir::new("fs");
+        let store = root.store();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        let folder = std::path::PathBuf::from(store.channel_folder(hub_id, channel_id));
+        store.create_channel(hub_id, channel_id).await.unwrap();
+        let first = SignedMessage::new(new_id(), Utc::now(), "first".to_string());
+        store
+            .add_message(hub_id, channel_id, first.clone())
+            .await
+            .expect("Failed to add a message.");
+        assert!(tokio::fs::read(journal::journal_path(&folder))
+            .await
+            .unwrap()
+            .is_empty());
+
+        // The server stopped halfway through appending the second message.
+        let path = std::path::PathBuf::from(store.current_day_file(hub_id, channel_id));
+        let second = SignedMessage {
+            sequence: 1,
+            ..SignedMessage::new(new_id(), Utc::now(), "second".to_string())
+        };
+        let record = journal::Record {
+            file: path.file_name().unwrap().to_string_lossy().to_string(),
+            offset: tokio::fs::metadata(&path).await.unwrap().len(),
+            bytes: bincode::serialize(&second).unwrap(),
+        };
+        journal::write(&folder, &record, store.fsync).await.unwrap();
+        let mut bytes = tokio::fs::read(&path).await.unwrap();
+        bytes.extend_from_slice(&record.bytes[..record.bytes.len() / 2]);
+        tokio::fs::write(&path, bytes).await.unwrap();
+        assert_eq!(store.recover_messages(hub_id, channel_id).await.unwrap(), 1);
+        assert_eq!(store.recover_messages(hub_id, channel_id).await.unwrap(), 0);
+        let messages = store
+            .get_last_messages(hub_id, channel_id, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            messages.iter().map(|m| m.id).collect::<Vec<ID>>(),
+            vec![second.id, first.id]
+        );
+
+        // The server stopped halfway through journaling the third message, before the message file was touched.
+        let len = tokio::fs::metadata(&path).await.unwrap().len();
+        let record = journal::Record {
+            file: record.file,
+            offset: len,
+            bytes: bincode::serialize(&SignedMessage::new(new_id(), Utc::now(), String::new()))
+                .unwrap(),
+        };
+        journal::write(&folder, &record, store.fsync).await.unwrap();
+        let journal_bytes = tokio::fs::read(journal::journal_path(&folder))
+            .await
+            .unwrap();
+        tokio::fs::write(
+            journal::journal_path(&folder),
+            &journal_bytes[..journal_bytes.len() - 1],
+        )
+        .await
+        .unwrap();
+        assert_eq!(store.recover_messages(hub_id, channel_id).await.unwrap(), 0);
+        assert_eq!(tokio::fs::metadata(&path).await.unwrap().len(), len);
+        store
+            .add_message(
+                hub_id,
+                channel_id,
+                SignedMessage::new(new_id(), Utc::now(), String::new()),
+            )
+            .await
+            .expect("Failed to add a message.");
+        assert_eq!(store.next_sequence(hub_id, channel_id).await, 3);
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    io::{Cursor, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{channel::SignedMessage, error::Error, storage::schema, Result, ID};
+
+/// Size in bytes of a serialized [`IndexEntry`].
+const ENTRY_SIZE: usize = 36;
+
+/// Location of a single message inside of a message file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndexEntry {
+    /// ID of the message.
+    pub id: ID,
+    /// Byte offset at which the message record starts.
+    pub offset: u64,
+    /// Length of the message record in bytes.
+    pub length: u32,
+    /// Position of the message in the channel, starting at 0 for the first message ever sent in it.
+    pub sequence: u64,
+}
+
+impl IndexEntry {
+    /// Byte offset right after the end of the message record.
+    pub fn end(&self) -> u64 {
+        self.offset + self.length as u64
+    }
+
+    fn to_bytes(self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..16].copy_from_slice(&self.id.as_u128().to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.length.to_le_bytes());
+        bytes[28..36].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let u128_at = |range: std::ops::Range<usize>| {
+            u128::from_le_bytes(bytes[range].try_into().expect("Index entry too short."))
+        };
+        let u64_at = |range: std::ops::Range<usize>| {
+            u64::from_le_bytes(bytes[range].try_into().expect("Index entry too short."))
+        };
+        Self {
+            id: ID::from_u128(u128_at(0..16)),
+            offset: u64_at(16..24),
+            length: u32::from_le_bytes(bytes[24..28].try_into().expect("Index entry too short.")),
+            sequence: u64_at(28..36),
+        }
+    }
+}
+
+/// Gets the path of the index file that goes with a message file.
+pub fn index_path(day_file: &Path) -> PathBuf {
+    day_file.with_extension("idx")
+}
+
+/// Reads the index entries stored for a message file without checking them against the message file, a torn entry at the end is ignored.
+pub async fn read(day_file: &Path) -> Vec<IndexEntry> {
+    fs::read(index_path(day_file))
+        .await
+        .map(|bytes| {
+            bytes
+                .chunks_exact(ENTRY_SIZE)
+                .map(IndexEntry::from_bytes)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Loads the index of a message file, any messages in the file that are not in the index are added to it.
+/// The index is trusted as long as its last entry ends where the message file ends, otherwise only the part of the file after the last valid entry is read.
+/// Compressed files are never appended to, so their index is only rebuilt if it is missing.
+/// Rebuilt entries get the sequence numbers stored in their records, records written before sequence numbers were stored are numbered starting at `first_sequence` if the index was empty.
+/// The index file is only written if entries were added or dropped.
+pub async fn load(day_file: &Path, first_sequence: u64) -> Result<Vec<IndexEntry>> {
+    let mut entries = read(day_file).await;
+    // `bytes` holds the part of the file to scan, starting at offset `base` in the file, and scanning starts at `start` in `bytes`.
+    // `stale` is set if entries that point past the end of the file were dropped.
+    let (version, base, start, bytes, stale) = if super::is_compressed(day_file).await {
+        if !entries.is_empty() {
+            return Ok(entries);
+        }
+        let bytes = super::read_day_bytes(day_file).await?;
+        let (version, first_record) = schema::message_file_start(&bytes)?;
+        (version, 0, first_record, bytes, false)
+    } else {
+        let file_len = fs::metadata(day_file).await?.len();
+        let valid = entries
+            .iter()
+            .take_while(|entry| entry.end() <= file_len)
+            .count();
+        let stale = valid != entries.len();
+        entries.truncate(valid);
+        let mut file = fs::File::open(day_file).await?;
+        let (version, first_record) = read_header(&mut file).await?;
+        let start = entries.last().map_or(first_record, |entry| entry.end());
+        if start >= file_len && !stale {
+            return Ok(entries);
+        }
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut tail = Vec::with_capacity(file_len.saturating_sub(start) as usize);
+        file.read_to_end(&mut tail).await?;
+        (version, start, 0, tail, stale)
+    };
+    let len = bytes.len() as u64;
+    let indexed = entries.len();
+    let mut sequence = entries.last().map_or(first_sequence, |e| e.sequence + 1);
+    let mut cursor = Cursor::new(bytes);
+    cursor.set_position(start);
+    while cursor.position() < len {
+        let position = cursor.position();
+        if let Ok(message) = schema::read_message(version, &mut cursor) {
+            if version >= schema::MESSAGE_SEQUENCE_VERSION {
+                sequence = message.sequence;
+            }
+            entries.push(IndexEntry {
+                id: message.id,
+                offset: base + position,
+                length: (cursor.position() - position) as u32,
+                sequence,
+            });
+            sequence += 1;
+        } else {
+            break;
+        }
+    }
+    if stale || entries.len() != indexed {
+        write(day_file, &entries).await?;
+    }
+    Ok(entries)
+}
+
+/// Replaces the whole index of a message file.
+pub async fn write(day_file: &Path, entries: &[IndexEntry]) -> Result {
+    let bytes: Vec<u8> = entries.iter().flat_map(|e| e.to_bytes()).collect();
+    super::write_atomic(&index_path(day_file).to_string_lossy(), &bytes).await
+}
+
+/// Appends an entry to the index of a message file, an entry that was torn by a crash at the end of the index is removed first.
+pub async fn append(day_file: &Path, entry: IndexEntry) -> Result {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index_path(day_file))
+        .await?;
+    let len = file.metadata().await?.len();
+    if len % ENTRY_SIZE as u64 != 0 {
+        file.set_len(len - len % ENTRY_SIZE as u64).await?;
+    }
+    file.write_all(&entry.to_bytes()).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+/// Reads the messages that the given index entries point to.
+/// Only the part of the message file between the first and the last entry is read, unless the file is compressed and has to be decompressed whole.
+/// The messages get the sequence numbers of their entries.
+pub async fn read_entries(day_file: &Path, entries: &[IndexEntry]) -> Result<Vec<SignedMessage>> {
+    let (first, last) = match (
+        entries.iter().map(|e| e.offset).min(),
+        entries.iter().map(|e| e.end()).max(),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(Vec::new()),
+    };
+    let (version, base, bytes) = if super::is_compressed(day_file).await {
+        let bytes = super::read_day_bytes(day_file).await?;
+        (schema::message_file_start(&bytes)?.0, 0, bytes)
+    } else {
+        let mut file = fs::File::open(day_file).await?;
+        let (version, _) = read_header(&mut file).await?;
+        file.seek(SeekFrom::Start(first)).await?;
+        let mut bytes = vec![0; (last - first) as usize];
+        file.read_exact(&mut bytes).await?;
+        (version, first, bytes)
+    };
+    entries
+        .iter()
+        .map(|entry| {
+            let record = usize::try_from(entry.offset - base)
+                .ok()
+                .zip(usize::try_from(entry.end() - base).ok())
+                .and_then(|(start, end)| bytes.get(start..end))
+                .ok_or(Error::MessageNotFound)?;
+            Ok(SignedMessage {
+                sequence: entry.sequence,
+                ..schema::read_message(version, record)?
+            })
+        })
+        .collect()
+}
+
+/// Reads the header of an uncompressed message file, see [`schema::message_file_start`].
+async fn read_header(file: &mut fs::File) -> Result<(u32, u64)> {
+    let mut header = Vec::with_capacity(schema::MESSAGE_HEADER_SIZE as usize);
+    file.take(schema::MESSAGE_HEADER_SIZE)
+        .read_to_end(&mut header)
+        .await?;
+    schema::message_file_start(&header)
+}
+
+/// Reads the message an index entry points to, the message gets the sequence number of the entry.
+pub async fn read_entry(day_file: &Path, entry: &IndexEntry) -> Result<SignedMessage> {
+    read_entries(day_file, &[*entry])
+        .await?
+        .pop()
+        .ok_or(Error::MessageNotFound)
+}
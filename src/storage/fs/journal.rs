@@ -0,0 +1,156 @@
+use std::{
+    convert::TryInto,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{config::FsyncPolicy, Result};
+
+/// Name of the journal file kept in every channel folder.
+pub const JOURNAL_FILE: &str = "journal";
+/// Size in bytes of the header in front of every journal record: the length of the record followed by its CRC32 checksum.
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Write to a file in a channel folder that was journaled before being made.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Record {
+    /// Path of the file to write to, relative to the channel folder.
+    pub file: String,
+    /// Byte offset at which the bytes are written, anything after it in the file is cut off.
+    pub offset: u64,
+    /// Bytes to write.
+    pub bytes: Vec<u8>,
+}
+
+/// Gets the path of the journal of a channel folder.
+pub fn journal_path(channel_folder: &Path) -> PathBuf {
+    channel_folder.join(JOURNAL_FILE)
+}
+
+/// Serializes a record with its length and checksum in front of it.
+fn encode(record: &Record) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(record)?;
+    let mut bytes = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Deserializes the records in a journal, stops at the first record that is incomplete or does not match its checksum.
+pub fn decode(mut bytes: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    while bytes.len() >= RECORD_HEADER_SIZE {
+        let len = u32::from_le_bytes(bytes[0..4].try_into().expect("Header too short.")) as usize;
+        let checksum = u32::from_le_bytes(bytes[4..8].try_into().expect("Header too short."));
+        let payload = match bytes.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + len) {
+            Some(payload) if crc32fast::hash(payload) == checksum => payload,
+            _ => break,
+        };
+        match bincode::deserialize(payload) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+        bytes = &bytes[RECORD_HEADER_SIZE + len..];
+    }
+    records
+}
+
+/// Replaces the contents of a channel's journal with the given record.
+pub async fn write(channel_folder: &Path, record: &Record, fsync: FsyncPolicy) -> Result {
+    let mut file = fs::File::create(journal_path(channel_folder)).await?;
+    file.write_all(&encode(record)?).await?;
+    file.flush().await?;
+    if fsync == FsyncPolicy::Always {
+        file.sync_all().await?;
+    }
+    Ok(())
+}
+
+/// Empties a channel's journal once the write it holds has been made.
+pub async fn clear(channel_folder: &Path) -> Result {
+    match fs::OpenOptions::new()
+        .write(true)
+        .open(journal_path(channel_folder))
+        .await
+    {
+        Ok(file) => Ok(file.set_len(0).await?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Makes the write described by a record, if it fails the file is cut back to where the write started so that no partial record is left in it.
+pub async fn apply(channel_folder: &Path, record: &Record, fsync: FsyncPolicy) -> Result {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(channel_folder.join(&record.file))
+        .await?;
+    let result = async {
+        file.set_len(record.offset).await?;
+        file.seek(SeekFrom::Start(record.offset)).await?;
+        file.write_all(&record.bytes).await?;
+        file.flush().await?;
+        if fsync == FsyncPolicy::Always {
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+    .await;
+    if result.is_err() {
+        let _ = file.set_len(record.offset).await;
+    }
+    result
+}
+
+/// Checks if the write described by a record was completely made.
+async fn is_applied(channel_folder: &Path, record: &Record) -> bool {
+    let mut file = match fs::File::open(channel_folder.join(&record.file)).await {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    let mut buf = vec![0; record.bytes.len()];
+    file.seek(SeekFrom::Start(record.offset)).await.is_ok()
+        && file.read_exact(&mut buf).await.is_ok()
+        && buf == record.bytes
+}
+
+/// Finishes the writes left in a channel's journal by a crash and empties the journal, a record that was torn while it was being journaled is dropped.
+/// Files that are written again are cut off at the offset of the write first, so any partial record at their end is removed.
+/// Returns the paths of the files that had to be written again.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The journal could not be read or emptied.
+/// * A file could not be written to.
+pub async fn recover(channel_folder: &Path, fsync: FsyncPolicy) -> Result<Vec<PathBuf>> {
+    let bytes = match fs::read(journal_path(channel_folder)).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut repaired = Vec::new();
+    for record in decode(&bytes) {
+        let path = channel_folder.join(&record.file);
+        let len = fs::metadata(&path).await.map_or(0, |m| m.len());
+        // Earlier contents of the file never made it to disk, writing the record would leave a hole in it.
+        if len < record.offset || is_applied(channel_folder, &record).await {
+            continue;
+        }
+        apply(channel_folder, &record, fsync).await?;
+        repaired.push(path);
+    }
+    clear(channel_folder).await?;
+    Ok(repaired)
+}
@@ -0,0 +1,760 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::RwLock;
+
+use super::{HubStore, MessageStore};
+use crate::{
+    channel::{Reaction, ReadMarker, RetentionPolicy, SignedMessage, ThreadSummary},
+    conversation::Conversation,
+    error::Error,
+    hub::Hub,
+    Result, ID,
+};
+
+/// (root, reply) pairs of the threads in each channel, oldest to newest.
+type ThreadRecords = HashMap<(ID, ID), Vec<(ID, ID)>>;
+
+/// Keeps hubs and messages in memory, nothing is persisted once the store is dropped.
+#[derive(Default)]
+pub struct MemoryStore {
+    hubs: RwLock<HashMap<ID, Hub>>,
+    conversations: RwLock<HashMap<ID, Conversation>>,
+    messages: RwLock<HashMap<(ID, ID), Vec<SignedMessage>>>,
+    /// Sequence number of the next message sent in each channel.
+    sequences: RwLock<HashMap<(ID, ID), u64>>,
+    revisions: RwLock<HashMap<(ID, ID, ID), Vec<SignedMessage>>>,
+    reactions: RwLock<HashMap<(ID, ID, ID), Vec<Reaction>>>,
+    threads: RwLock<ThreadRecords>,
+    /// Read markers of each user in each channel.
+    read_markers: RwLock<HashMap<(ID, ID, String), ReadMarker>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl HubStore for MemoryStore {
+    async fn save_hub(&self, hub: &Hub) -> Result {
+        let mut hubs = self.hubs.write().await;
+        if let Some(stored) = hubs.get(&hub.id) {
+            if stored.version + 1 != hub.version {
+                return Err(Error::Conflict);
+            }
+        }
+        hubs.insert(hub.id, hub.clone());
+        Ok(())
+    }
+
+    async fn load_hub(&self, id: ID) -> Result<Hub> {
+        self.hubs
+            .read()
+            .await
+            .get(&id)
+            .cloned()
+            .ok_or(Error::HubNotFound)
+    }
+
+    async fn hub_exists(&self, id: ID) -> bool {
+        self.hubs.read().await.contains_key(&id)
+    }
+
+    async fn delete_hub(&self, id: ID) -> Result {
+        self.hubs
+            .write()
+            .await
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(Error::HubNotFound)
+    }
+
+    async fn list_hubs(&self) -> Result<Vec<ID>> {
+        Ok(self.hubs.read().await.keys().cloned().collect())
+    }
+
+    async fn save_conversation(&self, conversation: &Conversation) -> Result {
+        self.conversations
+            .write()
+            .await
+            .insert(conversation.id, conversation.clone());
+        Ok(())
+    }
+
+    async fn load_conversation(&self, id: ID) -> Result<Conversation> {
+        self.conversations
+            .read()
+            .await
+            .get(&id)
+            .cloned()
+            .ok_or(Error::ConversationNotFound)
+    }
+
+    async fn list_conversations(&self, user_id: &str) -> Result<Vec<Conversation>> {
+        Ok(self
+            .conversations
+            .read()
+            .await
+            .values()
+            .filter(|conversation| conversation.is_participant(user_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn list_all_conversations(&self) -> Result<Vec<Conversation>> {
+        Ok(self.conversations.read().await.values().cloned().collect())
+    }
+}
+
+#[async_trait]
+impl MessageStore for MemoryStore {
+    async fn create_channel(&self, hub_id: ID, channel_id: ID) -> Result {
+        self.messages
+            .write()
+            .await
+            .entry((hub_id, channel_id))
+            .or_default();
+        Ok(())
+    }
+
+    async fn delete_hub_messages(&self, hub_id: ID) -> Result {
+        self.messages
+            .write()
+            .await
+            .retain(|(hub, _), _| hub != &hub_id);
+        self.sequences
+            .write()
+            .await
+            .retain(|(hub, _), _| hub != &hub_id);
+        self.revisions
+            .write()
+            .await
+            .retain(|(hub, _, _), _| hub != &hub_id);
+        self.reactions
+            .write()
+            .await
+            .retain(|(hub, _, _), _| hub != &hub_id);
+        self.threads
+            .write()
+            .await
+            .retain(|(hub, _), _| hub != &hub_id);
+        Ok(())
+    }
+
+    async fn add_message(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        mut message: SignedMessage,
+    ) -> Result<u64> {
+        let mut messages = self.messages.write().await;
+        let mut sequences = self.sequences.write().await;
+        let next = sequences.entry((hub_id, channel_id)).or_default();
+        message.sequence = message.sequence.max(*next);
+        *next = message.sequence + 1;
+        let sequence = message.sequence;
+        messages
+            .entry((hub_id, channel_id))
+            .or_default()
+            .push(message);
+        Ok(sequence)
+    }
+
+    async fn get_message(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+    ) -> Result<Option<SignedMessage>> {
+        Ok(self
+            .messages
+            .read()
+            .await
+            .get(&(hub_id, channel_id))
+            .and_then(|messages| messages.iter().find(|m| m.id == id).cloned()))
+    }
+
+    async fn get_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        ids: &[ID],
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(self
+            .messages
+            .read()
+            .await
+            .get(&(hub_id, channel_id))
+            .map(|messages| {
+                messages
+                    .iter()
+                    .filter(|m| ids.contains(&m.id))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn get_last_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(self
+            .messages
+            .read()
+            .await
+            .get(&(hub_id, channel_id))
+            .map(|messages| messages.iter().rev().take(max).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn get_messages_between(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        invert: bool,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        let messages = self.messages.read().await;
+        let messages = if let Some(messages) = messages.get(&(hub_id, channel_id)) {
+            messages
+        } else {
+            return Ok(Vec::new());
+        };
+        let filter = |m: &&SignedMessage| m.created >= from && m.created <= to;
+        Ok(if invert {
+            messages
+                .iter()
+                .rev()
+                .filter(filter)
+                .take(max)
+                .cloned()
+                .collect()
+        } else {
+            messages.iter().filter(filter).take(max).cloned().collect()
+        })
+    }
+
+    async fn get_messages_before(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(self
+            .messages
+            .read()
+            .await
+            .get(&(hub_id, channel_id))
+            .and_then(|messages| {
+                let end = messages.iter().position(|m| m.id == id)?;
+                Some(messages[end.saturating_sub(max)..end].to_vec())
+            })
+            .unwrap_or_default())
+    }
+
+    async fn get_messages_after(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(self
+            .messages
+            .read()
+            .await
+            .get(&(hub_id, channel_id))
+            .map(|messages| {
+                messages
+                    .iter()
+                    .skip_while(|m| m.id != id)
+                    .skip(1)
+                    .take(max)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn get_messages_from_sequence(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        from: u64,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(self
+            .messages
+            .read()
+            .await
+            .get(&(hub_id, channel_id))
+            .map(|messages| {
+                messages
+                    .iter()
+                    .skip_while(|m| m.sequence < from)
+                    .take(max)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn add_revision(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        revision: SignedMessage,
+    ) -> Result {
+        let mut revisions = self.revisions.write().await;
+        let revisions = revisions
+            .entry((hub_id, channel_id, message_id))
+            .or_default();
+        if revisions.iter().any(|existing| existing.id == revision.id) {
+            return Err(Error::RevisionAlreadyExists);
+        }
+        revisions.push(revision);
+        Ok(())
+    }
+
+    async fn get_revisions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(self
+            .revisions
+            .read()
+            .await
+            .get(&(hub_id, channel_id, message_id))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn add_reaction(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool> {
+        Ok(Reaction::add_to(
+            self.reactions
+                .write()
+                .await
+                .entry((hub_id, channel_id, message_id))
+                .or_default(),
+            user_id,
+            emoji,
+        ))
+    }
+
+    async fn remove_reaction(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool> {
+        let mut reactions = self.reactions.write().await;
+        let key = (hub_id, channel_id, message_id);
+        let removed = reactions
+            .get_mut(&key)
+            .is_some_and(|r| Reaction::remove_from(r, user_id, emoji));
+        if reactions.get(&key).is_some_and(|r| r.is_empty()) {
+            reactions.remove(&key);
+        }
+        Ok(removed)
+    }
+
+    async fn get_reactions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<Reaction>> {
+        Ok(self
+            .reactions
+            .read()
+            .await
+            .get(&(hub_id, channel_id, message_id))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn add_reply(&self, hub_id: ID, channel_id: ID, root_id: ID, message_id: ID) -> Result {
+        self.threads
+            .write()
+            .await
+            .entry((hub_id, channel_id))
+            .or_default()
+            .push((root_id, message_id));
+        Ok(())
+    }
+
+    async fn get_replies(&self, hub_id: ID, channel_id: ID, root_id: ID) -> Result<Vec<ID>> {
+        Ok(self
+            .threads
+            .read()
+            .await
+            .get(&(hub_id, channel_id))
+            .map(|records| {
+                records
+                    .iter()
+                    .filter(|(root, _)| root == &root_id)
+                    .map(|(_, reply)| *reply)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn get_threads(&self, hub_id: ID, channel_id: ID) -> Result<Vec<ThreadSummary>> {
+        Ok(self
+            .threads
+            .read()
+            .await
+            .get(&(hub_id, channel_id))
+            .map(|records| ThreadSummary::from_replies(records.iter().copied()))
+            .unwrap_or_default())
+    }
+
+    async fn set_read_marker(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        user_id: &str,
+        marker: ReadMarker,
+    ) -> Result<bool> {
+        let mut markers = self.read_markers.write().await;
+        let key = (hub_id, channel_id, user_id.to_string());
+        if markers
+            .get(&key)
+            .is_some_and(|stored| stored.sequence > marker.sequence)
+        {
+            return Ok(false);
+        }
+        markers.insert(key, marker);
+        Ok(true)
+    }
+
+    async fn get_read_marker(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        user_id: &str,
+    ) -> Result<Option<ReadMarker>> {
+        Ok(self
+            .read_markers
+            .read()
+            .await
+            .get(&(hub_id, channel_id, user_id.to_string()))
+            .copied())
+    }
+
+    async fn redact_message(&self, hub_id: ID, channel_id: ID, tombstone: SignedMessage) -> Result {
+        let mut messages = self.messages.write().await;
+        let message = messages
+            .get_mut(&(hub_id, channel_id))
+            .and_then(|messages| messages.iter_mut().find(|m| m.id == tombstone.id))
+            .ok_or(Error::MessageNotFound)?;
+        self.revisions
+            .write()
+            .await
+            .remove(&(hub_id, channel_id, tombstone.id));
+        self.reactions
+            .write()
+            .await
+            .remove(&(hub_id, channel_id, tombstone.id));
+        *message = SignedMessage {
+            sequence: message.sequence,
+            ..tombstone
+        };
+        Ok(())
+    }
+
+    async fn prune_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        policy: RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ID>> {
+        let mut messages = self.messages.write().await;
+        let messages = if let Some(messages) = messages.get_mut(&(hub_id, channel_id)) {
+            messages
+        } else {
+            return Ok(Vec::new());
+        };
+        let mut expired = 0;
+        if let Some(cutoff) = policy.cutoff(now) {
+            expired = messages.iter().take_while(|m| m.created < cutoff).count();
+        }
+        if let Some(max) = policy.max_messages {
+            expired = expired.max(messages.len().saturating_sub(max as usize));
+        }
+        let removed: Vec<ID> = messages.drain(..expired).map(|m| m.id).collect();
+        let mut revisions = self.revisions.write().await;
+        let mut reactions = self.reactions.write().await;
+        for id in removed.iter() {
+            revisions.remove(&(hub_id, channel_id, *id));
+            reactions.remove(&(hub_id, channel_id, *id));
+        }
+        if let Some(records) = self.threads.write().await.get_mut(&(hub_id, channel_id)) {
+            records.retain(|(root, reply)| !removed.contains(root) && !removed.contains(reply));
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemoryStore, MessageStore};
+    use crate::{
+        channel::{ReadMarker, RetentionPolicy, SignedMessage},
+        new_id, ID,
+    };
+    use chrono::{Duration, Utc};
+
+    #[tokio::test]
+    async fn messages_after() {
+        let store = MemoryStore::new();
+        let (hub_id, channel_id) = (ID::nil(), ID::nil());
+        let ids: Vec<ID> = (0..4).map(|_| new_id()).collect();
+        for id in ids.iter() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, Utc::now(), String::new()),
+                )
+                .await
+                .expect("Failed to add a message to the store.");
+        }
+        let after = store
+            .get_messages_after(hub_id, channel_id, ids[1], 10)
+            .await
+            .expect("Failed to get messages from the store.");
+        assert_eq!(
+            after.iter().map(|m| m.id).collect::<Vec<ID>>(),
+            ids[2..].to_vec()
+        );
+        let before = store
+            .get_messages_before(hub_id, channel_id, ids[3], 2)
+            .await
+            .expect("Failed to get messages from the store.");
+        assert_eq!(
+            before.iter().map(|m| m.id).collect::<Vec<ID>>(),
+            ids[1..3].to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn read_markers() {
+        let store = MemoryStore::new();
+        let (hub_id, channel_id) = (new_id(), new_id());
+        let marker = |sequence| ReadMarker {
+            message_id: new_id(),
+            sequence,
+            updated: Utc::now(),
+        };
+        assert_eq!(
+            store
+                .get_read_marker(hub_id, channel_id, "reader")
+                .await
+                .expect("Failed to get the read marker."),
+            None
+        );
+        let later = marker(5);
+        assert!(store
+            .set_read_marker(hub_id, channel_id, "reader", later)
+            .await
+            .expect("Failed to set the read marker."));
+        assert!(!store
+            .set_read_marker(hub_id, channel_id, "reader", marker(2))
+            .await
+            .expect("Failed to set the read marker."));
+        assert_eq!(
+            store
+                .get_read_marker(hub_id, channel_id, "reader")
+                .await
+                .expect("Failed to get the read marker."),
+            Some(later)
+        );
+        assert_eq!(
+            store
+                .get_read_marker(hub_id, channel_id, "other")
+                .await
+                .expect("Failed to get the read marker."),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn reactions() {
+        let store = MemoryStore::new();
+        let (hub_id, channel_id, message_id) = (ID::nil(), ID::nil(), new_id());
+        for (user, emoji) in [("a", "👍"), ("b", "🎉"), ("b", "👍"), ("a", "👍")].iter() {
+            store
+                .add_reaction(hub_id, channel_id, message_id, user, emoji)
+                .await
+                .expect("Failed to add a reaction.");
+        }
+        let reactions = store
+            .get_reactions(hub_id, channel_id, message_id)
+            .await
+            .expect("Failed to get the reactions.");
+        assert_eq!(
+            reactions
+                .iter()
+                .map(|r| (r.emoji.as_str(), r.users.len()))
+                .collect::<Vec<(&str, usize)>>(),
+            vec![("👍", 2), ("🎉", 1)]
+        );
+        assert!(store
+            .remove_reaction(hub_id, channel_id, message_id, "b", "🎉")
+            .await
+            .expect("Failed to remove a reaction."));
+        assert!(!store
+            .remove_reaction(hub_id, channel_id, message_id, "b", "🎉")
+            .await
+            .expect("Failed to remove a reaction."));
+        assert_eq!(
+            store
+                .get_reactions(hub_id, channel_id, message_id)
+                .await
+                .expect("Failed to get the reactions.")
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn threads() {
+        let store = MemoryStore::new();
+        let (hub_id, channel_id) = (ID::nil(), ID::nil());
+        let now = Utc::now();
+        let ids: Vec<ID> = (0..5).map(|_| new_id()).collect();
+        for (age, id) in ids.iter().enumerate().rev() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, now - Duration::days(age as i64), String::new()),
+                )
+                .await
+                .expect("Failed to add a message to the store.");
+        }
+        for (root, reply) in [(ids[4], ids[3]), (ids[2], ids[1]), (ids[4], ids[0])].iter() {
+            store
+                .add_reply(hub_id, channel_id, *root, *reply)
+                .await
+                .expect("Failed to add a reply.");
+        }
+        assert_eq!(
+            store
+                .get_replies(hub_id, channel_id, ids[4])
+                .await
+                .expect("Failed to get the replies."),
+            vec![ids[3], ids[0]]
+        );
+        let threads = store
+            .get_threads(hub_id, channel_id)
+            .await
+            .expect("Failed to get the threads.");
+        assert_eq!(
+            threads
+                .iter()
+                .map(|t| (t.root, t.reply_count, t.last_reply))
+                .collect::<Vec<(ID, u64, ID)>>(),
+            vec![(ids[4], 2, ids[0]), (ids[2], 1, ids[1])]
+        );
+        let policy = RetentionPolicy {
+            max_age_days: Some(3),
+            max_messages: None,
+        };
+        store
+            .prune_messages(hub_id, channel_id, policy, now)
+            .await
+            .expect("Failed to prune messages.");
+        let threads = store
+            .get_threads(hub_id, channel_id)
+            .await
+            .expect("Failed to get the threads.");
+        assert_eq!(
+            threads.iter().map(|t| t.root).collect::<Vec<ID>>(),
+            vec![ids[2]]
+        );
+    }
+
+    #[tokio::test]
+    async fn prune_messages() {
+        let store = MemoryStore::new();
+        let (hub_id, channel_id) = (ID::nil(), ID::nil());
+        let now = Utc::now();
+        let ids: Vec<ID> = (0..5).map(|_| new_id()).collect();
+        for (age, id) in ids.iter().enumerate().rev() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, now - Duration::days(age as i64), String::new()),
+                )
+                .await
+                .expect("Failed to add a message to the store.");
+        }
+        let policy = RetentionPolicy {
+            max_age_days: Some(2),
+            max_messages: None,
+        };
+        let pruned = store
+            .prune_messages(hub_id, channel_id, policy, now)
+            .await
+            .expect("Failed to prune messages.");
+        assert_eq!(pruned, vec![ids[4], ids[3]]);
+        let policy = RetentionPolicy {
+            max_age_days: None,
+            max_messages: Some(1),
+        };
+        let pruned = store
+            .prune_messages(hub_id, channel_id, policy, now)
+            .await
+            .expect("Failed to prune messages.");
+        assert_eq!(pruned, vec![ids[2], ids[1]]);
+        let left = store
+            .get_last_messages(hub_id, channel_id, 10)
+            .await
+            .expect("Failed to get messages from the store.");
+        assert_eq!(left.iter().map(|m| m.id).collect::<Vec<ID>>(), vec![ids[0]]);
+        assert_eq!(left[0].sequence, 4);
+        let sequence = store
+            .add_message(
+                hub_id,
+                channel_id,
+                SignedMessage::new(new_id(), now, String::new()),
+            )
+            .await
+            .expect("Failed to add a message to the store.");
+        assert_eq!(sequence, 5);
+        let from = store
+            .get_messages_from_sequence(hub_id, channel_id, 5, 10)
+            .await
+            .expect("Failed to get messages from the store.");
+        assert_eq!(
+            from.iter().map(|m| m.sequence).collect::<Vec<u64>>(),
+            vec![5]
+        );
+    }
+}
@@ -0,0 +1,696 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+
+use crate::{
+    channel::{Reaction, ReadMarker, RetentionPolicy, SignedMessage, ThreadSummary},
+    config::{Config, StorageBackend},
+    conversation::Conversation,
+    error::Error,
+    hub::Hub,
+    Result, ID,
+};
+
+pub use fs::FsStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+/// Default storage, keeps hubs and messages as bincode files on disk.
+pub mod fs;
+/// Volatile storage, keeps everything in memory, useful for tests and ephemeral deployments.
+pub mod memory;
+/// Versioned serialization of hubs and message files, with migrations that upgrade records written by older versions of the server.
+pub mod schema;
+/// Embedded SQLite database storage.
+pub mod sqlite;
+
+/// Storage backend for hub records.
+#[async_trait]
+pub trait HubStore: Send + Sync {
+    /// Saves a hub, overwriting any previously saved version of it.
+    /// Returns [`crate::error::Error::Conflict`] if a saved version of the hub exists and its version is not exactly one less than the given hub's version.
+    async fn save_hub(&self, hub: &Hub) -> Result;
+
+    /// Loads the hub with the given ID, returns [`crate::error::Error::HubNotFound`] if there is no such hub.
+    async fn load_hub(&self, id: ID) -> Result<Hub>;
+
+    /// Checks if a hub with the given ID has been saved.
+    async fn hub_exists(&self, id: ID) -> bool;
+
+    /// Deletes the hub record with the given ID.
+    async fn delete_hub(&self, id: ID) -> Result;
+
+    /// Lists the IDs of all the saved hubs.
+    async fn list_hubs(&self) -> Result<Vec<ID>>;
+
+    /// Rewrites the hubs that were saved by older versions of the server in the current [`schema::SCHEMA_VERSION`], returns the number of hubs that were rewritten.
+    /// Stores that upgrade their records some other way do nothing.
+    async fn upgrade_hubs(&self) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Saves a direct conversation, overwriting any previously saved version of it.
+    async fn save_conversation(&self, conversation: &Conversation) -> Result;
+
+    /// Loads the conversation with the given ID, returns [`crate::error::Error::ConversationNotFound`] if there is no such conversation.
+    async fn load_conversation(&self, id: ID) -> Result<Conversation>;
+
+    /// Lists the conversations that a user is in, in no particular order.
+    async fn list_conversations(&self, user_id: &str) -> Result<Vec<Conversation>>;
+
+    /// Lists every saved conversation, in no particular order.
+    async fn list_all_conversations(&self) -> Result<Vec<Conversation>>;
+}
+
+/// Storage backend for the messages sent in channels.
+#[async_trait]
+pub trait MessageStore: Send + Sync {
+    /// Prepares the storage for a new channel.
+    async fn create_channel(&self, hub_id: ID, channel_id: ID) -> Result;
+
+    /// Deletes the messages of every channel in a hub.
+    async fn delete_hub_messages(&self, hub_id: ID) -> Result;
+
+    /// Appends a message to a channel and returns the sequence number it was stored with.
+    /// The message gets the next sequence number of the channel unless it already has a higher one, which keeps the numbers of messages that are copied from another store.
+    async fn add_message(&self, hub_id: ID, channel_id: ID, message: SignedMessage) -> Result<u64>;
+
+    /// Appends a message that was stored before, such as one read from an archive or another store, like [`MessageStore::add_message`].
+    /// Stores that group messages by the day they were stored in keep the message with the other messages sent on the day it was created, unless later messages were already stored.
+    async fn import_message(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message: SignedMessage,
+    ) -> Result<u64> {
+        self.add_message(hub_id, channel_id, message).await
+    }
+
+    /// Gets the first message with the given ID.
+    async fn get_message(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+    ) -> Result<Option<SignedMessage>>;
+
+    /// Gets all of the messages whose IDs are in `ids` that could be found.
+    async fn get_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        ids: &[ID],
+    ) -> Result<Vec<SignedMessage>>;
+
+    /// Gets the last `max` messages sent, ordered newest to oldest.
+    async fn get_last_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>>;
+
+    /// Gets up to `max` messages sent between `from` and `to`, ordered oldest to newest unless `invert` is true.
+    async fn get_messages_between(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        invert: bool,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>>;
+
+    /// Gets up to `max` of the messages sent right before the message with the given ID, ordered oldest to newest.
+    async fn get_messages_before(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>>;
+
+    /// Gets up to `max` messages sent after the message with the given ID, ordered oldest to newest.
+    async fn get_messages_after(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>>;
+
+    /// Gets up to `max` of the messages whose sequence numbers are `from` or higher, ordered by sequence number.
+    async fn get_messages_from_sequence(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        from: u64,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>>;
+
+    /// Appends a revision to the message with the given ID, the original message is never changed.
+    /// Returns [`Error::RevisionAlreadyExists`] if the message already has a revision with the same ID.
+    async fn add_revision(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        revision: SignedMessage,
+    ) -> Result;
+
+    /// Gets the revisions of the message with the given ID ordered oldest to newest, the original message is not included.
+    async fn get_revisions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<SignedMessage>>;
+
+    /// Adds a user's reaction to a message, returns false if the user had already reacted with the same emoji.
+    async fn add_reaction(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool>;
+
+    /// Removes a user's reaction from a message, returns false if the user had not reacted with the emoji.
+    async fn remove_reaction(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool>;
+
+    /// Gets the reactions to a message, ordered by when each emoji was first used.
+    async fn get_reactions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<Reaction>>;
+
+    /// Records that a message is a reply in the thread started by `root_id`.
+    async fn add_reply(&self, hub_id: ID, channel_id: ID, root_id: ID, message_id: ID) -> Result;
+
+    /// Gets the IDs of the replies in the thread started by `root_id`, ordered oldest to newest.
+    async fn get_replies(&self, hub_id: ID, channel_id: ID, root_id: ID) -> Result<Vec<ID>>;
+
+    /// Lists the threads in a channel, ordered by when each thread got its first reply.
+    async fn get_threads(&self, hub_id: ID, channel_id: ID) -> Result<Vec<ThreadSummary>>;
+
+    /// Moves a user's read marker in a channel, returns false without changing anything if the user's marker is already at a message with a higher sequence number.
+    async fn set_read_marker(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        user_id: &str,
+        marker: ReadMarker,
+    ) -> Result<bool>;
+
+    /// Gets a user's read marker in a channel, `None` if the user has not marked any message as read.
+    async fn get_read_marker(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        user_id: &str,
+    ) -> Result<Option<ReadMarker>>;
+
+    /// Replaces the message with the same ID as the tombstone with the tombstone and deletes the message's revisions and reactions.
+    /// Returns [`crate::error::Error::MessageNotFound`] if there is no such message.
+    async fn redact_message(&self, hub_id: ID, channel_id: ID, tombstone: SignedMessage) -> Result;
+
+    /// Deletes the messages in a channel that the given retention policy no longer allows keeping at the time `now`, returns the IDs of the deleted messages.
+    /// Stores are allowed to keep some expired messages if deleting them individually would be too expensive.
+    /// The revisions and reactions of deleted messages are deleted along with them, as are the thread records that refer to them.
+    async fn prune_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        policy: RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ID>>;
+
+    /// Compresses the messages in a channel that were sent before the given time, returns the number of files that were compressed.
+    /// Compressed messages must still be readable through all of the other methods, stores that have nothing to compress do nothing.
+    async fn compact_messages(
+        &self,
+        _hub_id: ID,
+        _channel_id: ID,
+        _before: DateTime<Utc>,
+    ) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Finishes or undoes the writes to a channel's messages that were interrupted by the server stopping, returns the number of files that had to be repaired.
+    /// Stores whose writes cannot be interrupted do nothing.
+    async fn recover_messages(&self, _hub_id: ID, _channel_id: ID) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Rewrites the message files of a channel that were written by older versions of the server in the current [`schema::SCHEMA_VERSION`], returns the number of files that were rewritten.
+    /// Stores that upgrade their records some other way do nothing.
+    async fn upgrade_messages(&self, _hub_id: ID, _channel_id: ID) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT_STORE: Arc<MemoryStore> = Arc::new(MemoryStore::new());
+    static ref HUB_STORE: RwLock<Arc<dyn HubStore>> = RwLock::new(DEFAULT_STORE.clone());
+    static ref MESSAGE_STORE: RwLock<Arc<dyn MessageStore>> = RwLock::new(DEFAULT_STORE.clone());
+    static ref DATA_DIR: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// Gets the hub store currently in use.
+/// Until [`init`] or one of the setters is called this is a [`MemoryStore`], so nothing is written to disk before a data folder has been configured.
+pub fn hub_store() -> Arc<dyn HubStore> {
+    HUB_STORE
+        .read()
+        .expect("Hub store lock was poisoned.")
+        .clone()
+}
+
+/// Gets the message store currently in use, see [`hub_store`].
+pub fn message_store() -> Arc<dyn MessageStore> {
+    MESSAGE_STORE
+        .read()
+        .expect("Message store lock was poisoned.")
+        .clone()
+}
+
+/// Gets the data folder that the stores were set up with by [`init`].
+/// Used by the parts of the server that keep files next to the stores but are not reached through them, such as attachments.
+///
+/// # Errors
+///
+/// This function will return [`Error::StorageNotInitialized`] if [`init`] has not been called.
+pub fn data_dir() -> Result<String> {
+    DATA_DIR
+        .read()
+        .expect("Data folder lock was poisoned.")
+        .clone()
+        .ok_or(Error::StorageNotInitialized)
+}
+
+/// Replaces the hub store used by the rest of the server.
+pub fn set_hub_store(store: Arc<dyn HubStore>) {
+    *HUB_STORE.write().expect("Hub store lock was poisoned.") = store;
+}
+
+/// Replaces the message store used by the rest of the server.
+pub fn set_message_store(store: Arc<dyn MessageStore>) {
+    *MESSAGE_STORE
+        .write()
+        .expect("Message store lock was poisoned.") = store;
+}
+
+/// Uses the given store for both hubs and messages.
+pub fn use_store<S: HubStore + MessageStore + 'static>(store: S) {
+    let store = Arc::new(store);
+    set_hub_store(store.clone());
+    set_message_store(store);
+}
+
+/// Sets up the stores used by the rest of the server according to the configured backend, the filesystem store keeps its files in the configured data folder.
+///
+/// # Errors
+///
+/// This function will return an error if the SQLite database could not be opened.
+pub fn init(config: &Config) -> Result {
+    *DATA_DIR.write().expect("Data folder lock was poisoned.") = Some(config.data_dir.clone());
+    match &config.storage {
+        StorageBackend::Filesystem => {
+            use_store(FsStore::in_dir(&config.data_dir).with_fsync(config.fsync))
+        }
+        StorageBackend::Sqlite { path } => use_store(SqliteStore::open(path)?),
+        StorageBackend::Memory => use_store(MemoryStore::new()),
+    }
+    Ok(())
+}
+
+/// How often message files are checked for compression when it is enabled in the configuration.
+pub const COMPACTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Compresses the messages sent more than `after_days` days ago in every channel of a hub, returns the number of files that were compressed.
+///
+/// # Errors
+///
+/// This function will return an error if the hub could not be loaded or any of its messages could not be compressed.
+pub async fn compact_hub(hub_id: ID, after_days: u32) -> Result<usize> {
+    let hub = hub_store().load_hub(hub_id).await?;
+    let before = Utc::now() - chrono::Duration::days(after_days as i64);
+    let mut count = 0;
+    for channel_id in hub.channels.keys() {
+        count += message_store()
+            .compact_messages(hub_id, *channel_id, before)
+            .await?;
+    }
+    Ok(count)
+}
+
+/// Compresses the old messages of every hub every [`COMPACTION_INTERVAL`], meant to be spawned as a background task.
+/// Hubs that fail to be compacted are logged and tried again the next time.
+pub async fn compact_periodically(after_days: u32) {
+    let mut interval = tokio::time::interval(COMPACTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        match hub_store().list_hubs().await {
+            Ok(hub_ids) => {
+                for hub_id in hub_ids {
+                    if let Err(err) = compact_hub(hub_id, after_days).await {
+                        println!(
+                            "WARNING: Failed to compact the messages of hub {}: {}",
+                            hub_id, err
+                        );
+                    }
+                }
+            }
+            Err(err) => println!("WARNING: Failed to list the hubs to compact: {}", err),
+        }
+    }
+}
+
+/// Repairs the writes to messages that were interrupted the last time the server stopped in every channel of every hub, returns the number of files that were repaired.
+/// Meant to be called when the server starts, before any messages are written. Hubs that cannot be loaded are skipped.
+///
+/// # Errors
+///
+/// This function will return an error if the hubs could not be listed or a file could not be repaired.
+pub async fn recover_all() -> Result<usize> {
+    let mut files = 0;
+    for (hub_id, channel_id) in all_channels(hub_store().as_ref(), false).await? {
+        files += message_store().recover_messages(hub_id, channel_id).await?;
+    }
+    Ok(files)
+}
+
+/// Rewrites every hub and message file in the stores currently in use that was written by an older version of the server, returns the number of hubs and the number of message files that were rewritten.
+/// Records are also upgraded whenever they are loaded, this only saves having to upgrade them again every time.
+///
+/// # Errors
+///
+/// This function will return an error if any hub could not be loaded or any record could not be rewritten.
+pub async fn upgrade_all() -> Result<(usize, usize)> {
+    let hubs = hub_store().upgrade_hubs().await?;
+    let mut files = 0;
+    for (hub_id, channel_id) in all_channels(hub_store().as_ref(), true).await? {
+        files += message_store().upgrade_messages(hub_id, channel_id).await?;
+    }
+    Ok((hubs, files))
+}
+
+/// Lists the hub and channel IDs of every channel in a store, including the channels that hold the messages of direct conversations.
+/// If `strict` is false hubs that cannot be loaded are skipped instead of returning an error.
+async fn all_channels(hubs: &dyn HubStore, strict: bool) -> Result<Vec<(ID, ID)>> {
+    let mut channels = Vec::new();
+    for hub_id in hubs.list_hubs().await? {
+        match hubs.load_hub(hub_id).await {
+            Ok(hub) => channels.extend(hub.channels.keys().map(|channel_id| (hub_id, *channel_id))),
+            Err(error) if strict => return Err(error),
+            Err(_) => {}
+        }
+    }
+    for conversation in hubs.list_all_conversations().await? {
+        channels.push((conversation.id, conversation.id));
+    }
+    Ok(channels)
+}
+
+/// Number of messages that [`migrate`] reads from the source store at a time.
+pub const MIGRATION_PAGE_SIZE: usize = 1000;
+
+/// Copies every hub and direct conversation, all of their messages and the read markers of their members from one pair of stores to another.
+/// Hubs are saved in the destination before their messages are copied and messages that are already in the destination are not copied again, so a migration that was interrupted can simply be run again.
+/// Returns the number of hubs that were not in the destination yet.
+///
+/// # Errors
+///
+/// This function will return an error if any hub or message could not be read from the source or written to the destination.
+pub async fn migrate(
+    from_hubs: &dyn HubStore,
+    from_messages: &dyn MessageStore,
+    to_hubs: &dyn HubStore,
+    to_messages: &dyn MessageStore,
+) -> Result<usize> {
+    let mut count = 0;
+    for hub_id in from_hubs.list_hubs().await? {
+        let hub = from_hubs.load_hub(hub_id).await?;
+        if !to_hubs.hub_exists(hub_id).await {
+            to_hubs.save_hub(&hub).await?;
+            count += 1;
+        }
+        let readers: Vec<String> = hub.members.keys().cloned().collect();
+        for channel_id in hub.channels.keys() {
+            migrate_channel(from_messages, to_messages, hub_id, *channel_id, &readers).await?;
+        }
+    }
+    for conversation in from_hubs.list_all_conversations().await? {
+        to_hubs.save_conversation(&conversation).await?;
+        migrate_channel(
+            from_messages,
+            to_messages,
+            conversation.id,
+            conversation.id,
+            &conversation.participants,
+        )
+        .await?;
+    }
+    Ok(count)
+}
+
+/// Copies the messages of a channel along with their revisions, reactions and thread records, skipping the ones that are already in the destination.
+/// Messages are copied [`MIGRATION_PAGE_SIZE`] at a time, oldest first, then the read markers of the given readers are copied with the sequence numbers the messages got in the destination.
+async fn migrate_channel(
+    from_messages: &dyn MessageStore,
+    to_messages: &dyn MessageStore,
+    hub_id: ID,
+    channel_id: ID,
+    readers: &[String],
+) -> Result {
+    to_messages.create_channel(hub_id, channel_id).await?;
+    let mut from_sequence = 0;
+    loop {
+        let page = from_messages
+            .get_messages_from_sequence(hub_id, channel_id, from_sequence, MIGRATION_PAGE_SIZE)
+            .await?;
+        let last = match page.last() {
+            Some(last) => last.sequence,
+            None => break,
+        };
+        let ids: Vec<ID> = page.iter().map(|message| message.id).collect();
+        let copied: HashSet<ID> = to_messages
+            .get_messages(hub_id, channel_id, &ids)
+            .await?
+            .iter()
+            .map(|message| message.id)
+            .collect();
+        for message in page {
+            migrate_message(
+                from_messages,
+                to_messages,
+                hub_id,
+                channel_id,
+                message,
+                &copied,
+            )
+            .await?;
+        }
+        from_sequence = last + 1;
+    }
+    for thread in from_messages.get_threads(hub_id, channel_id).await? {
+        let copied_replies = to_messages
+            .get_replies(hub_id, channel_id, thread.root)
+            .await?;
+        for reply in from_messages
+            .get_replies(hub_id, channel_id, thread.root)
+            .await?
+        {
+            if !copied_replies.contains(&reply) {
+                to_messages
+                    .add_reply(hub_id, channel_id, thread.root, reply)
+                    .await?;
+            }
+        }
+    }
+    for user_id in readers {
+        if let Some(mut marker) = from_messages
+            .get_read_marker(hub_id, channel_id, user_id)
+            .await?
+        {
+            if let Some(message) = to_messages
+                .get_message(hub_id, channel_id, marker.message_id)
+                .await?
+            {
+                marker.sequence = message.sequence;
+            }
+            to_messages
+                .set_read_marker(hub_id, channel_id, user_id, marker)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies a message unless its ID is in `copied`, then copies the revisions and reactions of the message that are not in the destination yet.
+async fn migrate_message(
+    from_messages: &dyn MessageStore,
+    to_messages: &dyn MessageStore,
+    hub_id: ID,
+    channel_id: ID,
+    message: SignedMessage,
+    copied: &HashSet<ID>,
+) -> Result {
+    let message_id = message.id;
+    if !copied.contains(&message_id) {
+        to_messages
+            .import_message(hub_id, channel_id, message)
+            .await?;
+    }
+    let copied_revisions: HashSet<ID> = to_messages
+        .get_revisions(hub_id, channel_id, message_id)
+        .await?
+        .iter()
+        .map(|revision| revision.id)
+        .collect();
+    for revision in from_messages
+        .get_revisions(hub_id, channel_id, message_id)
+        .await?
+    {
+        if !copied_revisions.contains(&revision.id) {
+            to_messages
+                .add_revision(hub_id, channel_id, message_id, revision)
+                .await?;
+        }
+    }
+    // Adding a reaction that is already there does nothing.
+    for reaction in from_messages
+        .get_reactions(hub_id, channel_id, message_id)
+        .await?
+    {
+        for user_id in reaction.users.iter() {
+            to_messages
+                .add_reaction(hub_id, channel_id, message_id, user_id, &reaction.emoji)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{migrate, HubStore, MemoryStore, MessageStore};
+    use crate::{
+        channel::{ReadMarker, SignedMessage},
+        conversation::Conversation,
+        hub::Hub,
+        new_id,
+    };
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn repeated_migration() {
+        let (from, to) = (MemoryStore::new(), MemoryStore::new());
+        let owner = new_id().to_string();
+        let mut hub = Hub::new("migrated".to_string(), new_id(), owner.clone());
+        let channel_id = hub
+            .new_channel(&owner, "migrated".to_string())
+            .expect("Failed to add a channel.");
+        from.save_hub(&hub).await.expect("Failed to save the hub.");
+        for _ in 0..3 {
+            from.add_message(
+                hub.id,
+                channel_id,
+                SignedMessage::new(new_id(), Utc::now(), String::new()),
+            )
+            .await
+            .expect("Failed to add a message.");
+        }
+        // A migration that stopped after copying the hub and one of its messages.
+        to.save_hub(&hub).await.expect("Failed to save the hub.");
+        let first = from
+            .get_messages_from_sequence(hub.id, channel_id, 0, 1)
+            .await
+            .expect("Failed to get the messages.");
+        to.add_message(hub.id, channel_id, first[0].clone())
+            .await
+            .expect("Failed to add a message.");
+        let read = from
+            .get_messages_from_sequence(hub.id, channel_id, 1, 1)
+            .await
+            .expect("Failed to get the messages.");
+        let marker = ReadMarker {
+            message_id: read[0].id,
+            sequence: read[0].sequence,
+            updated: Utc::now(),
+        };
+        from.set_read_marker(hub.id, channel_id, &owner, marker)
+            .await
+            .expect("Failed to set the read marker.");
+        for expected in [0, 0].iter() {
+            assert_eq!(
+                migrate(&from, &from, &to, &to)
+                    .await
+                    .expect("Failed to migrate."),
+                *expected
+            );
+        }
+        let copied = to
+            .get_last_messages(hub.id, channel_id, usize::MAX)
+            .await
+            .expect("Failed to get the messages.");
+        assert_eq!(copied.len(), 3);
+        assert_eq!(
+            copied.iter().map(|m| m.sequence).collect::<Vec<u64>>(),
+            vec![2, 1, 0]
+        );
+        assert_eq!(
+            to.get_read_marker(hub.id, channel_id, &owner)
+                .await
+                .expect("Failed to get the read marker."),
+            Some(marker)
+        );
+    }
+
+    #[tokio::test]
+    async fn migrate_conversations() {
+        let (from, to) = (MemoryStore::new(), MemoryStore::new());
+        let conversation = Conversation::new(&["AA".repeat(20), "BB".repeat(20)])
+            .expect("Failed to create a conversation.");
+        from.save_conversation(&conversation)
+            .await
+            .expect("Failed to save the conversation.");
+        from.add_message(
+            conversation.id,
+            conversation.id,
+            SignedMessage::new(new_id(), Utc::now(), String::new()),
+        )
+        .await
+        .expect("Failed to add a message.");
+        migrate(&from, &from, &to, &to)
+            .await
+            .expect("Failed to migrate.");
+        assert_eq!(
+            to.load_conversation(conversation.id)
+                .await
+                .expect("Failed to load the conversation."),
+            conversation
+        );
+        assert_eq!(
+            to.get_last_messages(conversation.id, conversation.id, usize::MAX)
+                .await
+                .expect("Failed to get the messages.")
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,732 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    io::Read,
+};
+
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    channel::{Channel, ChannelCategory, ChannelMode, ReadMarker, RetentionPolicy, SignedMessage},
+    conversation::Conversation,
+    error::Error,
+    hub::{Hub, HubMember, PermissionGroup},
+    Result, ID,
+};
+
+/// Version of the layout of the hubs, channels and messages written by this version of the server.
+/// Every change to the serialized form of [`Hub`], [`HubMember`], [`PermissionGroup`], [`Channel`], [`SignedMessage`], [`Conversation`] or [`ReadMarker`] must increase it and add an entry to [`MIGRATIONS`].
+pub const SCHEMA_VERSION: u32 = 7;
+/// First schema version in which message records store their sequence number.
+pub const MESSAGE_SEQUENCE_VERSION: u32 = 4;
+
+/// Bytes at the start of every hub file that has a versioned header.
+const HUB_FILE_MAGIC: &[u8; 4] = b"WHBV";
+/// Size of the versioned hub header: magic bytes, schema version, CRC32 checksum and payload length.
+const HUB_HEADER_SIZE: usize = 20;
+/// Bytes at the start of hub files written with a checksum header but no schema version.
+const LEGACY_HUB_FILE_MAGIC: &[u8; 4] = b"WHUB";
+/// Size of the unversioned hub header: magic bytes, CRC32 checksum and payload length.
+const LEGACY_HUB_HEADER_SIZE: usize = 16;
+/// Bytes at the start of every message file that has a versioned header.
+const MESSAGE_FILE_MAGIC: &[u8; 4] = b"WMSG";
+/// Size of the message file header: magic bytes and schema version.
+pub const MESSAGE_HEADER_SIZE: u64 = 8;
+/// Bytes at the start of every other record that has a versioned header.
+const RECORD_MAGIC: &[u8; 4] = b"WREC";
+/// Size of the record header: magic bytes and schema version.
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Function that upgrades a serialized record by one schema version.
+pub type Upgrade = fn(&[u8]) -> Result<Vec<u8>>;
+
+/// Upgrade of serialized records from one schema version to the next.
+pub struct Migration {
+    /// Version that the migration upgrades records from, upgraded records have version `from + 1`.
+    pub from: u32,
+    /// Short description of what changed.
+    pub description: &'static str,
+    /// Upgrades a serialized hub.
+    pub hub: Upgrade,
+    /// Upgrades a serialized channel, used by stores that keep channels separately from their hub.
+    pub channel: Upgrade,
+}
+
+/// Every migration, ordered by the version they upgrade from.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "Count the number of times hubs are saved.",
+        hub: |bytes| convert::<HubV0<ChannelV0>, HubV1<ChannelV0>>(bytes, HubV0::into_v1),
+        channel: |bytes| Ok(bytes.to_vec()),
+    },
+    Migration {
+        from: 1,
+        description: "Add retention policies to channels.",
+        hub: |bytes| {
+            convert::<HubV1<ChannelV0>, _>(bytes, |hub| hub.map_channels(ChannelV0::into_v2))
+        },
+        channel: |bytes| convert(bytes, ChannelV0::into_v2),
+    },
+    Migration {
+        from: 2,
+        description: "Add pinned messages to channels.",
+        hub: |bytes| {
+            convert::<HubV1<ChannelV2>, _>(bytes, |hub| hub.map_channels(ChannelV2::into_v3))
+        },
+        channel: |bytes| convert(bytes, ChannelV2::into_v3),
+    },
+    Migration {
+        from: 3,
+        description: "Store the sequence numbers of messages in message records.",
+        hub: |bytes| Ok(bytes.to_vec()),
+        channel: |bytes| Ok(bytes.to_vec()),
+    },
+    Migration {
+        from: 4,
+        description: "Add channel categories and channel positions.",
+        hub: |bytes| convert::<HubV1<ChannelV3>, _>(bytes, HubV1::into_v5),
+        channel: |bytes| convert(bytes, ChannelV3::into_v5),
+    },
+    Migration {
+        from: 5,
+        description: "Add modes to channels.",
+        hub: |bytes| convert::<HubV5<ChannelV5>, _>(bytes, HubV5::into_v6),
+        channel: |bytes| convert(bytes, ChannelV5::into_v6),
+    },
+    Migration {
+        from: 6,
+        description: "Add a versioned header to conversation and read marker records.",
+        hub: |bytes| Ok(bytes.to_vec()),
+        channel: |bytes| Ok(bytes.to_vec()),
+    },
+];
+
+/// Message record layout used before version 4, when messages did not have a sequence number.
+#[derive(Serialize, Deserialize)]
+struct SignedMessageV3 {
+    id: ID,
+    created: DateTime<Utc>,
+    armoured_content: String,
+}
+
+/// Channel layout used before version 2, when channels did not have a retention policy.
+#[derive(Serialize, Deserialize)]
+struct ChannelV0 {
+    id: ID,
+    hub_id: ID,
+    description: String,
+    name: String,
+    created: DateTime<Utc>,
+}
+
+impl ChannelV0 {
+    fn into_v2(self) -> ChannelV2 {
+        ChannelV2 {
+            id: self.id,
+            hub_id: self.hub_id,
+            description: self.description,
+            name: self.name,
+            created: self.created,
+            retention: RetentionPolicy::default(),
+        }
+    }
+}
+
+/// Channel layout used in version 2, before messages could be pinned.
+#[derive(Serialize, Deserialize)]
+struct ChannelV2 {
+    id: ID,
+    hub_id: ID,
+    description: String,
+    name: String,
+    created: DateTime<Utc>,
+    retention: RetentionPolicy,
+}
+
+impl ChannelV2 {
+    fn into_v3(self) -> ChannelV3 {
+        ChannelV3 {
+            id: self.id,
+            hub_id: self.hub_id,
+            description: self.description,
+            name: self.name,
+            created: self.created,
+            retention: self.retention,
+            pinned: Vec::new(),
+        }
+    }
+}
+
+/// Channel layout used in versions 3 and 4, before channels could be put in categories.
+#[derive(Serialize, Deserialize)]
+struct ChannelV3 {
+    id: ID,
+    hub_id: ID,
+    description: String,
+    name: String,
+    created: DateTime<Utc>,
+    retention: RetentionPolicy,
+    pinned: Vec<ID>,
+}
+
+impl ChannelV3 {
+    fn into_v5(self) -> ChannelV5 {
+        ChannelV5 {
+            id: self.id,
+            hub_id: self.hub_id,
+            description: self.description,
+            name: self.name,
+            created: self.created,
+            retention: self.retention,
+            pinned: self.pinned,
+            category: None,
+            position: 0,
+        }
+    }
+}
+
+/// Channel layout used in version 5, before channels had a mode.
+#[derive(Serialize, Deserialize)]
+struct ChannelV5 {
+    id: ID,
+    hub_id: ID,
+    description: String,
+    name: String,
+    created: DateTime<Utc>,
+    retention: RetentionPolicy,
+    pinned: Vec<ID>,
+    category: Option<ID>,
+    position: u32,
+}
+
+impl ChannelV5 {
+    fn into_v6(self) -> Channel {
+        Channel {
+            id: self.id,
+            hub_id: self.hub_id,
+            description: self.description,
+            name: self.name,
+            created: self.created,
+            retention: self.retention,
+            pinned: self.pinned,
+            category: self.category,
+            position: self.position,
+            mode: ChannelMode::Normal,
+        }
+    }
+}
+
+/// Hub layout used before version 1, when hubs did not have a version.
+#[derive(Serialize, Deserialize)]
+struct HubV0<C> {
+    channels: HashMap<ID, C>,
+    members: HashMap<String, HubMember>,
+    bans: HashSet<String>,
+    mutes: HashSet<String>,
+    description: String,
+    owner: String,
+    groups: HashMap<ID, PermissionGroup>,
+    default_group: ID,
+    name: String,
+    id: ID,
+    created: DateTime<Utc>,
+}
+
+impl<C> HubV0<C> {
+    fn into_v1(self) -> HubV1<C> {
+        HubV1 {
+            channels: self.channels,
+            members: self.members,
+            bans: self.bans,
+            mutes: self.mutes,
+            description: self.description,
+            owner: self.owner,
+            groups: self.groups,
+            default_group: self.default_group,
+            name: self.name,
+            id: self.id,
+            created: self.created,
+            version: 0,
+        }
+    }
+}
+
+/// Hub layout used from version 1 to 4, before hubs had channel categories.
+#[derive(Serialize, Deserialize)]
+struct HubV1<C> {
+    channels: HashMap<ID, C>,
+    members: HashMap<String, HubMember>,
+    bans: HashSet<String>,
+    mutes: HashSet<String>,
+    description: String,
+    owner: String,
+    groups: HashMap<ID, PermissionGroup>,
+    default_group: ID,
+    name: String,
+    id: ID,
+    created: DateTime<Utc>,
+    version: u64,
+}
+
+impl<C> HubV1<C> {
+    fn map_channels<D>(self, f: fn(C) -> D) -> HubV1<D> {
+        HubV1 {
+            channels: self
+                .channels
+                .into_iter()
+                .map(|(id, channel)| (id, f(channel)))
+                .collect(),
+            members: self.members,
+            bans: self.bans,
+            mutes: self.mutes,
+            description: self.description,
+            owner: self.owner,
+            groups: self.groups,
+            default_group: self.default_group,
+            name: self.name,
+            id: self.id,
+            created: self.created,
+            version: self.version,
+        }
+    }
+}
+
+impl HubV1<ChannelV3> {
+    /// Channels are given positions in the order they were created in.
+    fn into_v5(self) -> HubV5<ChannelV5> {
+        let mut channels: Vec<ChannelV5> = self
+            .channels
+            .into_values()
+            .map(ChannelV3::into_v5)
+            .collect();
+        channels.sort_by_key(|channel| (channel.created, channel.id));
+        HubV5 {
+            channels: channels
+                .into_iter()
+                .enumerate()
+                .map(|(position, channel)| {
+                    (
+                        channel.id,
+                        ChannelV5 {
+                            position: position as u32,
+                            ..channel
+                        },
+                    )
+                })
+                .collect(),
+            categories: HashMap::new(),
+            members: self.members,
+            bans: self.bans,
+            mutes: self.mutes,
+            description: self.description,
+            owner: self.owner,
+            groups: self.groups,
+            default_group: self.default_group,
+            name: self.name,
+            id: self.id,
+            created: self.created,
+            version: self.version,
+        }
+    }
+}
+
+/// Hub layout used since version 5, only the layout of its channels has changed since.
+#[derive(Serialize, Deserialize)]
+struct HubV5<C> {
+    channels: HashMap<ID, C>,
+    members: HashMap<String, HubMember>,
+    bans: HashSet<String>,
+    mutes: HashSet<String>,
+    description: String,
+    owner: String,
+    groups: HashMap<ID, PermissionGroup>,
+    default_group: ID,
+    name: String,
+    id: ID,
+    created: DateTime<Utc>,
+    version: u64,
+    categories: HashMap<ID, ChannelCategory>,
+}
+
+impl HubV5<ChannelV5> {
+    fn into_v6(self) -> Hub {
+        Hub {
+            channels: self
+                .channels
+                .into_iter()
+                .map(|(id, channel)| (id, channel.into_v6()))
+                .collect(),
+            categories: self.categories,
+            members: self.members,
+            bans: self.bans,
+            mutes: self.mutes,
+            description: self.description,
+            owner: self.owner,
+            groups: self.groups,
+            default_group: self.default_group,
+            name: self.name,
+            id: self.id,
+            created: self.created,
+            version: self.version,
+        }
+    }
+}
+
+/// Deserializes a record, failing if any bytes are left over so that a record is never mistaken for an older, shorter layout.
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .deserialize(bytes)?)
+}
+
+/// Deserializes a record in one layout and serializes it in another.
+fn convert<A: DeserializeOwned, B: Serialize>(bytes: &[u8], f: fn(A) -> B) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(&f(decode(bytes)?))?)
+}
+
+/// Applies every migration from the given version onwards to a serialized record.
+fn upgrade(version: u32, bytes: &[u8], step: fn(&Migration) -> Upgrade) -> Result<Vec<u8>> {
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(version));
+    }
+    MIGRATIONS
+        .iter()
+        .filter(|migration| migration.from >= version)
+        .try_fold(bytes.to_vec(), |bytes, migration| step(migration)(&bytes))
+}
+
+/// Finds the schema version of a serialized hub that was written without one by checking which layout it can be deserialized as, newest first.
+fn detect_hub_version(bytes: &[u8]) -> Result<u32> {
+    if decode::<HubV1<ChannelV3>>(bytes).is_ok() {
+        Ok(3)
+    } else if decode::<HubV1<ChannelV2>>(bytes).is_ok() {
+        Ok(2)
+    } else if decode::<HubV1<ChannelV0>>(bytes).is_ok() {
+        Ok(1)
+    } else if decode::<HubV0<ChannelV0>>(bytes).is_ok() {
+        Ok(0)
+    } else {
+        Err(Error::HubCorrupt)
+    }
+}
+
+/// Finds the schema version of a serialized channel that was written without one, see [`detect_hub_version`].
+fn detect_channel_version(bytes: &[u8]) -> Result<u32> {
+    if decode::<ChannelV3>(bytes).is_ok() {
+        Ok(3)
+    } else if decode::<ChannelV2>(bytes).is_ok() {
+        Ok(2)
+    } else if decode::<ChannelV0>(bytes).is_ok() {
+        Ok(0)
+    } else {
+        Err(Error::HubCorrupt)
+    }
+}
+
+/// Serializes a hub and prefixes it with a header containing the schema version, a checksum and the length of the serialized hub.
+pub fn encode_hub(hub: &Hub) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(hub)?;
+    let mut bytes = Vec::with_capacity(HUB_HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(HUB_FILE_MAGIC);
+    bytes.extend_from_slice(&SCHEMA_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend(payload);
+    Ok(bytes)
+}
+
+/// Checks the header of an encoded hub and returns the schema version it was written with and the serialized hub.
+/// Hubs written before the version was added to the header have no version, as do hubs written before there was a header at all.
+fn split_hub_header(bytes: &[u8]) -> Result<(Option<u32>, &[u8])> {
+    let (version, header_size) = if bytes.starts_with(HUB_FILE_MAGIC) {
+        if bytes.len() < HUB_HEADER_SIZE {
+            return Err(Error::HubCorrupt);
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().expect("Header too short."));
+        (Some(version), HUB_HEADER_SIZE)
+    } else if bytes.starts_with(LEGACY_HUB_FILE_MAGIC) {
+        if bytes.len() < LEGACY_HUB_HEADER_SIZE {
+            return Err(Error::HubCorrupt);
+        }
+        (None, LEGACY_HUB_HEADER_SIZE)
+    } else {
+        return Ok((None, bytes));
+    };
+    let checksum = u32::from_le_bytes(
+        bytes[header_size - 12..header_size - 8]
+            .try_into()
+            .expect("Header too short."),
+    );
+    let length = u64::from_le_bytes(
+        bytes[header_size - 8..header_size]
+            .try_into()
+            .expect("Header too short."),
+    );
+    let payload = &bytes[header_size..];
+    if payload.len() as u64 != length || crc32fast::hash(payload) != checksum {
+        return Err(Error::HubCorrupt);
+    }
+    Ok((version, payload))
+}
+
+/// Gets the schema version an encoded hub was written with, checking its layout if its header does not say.
+pub fn hub_version(bytes: &[u8]) -> Result<u32> {
+    match split_hub_header(bytes)? {
+        (Some(version), _) => Ok(version),
+        (None, payload) => detect_hub_version(payload),
+    }
+}
+
+/// Checks if an encoded hub has a header with the current schema version, in which case it does not need to be rewritten.
+pub fn is_current_hub(bytes: &[u8]) -> bool {
+    matches!(split_hub_header(bytes), Ok((Some(SCHEMA_VERSION), _)))
+}
+
+/// Deserializes a hub encoded by [`encode_hub`] or by an older version of the server, upgrading it to the current schema version.
+pub fn decode_hub(bytes: &[u8]) -> Result<Hub> {
+    let (version, payload) = split_hub_header(bytes)?;
+    let version = match version {
+        Some(version) => version,
+        None => detect_hub_version(payload)?,
+    };
+    let upgraded = upgrade(version, payload, |migration| migration.hub)?;
+    decode(&upgraded).map_err(|_| Error::HubCorrupt)
+}
+
+/// Deserializes a channel written with the given schema version, upgrading it to the current schema version.
+/// If the version is not known the channel's layout is checked against every layout it could have.
+pub fn decode_channel(version: Option<u32>, bytes: &[u8]) -> Result<Channel> {
+    let version = match version {
+        Some(version) => version,
+        None => detect_channel_version(bytes)?,
+    };
+    let upgraded = upgrade(version, bytes, |migration| migration.channel)?;
+    decode(&upgraded).map_err(|_| Error::HubCorrupt)
+}
+
+/// Header written at the start of every message file.
+pub fn message_file_header() -> [u8; MESSAGE_HEADER_SIZE as usize] {
+    let mut header = [0; MESSAGE_HEADER_SIZE as usize];
+    header[0..4].copy_from_slice(MESSAGE_FILE_MAGIC);
+    header[4..8].copy_from_slice(&SCHEMA_VERSION.to_le_bytes());
+    header
+}
+
+/// Gets the schema version of the records in a message file and the byte offset at which the first record starts.
+/// Files written before message files had a header start with their first record and have version 0.
+pub fn message_file_start(bytes: &[u8]) -> Result<(u32, u64)> {
+    if !bytes.starts_with(MESSAGE_FILE_MAGIC) {
+        return Ok((0, 0));
+    }
+    if (bytes.len() as u64) < MESSAGE_HEADER_SIZE {
+        return Err(Error::MessageNotFound);
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().expect("Header too short."));
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(version));
+    }
+    Ok((version, MESSAGE_HEADER_SIZE))
+}
+
+/// Reads a message record written with the given schema version.
+/// Records written before [`MESSAGE_SEQUENCE_VERSION`] are read with a sequence number of 0, their sequence number has to be taken from the index of their file.
+pub fn read_message<R: Read>(version: u32, reader: R) -> Result<SignedMessage> {
+    if version >= MESSAGE_SEQUENCE_VERSION {
+        Ok(bincode::deserialize_from(reader)?)
+    } else {
+        let message: SignedMessageV3 = bincode::deserialize_from(reader)?;
+        Ok(SignedMessage::new(
+            message.id,
+            message.created,
+            message.armoured_content,
+        ))
+    }
+}
+
+/// Serializes a record and prefixes it with a header containing the schema version.
+fn encode_record<T: Serialize>(record: &T) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(RECORD_HEADER_SIZE);
+    bytes.extend_from_slice(RECORD_MAGIC);
+    bytes.extend_from_slice(&SCHEMA_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, record)?;
+    Ok(bytes)
+}
+
+/// Deserializes a record encoded by [`encode_record`].
+/// Records written before they had a header have the same layout as the current ones, newer layouts will have to be upgraded here by version.
+fn decode_record<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    if !bytes.starts_with(RECORD_MAGIC) {
+        return decode(bytes);
+    }
+    if bytes.len() < RECORD_HEADER_SIZE {
+        return Err(Error::RecordCorrupt);
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().expect("Header too short."));
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(version));
+    }
+    decode(&bytes[RECORD_HEADER_SIZE..])
+}
+
+/// Serializes a conversation with a header containing the schema version.
+pub fn encode_conversation(conversation: &Conversation) -> Result<Vec<u8>> {
+    encode_record(conversation)
+}
+
+/// Deserializes a conversation encoded by [`encode_conversation`] or by an older version of the server.
+pub fn decode_conversation(bytes: &[u8]) -> Result<Conversation> {
+    decode_record(bytes)
+}
+
+/// Serializes the read markers of a channel, keyed by user ID, with a header containing the schema version.
+pub fn encode_read_markers(markers: &HashMap<String, ReadMarker>) -> Result<Vec<u8>> {
+    encode_record(markers)
+}
+
+/// Deserializes read markers encoded by [`encode_read_markers`] or by an older version of the server.
+pub fn decode_read_markers(bytes: &[u8]) -> Result<HashMap<String, ReadMarker>> {
+    decode_record(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::new_id;
+
+    fn legacy_hub() -> (Hub, HubV0<ChannelV0>) {
+        let mut hub = Hub::new("test_hub".to_string(), new_id(), "owner".to_string());
+        let channel = Channel::new("general".to_string(), new_id(), hub.id);
+        hub.channels.insert(channel.id, channel.clone());
+        let legacy = HubV0 {
+            channels: hub
+                .channels
+                .values()
+                .map(|channel| {
+                    (
+                        channel.id,
+                        ChannelV0 {
+                            id: channel.id,
+                            hub_id: channel.hub_id,
+                            description: channel.description.clone(),
+                            name: channel.name.clone(),
+                            created: channel.created,
+                        },
+                    )
+                })
+                .collect(),
+            members: hub.members.clone(),
+            bans: hub.bans.clone(),
+            mutes: hub.mutes.clone(),
+            description: hub.description.clone(),
+            owner: hub.owner.clone(),
+            groups: hub.groups.clone(),
+            default_group: hub.default_group,
+            name: hub.name.clone(),
+            id: hub.id,
+            created: hub.created,
+        };
+        (hub, legacy)
+    }
+
+    #[test]
+    fn upgrades() {
+        let (hub, v0) = legacy_hub();
+        let v0_bytes = bincode::serialize(&v0).unwrap();
+        let v1 = v0.into_v1();
+        let v1_bytes = bincode::serialize(&v1).unwrap();
+        let v2 = v1.map_channels(ChannelV0::into_v2);
+        let v2_bytes = bincode::serialize(&v2).unwrap();
+        let channel = bincode::serialize(v2.channels.values().next().unwrap()).unwrap();
+        let v3_bytes = bincode::serialize(&v2.map_channels(ChannelV2::into_v3)).unwrap();
+        let layouts = vec![(0, v0_bytes), (1, v1_bytes), (2, v2_bytes), (3, v3_bytes)];
+        for (version, bytes) in layouts {
+            assert_eq!(detect_hub_version(&bytes).unwrap(), version);
+            let decoded = decode_hub(&bytes).unwrap();
+            assert_eq!(decoded.id, hub.id);
+            assert_eq!(decoded.members, hub.members);
+            assert_eq!(decoded.channels.len(), 1);
+        }
+        assert_eq!(detect_channel_version(&channel).unwrap(), 2);
+        let upgraded = decode_channel(None, &channel).unwrap();
+        assert_eq!(upgraded.name, "general");
+        assert!(upgraded.pinned.is_empty());
+        assert_eq!(upgraded.category, None);
+        assert_eq!(upgraded.mode, ChannelMode::Normal);
+        let current = bincode::serialize(&hub.channels[&upgraded.id]).unwrap();
+        assert_eq!(
+            decode_channel(Some(SCHEMA_VERSION), &current).unwrap().id,
+            upgraded.id
+        );
+
+        let encoded = encode_hub(&hub).unwrap();
+        assert!(is_current_hub(&encoded));
+        assert_eq!(hub_version(&encoded).unwrap(), SCHEMA_VERSION);
+        assert_eq!(decode_hub(&encoded).unwrap().name, hub.name);
+        let mut newer = encoded;
+        newer[4..8].copy_from_slice(&(SCHEMA_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            decode_hub(&newer),
+            Err(Error::UnsupportedSchemaVersion(_))
+        ));
+
+        let message = SignedMessage {
+            sequence: 7,
+            ..SignedMessage::new(new_id(), Utc::now(), "text".to_string())
+        };
+        let legacy = bincode::serialize(&SignedMessageV3 {
+            id: message.id,
+            created: message.created,
+            armoured_content: message.armoured_content.clone(),
+        })
+        .unwrap();
+        assert_eq!(
+            read_message(3, legacy.as_slice()).unwrap(),
+            SignedMessage {
+                sequence: 0,
+                ..message.clone()
+            }
+        );
+        let current = bincode::serialize(&message).unwrap();
+        assert_eq!(
+            read_message(SCHEMA_VERSION, current.as_slice()).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn records() {
+        let conversation = Conversation::new(&["AA".repeat(20), "BB".repeat(20)]).unwrap();
+        let encoded = encode_conversation(&conversation).unwrap();
+        assert!(encoded.starts_with(RECORD_MAGIC));
+        assert_eq!(decode_conversation(&encoded).unwrap(), conversation);
+        let headerless = bincode::serialize(&conversation).unwrap();
+        assert_eq!(decode_conversation(&headerless).unwrap(), conversation);
+        let mut newer = encoded;
+        newer[4..8].copy_from_slice(&(SCHEMA_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            decode_conversation(&newer),
+            Err(Error::UnsupportedSchemaVersion(_))
+        ));
+        assert!(matches!(
+            decode_conversation(RECORD_MAGIC),
+            Err(Error::RecordCorrupt)
+        ));
+
+        let mut markers = HashMap::new();
+        markers.insert(
+            "user".to_string(),
+            ReadMarker {
+                message_id: new_id(),
+                sequence: 3,
+                updated: Utc::now(),
+            },
+        );
+        let encoded = encode_read_markers(&markers).unwrap();
+        assert_eq!(decode_read_markers(&encoded).unwrap(), markers);
+        let headerless = bincode::serialize(&markers).unwrap();
+        assert_eq!(decode_read_markers(&headerless).unwrap(), markers);
+    }
+}
@@ -0,0 +1,1292 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::{schema, HubStore, MessageStore};
+use crate::{
+    channel::{
+        Channel, ChannelCategory, Reaction, ReadMarker, RetentionPolicy, SignedMessage,
+        ThreadSummary,
+    },
+    conversation::Conversation,
+    error::Error,
+    hub::{Hub, HubMember, PermissionGroup},
+    Result, ID,
+};
+
+/// Tables and indexes used by the SQLite store, created when the database is opened.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS hubs (
+    id BLOB PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    default_group BLOB NOT NULL,
+    created TEXT NOT NULL,
+    bans BLOB NOT NULL,
+    mutes BLOB NOT NULL,
+    version INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS channels (
+    hub_id BLOB NOT NULL,
+    id BLOB NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (hub_id, id)
+);
+CREATE TABLE IF NOT EXISTS channel_categories (
+    hub_id BLOB NOT NULL,
+    id BLOB NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (hub_id, id)
+);
+CREATE TABLE IF NOT EXISTS members (
+    hub_id BLOB NOT NULL,
+    user_id TEXT NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (hub_id, user_id)
+);
+CREATE TABLE IF NOT EXISTS permission_groups (
+    hub_id BLOB NOT NULL,
+    id BLOB NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (hub_id, id)
+);
+CREATE TABLE IF NOT EXISTS messages (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    hub_id BLOB NOT NULL,
+    channel_id BLOB NOT NULL,
+    id BLOB NOT NULL,
+    created TEXT NOT NULL,
+    armoured_content TEXT NOT NULL,
+    sequence INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS messages_by_id ON messages (hub_id, channel_id, id);
+CREATE INDEX IF NOT EXISTS messages_by_created ON messages (hub_id, channel_id, created);
+CREATE TABLE IF NOT EXISTS revisions (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    hub_id BLOB NOT NULL,
+    channel_id BLOB NOT NULL,
+    message_id BLOB NOT NULL,
+    id BLOB NOT NULL,
+    created TEXT NOT NULL,
+    armoured_content TEXT NOT NULL,
+    sequence INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS revisions_by_message ON revisions (hub_id, channel_id, message_id);
+CREATE TABLE IF NOT EXISTS reactions (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    hub_id BLOB NOT NULL,
+    channel_id BLOB NOT NULL,
+    message_id BLOB NOT NULL,
+    emoji TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    UNIQUE (hub_id, channel_id, message_id, emoji, user_id)
+);
+CREATE TABLE IF NOT EXISTS thread_replies (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    hub_id BLOB NOT NULL,
+    channel_id BLOB NOT NULL,
+    root_id BLOB NOT NULL,
+    message_id BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS thread_replies_by_root ON thread_replies (hub_id, channel_id, root_id);
+CREATE TABLE IF NOT EXISTS channel_sequences (
+    hub_id BLOB NOT NULL,
+    channel_id BLOB NOT NULL,
+    next INTEGER NOT NULL,
+    PRIMARY KEY (hub_id, channel_id)
+);
+CREATE TABLE IF NOT EXISTS read_markers (
+    hub_id BLOB NOT NULL,
+    channel_id BLOB NOT NULL,
+    user_id TEXT NOT NULL,
+    message_id BLOB NOT NULL,
+    sequence INTEGER NOT NULL,
+    updated TEXT NOT NULL,
+    PRIMARY KEY (hub_id, channel_id, user_id)
+);
+CREATE TABLE IF NOT EXISTS conversations (
+    id BLOB PRIMARY KEY NOT NULL,
+    data BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS conversation_participants (
+    user_id TEXT NOT NULL,
+    conversation_id BLOB NOT NULL,
+    PRIMARY KEY (user_id, conversation_id)
+);
+";
+
+/// Adds the sequence columns to message and revision tables created before messages had sequence numbers.
+/// Existing messages are numbered in the order they were sent and revisions take the number of the message they revise.
+const ADD_SEQUENCES: &str = "
+ALTER TABLE messages ADD COLUMN sequence INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE revisions ADD COLUMN sequence INTEGER NOT NULL DEFAULT 0;
+UPDATE messages SET sequence = (SELECT COUNT(*) FROM messages AS earlier WHERE earlier.hub_id = messages.hub_id AND earlier.channel_id = messages.channel_id AND earlier.seq < messages.seq);
+UPDATE revisions SET sequence = COALESCE((SELECT sequence FROM messages WHERE messages.hub_id = revisions.hub_id AND messages.channel_id = revisions.channel_id AND messages.id = revisions.message_id ORDER BY seq LIMIT 1), 0);
+INSERT OR REPLACE INTO channel_sequences (hub_id, channel_id, next) SELECT hub_id, channel_id, MAX(sequence) + 1 FROM messages GROUP BY hub_id, channel_id;
+";
+
+/// Columns selected whenever a [`SignedMessage`] is read from the database.
+const MESSAGE_COLUMNS: &str = "id, created, armoured_content, sequence";
+
+/// Upgrades the channels stored in the database to the current [`schema::SCHEMA_VERSION`], which is kept in the database's `user_version`.
+/// Databases created before the schema version was recorded have a `user_version` of 0, the layout of each of their channels is checked separately.
+fn upgrade_schema(connection: &mut Connection) -> Result {
+    let version: u32 = connection.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+    if version > schema::SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(version));
+    }
+    if version == schema::SCHEMA_VERSION {
+        return Ok(());
+    }
+    let known_version = if version == 0 { None } else { Some(version) };
+    let transaction = connection.transaction()?;
+    let channels = {
+        let mut statement = transaction.prepare("SELECT hub_id, id, data FROM channels")?;
+        let rows = statement
+            .query_map(params![], |row| {
+                Ok((
+                    row.get::<_, Vec<u8>>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, Vec<u8>>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows
+    };
+    for (hub_id, id, data) in channels {
+        let channel = schema::decode_channel(known_version, &data)?;
+        transaction.execute(
+            "UPDATE channels SET data = ?3 WHERE hub_id = ?1 AND id = ?2",
+            params![hub_id, id, bincode::serialize(&channel)?],
+        )?;
+    }
+    transaction.execute_batch(&format!("PRAGMA user_version = {}", schema::SCHEMA_VERSION))?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Stores hubs, hub members, permission groups and messages in an embedded SQLite database.
+#[derive(Clone)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at the given path and makes sure all the tables exist.
+    pub fn open(path: &str) -> Result<Self> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Creates a database that only lives in memory.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        let has_version = connection
+            .prepare("SELECT version FROM hubs LIMIT 0")
+            .is_ok();
+        if !has_version {
+            connection
+                .execute_batch("ALTER TABLE hubs ADD COLUMN version INTEGER NOT NULL DEFAULT 0")?;
+        }
+        let has_sequences = connection
+            .prepare("SELECT sequence FROM messages LIMIT 0")
+            .is_ok();
+        if !has_sequences {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(ADD_SEQUENCES)?;
+            transaction.commit()?;
+        }
+        connection.execute_batch(
+            "CREATE INDEX IF NOT EXISTS messages_by_sequence ON messages (hub_id, channel_id, sequence);",
+        )?;
+        upgrade_schema(&mut connection)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs the given function with the database connection on a thread where blocking is acceptable.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| Error::Other("SQLite connection lock was poisoned.".to_string()))?;
+            f(&mut connection)
+        })
+        .await
+        .map_err(|err| Error::Other(err.to_string()))?
+    }
+
+    /// Runs a query that returns messages, binding the given hub and channel IDs to the first two parameters.
+    async fn query_messages(
+        &self,
+        sql: String,
+        hub_id: ID,
+        channel_id: ID,
+        extra: Vec<rusqlite::types::Value>,
+    ) -> Result<Vec<SignedMessage>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(&sql)?;
+            let mut values: Vec<rusqlite::types::Value> =
+                vec![id_to_blob(hub_id).into(), id_to_blob(channel_id).into()];
+            values.extend(extra);
+            let rows = statement
+                .query_map(rusqlite::params_from_iter(values), raw_message)?
+                .collect::<rusqlite::Result<Vec<RawMessage>>>()?;
+            rows.into_iter().map(message_from_raw).collect()
+        })
+        .await
+    }
+}
+
+/// Columns of a message row as they are stored, converted with [`message_from_raw`].
+type RawMessage = (Vec<u8>, String, String, i64);
+
+fn raw_message(row: &Row) -> rusqlite::Result<RawMessage> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+}
+
+fn message_from_raw(raw: RawMessage) -> Result<SignedMessage> {
+    Ok(SignedMessage {
+        sequence: raw.3 as u64,
+        ..SignedMessage::new(ID::from_slice(&raw.0)?, time_from_text(&raw.1)?, raw.2)
+    })
+}
+
+fn id_to_blob(id: ID) -> Vec<u8> {
+    id.as_bytes().to_vec()
+}
+
+/// Formats a time so that the text representations of times sort in the same order as the times themselves.
+fn time_to_text(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+fn time_from_text(text: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(text)?.with_timezone(&Utc))
+}
+
+/// Converts a result limit to the value SQLite expects, where a negative limit means no limit.
+fn sql_limit(max: usize) -> i64 {
+    i64::try_from(max).unwrap_or(-1)
+}
+
+#[async_trait]
+impl HubStore for SqliteStore {
+    async fn save_hub(&self, hub: &Hub) -> Result {
+        let hub = hub.clone();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let hub_id = id_to_blob(hub.id);
+            let stored_version = transaction
+                .query_row(
+                    "SELECT version FROM hubs WHERE id = ?1",
+                    params![hub_id],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?;
+            if let Some(version) = stored_version {
+                if version as u64 + 1 != hub.version {
+                    return Err(Error::Conflict);
+                }
+            }
+            transaction.execute(
+                "INSERT OR REPLACE INTO hubs (id, name, description, owner, default_group, created, bans, mutes, version) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    hub_id,
+                    hub.name,
+                    hub.description,
+                    hub.owner,
+                    id_to_blob(hub.default_group),
+                    time_to_text(&hub.created),
+                    bincode::serialize(&hub.bans)?,
+                    bincode::serialize(&hub.mutes)?,
+                    hub.version as i64,
+                ],
+            )?;
+            for table in &["channels", "channel_categories", "members", "permission_groups"] {
+                transaction.execute(
+                    &format!("DELETE FROM {} WHERE hub_id = ?1", table),
+                    params![hub_id],
+                )?;
+            }
+            for channel in hub.channels.values() {
+                transaction.execute(
+                    "INSERT INTO channels (hub_id, id, data) VALUES (?1, ?2, ?3)",
+                    params![hub_id, id_to_blob(channel.id), bincode::serialize(channel)?],
+                )?;
+            }
+            for category in hub.categories.values() {
+                transaction.execute(
+                    "INSERT INTO channel_categories (hub_id, id, data) VALUES (?1, ?2, ?3)",
+                    params![hub_id, id_to_blob(category.id), bincode::serialize(category)?],
+                )?;
+            }
+            for member in hub.members.values() {
+                transaction.execute(
+                    "INSERT INTO members (hub_id, user_id, data) VALUES (?1, ?2, ?3)",
+                    params![hub_id, member.user_id, bincode::serialize(member)?],
+                )?;
+            }
+            for group in hub.groups.values() {
+                transaction.execute(
+                    "INSERT INTO permission_groups (hub_id, id, data) VALUES (?1, ?2, ?3)",
+                    params![hub_id, id_to_blob(group.id), bincode::serialize(group)?],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_hub(&self, id: ID) -> Result<Hub> {
+        self.with_connection(move |connection| {
+            let hub_id = id_to_blob(id);
+            let row = connection
+                .query_row(
+                    "SELECT name, description, owner, default_group, created, bans, mutes, version FROM hubs WHERE id = ?1",
+                    params![hub_id],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, Vec<u8>>(3)?,
+                            row.get::<_, String>(4)?,
+                            row.get::<_, Vec<u8>>(5)?,
+                            row.get::<_, Vec<u8>>(6)?,
+                            row.get::<_, i64>(7)?,
+                        ))
+                    },
+                )
+                .optional()?
+                .ok_or(Error::HubNotFound)?;
+            let load_blobs = |table: &str| -> Result<Vec<Vec<u8>>> {
+                let mut statement =
+                    connection.prepare(&format!("SELECT data FROM {} WHERE hub_id = ?1", table))?;
+                let rows = statement
+                    .query_map(params![hub_id], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?;
+                Ok(rows)
+            };
+            let mut channels = HashMap::new();
+            for data in load_blobs("channels")? {
+                let channel: Channel = bincode::deserialize(&data)?;
+                channels.insert(channel.id, channel);
+            }
+            let mut categories = HashMap::new();
+            for data in load_blobs("channel_categories")? {
+                let category: ChannelCategory = bincode::deserialize(&data)?;
+                categories.insert(category.id, category);
+            }
+            let mut members = HashMap::new();
+            for data in load_blobs("members")? {
+                let member: HubMember = bincode::deserialize(&data)?;
+                members.insert(member.user_id.clone(), member);
+            }
+            let mut groups = HashMap::new();
+            for data in load_blobs("permission_groups")? {
+                let group: PermissionGroup = bincode::deserialize(&data)?;
+                groups.insert(group.id, group);
+            }
+            Ok(Hub {
+                id,
+                name: row.0,
+                description: row.1,
+                owner: row.2,
+                default_group: ID::from_slice(&row.3)?,
+                created: time_from_text(&row.4)?,
+                bans: bincode::deserialize::<HashSet<String>>(&row.5)?,
+                mutes: bincode::deserialize::<HashSet<String>>(&row.6)?,
+                version: row.7 as u64,
+                channels,
+                members,
+                groups,
+                categories,
+            })
+        })
+        .await
+    }
+
+    async fn hub_exists(&self, id: ID) -> bool {
+        self.with_connection(move |connection| {
+            Ok(connection
+                .query_row(
+                    "SELECT 1 FROM hubs WHERE id = ?1",
+                    params![id_to_blob(id)],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some())
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    async fn delete_hub(&self, id: ID) -> Result {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let hub_id = id_to_blob(id);
+            let deleted = transaction.execute("DELETE FROM hubs WHERE id = ?1", params![hub_id])?;
+            for table in &[
+                "channels",
+                "channel_categories",
+                "members",
+                "permission_groups",
+            ] {
+                transaction.execute(
+                    &format!("DELETE FROM {} WHERE hub_id = ?1", table),
+                    params![hub_id],
+                )?;
+            }
+            transaction.commit()?;
+            if deleted == 0 {
+                Err(Error::HubNotFound)
+            } else {
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    async fn list_hubs(&self) -> Result<Vec<ID>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare("SELECT id FROM hubs")?;
+            let rows = statement
+                .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+                .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?;
+            rows.iter()
+                .map(|bytes| Ok(ID::from_slice(bytes)?))
+                .collect()
+        })
+        .await
+    }
+
+    async fn save_conversation(&self, conversation: &Conversation) -> Result {
+        let conversation = conversation.clone();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let conversation_id = id_to_blob(conversation.id);
+            transaction.execute(
+                "INSERT OR REPLACE INTO conversations (id, data) VALUES (?1, ?2)",
+                params![conversation_id, bincode::serialize(&conversation)?],
+            )?;
+            transaction.execute(
+                "DELETE FROM conversation_participants WHERE conversation_id = ?1",
+                params![conversation_id],
+            )?;
+            for user_id in &conversation.participants {
+                transaction.execute(
+                    "INSERT INTO conversation_participants (user_id, conversation_id) VALUES (?1, ?2)",
+                    params![user_id, conversation_id],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_conversation(&self, id: ID) -> Result<Conversation> {
+        self.with_connection(move |connection| {
+            let data = connection
+                .query_row(
+                    "SELECT data FROM conversations WHERE id = ?1",
+                    params![id_to_blob(id)],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .optional()?
+                .ok_or(Error::ConversationNotFound)?;
+            Ok(bincode::deserialize(&data)?)
+        })
+        .await
+    }
+
+    async fn list_conversations(&self, user_id: &str) -> Result<Vec<Conversation>> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT data FROM conversations JOIN conversation_participants ON conversations.id = conversation_participants.conversation_id WHERE conversation_participants.user_id = ?1",
+            )?;
+            let rows = statement
+                .query_map(params![user_id], |row| row.get::<_, Vec<u8>>(0))?
+                .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?;
+            rows.iter()
+                .map(|data| Ok(bincode::deserialize(data)?))
+                .collect()
+        })
+        .await
+    }
+
+    async fn list_all_conversations(&self) -> Result<Vec<Conversation>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare("SELECT data FROM conversations")?;
+            let rows = statement
+                .query_map(params![], |row| row.get::<_, Vec<u8>>(0))?
+                .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?;
+            rows.iter()
+                .map(|data| Ok(bincode::deserialize(data)?))
+                .collect()
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl MessageStore for SqliteStore {
+    async fn create_channel(&self, _hub_id: ID, _channel_id: ID) -> Result {
+        Ok(())
+    }
+
+    async fn delete_hub_messages(&self, hub_id: ID) -> Result {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute(
+                "DELETE FROM messages WHERE hub_id = ?1",
+                params![id_to_blob(hub_id)],
+            )?;
+            transaction.execute(
+                "DELETE FROM revisions WHERE hub_id = ?1",
+                params![id_to_blob(hub_id)],
+            )?;
+            transaction.execute(
+                "DELETE FROM reactions WHERE hub_id = ?1",
+                params![id_to_blob(hub_id)],
+            )?;
+            transaction.execute(
+                "DELETE FROM thread_replies WHERE hub_id = ?1",
+                params![id_to_blob(hub_id)],
+            )?;
+            transaction.execute(
+                "DELETE FROM channel_sequences WHERE hub_id = ?1",
+                params![id_to_blob(hub_id)],
+            )?;
+            transaction.execute(
+                "DELETE FROM read_markers WHERE hub_id = ?1",
+                params![id_to_blob(hub_id)],
+            )?;
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn add_message(&self, hub_id: ID, channel_id: ID, message: SignedMessage) -> Result<u64> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let next = transaction
+                .query_row(
+                    "SELECT next FROM channel_sequences WHERE hub_id = ?1 AND channel_id = ?2",
+                    params![id_to_blob(hub_id), id_to_blob(channel_id)],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?
+                .unwrap_or(0);
+            let sequence = i64::try_from(message.sequence).unwrap_or(i64::MAX).max(next);
+            transaction.execute(
+                "INSERT INTO messages (hub_id, channel_id, id, created, armoured_content, sequence) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    id_to_blob(hub_id),
+                    id_to_blob(channel_id),
+                    id_to_blob(message.id),
+                    time_to_text(&message.created),
+                    message.armoured_content,
+                    sequence,
+                ],
+            )?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO channel_sequences (hub_id, channel_id, next) VALUES (?1, ?2, ?3)",
+                params![id_to_blob(hub_id), id_to_blob(channel_id), sequence + 1],
+            )?;
+            transaction.commit()?;
+            Ok(sequence as u64)
+        })
+        .await
+    }
+
+    async fn get_message(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+    ) -> Result<Option<SignedMessage>> {
+        Ok(self
+            .query_messages(
+                format!("SELECT {} FROM messages WHERE hub_id = ?1 AND channel_id = ?2 AND id = ?3 ORDER BY seq LIMIT 1", MESSAGE_COLUMNS),
+                hub_id,
+                channel_id,
+                vec![id_to_blob(id).into()],
+            )
+            .await?
+            .pop())
+    }
+
+    async fn get_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        ids: &[ID],
+    ) -> Result<Vec<SignedMessage>> {
+        let mut result = Vec::new();
+        for id in ids {
+            if let Some(message) = self.get_message(hub_id, channel_id, *id).await? {
+                result.push(message);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn get_last_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        self.query_messages(
+            format!("SELECT {} FROM messages WHERE hub_id = ?1 AND channel_id = ?2 ORDER BY seq DESC LIMIT ?3", MESSAGE_COLUMNS),
+            hub_id,
+            channel_id,
+            vec![sql_limit(max).into()],
+        )
+        .await
+    }
+
+    async fn get_messages_between(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        invert: bool,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        let order = if invert { "DESC" } else { "ASC" };
+        self.query_messages(
+            format!("SELECT {} FROM messages WHERE hub_id = ?1 AND channel_id = ?2 AND created >= ?3 AND created <= ?4 ORDER BY created {}, seq {} LIMIT ?5", MESSAGE_COLUMNS, order, order),
+            hub_id,
+            channel_id,
+            vec![
+                time_to_text(&from).into(),
+                time_to_text(&to).into(),
+                sql_limit(max).into(),
+            ],
+        )
+        .await
+    }
+
+    async fn get_messages_before(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        self.query_messages(
+            format!("SELECT * FROM (SELECT {}, seq FROM messages WHERE hub_id = ?1 AND channel_id = ?2 AND seq < (SELECT seq FROM messages WHERE hub_id = ?1 AND channel_id = ?2 AND id = ?3 ORDER BY seq LIMIT 1) ORDER BY seq DESC LIMIT ?4) ORDER BY seq", MESSAGE_COLUMNS),
+            hub_id,
+            channel_id,
+            vec![id_to_blob(id).into(), sql_limit(max).into()],
+        )
+        .await
+    }
+
+    async fn get_messages_after(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        id: ID,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        self.query_messages(
+            format!("SELECT {} FROM messages WHERE hub_id = ?1 AND channel_id = ?2 AND seq > (SELECT seq FROM messages WHERE hub_id = ?1 AND channel_id = ?2 AND id = ?3 ORDER BY seq LIMIT 1) ORDER BY seq LIMIT ?4", MESSAGE_COLUMNS),
+            hub_id,
+            channel_id,
+            vec![id_to_blob(id).into(), sql_limit(max).into()],
+        )
+        .await
+    }
+
+    async fn get_messages_from_sequence(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        from: u64,
+        max: usize,
+    ) -> Result<Vec<SignedMessage>> {
+        self.query_messages(
+            format!("SELECT {} FROM messages WHERE hub_id = ?1 AND channel_id = ?2 AND sequence >= ?3 ORDER BY sequence LIMIT ?4", MESSAGE_COLUMNS),
+            hub_id,
+            channel_id,
+            vec![
+                i64::try_from(from).unwrap_or(i64::MAX).into(),
+                sql_limit(max).into(),
+            ],
+        )
+        .await
+    }
+
+    async fn add_revision(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        revision: SignedMessage,
+    ) -> Result {
+        self.with_connection(move |connection| {
+            let exists = connection
+                .query_row(
+                    "SELECT 1 FROM revisions WHERE hub_id = ?1 AND channel_id = ?2 AND message_id = ?3 AND id = ?4",
+                    params![
+                        id_to_blob(hub_id),
+                        id_to_blob(channel_id),
+                        id_to_blob(message_id),
+                        id_to_blob(revision.id),
+                    ],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            if exists {
+                return Err(Error::RevisionAlreadyExists);
+            }
+            connection.execute(
+                "INSERT INTO revisions (hub_id, channel_id, message_id, id, created, armoured_content, sequence) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    id_to_blob(hub_id),
+                    id_to_blob(channel_id),
+                    id_to_blob(message_id),
+                    id_to_blob(revision.id),
+                    time_to_text(&revision.created),
+                    revision.armoured_content,
+                    i64::try_from(revision.sequence).unwrap_or(i64::MAX),
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_revisions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<SignedMessage>> {
+        self.query_messages(
+            format!("SELECT {} FROM revisions WHERE hub_id = ?1 AND channel_id = ?2 AND message_id = ?3 ORDER BY seq", MESSAGE_COLUMNS),
+            hub_id,
+            channel_id,
+            vec![id_to_blob(message_id).into()],
+        )
+        .await
+    }
+
+    async fn add_reaction(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool> {
+        let (user_id, emoji) = (user_id.to_string(), emoji.to_string());
+        self.with_connection(move |connection| {
+            let added = connection.execute(
+                "INSERT OR IGNORE INTO reactions (hub_id, channel_id, message_id, emoji, user_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    id_to_blob(hub_id),
+                    id_to_blob(channel_id),
+                    id_to_blob(message_id),
+                    emoji,
+                    user_id,
+                ],
+            )?;
+            Ok(added == 1)
+        })
+        .await
+    }
+
+    async fn remove_reaction(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<bool> {
+        let (user_id, emoji) = (user_id.to_string(), emoji.to_string());
+        self.with_connection(move |connection| {
+            let removed = connection.execute(
+                "DELETE FROM reactions WHERE hub_id = ?1 AND channel_id = ?2 AND message_id = ?3 AND emoji = ?4 AND user_id = ?5",
+                params![
+                    id_to_blob(hub_id),
+                    id_to_blob(channel_id),
+                    id_to_blob(message_id),
+                    emoji,
+                    user_id,
+                ],
+            )?;
+            Ok(removed == 1)
+        })
+        .await
+    }
+
+    async fn get_reactions(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    ) -> Result<Vec<Reaction>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT emoji, user_id FROM reactions WHERE hub_id = ?1 AND channel_id = ?2 AND message_id = ?3 ORDER BY seq",
+            )?;
+            let rows = statement
+                .query_map(
+                    params![
+                        id_to_blob(hub_id),
+                        id_to_blob(channel_id),
+                        id_to_blob(message_id)
+                    ],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+                )?
+                .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+            let mut reactions = Vec::new();
+            for (emoji, user_id) in rows {
+                Reaction::add_to(&mut reactions, &user_id, &emoji);
+            }
+            Ok(reactions)
+        })
+        .await
+    }
+
+    async fn add_reply(&self, hub_id: ID, channel_id: ID, root_id: ID, message_id: ID) -> Result {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO thread_replies (hub_id, channel_id, root_id, message_id) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    id_to_blob(hub_id),
+                    id_to_blob(channel_id),
+                    id_to_blob(root_id),
+                    id_to_blob(message_id),
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_replies(&self, hub_id: ID, channel_id: ID, root_id: ID) -> Result<Vec<ID>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT message_id FROM thread_replies WHERE hub_id = ?1 AND channel_id = ?2 AND root_id = ?3 ORDER BY seq",
+            )?;
+            let rows = statement
+                .query_map(
+                    params![
+                        id_to_blob(hub_id),
+                        id_to_blob(channel_id),
+                        id_to_blob(root_id)
+                    ],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )?
+                .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?;
+            rows.iter().map(|id| Ok(ID::from_slice(id)?)).collect()
+        })
+        .await
+    }
+
+    async fn get_threads(&self, hub_id: ID, channel_id: ID) -> Result<Vec<ThreadSummary>> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT root_id, message_id FROM thread_replies WHERE hub_id = ?1 AND channel_id = ?2 ORDER BY seq",
+            )?;
+            let rows = statement
+                .query_map(
+                    params![id_to_blob(hub_id), id_to_blob(channel_id)],
+                    |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?)),
+                )?
+                .collect::<rusqlite::Result<Vec<(Vec<u8>, Vec<u8>)>>>()?;
+            let replies = rows
+                .iter()
+                .map(|(root, reply)| Ok((ID::from_slice(root)?, ID::from_slice(reply)?)))
+                .collect::<Result<Vec<(ID, ID)>>>()?;
+            Ok(ThreadSummary::from_replies(replies))
+        })
+        .await
+    }
+
+    async fn set_read_marker(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        user_id: &str,
+        marker: ReadMarker,
+    ) -> Result<bool> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let changed = connection.execute(
+                "INSERT INTO read_markers (hub_id, channel_id, user_id, message_id, sequence, updated) VALUES (?1, ?2, ?3, ?4, ?5, ?6) ON CONFLICT (hub_id, channel_id, user_id) DO UPDATE SET message_id = excluded.message_id, sequence = excluded.sequence, updated = excluded.updated WHERE excluded.sequence >= read_markers.sequence",
+                params![
+                    id_to_blob(hub_id),
+                    id_to_blob(channel_id),
+                    user_id,
+                    id_to_blob(marker.message_id),
+                    marker.sequence as i64,
+                    time_to_text(&marker.updated),
+                ],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn get_read_marker(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        user_id: &str,
+    ) -> Result<Option<ReadMarker>> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let row = connection
+                .query_row(
+                    "SELECT message_id, sequence, updated FROM read_markers WHERE hub_id = ?1 AND channel_id = ?2 AND user_id = ?3",
+                    params![id_to_blob(hub_id), id_to_blob(channel_id), user_id],
+                    |row| {
+                        Ok((
+                            row.get::<_, Vec<u8>>(0)?,
+                            row.get::<_, i64>(1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            row.map(|(message_id, sequence, updated)| {
+                Ok(ReadMarker {
+                    message_id: ID::from_slice(&message_id)?,
+                    sequence: sequence as u64,
+                    updated: time_from_text(&updated)?,
+                })
+            })
+            .transpose()
+        })
+        .await
+    }
+
+    async fn redact_message(&self, hub_id: ID, channel_id: ID, tombstone: SignedMessage) -> Result {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let params = params![
+                id_to_blob(hub_id),
+                id_to_blob(channel_id),
+                id_to_blob(tombstone.id)
+            ];
+            let seq = transaction
+                .query_row(
+                    "SELECT seq FROM messages WHERE hub_id = ?1 AND channel_id = ?2 AND id = ?3 ORDER BY seq LIMIT 1",
+                    params,
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?
+                .ok_or(Error::MessageNotFound)?;
+            transaction.execute(
+                "UPDATE messages SET armoured_content = ?1 WHERE seq = ?2",
+                params![tombstone.armoured_content, seq],
+            )?;
+            transaction.execute(
+                "DELETE FROM revisions WHERE hub_id = ?1 AND channel_id = ?2 AND message_id = ?3",
+                params,
+            )?;
+            transaction.execute(
+                "DELETE FROM reactions WHERE hub_id = ?1 AND channel_id = ?2 AND message_id = ?3",
+                params,
+            )?;
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn prune_messages(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        policy: RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ID>> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let mut values: Vec<rusqlite::types::Value> =
+                vec![id_to_blob(hub_id).into(), id_to_blob(channel_id).into()];
+            let mut conditions = Vec::new();
+            if let Some(cutoff) = policy.cutoff(now) {
+                values.push(time_to_text(&cutoff).into());
+                conditions.push(format!("created < ?{}", values.len()));
+            }
+            if let Some(max) = policy.max_messages {
+                values.push(i64::try_from(max).unwrap_or(i64::MAX).into());
+                conditions.push(format!("seq IN (SELECT seq FROM messages WHERE hub_id = ?1 AND channel_id = ?2 ORDER BY seq DESC LIMIT -1 OFFSET ?{})", values.len()));
+            }
+            if conditions.is_empty() {
+                return Ok(Vec::new());
+            }
+            let condition = format!(
+                "hub_id = ?1 AND channel_id = ?2 AND ({})",
+                conditions.join(" OR ")
+            );
+            let ids = {
+                let mut statement =
+                    transaction.prepare(&format!("SELECT id FROM messages WHERE {}", condition))?;
+                let rows = statement
+                    .query_map(rusqlite::params_from_iter(values.iter()), |row| {
+                        row.get::<_, Vec<u8>>(0)
+                    })?
+                    .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?;
+                rows.iter()
+                    .map(|id| Ok(ID::from_slice(id)?))
+                    .collect::<Result<Vec<ID>>>()?
+            };
+            transaction.execute(
+                &format!("DELETE FROM messages WHERE {}", condition),
+                rusqlite::params_from_iter(values.iter()),
+            )?;
+            for table in ["revisions", "reactions"].iter() {
+                transaction.execute(
+                    &format!("DELETE FROM {} WHERE hub_id = ?1 AND channel_id = ?2 AND message_id NOT IN (SELECT id FROM messages WHERE hub_id = ?1 AND channel_id = ?2)", table),
+                    params![id_to_blob(hub_id), id_to_blob(channel_id)],
+                )?;
+            }
+            transaction.execute(
+                "DELETE FROM thread_replies WHERE hub_id = ?1 AND channel_id = ?2 AND (root_id NOT IN (SELECT id FROM messages WHERE hub_id = ?1 AND channel_id = ?2) OR message_id NOT IN (SELECT id FROM messages WHERE hub_id = ?1 AND channel_id = ?2))",
+                params![id_to_blob(hub_id), id_to_blob(channel_id)],
+            )?;
+            transaction.commit()?;
+            Ok(ids)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HubStore, MessageStore, SqliteStore};
+    use crate::{
+        channel::{ReadMarker, RetentionPolicy, SignedMessage},
+        conversation::Conversation,
+        error::Error,
+        hub::Hub,
+        new_id, ID,
+    };
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn save_load() {
+        let store = SqliteStore::open_in_memory().expect("Failed to open the database.");
+        let id = ID::nil();
+        let hub = Hub::new("test_hub".to_string(), id, id.to_string());
+        store.save_hub(&hub).await.expect("Failed to save the hub.");
+        let loaded = store.load_hub(id).await.expect("Failed to load the hub.");
+        assert_eq!(loaded.members, hub.members);
+        assert_eq!(loaded.groups, hub.groups);
+        assert_eq!(loaded.created, hub.created);
+    }
+
+    #[tokio::test]
+    async fn conversations() {
+        let store = SqliteStore::open_in_memory().expect("Failed to open the database.");
+        let (alice, bob, carol) = ("AA".repeat(20), "BB".repeat(20), "CC".repeat(20));
+        let pair = Conversation::new(&[alice.clone(), bob.clone()])
+            .expect("Failed to create a conversation.");
+        let group = Conversation::new(&[alice.clone(), bob, carol.clone()])
+            .expect("Failed to create a conversation.");
+        for conversation in [&pair, &group].iter() {
+            store
+                .save_conversation(conversation)
+                .await
+                .expect("Failed to save the conversation.");
+        }
+        assert_eq!(
+            store
+                .load_conversation(pair.id)
+                .await
+                .expect("Failed to load the conversation."),
+            pair
+        );
+        assert_eq!(
+            store
+                .list_conversations(&alice)
+                .await
+                .expect("Failed to list conversations.")
+                .len(),
+            2
+        );
+        assert_eq!(
+            store
+                .list_conversations(&carol)
+                .await
+                .expect("Failed to list conversations."),
+            vec![group]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_markers() {
+        let store = SqliteStore::open_in_memory().expect("Failed to open the database.");
+        let (hub_id, channel_id) = (new_id(), new_id());
+        let marker = |sequence| ReadMarker {
+            message_id: new_id(),
+            sequence,
+            updated: Utc::now(),
+        };
+        assert_eq!(
+            store
+                .get_read_marker(hub_id, channel_id, "reader")
+                .await
+                .expect("Failed to get the read marker."),
+            None
+        );
+        let later = marker(5);
+        assert!(store
+            .set_read_marker(hub_id, channel_id, "reader", later)
+            .await
+            .expect("Failed to set the read marker."));
+        assert!(!store
+            .set_read_marker(hub_id, channel_id, "reader", marker(2))
+            .await
+            .expect("Failed to set the read marker."));
+        assert_eq!(
+            store
+                .get_read_marker(hub_id, channel_id, "reader")
+                .await
+                .expect("Failed to get the read marker."),
+            Some(later)
+        );
+        assert_eq!(
+            store
+                .get_read_marker(hub_id, channel_id, "other")
+                .await
+                .expect("Failed to get the read marker."),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn revisions() {
+        let store = SqliteStore::open_in_memory().expect("Failed to open the database.");
+        let (hub_id, channel_id) = (new_id(), new_id());
+        let ids: Vec<ID> = (0..2).map(|_| new_id()).collect();
+        for id in ids.iter() {
+            store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(*id, Utc::now(), String::new()),
+                )
+                .await
+                .expect("Failed to add a message.");
+        }
+        let revisions: Vec<ID> = (0..2).map(|_| new_id()).collect();
+        for (revision, content) in revisions.iter().zip(["first", "second"].iter()) {
+            store
+                .add_revision(
+                    hub_id,
+                    channel_id,
+                    ids[0],
+                    SignedMessage::new(*revision, Utc::now(), content.to_string()),
+                )
+                .await
+                .expect("Failed to add a revision.");
+        }
+        let stored = store
+            .get_revisions(hub_id, channel_id, ids[0])
+            .await
+            .expect("Failed to get the revisions.");
+        assert_eq!(stored.iter().map(|r| r.id).collect::<Vec<ID>>(), revisions);
+        assert_eq!(stored[1].armoured_content, "second");
+        assert!(matches!(
+            store
+                .add_revision(
+                    hub_id,
+                    channel_id,
+                    ids[0],
+                    SignedMessage::new(revisions[0], Utc::now(), "again".to_string()),
+                )
+                .await,
+            Err(Error::RevisionAlreadyExists)
+        ));
+        let policy = RetentionPolicy {
+            max_age_days: None,
+            max_messages: Some(1),
+        };
+        store
+            .prune_messages(hub_id, channel_id, policy, Utc::now())
+            .await
+            .expect("Failed to prune messages.");
+        assert!(store
+            .get_revisions(hub_id, channel_id, ids[0])
+            .await
+            .expect("Failed to get the revisions.")
+            .is_empty());
+    }
+    #[tokio::test]
+    async fn sequences() {
+        let store = SqliteStore::open_in_memory().expect("Failed to open the database.");
+        let (hub_id, channel_id) = (new_id(), new_id());
+        for expected in 0..3 {
+            let sequence = store
+                .add_message(
+                    hub_id,
+                    channel_id,
+                    SignedMessage::new(new_id(), Utc::now(), String::new()),
+                )
+                .await
+                .expect("Failed to add a message.");
+            assert_eq!(sequence, expected);
+        }
+        let policy = RetentionPolicy {
+            max_age_days: None,
+            max_messages: Some(0),
+        };
+        store
+            .prune_messages(hub_id, channel_id, policy, Utc::now())
+            .await
+            .expect("Failed to prune messages.");
+        let sequence = store
+            .add_message(
+                hub_id,
+                channel_id,
+                SignedMessage::new(new_id(), Utc::now(), String::new()),
+            )
+            .await
+            .expect("Failed to add a message.");
+        assert_eq!(sequence, 3);
+        let messages = store
+            .get_messages_from_sequence(hub_id, channel_id, 0, 10)
+            .await
+            .expect("Failed to get the messages.");
+        assert_eq!(
+            messages.iter().map(|m| m.sequence).collect::<Vec<u64>>(),
+            vec![3]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use std::{convert::TryFrom, path::PathBuf};
+
+use lazy_static::lazy_static;
+use pgp::{
+    composed::{key::SecretKeyParamsBuilder, KeyType, Message as OpenPGPMessage, SignedSecretKey},
+    packet::LiteralData,
+};
+
+use crate::{channel::Message, new_id, storage::FsStore};
+
+/// Uniquely named folder inside of the system's temporary folder that is deleted when it is dropped, even if the test using it panics.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Picks a new folder for the given test module, the folder is created by whatever is first written to it.
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("wicrs_{}_test_{:x}", name, new_id().as_u128())))
+    }
+
+    /// Gets the path of the folder.
+    pub fn path(&self) -> String {
+        self.0.to_string_lossy().to_string()
+    }
+
+    /// Creates a file system store that keeps its data in the standard folders inside of this folder.
+    pub fn store(&self) -> FsStore {
+        FsStore::in_dir(&self.path())
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+lazy_static! {
+    /// Key that tests sign tombstones with in place of the server's key, an EdDSA key since those are quick to generate.
+    pub static ref SECRET_KEY: SignedSecretKey = SecretKeyParamsBuilder::default()
+        .key_type(KeyType::EdDSA)
+        .can_sign(true)
+        .primary_user_id("WICRS Test <test@wicrs.test>".to_string())
+        .build()
+        .expect("Failed to build the key parameters.")
+        .generate()
+        .expect("Failed to generate a key.")
+        .sign(String::new)
+        .expect("Failed to sign the key.");
+}
+
+/// Armours a message the way it is stored once signed by the server and then the user, without the signatures, which are only checked when messages are received.
+pub fn double_signed(message: &Message) -> String {
+    let server_signed = OpenPGPMessage::try_from(message)
+        .and_then(|signed| Ok(signed.to_armored_string(None)?))
+        .expect("Failed to armour the message.");
+    OpenPGPMessage::Literal(LiteralData::from_str(
+        &message.id.to_string(),
+        &server_signed,
+    ))
+    .to_armored_string(None)
+    .expect("Failed to armour the message.")
+}
@@ -1,10 +1,10 @@
 use std::sync::Arc;
 
 use crate::{
-    channel::Message,
+    api,
+    channel::{Message, MessageCursor, MessagePage, MessagePosition, SignedMessage},
     error::Error,
-    hub::Hub,
-    permission::ChannelPermission,
+    permission::{ChannelPermission, PermissionSetting},
     server::{Server, ServerNotification},
 };
 use crate::{server::client_command, ID};
@@ -46,14 +46,121 @@ pub enum ClientMessage {
         hub_id: ID,
         channel_id: ID,
     },
+    /// Starts sending a message, `reply_to` is the ID of the message that it replies to, if any.
     SendMessageInit {
         hub_id: ID,
         channel_id: ID,
         content: String,
+        #[serde(default)]
+        reply_to: Option<ID>,
+        /// Hashes of attachments uploaded to the channel.
+        #[serde(default)]
+        attachments: Vec<String>,
     },
     SendMessage {
         signed_message: String,
     },
+    /// Starts editing a message, answered with [`ServerMessage::MessageForSigning`] like [`ClientMessage::SendMessageInit`].
+    EditMessageInit {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        content: String,
+    },
+    /// Finishes editing a message with the revision from [`ClientMessage::EditMessageInit`] after it has been signed by the client.
+    EditMessage {
+        signed_message: String,
+    },
+    /// Deletes a message, users can delete their own messages and moderators can delete anyone's messages.
+    DeleteMessage {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    },
+    /// Reacts to a message with an emoji.
+    AddReaction {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        emoji: String,
+    },
+    /// Removes a reaction from a message.
+    RemoveReaction {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        emoji: String,
+    },
+    /// Pins a message in a channel, requires permission to manage the channel.
+    PinMessage {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    },
+    /// Unpins a message in a channel, requires permission to manage the channel.
+    UnpinMessage {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    },
+    /// Sets a channel permission for a hub member in the channels of a category, requires permission to administrate the hub.
+    SetMemberCategoryPermission {
+        hub_id: ID,
+        member_id: String,
+        category_id: ID,
+        permission: ChannelPermission,
+        value: PermissionSetting,
+    },
+    /// Sets a channel permission for a permission group in the channels of a category, requires permission to administrate the hub.
+    SetGroupCategoryPermission {
+        hub_id: ID,
+        group_id: ID,
+        category_id: ID,
+        permission: ChannelPermission,
+        value: PermissionSetting,
+    },
+    /// Marks the messages in a channel up to and including the given message as read, the marker is shared by all of the user's devices.
+    MarkRead {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+    },
+    /// Lists a page of messages, at most one of `before`, `after` and `around` can be set to a cursor returned in [`ServerMessage::Messages`].
+    /// Alternatively `from_sequence` can be set to list the messages starting at a sequence number.
+    ListMessages {
+        hub_id: ID,
+        channel_id: ID,
+        before: Option<String>,
+        after: Option<String>,
+        around: Option<String>,
+        #[serde(default)]
+        from_sequence: Option<u64>,
+        limit: usize,
+    },
+    /// Subscribes to the new messages in a direct conversation, they are sent as [`ServerMessage::ChatMessage`] with the conversation's ID as both the hub and the channel ID.
+    SubscribeConversation {
+        conversation_id: ID,
+    },
+    UnsubscribeConversation {
+        conversation_id: ID,
+    },
+    /// Starts sending a message in a direct conversation, finished with [`ClientMessage::SendMessage`] like messages sent in channels.
+    SendDirectMessageInit {
+        conversation_id: ID,
+        content: String,
+        #[serde(default)]
+        reply_to: Option<ID>,
+    },
+    /// Lists a page of messages in a direct conversation, see [`ClientMessage::ListMessages`].
+    ListDirectMessages {
+        conversation_id: ID,
+        before: Option<String>,
+        after: Option<String>,
+        around: Option<String>,
+        #[serde(default)]
+        from_sequence: Option<u64>,
+        limit: usize,
+    },
 }
 
 /// Messages that the server can send to clients.
@@ -68,6 +175,8 @@ pub enum ServerMessage {
         channel_id: ID,
         message_id: ID,
         armoured_message: String,
+        /// Sequence number of the message in its channel, clients that see a gap in the numbers have missed messages.
+        sequence: u64,
     },
     HubUpdated {
         hub_id: ID,
@@ -87,6 +196,59 @@ pub enum ServerMessage {
     MessageForSigning {
         server_signed_message: String,
     },
+    /// A message was edited, `armoured_revision` is the new double signed revision of the message.
+    MessageEdited {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        armoured_revision: String,
+    },
+    /// A message was deleted, `armoured_tombstone` is the tombstone signed by the server that replaced it.
+    MessageDeleted {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        armoured_tombstone: String,
+    },
+    /// A user reacted to a message.
+    ReactionAdded {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: String,
+        emoji: String,
+    },
+    /// A user removed their reaction to a message.
+    ReactionRemoved {
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        user_id: String,
+        emoji: String,
+    },
+    /// A page of messages ordered oldest to newest, the cursors are for the first and last messages in the page.
+    Messages {
+        hub_id: ID,
+        channel_id: ID,
+        messages: Vec<SignedMessage>,
+        start_cursor: Option<String>,
+        end_cursor: Option<String>,
+        has_before: bool,
+        has_after: bool,
+    },
+}
+
+/// Builds the response to a request for a page of messages.
+fn messages_page(hub_id: ID, channel_id: ID, page: MessagePage) -> ServerMessage {
+    ServerMessage::Messages {
+        hub_id,
+        channel_id,
+        start_cursor: page.messages.first().map(|m| MessageCursor(m.id).encode()),
+        end_cursor: page.messages.last().map(|m| MessageCursor(m.id).encode()),
+        messages: page.messages,
+        has_before: page.has_before,
+        has_after: page.has_after,
+    }
 }
 
 pub async fn handle_connection(
@@ -236,25 +398,26 @@ pub async fn handle_connection(
                                         hub_id,
                                         channel_id,
                                         content,
+                                        reply_to,
+                                        attachments,
                                     } => {
-                                        let hub = Hub::load(hub_id).await?;
-                                        let member = hub.get_member(&user_id)?;
-                                        crate::check_permission!(
-                                            &member,
+                                        match api::init_message(
+                                            &user_id,
+                                            hub_id,
                                             channel_id,
-                                            ChannelPermission::Write,
-                                            &hub
-                                        );
-                                        ServerMessage::MessageForSigning {
-                                            server_signed_message: Message::new(
-                                                user_id.clone(),
-                                                content,
-                                                hub_id,
-                                                channel_id,
-                                            )
-                                            .sign(&server_keys.secret_key, String::new)?
-                                            .compress(CompressionAlgorithm::ZIP)?
-                                            .to_armored_string(None)?,
+                                            content,
+                                            reply_to,
+                                            &attachments,
+                                        )
+                                        .await
+                                        {
+                                            Ok(message) => ServerMessage::MessageForSigning {
+                                                server_signed_message: message
+                                                    .sign(&server_keys.secret_key, String::new)?
+                                                    .compress(CompressionAlgorithm::ZIP)?
+                                                    .to_armored_string(None)?,
+                                            },
+                                            Err(err) => ServerMessage::Error(err.to_string()),
                                         }
                                     }
                                     ClientMessage::SendMessage { signed_message } => {
@@ -263,26 +426,395 @@ pub async fn handle_connection(
                                             &server_keys.public_key,
                                             &public_key,
                                         )?;
-                                        if let Err(err) = crate::channel::Channel::write_message(
-                                            message.hub_id,
-                                            message.channel_id,
-                                            crate::channel::SignedMessage::new(
-                                                message.id,
-                                                message.created,
+                                        match api::send_message(
+                                            &user_id,
+                                            &message,
+                                            signed_message.clone(),
+                                        )
+                                        .await
+                                        {
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                            Ok(sequence) => {
+                                                if addr
+                                                    .call(ServerNotification::NewMessage(
+                                                        message.hub_id,
+                                                        message.channel_id,
+                                                        message.id,
+                                                        signed_message,
+                                                        message,
+                                                        sequence,
+                                                    ))
+                                                    .await
+                                                    .is_ok()
+                                                {
+                                                    ServerMessage::Success
+                                                } else {
+                                                    ServerMessage::Error(
+                                                        internal_message_error.clone(),
+                                                    )
+                                                }
+                                            }
+                                        }
+                                    }
+                                    ClientMessage::EditMessageInit {
+                                        hub_id,
+                                        channel_id,
+                                        message_id,
+                                        content,
+                                    } => {
+                                        match api::init_message_edit(
+                                            &user_id, hub_id, channel_id, message_id, content,
+                                        )
+                                        .await
+                                        {
+                                            Ok(revision) => ServerMessage::MessageForSigning {
+                                                server_signed_message: revision
+                                                    .sign(&server_keys.secret_key, String::new)?
+                                                    .compress(CompressionAlgorithm::ZIP)?
+                                                    .to_armored_string(None)?,
+                                            },
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::EditMessage { signed_message } => {
+                                        let revision = Message::from_double_signed_verify(
+                                            &signed_message,
+                                            &server_keys.public_key,
+                                            &public_key,
+                                        )?;
+                                        if let Some(message_id) = revision.revision_of {
+                                            if let Err(err) = api::edit_message(
+                                                &user_id,
+                                                &revision,
                                                 signed_message.clone(),
-                                            ),
+                                            )
+                                            .await
+                                            {
+                                                ServerMessage::Error(err.to_string())
+                                            } else if addr
+                                                .call(ServerNotification::MessageEdited(
+                                                    revision.hub_id,
+                                                    revision.channel_id,
+                                                    message_id,
+                                                    signed_message,
+                                                    revision,
+                                                ))
+                                                .await
+                                                .is_ok()
+                                            {
+                                                ServerMessage::Success
+                                            } else {
+                                                ServerMessage::Error(internal_message_error.clone())
+                                            }
+                                        } else {
+                                            ServerMessage::Error(Error::InvalidMessage.to_string())
+                                        }
+                                    }
+                                    ClientMessage::DeleteMessage {
+                                        hub_id,
+                                        channel_id,
+                                        message_id,
+                                    } => {
+                                        match api::delete_message(
+                                            &user_id,
+                                            hub_id,
+                                            channel_id,
+                                            message_id,
+                                            &server_keys.secret_key,
+                                        )
+                                        .await
+                                        {
+                                            Ok(tombstone) => {
+                                                if addr
+                                                    .call(ServerNotification::MessageDeleted(
+                                                        hub_id,
+                                                        channel_id,
+                                                        message_id,
+                                                        tombstone.armoured_content,
+                                                    ))
+                                                    .await
+                                                    .is_ok()
+                                                {
+                                                    ServerMessage::Success
+                                                } else {
+                                                    ServerMessage::Error(
+                                                        internal_message_error.clone(),
+                                                    )
+                                                }
+                                            }
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::AddReaction {
+                                        hub_id,
+                                        channel_id,
+                                        message_id,
+                                        emoji,
+                                    } => {
+                                        match api::add_reaction(
+                                            &user_id, hub_id, channel_id, message_id, &emoji,
+                                        )
+                                        .await
+                                        {
+                                            Ok(true) => {
+                                                if addr
+                                                    .call(ServerNotification::ReactionAdded(
+                                                        hub_id,
+                                                        channel_id,
+                                                        message_id,
+                                                        user_id.clone(),
+                                                        emoji,
+                                                    ))
+                                                    .await
+                                                    .is_ok()
+                                                {
+                                                    ServerMessage::Success
+                                                } else {
+                                                    ServerMessage::Error(
+                                                        internal_message_error.clone(),
+                                                    )
+                                                }
+                                            }
+                                            Ok(false) => ServerMessage::Success,
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::RemoveReaction {
+                                        hub_id,
+                                        channel_id,
+                                        message_id,
+                                        emoji,
+                                    } => {
+                                        match api::remove_reaction(
+                                            &user_id, hub_id, channel_id, message_id, &emoji,
+                                        )
+                                        .await
+                                        {
+                                            Ok(true) => {
+                                                if addr
+                                                    .call(ServerNotification::ReactionRemoved(
+                                                        hub_id,
+                                                        channel_id,
+                                                        message_id,
+                                                        user_id.clone(),
+                                                        emoji,
+                                                    ))
+                                                    .await
+                                                    .is_ok()
+                                                {
+                                                    ServerMessage::Success
+                                                } else {
+                                                    ServerMessage::Error(
+                                                        internal_message_error.clone(),
+                                                    )
+                                                }
+                                            }
+                                            Ok(false) => ServerMessage::Success,
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::PinMessage {
+                                        hub_id,
+                                        channel_id,
+                                        message_id,
+                                    } => {
+                                        match api::pin_message(
+                                            &user_id, hub_id, channel_id, message_id,
+                                        )
+                                        .await
+                                        {
+                                            Ok(true) => {
+                                                if addr
+                                                    .call(ServerNotification::HubUpdated(
+                                                        hub_id,
+                                                        HubUpdateType::MessagePinned(
+                                                            channel_id, message_id,
+                                                        ),
+                                                    ))
+                                                    .await
+                                                    .is_ok()
+                                                {
+                                                    ServerMessage::Success
+                                                } else {
+                                                    ServerMessage::Error(
+                                                        internal_message_error.clone(),
+                                                    )
+                                                }
+                                            }
+                                            Ok(false) => ServerMessage::Success,
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::UnpinMessage {
+                                        hub_id,
+                                        channel_id,
+                                        message_id,
+                                    } => {
+                                        match api::unpin_message(
+                                            &user_id, hub_id, channel_id, message_id,
+                                        )
+                                        .await
+                                        {
+                                            Ok(true) => {
+                                                if addr
+                                                    .call(ServerNotification::HubUpdated(
+                                                        hub_id,
+                                                        HubUpdateType::MessageUnpinned(
+                                                            channel_id, message_id,
+                                                        ),
+                                                    ))
+                                                    .await
+                                                    .is_ok()
+                                                {
+                                                    ServerMessage::Success
+                                                } else {
+                                                    ServerMessage::Error(
+                                                        internal_message_error.clone(),
+                                                    )
+                                                }
+                                            }
+                                            Ok(false) => ServerMessage::Success,
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::ListMessages {
+                                        hub_id,
+                                        channel_id,
+                                        before,
+                                        after,
+                                        around,
+                                        from_sequence,
+                                        limit,
+                                    } => {
+                                        let result = match MessagePosition::from_cursors(
+                                            before.as_deref(),
+                                            after.as_deref(),
+                                            around.as_deref(),
+                                            from_sequence,
+                                        ) {
+                                            Ok(position) => {
+                                                api::list_messages(
+                                                    &user_id, hub_id, channel_id, position, limit,
+                                                )
+                                                .await
+                                            }
+                                            Err(err) => Err(err),
+                                        };
+                                        result.map_or_else(
+                                            |err| ServerMessage::Error(err.to_string()),
+                                            |page| messages_page(hub_id, channel_id, page),
+                                        )
+                                    }
+                                    ClientMessage::SetMemberCategoryPermission {
+                                        hub_id,
+                                        member_id,
+                                        category_id,
+                                        permission,
+                                        value,
+                                    } => {
+                                        match api::set_member_category_permission(
+                                            &user_id,
+                                            hub_id,
+                                            &member_id,
+                                            category_id,
+                                            permission,
+                                            value,
                                         )
                                         .await
                                         {
-                                            ServerMessage::Error(err.to_string())
-                                        } else if addr
-                                            .call(ServerNotification::NewMessage(
-                                                message.hub_id,
-                                                message.channel_id,
-                                                message.id,
-                                                signed_message,
-                                                message,
-                                            ))
+                                            Ok(()) => {
+                                                if addr
+                                                    .call(ServerNotification::HubUpdated(
+                                                        hub_id,
+                                                        HubUpdateType::CategoryPermissionChanged(
+                                                            category_id,
+                                                        ),
+                                                    ))
+                                                    .await
+                                                    .is_ok()
+                                                {
+                                                    ServerMessage::Success
+                                                } else {
+                                                    ServerMessage::Error(
+                                                        internal_message_error.clone(),
+                                                    )
+                                                }
+                                            }
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::SetGroupCategoryPermission {
+                                        hub_id,
+                                        group_id,
+                                        category_id,
+                                        permission,
+                                        value,
+                                    } => {
+                                        match api::set_group_category_permission(
+                                            &user_id,
+                                            hub_id,
+                                            group_id,
+                                            category_id,
+                                            permission,
+                                            value,
+                                        )
+                                        .await
+                                        {
+                                            Ok(()) => {
+                                                if addr
+                                                    .call(ServerNotification::HubUpdated(
+                                                        hub_id,
+                                                        HubUpdateType::CategoryPermissionChanged(
+                                                            category_id,
+                                                        ),
+                                                    ))
+                                                    .await
+                                                    .is_ok()
+                                                {
+                                                    ServerMessage::Success
+                                                } else {
+                                                    ServerMessage::Error(
+                                                        internal_message_error.clone(),
+                                                    )
+                                                }
+                                            }
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::MarkRead {
+                                        hub_id,
+                                        channel_id,
+                                        message_id,
+                                    } => api::mark_read(&user_id, hub_id, channel_id, message_id)
+                                        .await
+                                        .map_or_else(
+                                            |err| ServerMessage::Error(err.to_string()),
+                                            |_| ServerMessage::Success,
+                                        ),
+                                    ClientMessage::SubscribeConversation { conversation_id } => {
+                                        if let Ok(result) = addr
+                                            .call(client_command::SubscribeConversation {
+                                                user_id: user_id.clone(),
+                                                conversation_id,
+                                                connection_id,
+                                            })
+                                            .await
+                                        {
+                                            result.map_or_else(
+                                                |err| ServerMessage::Error(err.to_string()),
+                                                |_| ServerMessage::Success,
+                                            )
+                                        } else {
+                                            ServerMessage::Error(internal_message_error.clone())
+                                        }
+                                    }
+                                    ClientMessage::UnsubscribeConversation { conversation_id } => {
+                                        if addr
+                                            .call(client_command::UnsubscribeChannel {
+                                                hub_id: conversation_id,
+                                                channel_id: conversation_id,
+                                                connection_id,
+                                            })
                                             .await
                                             .is_ok()
                                         {
@@ -291,6 +823,64 @@ pub async fn handle_connection(
                                             ServerMessage::Error(internal_message_error.clone())
                                         }
                                     }
+                                    ClientMessage::SendDirectMessageInit {
+                                        conversation_id,
+                                        content,
+                                        reply_to,
+                                    } => {
+                                        match api::init_direct_message(
+                                            &user_id,
+                                            conversation_id,
+                                            content,
+                                            reply_to,
+                                        )
+                                        .await
+                                        {
+                                            Ok(message) => ServerMessage::MessageForSigning {
+                                                server_signed_message: message
+                                                    .sign(&server_keys.secret_key, String::new)?
+                                                    .compress(CompressionAlgorithm::ZIP)?
+                                                    .to_armored_string(None)?,
+                                            },
+                                            Err(err) => ServerMessage::Error(err.to_string()),
+                                        }
+                                    }
+                                    ClientMessage::ListDirectMessages {
+                                        conversation_id,
+                                        before,
+                                        after,
+                                        around,
+                                        from_sequence,
+                                        limit,
+                                    } => {
+                                        let result = match MessagePosition::from_cursors(
+                                            before.as_deref(),
+                                            after.as_deref(),
+                                            around.as_deref(),
+                                            from_sequence,
+                                        ) {
+                                            Ok(position) => {
+                                                api::list_direct_messages(
+                                                    &user_id,
+                                                    conversation_id,
+                                                    position,
+                                                    limit,
+                                                )
+                                                .await
+                                            }
+                                            Err(err) => Err(err),
+                                        };
+                                        result.map_or_else(
+                                            |err| ServerMessage::Error(err.to_string()),
+                                            |page| {
+                                                messages_page(
+                                                    conversation_id,
+                                                    conversation_id,
+                                                    page,
+                                                )
+                                            },
+                                        )
+                                    }
                                 }
                             } else {
                                 ServerMessage::InvalidCommand